@@ -0,0 +1,49 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! # Codec Benchmarks
+//!
+//! Times `encode` and `decode` over the scenarios in `testing::scenarios`. Run with
+//! `cargo bench --features testing`.
+
+use descriptor_codec::{decode, encode, testing};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+/// Minimum time spent on each measurement
+const TARGET: Duration = Duration::from_millis(500);
+
+/// Returns the mean time of `f`, running it until `TARGET` has elapsed
+fn measure(mut f: impl FnMut()) -> Duration {
+    // Warm up caches and the allocator
+    f();
+
+    let start = Instant::now();
+    let mut iterations = 0;
+    while start.elapsed() < TARGET {
+        f();
+        iterations += 1;
+    }
+    start.elapsed() / iterations
+}
+
+fn main() {
+    for scenario in testing::scenarios() {
+        let descriptor = scenario.descriptor.as_str();
+        let encoded = encode(descriptor).unwrap();
+
+        let encode_time = measure(|| {
+            black_box(encode(black_box(descriptor)).unwrap());
+        });
+        let decode_time = measure(|| {
+            black_box(decode(black_box(&encoded)).unwrap());
+        });
+
+        println!(
+            "{:<16} {:>5} bytes  encode {:>10.2?}  decode {:>10.2?}",
+            scenario.name,
+            encoded.len(),
+            encode_time,
+            decode_time
+        );
+    }
+}
@@ -0,0 +1,5 @@
+// SPDX-License-Identifier: CC0-1.0
+
+fn main() {
+    napi_build::setup();
+}
@@ -0,0 +1,91 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! # Node.js Bindings
+//!
+//! Native encode and decode for Node.js and Electron wallets. Each function has an async variant
+//! that runs on the libuv thread pool, so large bundles never block the main thread.
+
+#![deny(missing_docs)]
+
+use descriptor_codec::flat_api;
+use napi::bindgen_prelude::{AbortSignal, AsyncTask, Buffer};
+use napi::{Env, Error, Result, Task};
+use napi_derive::napi;
+
+/// Parses and encodes a Bitcoin descriptor
+#[napi]
+pub fn encode(descriptor: String) -> Result<Buffer> {
+    Ok(encode_descriptor(&descriptor)?.into())
+}
+
+/// Decodes an encoded Bitcoin descriptor
+#[napi]
+pub fn decode(data: Buffer) -> Result<String> {
+    decode_descriptor(&data)
+}
+
+/// Parses and encodes a Bitcoin descriptor off the main thread
+#[napi]
+pub fn encode_async(descriptor: String, signal: Option<AbortSignal>) -> AsyncTask<EncodeTask> {
+    AsyncTask::with_optional_signal(EncodeTask { descriptor }, signal)
+}
+
+/// Decodes an encoded Bitcoin descriptor off the main thread
+#[napi]
+pub fn decode_async(data: Buffer, signal: Option<AbortSignal>) -> AsyncTask<DecodeTask> {
+    AsyncTask::with_optional_signal(
+        DecodeTask {
+            data: data.to_vec(),
+        },
+        signal,
+    )
+}
+
+/// Encoding run on the thread pool
+pub struct EncodeTask {
+    descriptor: String,
+}
+
+impl Task for EncodeTask {
+    type Output = Vec<u8>;
+    type JsValue = Buffer;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        encode_descriptor(&self.descriptor)
+    }
+
+    fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+        Ok(output.into())
+    }
+}
+
+/// Decoding run on the thread pool
+pub struct DecodeTask {
+    data: Vec<u8>,
+}
+
+impl Task for DecodeTask {
+    type Output = String;
+    type JsValue = String;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        decode_descriptor(&self.data)
+    }
+
+    fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+        Ok(output)
+    }
+}
+
+fn encode_descriptor(descriptor: &str) -> Result<Vec<u8>> {
+    flat_api::encode(descriptor).map_err(to_error)
+}
+
+fn decode_descriptor(data: &[u8]) -> Result<String> {
+    flat_api::decode(data).map_err(to_error)
+}
+
+/// Converts a flat API error code into a JavaScript error with the code in its message
+fn to_error(code: i32) -> Error {
+    Error::from_reason(format!("{} (code: {code})", flat_api::error_message(code)))
+}
@@ -0,0 +1,32 @@
+// SPDX-License-Identifier: CC0-1.0
+
+// Smoke test of the built addon: run `npm run build` first
+
+const assert = require('node:assert');
+const { encode, decode, encodeAsync, decodeAsync } = require('./index.js');
+
+const descriptor =
+  'wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)#8zl0zxma';
+
+async function main() {
+  const encoded = encode(descriptor);
+  assert.ok(Buffer.isBuffer(encoded));
+  assert.ok(encoded.length < descriptor.length);
+  assert.strictEqual(decode(encoded), descriptor);
+
+  assert.deepStrictEqual(await encodeAsync(descriptor), encoded);
+  assert.strictEqual(await decodeAsync(encoded), descriptor);
+
+  // Encode errors have codes below 100, decode errors 100 and above
+  assert.throws(() => encode('wpkh(invalid)'), /\(code: \d{1,2}\)$/);
+  assert.throws(() => decode(Buffer.alloc(0)), /\(code: 1\d\d\)$/);
+  await assert.rejects(decodeAsync(Buffer.alloc(0)), /\(code: 1\d\d\)$/);
+}
+
+main().then(
+  () => console.log('ok'),
+  (err) => {
+    console.error(err);
+    process.exit(1);
+  },
+);
@@ -0,0 +1,149 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! # Accounts
+//!
+//! Expands a master extended private key into the standard descriptors of one or more account
+//! types, so a whole HD wallet can be backed up as a single bundle.
+
+use super::*;
+use bitcoin::{NetworkKind, bip32::Xpriv};
+
+/// A standard account type, by its BIP-43 purpose
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Purpose {
+    /// BIP-44 legacy `pkh`
+    Bip44,
+    /// BIP-49 nested segwit `sh(wpkh)`
+    Bip49,
+    /// BIP-84 native segwit `wpkh`
+    Bip84,
+    /// BIP-86 taproot `tr`
+    Bip86,
+}
+
+impl Purpose {
+    /// Every standard account type
+    pub const ALL: [Purpose; 4] = [
+        Purpose::Bip44,
+        Purpose::Bip49,
+        Purpose::Bip84,
+        Purpose::Bip86,
+    ];
+
+    /// Returns the purpose number used as the first derivation step
+    pub fn value(&self) -> u32 {
+        match self {
+            Purpose::Bip44 => 44,
+            Purpose::Bip49 => 49,
+            Purpose::Bip84 => 84,
+            Purpose::Bip86 => 86,
+        }
+    }
+
+    /// Wraps a key expression in the descriptor of this account type
+    fn descriptor(&self, key: &str) -> String {
+        match self {
+            Purpose::Bip44 => format!("pkh({key})"),
+            Purpose::Bip49 => format!("sh(wpkh({key}))"),
+            Purpose::Bip84 => format!("wpkh({key})"),
+            Purpose::Bip86 => format!("tr({key})"),
+        }
+    }
+}
+
+/// Returns the standard descriptor of each account type for a master `xprv`
+///
+/// Each descriptor keeps the master key and derives `purpose'/coin'/account'/<0;1>/*`, with coin
+/// type 1 for test networks. Hardened derivation needs the private key, so a master `xpub` is
+/// rejected. The public form can be produced with [`derive_watch_only`].
+pub fn account_descriptors(
+    master: &str,
+    account: u32,
+    purposes: &[Purpose],
+) -> Result<Vec<String>, encoder::Error> {
+    let xprv = Xpriv::from_str(master).map_err(|_| encoder::Error::NotMasterKey)?;
+    if xprv.depth != 0 {
+        return Err(encoder::Error::NotMasterKey);
+    }
+
+    let coin = match xprv.network {
+        NetworkKind::Main => 0,
+        NetworkKind::Test => 1,
+    };
+    purposes
+        .iter()
+        .map(|purpose| {
+            let key = format!("{xprv}/{}'/{coin}'/{account}'/<0;1>/*", purpose.value());
+            let descriptor = purpose.descriptor(&key);
+            // Validate the key and account number
+            parse_descriptor(&descriptor)?;
+            Ok(descriptor)
+        })
+        .collect()
+}
+
+/// Encodes the standard descriptor of each account type for a master `xprv` as a bundle
+///
+/// See [`account_descriptors`].
+pub fn encode_accounts(
+    master: &str,
+    account: u32,
+    purposes: &[Purpose],
+) -> Result<Vec<u8>, encoder::Error> {
+    let entries = account_descriptors(master, account, purposes)?
+        .iter()
+        .map(|descriptor| encode(descriptor))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(bundle::encode_bundle(&entries))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XPRV: &str = "xprv9s21ZrQH143K31xYSDQpPDxsXRTUcvj2iNHm5NUtrGiGG5e2DtALGdso3pGz6ssrdK4PFmM8NSpSBHNqPqm55Qn3LqFtT2emdEXVYsCzC2U";
+
+    #[test]
+    fn test_account_descriptors() {
+        let descriptors = account_descriptors(XPRV, 0, &Purpose::ALL).unwrap();
+        assert_eq!(
+            descriptors,
+            vec![
+                format!("pkh({XPRV}/44'/0'/0'/<0;1>/*)"),
+                format!("sh(wpkh({XPRV}/49'/0'/0'/<0;1>/*))"),
+                format!("wpkh({XPRV}/84'/0'/0'/<0;1>/*)"),
+                format!("tr({XPRV}/86'/0'/0'/<0;1>/*)"),
+            ]
+        );
+
+        let bundle = encode_accounts(XPRV, 0, &Purpose::ALL).unwrap();
+        let decoded = bundle::decode_bundle(&bundle).unwrap();
+        assert_eq!(decoded.len(), 4);
+        for (decoded, descriptor) in decoded.iter().zip(&descriptors) {
+            assert_eq!(
+                parse_descriptor(decoded).unwrap(),
+                parse_descriptor(descriptor).unwrap()
+            );
+        }
+
+        // Hardened derivation needs the master private key
+        let xpub = "xpub661MyMwAqRbcFW31YEwpkMuc5THy2PSt5bDMsktWQcFF8syAmRUapSCGu8ED9W6oDMSgv6Zz8idoc4a6mr8BDzTJY47LJhkJ8UB7WEGuduB";
+        assert_eq!(
+            account_descriptors(xpub, 0, &Purpose::ALL),
+            Err(encoder::Error::NotMasterKey)
+        );
+        let child = Xpriv::from_str(XPRV)
+            .unwrap()
+            .derive_priv(&secp256k1::Secp256k1::new(), &[0.into()])
+            .unwrap()
+            .to_string();
+        assert_eq!(
+            account_descriptors(&child, 0, &[Purpose::Bip84]),
+            Err(encoder::Error::NotMasterKey)
+        );
+        assert!(matches!(
+            account_descriptors(XPRV, 1 << 31, &[Purpose::Bip84]),
+            Err(encoder::Error::InvalidKey(_))
+        ));
+    }
+}
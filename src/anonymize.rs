@@ -0,0 +1,427 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! # Key Anonymization
+//!
+//! Replaces every key in an encoded descriptor with a deterministic placeholder of the same
+//! kind, so the structure of a wallet can be shared without revealing its keys. The returned
+//! [`KeyMapping`] restores the original keys with [`deanonymize`].
+
+use super::*;
+use bitcoin::{
+    NetworkKind, PublicKey,
+    bip32::{ChainCode, ChildNumber, DerivationPath, Fingerprint, Xpub},
+    hashes::HashEngine,
+    secp256k1::{All, Secp256k1, SecretKey},
+};
+use miniscript::descriptor::{DescriptorMultiXKey, DescriptorXKey, SinglePub, SinglePubKey};
+use std::convert::Infallible;
+use std::fmt;
+
+/// Map from placeholder keys to the keys they replaced
+///
+/// Displayed as one line per key, holding the placeholder and the original key separated by a
+/// space. Original secret keys are kept, so the mapping must be stored as securely as the
+/// descriptor itself. They are redacted unless the mapping is wrapped in [`RevealSecrets`].
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct KeyMapping {
+    entries: Vec<(DescriptorPublicKey, String)>,
+}
+
+impl KeyMapping {
+    /// Returns the number of keys in the mapping
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns whether the mapping is empty
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the original key replaced by a placeholder, which may be a secret key
+    pub fn original(&self, placeholder: &DescriptorPublicKey) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(pk, _)| pk == placeholder)
+            .map(|(_, original)| original.as_str())
+    }
+}
+
+impl fmt::Debug for KeyMapping {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let entries = self
+            .entries
+            .iter()
+            .map(|(placeholder, original)| (placeholder, secret::redact(original)));
+        f.debug_struct("KeyMapping")
+            .field("entries", &entries.collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl fmt::Display for KeyMapping {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (placeholder, original) in &self.entries {
+            writeln!(f, "{placeholder} {}", secret::redact(original))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for RevealSecrets<'_, KeyMapping> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (placeholder, original) in &self.0.entries {
+            writeln!(f, "{placeholder} {original}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for KeyMapping {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut entries = Vec::new();
+        let lines = s.lines().filter(|line| !line.trim().is_empty());
+        for (i, line) in lines.enumerate() {
+            let mut fields = line.split_whitespace();
+            let (Some(placeholder), Some(original), None) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                return Err(Error::InvalidMapping(i));
+            };
+
+            let placeholder =
+                DescriptorPublicKey::from_str(placeholder).map_err(|_| Error::InvalidMapping(i))?;
+            if DescriptorPublicKey::from_str(original).is_err()
+                && DescriptorSecretKey::from_str(original).is_err()
+            {
+                return Err(Error::InvalidMapping(i));
+            }
+
+            entries.push((placeholder, original.to_string()));
+        }
+
+        Ok(Self { entries })
+    }
+}
+
+/// Replaces every key in an encoded descriptor with a deterministic placeholder
+///
+/// Placeholders keep the kind of the key they replace, along with its derivation paths and
+/// wildcard, so the anonymized encoding has the same structure. Each distinct key is replaced by
+/// the same placeholder key wherever it appears, so reuse of a key along other paths is kept.
+/// Secret keys are replaced by public placeholders. Hashes and timelocks are kept.
+pub fn anonymize(bytes: &[u8]) -> Result<(Vec<u8>, KeyMapping), Error> {
+    struct Anonymizer<'a> {
+        key_map: &'a KeyMap,
+        mapping: KeyMapping,
+        /// Distinct keys seen so far, by [`key_material`], which seed their placeholders
+        materials: Vec<String>,
+        secp: Secp256k1<All>,
+    }
+
+    impl Translator<DescriptorPublicKey, DescriptorPublicKey, Infallible> for Anonymizer<'_> {
+        fn pk(&mut self, pk: &DescriptorPublicKey) -> Result<DescriptorPublicKey, Infallible> {
+            let original = match self.key_map.get(pk) {
+                Some(sk) => sk.to_string(),
+                None => pk.to_string(),
+            };
+            if let Some((placeholder, _)) =
+                self.mapping.entries.iter().find(|(_, o)| *o == original)
+            {
+                return Ok(placeholder.clone());
+            }
+
+            let material = match self.key_map.get(pk) {
+                Some(DescriptorSecretKey::Single(single)) => single.key.to_wif(),
+                Some(DescriptorSecretKey::XPrv(xprv)) => xprv.xkey.to_string(),
+                Some(DescriptorSecretKey::MultiXPrv(xprv)) => xprv.xkey.to_string(),
+                None => key_material(pk),
+            };
+            let index = match self.materials.iter().position(|key| *key == material) {
+                Some(index) => index,
+                None => {
+                    self.materials.push(material);
+                    self.materials.len() - 1
+                }
+            };
+
+            let seed = Seed::new(index as u32);
+            let placeholder = match self.key_map.get(pk) {
+                Some(DescriptorSecretKey::Single(single)) => {
+                    DescriptorPublicKey::Single(SinglePub {
+                        origin: seed.origin(&single.origin),
+                        key: SinglePubKey::FullKey(PublicKey {
+                            inner: seed.public_key(&self.secp),
+                            compressed: single.key.compressed,
+                        }),
+                    })
+                }
+                Some(DescriptorSecretKey::XPrv(xprv)) => {
+                    DescriptorPublicKey::XPub(DescriptorXKey {
+                        origin: seed.origin(&xprv.origin),
+                        xkey: seed.xpub(
+                            xprv.xkey.network,
+                            xprv.xkey.depth,
+                            xprv.xkey.child_number,
+                            &self.secp,
+                        ),
+                        derivation_path: xprv.derivation_path.clone(),
+                        wildcard: xprv.wildcard,
+                    })
+                }
+                Some(DescriptorSecretKey::MultiXPrv(xprv)) => {
+                    DescriptorPublicKey::MultiXPub(DescriptorMultiXKey {
+                        origin: seed.origin(&xprv.origin),
+                        xkey: seed.xpub(
+                            xprv.xkey.network,
+                            xprv.xkey.depth,
+                            xprv.xkey.child_number,
+                            &self.secp,
+                        ),
+                        derivation_paths: xprv.derivation_paths.clone(),
+                        wildcard: xprv.wildcard,
+                    })
+                }
+                None => seed.replace(pk, &self.secp),
+            };
+
+            self.mapping.entries.push((placeholder.clone(), original));
+            Ok(placeholder)
+        }
+
+        miniscript::translate_hash_clone!(DescriptorPublicKey, DescriptorPublicKey, Infallible);
+    }
+
+    let (descriptor, key_map) =
+        decoder::decode_bytes_underived(bytes, decoder::DecodeContext::default())?;
+    let mut anonymizer = Anonymizer {
+        key_map: &key_map,
+        mapping: KeyMapping::default(),
+        materials: Vec::new(),
+        secp: Secp256k1::new(),
+    };
+    let descriptor = descriptor
+        .translate_pk(&mut anonymizer)
+        .map_err(|e| match e {
+            TranslateErr::TranslatorErr(e) => match e {},
+            TranslateErr::OuterError(e) => Error::InvalidMiniscript(0, e),
+        })?;
+
+    let (template, payload) = encoder::encode(descriptor, &KeyMap::new());
+    Ok(([template, payload].concat(), anonymizer.mapping))
+}
+
+/// Decodes an anonymized descriptor, restoring its original keys
+///
+/// Fails with [`Error::UnmappedKey`] if a key in `bytes` is not in `mapping`.
+pub fn deanonymize(bytes: &[u8], mapping: &KeyMapping) -> Result<String, Error> {
+    struct Deanonymizer<'a>(&'a KeyMapping);
+
+    impl Translator<DescriptorPublicKey, String, Error> for Deanonymizer<'_> {
+        fn pk(&mut self, pk: &DescriptorPublicKey) -> Result<String, Error> {
+            self.0
+                .original(pk)
+                .map(str::to_string)
+                .ok_or(Error::UnmappedKey)
+        }
+
+        fn sha256(&mut self, sha256: &sha256::Hash) -> Result<String, Error> {
+            Ok(sha256.to_string())
+        }
+
+        fn hash256(&mut self, hash256: &hash256::Hash) -> Result<String, Error> {
+            Ok(hash256.to_string())
+        }
+
+        fn ripemd160(&mut self, ripemd160: &ripemd160::Hash) -> Result<String, Error> {
+            Ok(ripemd160.to_string())
+        }
+
+        fn hash160(&mut self, hash160: &hash160::Hash) -> Result<String, Error> {
+            Ok(hash160.to_string())
+        }
+    }
+
+    let (descriptor, _) =
+        decoder::decode_bytes_underived(bytes, decoder::DecodeContext::default())?;
+    let descriptor = descriptor
+        .translate_pk(&mut Deanonymizer(mapping))
+        .map_err(|e| match e {
+            TranslateErr::TranslatorErr(e) => e,
+            TranslateErr::OuterError(e) => Error::InvalidMiniscript(0, e),
+        })?;
+
+    Ok(descriptor.to_string())
+}
+
+/// Source of the deterministic values of a placeholder key
+struct Seed(sha256::Hash);
+
+impl Seed {
+    fn new(index: u32) -> Self {
+        let mut engine = sha256::Hash::engine();
+        engine.input(b"descriptor-codec/anonymize");
+        engine.input(&index.to_be_bytes());
+        Self(sha256::Hash::from_engine(engine))
+    }
+
+    fn derive(&self, label: &[u8]) -> [u8; 32] {
+        let mut engine = sha256::Hash::engine();
+        engine.input(self.0.as_byte_array());
+        engine.input(label);
+        sha256::Hash::from_engine(engine).to_byte_array()
+    }
+
+    fn fingerprint(&self, label: &[u8]) -> Fingerprint {
+        let bytes = self.derive(label);
+        Fingerprint::from([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
+
+    fn public_key(&self, secp: &Secp256k1<All>) -> secp256k1::PublicKey {
+        let sk = SecretKey::from_slice(&self.derive(b"key")).expect("hash is a valid secret key");
+        sk.public_key(secp)
+    }
+
+    fn origin(
+        &self,
+        origin: &Option<(Fingerprint, DerivationPath)>,
+    ) -> Option<(Fingerprint, DerivationPath)> {
+        origin
+            .as_ref()
+            .map(|(_, path)| (self.fingerprint(b"origin"), path.clone()))
+    }
+
+    fn xpub(
+        &self,
+        network: NetworkKind,
+        depth: u8,
+        child_number: ChildNumber,
+        secp: &Secp256k1<All>,
+    ) -> Xpub {
+        Xpub {
+            network,
+            depth,
+            parent_fingerprint: self.fingerprint(b"parent"),
+            child_number,
+            public_key: self.public_key(secp),
+            chain_code: ChainCode::from(self.derive(b"chain")),
+        }
+    }
+
+    fn replace(&self, pk: &DescriptorPublicKey, secp: &Secp256k1<All>) -> DescriptorPublicKey {
+        match pk {
+            DescriptorPublicKey::Single(single) => DescriptorPublicKey::Single(SinglePub {
+                origin: self.origin(&single.origin),
+                key: match single.key {
+                    SinglePubKey::FullKey(key) => SinglePubKey::FullKey(PublicKey {
+                        inner: self.public_key(secp),
+                        compressed: key.compressed,
+                    }),
+                    SinglePubKey::XOnly(_) => {
+                        SinglePubKey::XOnly(self.public_key(secp).x_only_public_key().0)
+                    }
+                },
+            }),
+            DescriptorPublicKey::XPub(xpub) => DescriptorPublicKey::XPub(DescriptorXKey {
+                origin: self.origin(&xpub.origin),
+                xkey: self.xpub(
+                    xpub.xkey.network,
+                    xpub.xkey.depth,
+                    xpub.xkey.child_number,
+                    secp,
+                ),
+                derivation_path: xpub.derivation_path.clone(),
+                wildcard: xpub.wildcard,
+            }),
+            DescriptorPublicKey::MultiXPub(xpub) => {
+                DescriptorPublicKey::MultiXPub(DescriptorMultiXKey {
+                    origin: self.origin(&xpub.origin),
+                    xkey: self.xpub(
+                        xpub.xkey.network,
+                        xpub.xkey.depth,
+                        xpub.xkey.child_number,
+                        secp,
+                    ),
+                    derivation_paths: xpub.derivation_paths.clone(),
+                    wildcard: xpub.wildcard,
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_anonymize() {
+        let xpub = "xpub6EigxozzGaNVWUwEFnbyX6oHPdpWTKgJgbfpRbAcdiGpGMrdpPinCoHBXehu35sqJHpgLDTxigAnFQG3opKjXQoSmGMrMNHz81ALZSBRCWw";
+        let xprv = "xprv9s21ZrQH143K31xYSDQpPDxsXRTUcvj2iNHm5NUtrGiGG5e2DtALGdso3pGz6ssrdK4PFmM8NSpSBHNqPqm55Qn3LqFtT2emdEXVYsCzC2U";
+        let desc_str = format!(
+            "wsh(or_d(multi(2,[2c49202a/45'/0'/0'/0]{xpub}/<0;1>/*,{xprv}/0/*,03a0434d9e47f3c86235477c7b1ae6ae5d3442d49b1943c2b752a68e2a47e247c7),and_v(v:pk([2c49202a/45'/0'/0'/0]{xpub}/<0;1>/*),older(144))))"
+        );
+        let encoded = encode(&desc_str).unwrap();
+
+        let (anonymized, mapping) = anonymize(&encoded).unwrap();
+        assert_eq!(mapping.len(), 3);
+        let decoded = decode(&anonymized).unwrap();
+        assert!(!decoded.contains(xpub));
+        assert!(!decoded.contains("xprv"));
+        assert!(!decoded.contains("2c49202a"));
+        assert!(decoded.contains("/45'/0'/0'/0]xpub"));
+        assert!(decoded.contains("older(144)"));
+
+        // Anonymization is deterministic
+        assert_eq!(
+            anonymize(&encoded).unwrap(),
+            (anonymized.clone(), mapping.clone())
+        );
+
+        let restored = deanonymize(&anonymized, &mapping).unwrap();
+        assert_eq!(encode(&restored).unwrap(), encoded);
+
+        // Secret keys are only displayed when revealed
+        assert!(!mapping.to_string().contains("xprv"));
+        assert!(!format!("{mapping:?}").contains("xprv"));
+        assert!(mapping.to_string().contains(secret::REDACTED));
+        assert!(RevealSecrets(&mapping).to_string().contains(xprv));
+        assert!(KeyMapping::from_str(&mapping.to_string()).is_err());
+
+        let mapping = KeyMapping::from_str(&RevealSecrets(&mapping).to_string()).unwrap();
+        assert_eq!(deanonymize(&anonymized, &mapping).unwrap(), restored);
+        assert_eq!(
+            deanonymize(&anonymized, &KeyMapping::default()),
+            Err(Error::UnmappedKey)
+        );
+        assert_eq!(
+            KeyMapping::from_str("not-a-key 02"),
+            Err(Error::InvalidMapping(0))
+        );
+    }
+
+    #[test]
+    fn test_anonymize_key_reuse() {
+        let xpub = "xpub6EigxozzGaNVWUwEFnbyX6oHPdpWTKgJgbfpRbAcdiGpGMrdpPinCoHBXehu35sqJHpgLDTxigAnFQG3opKjXQoSmGMrMNHz81ALZSBRCWw";
+        let xprv = "xprv9s21ZrQH143K31xYSDQpPDxsXRTUcvj2iNHm5NUtrGiGG5e2DtALGdso3pGz6ssrdK4PFmM8NSpSBHNqPqm55Qn3LqFtT2emdEXVYsCzC2U";
+        let desc_str = format!(
+            "wsh(or_d(pk({xpub}/0/*),and_v(v:pk({xpub}/1/*),or_d(pk({xprv}/0/*),pk({xprv}/1/*)))))"
+        );
+        let (anonymized, mapping) = anonymize(&encode(&desc_str).unwrap()).unwrap();
+        assert_eq!(mapping.len(), 4);
+
+        // Each distinct key keeps a single placeholder along both of its paths
+        let (descriptor, _) = decode_to_descriptor(&anonymized).unwrap();
+        let mut materials = Vec::new();
+        descriptor.for_each_key(|pk| {
+            materials.push(key_material(pk));
+            true
+        });
+        assert_eq!(materials[0], materials[1]);
+        assert_eq!(materials[2], materials[3]);
+        assert_ne!(materials[0], materials[2]);
+        assert!(!materials.contains(&xpub.to_string()));
+    }
+}
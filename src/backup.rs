@@ -0,0 +1,1031 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! # Backup Files
+//!
+//! A container for storing encoded descriptors on disk, conventionally with the `.descbak`
+//! extension. A file is the magic bytes `DESCBAK`, a version byte, metadata records as in an
+//! encoding, then each entry, and ends with the first 4 bytes of the SHA256 hash of everything
+//! before it.
+//!
+//! An entry is a variable-length ID, a length-prefixed UTF-8 label, a variable-length kind, and
+//! length-prefixed data. IDs and labels are never encrypted, so they serve as an index of the
+//! file. The data of a plain entry is an encoding. An encrypted entry holds a nonce followed by
+//! the encoding sealed with XChaCha20-Poly1305, so entries can be encrypted under different keys.
+//! The nonce is random, so encrypting the same encoding twice gives different data. A
+//! deterministically encrypted entry derives its nonce from the key and the encoding, so equal
+//! encodings give equal data on every device.
+//!
+//! Entries run until the checksum, so entries can be added or removed without decoding the others.
+//!
+//! The key encrypting entries may itself be stored in the metadata, wrapped by a [`BackupCipher`]
+//! such as a secure element or OS keystore, so restoring needs the device rather than the key.
+//!
+//! Encrypting and decrypting entries needs the `encryption` feature. Without it, encrypted entries
+//! are still read, written, listed, and removed, but not opened.
+
+use super::*;
+#[cfg(feature = "encryption")]
+use bitcoin::hashes::{HashEngine, Hmac, HmacEngine};
+#[cfg(feature = "encryption")]
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+#[cfg(feature = "encryption")]
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use kdf::KdfParams;
+use std::hash::{BuildHasher, RandomState};
+use std::io::Write;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::{error, fmt, fs, io};
+
+/// Bytes starting every backup file
+pub const MAGIC: [u8; 7] = *b"DESCBAK";
+
+/// Version of the backup file format written by this crate
+pub const VERSION: u8 = 1;
+
+/// Number of checksum bytes ending every backup file
+const CHECKSUM_LEN: usize = 4;
+
+/// Kind of an entry holding an encoding
+const PLAIN: u64 = 0;
+
+/// Kind of an entry holding an encoding encrypted under a random nonce
+const RANDOMIZED: u64 = 1;
+
+/// Kind of an entry holding an encoding encrypted independently of its ID and label
+const DETERMINISTIC: u64 = 2;
+
+/// Number of nonce bytes starting encrypted data
+#[cfg(feature = "encryption")]
+const NONCE_LEN: usize = 24;
+
+/// Number of authentication tag bytes ending encrypted data
+#[cfg(feature = "encryption")]
+const TAG_LEN: usize = 16;
+
+/// Contents of a backup file
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Backup {
+    /// Metadata records about the whole backup
+    pub metadata: Vec<Tlv>,
+    /// Entries, each with a distinct ID
+    pub entries: Vec<BackupEntry>,
+}
+
+impl Backup {
+    /// Returns the entry with the given ID
+    pub fn entry(&self, id: u32) -> Option<&BackupEntry> {
+        self.entries.iter().find(|entry| entry.id == id)
+    }
+
+    /// Returns the parameters for deriving entry keys from a passphrase, if stored
+    pub fn kdf_params(&self) -> Result<Option<KdfParams>, Error> {
+        self.metadata
+            .iter()
+            .find(|record| record.kind == metadata::KDF_PARAMS)
+            .map(KdfParams::from_tlv)
+            .transpose()
+    }
+
+    /// Returns the entry key stored in the backup, unwrapped by `cipher`, if stored
+    ///
+    /// Fails with [`BackupError::DecryptionFailed`] if the key was wrapped by another cipher.
+    pub fn unwrap_key(&self, cipher: &dyn BackupCipher) -> Result<Option<[u8; 32]>, Error> {
+        self.metadata
+            .iter()
+            .find(|record| record.kind == metadata::WRAPPED_KEY)
+            .map(|record| cipher.unwrap_key(&record.value))
+            .transpose()
+    }
+
+    /// Stores an entry key wrapped by `cipher`, replacing any stored
+    pub fn set_wrapped_key(
+        &mut self,
+        cipher: &dyn BackupCipher,
+        key: &[u8; 32],
+    ) -> Result<(), Error> {
+        let value = cipher.wrap_key(key)?;
+        self.metadata
+            .retain(|record| record.kind != metadata::WRAPPED_KEY);
+        self.metadata.push(Tlv {
+            kind: metadata::WRAPPED_KEY,
+            value,
+        });
+        Ok(())
+    }
+
+    /// Stores the parameters for deriving entry keys from a passphrase, replacing any stored
+    ///
+    /// Entries encrypted under keys derived with older parameters stay readable only if those
+    /// parameters are kept, so raise costs when writing a new backup.
+    pub fn set_kdf_params(&mut self, params: &KdfParams) {
+        self.metadata
+            .retain(|record| record.kind != metadata::KDF_PARAMS);
+        self.metadata.push(params.to_tlv());
+    }
+}
+
+/// An encoded descriptor in a backup file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupEntry {
+    /// ID, unique within the file
+    pub id: u32,
+    /// Name shown when listing the file, never encrypted
+    pub label: Option<String>,
+    data: Vec<u8>,
+    kind: u64,
+}
+
+impl BackupEntry {
+    /// Creates an entry holding an encoding in the clear
+    pub fn plain(id: u32, label: Option<String>, bytes: &[u8]) -> Self {
+        Self {
+            id,
+            label,
+            data: bytes.to_vec(),
+            kind: PLAIN,
+        }
+    }
+
+    /// Returns whether the entry is encrypted
+    pub fn is_encrypted(&self) -> bool {
+        self.kind != PLAIN
+    }
+
+    /// Returns the encoding of a plain entry
+    pub fn bytes(&self) -> Option<&[u8]> {
+        match self.kind {
+            PLAIN => Some(&self.data),
+            _ => None,
+        }
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        let label = self.label.as_deref().unwrap_or_default();
+        out.extend(varint::encode(self.id as u128));
+        out.extend(varint::encode(label.len() as u128));
+        out.extend_from_slice(label.as_bytes());
+        out.extend(varint::encode(self.kind as u128));
+        out.extend(varint::encode(self.data.len() as u128));
+        out.extend_from_slice(&self.data);
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl BackupEntry {
+    /// Creates an entry holding an encoding encrypted under a 32-byte key
+    ///
+    /// The encoding is sealed with XChaCha20-Poly1305 under a random nonce, authenticating the ID
+    /// and the label along with it, so none can be changed without the key, and encrypting the
+    /// same encoding twice gives unrelated data.
+    ///
+    /// Fails with [`BackupError::RandomnessUnavailable`] if the OS cannot provide a nonce.
+    pub fn encrypted(
+        id: u32,
+        label: Option<String>,
+        bytes: &[u8],
+        key: &[u8; 32],
+    ) -> Result<Self, Error> {
+        let nonce = random_nonce()?;
+        let aad = entry_aad(id, label.as_deref());
+        let data = seal(&entry_cipher(key), &nonce, bytes, &aad);
+        Ok(Self {
+            id,
+            label,
+            data,
+            kind: RANDOMIZED,
+        })
+    }
+
+    /// Creates an entry holding an encoding encrypted under a 32-byte key, with data that depends
+    /// only on the key and the encoding
+    ///
+    /// The nonce is derived from the key and the SHA256 hash of the encoding, the ID used by
+    /// [`bundle_manifest`], so encrypting the same encoding on two devices gives identical data
+    /// that can be deduplicated. Unlike [`BackupEntry::encrypted`], the ID and label are not
+    /// authenticated.
+    ///
+    /// Only use this when deduplication is needed: anyone who sees two entries under the same key,
+    /// in one file or across backups, learns whether they hold the same descriptor.
+    pub fn encrypted_deterministic(
+        id: u32,
+        label: Option<String>,
+        bytes: &[u8],
+        key: &[u8; 32],
+    ) -> Self {
+        let nonce = deterministic_nonce(key, bytes);
+        let data = seal(&entry_cipher(key), &nonce, bytes, &[]);
+        Self {
+            id,
+            label,
+            data,
+            kind: DETERMINISTIC,
+        }
+    }
+
+    /// Returns the encoding of an entry, decrypting it with `key` if it is encrypted
+    ///
+    /// Fails with [`BackupError::DecryptionFailed`] if the key is wrong or the entry was altered.
+    pub fn decrypt(&self, key: &[u8; 32]) -> Result<Vec<u8>, Error> {
+        let aad = match self.kind {
+            PLAIN => return Ok(self.data.clone()),
+            DETERMINISTIC => Vec::new(),
+            _ => entry_aad(self.id, self.label.as_deref()),
+        };
+        open(&entry_cipher(key), &self.data, &aad)
+    }
+}
+
+/// Wraps and unwraps the key encrypting backup entries
+///
+/// Implementations may delegate to a secure element, TPM, or OS keystore that never reveals its
+/// own key, returning [`BackupError::KeyUnavailable`] when it cannot be reached and
+/// [`BackupError::DecryptionFailed`] when a wrapped key is not its own.
+pub trait BackupCipher: fmt::Debug + Send + Sync {
+    /// Wraps an entry key so only this cipher can recover it
+    fn wrap_key(&self, key: &[u8; 32]) -> Result<Vec<u8>, Error>;
+
+    /// Recovers an entry key wrapped by [`BackupCipher::wrap_key`]
+    fn unwrap_key(&self, wrapped: &[u8]) -> Result<[u8; 32], Error>;
+}
+
+/// Wraps entry keys in software under a 32-byte key-encryption key
+///
+/// A wrapped key is a random nonce followed by the key sealed with XChaCha20-Poly1305, as in an
+/// encrypted entry.
+#[cfg(feature = "encryption")]
+#[derive(Clone)]
+pub struct SoftwareCipher([u8; 32]);
+
+#[cfg(feature = "encryption")]
+impl SoftwareCipher {
+    /// Creates a cipher from a key-encryption key
+    pub fn new(key: [u8; 32]) -> Self {
+        Self(key)
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl fmt::Debug for SoftwareCipher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("SoftwareCipher(..)")
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl BackupCipher for SoftwareCipher {
+    fn wrap_key(&self, key: &[u8; 32]) -> Result<Vec<u8>, Error> {
+        Ok(seal(
+            &entry_cipher(&self.0),
+            &random_nonce()?,
+            key,
+            WRAP_AAD,
+        ))
+    }
+
+    fn unwrap_key(&self, wrapped: &[u8]) -> Result<[u8; 32], Error> {
+        let key = open(&entry_cipher(&self.0), wrapped, WRAP_AAD)?;
+        key.try_into()
+            .map_err(|_| BackupError::DecryptionFailed.into())
+    }
+}
+
+/// Error reading, decrypting, or unlocking a backup
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackupError {
+    /// Backup has a wrong magic or checksum, a malformed entry, or a repeated ID
+    Invalid,
+    /// Backup version is not supported
+    UnsupportedVersion(u8),
+    /// Key is wrong or encrypted data was altered
+    DecryptionFailed,
+    /// Backup cipher cannot wrap or unwrap a key, such as a locked secure element
+    KeyUnavailable,
+    /// OS random number generator cannot be read
+    RandomnessUnavailable,
+}
+
+impl fmt::Display for BackupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Invalid => write!(f, "invalid backup magic or checksum"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "unsupported backup version (version: {version})")
+            }
+            Self::DecryptionFailed => write!(f, "wrong key or altered encrypted data"),
+            Self::KeyUnavailable => write!(f, "backup cipher key unavailable"),
+            Self::RandomnessUnavailable => write!(f, "OS random number generator unavailable"),
+        }
+    }
+}
+
+impl error::Error for BackupError {}
+
+/// Error reading or updating a backup file
+#[derive(Debug)]
+pub enum BackupFileError {
+    /// File cannot be read
+    Io(io::Error),
+    /// File is not a valid backup, or an added entry is not a valid encoding
+    Invalid(Error),
+    /// No entry has the given ID
+    NoSuchEntry(u32),
+}
+
+impl fmt::Display for BackupFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(_) => write!(f, "cannot read backup file"),
+            Self::Invalid(_) => write!(f, "invalid backup file"),
+            Self::NoSuchEntry(id) => write!(f, "no backup entry (id: {id})"),
+        }
+    }
+}
+
+impl error::Error for BackupFileError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::Invalid(err) => Some(err),
+            Self::NoSuchEntry(_) => None,
+        }
+    }
+}
+
+/// Serializes a backup
+pub fn encode_backup(backup: &Backup) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.push(VERSION);
+    metadata::encode_records(&backup.metadata, &mut bytes);
+    for entry in &backup.entries {
+        entry.encode(&mut bytes);
+    }
+    bytes.extend(backup_checksum(&bytes));
+    bytes
+}
+
+/// Parses a backup, validating its checksum but not decoding or decrypting its entries
+///
+/// Fails with [`BackupError::Invalid`] on a wrong magic or checksum, a malformed entry, or a
+/// repeated ID, or [`BackupError::UnsupportedVersion`] on a newer version.
+pub fn decode_backup(bytes: &[u8]) -> Result<Backup, Error> {
+    Ok(parse_backup(bytes)?.0)
+}
+
+/// Parses a backup, also returning the byte range of each entry
+fn parse_backup(bytes: &[u8]) -> Result<(Backup, Vec<Range<usize>>), Error> {
+    if !bytes.starts_with(&MAGIC) || bytes.len() < MAGIC.len() + 1 + CHECKSUM_LEN {
+        return Err(BackupError::Invalid.into());
+    }
+    let (body, checksum) = bytes.split_at(bytes.len() - CHECKSUM_LEN);
+    if backup_checksum(body) != checksum {
+        return Err(BackupError::Invalid.into());
+    }
+    let version = body[MAGIC.len()];
+    if version != VERSION {
+        return Err(BackupError::UnsupportedVersion(version).into());
+    }
+
+    let mut index = MAGIC.len() + 1;
+    let metadata = metadata::read_records(body, &mut index)?;
+    if let Some(record) = metadata.iter().find(|record| record.is_required()) {
+        return Err(Error::UnknownRequiredTlv(record.kind));
+    }
+
+    let mut entries: Vec<BackupEntry> = Vec::new();
+    let mut ranges = Vec::new();
+    while index < body.len() {
+        let start = index;
+        let id = read_varint(body, &mut index)?;
+        let label = read_slice(body, &mut index)?;
+        let kind = read_varint(body, &mut index)?;
+        let data = read_slice(body, &mut index)?;
+
+        let id = u32::try_from(id).map_err(|_| BackupError::Invalid)?;
+        let label = std::str::from_utf8(label).map_err(|_| BackupError::Invalid)?;
+        if !matches!(kind, PLAIN | RANDOMIZED | DETERMINISTIC) {
+            return Err(BackupError::Invalid.into());
+        }
+        if entries.iter().any(|entry| entry.id == id) {
+            return Err(BackupError::Invalid.into());
+        }
+
+        entries.push(BackupEntry {
+            id,
+            label: (!label.is_empty()).then(|| label.to_string()),
+            data: data.to_vec(),
+            kind,
+        });
+        ranges.push(start..index);
+    }
+
+    Ok((Backup { metadata, entries }, ranges))
+}
+
+/// Writes a backup file atomically, as with [`write_file_atomic`]
+pub fn write_backup_file(path: impl AsRef<Path>, backup: &Backup) -> io::Result<()> {
+    write_file_atomic(path, &encode_backup(backup))
+}
+
+/// Writes a file atomically
+///
+/// The bytes are written to a temporary file in the same directory, flushed to disk, and renamed
+/// over `path`, so a crash leaves either the old file or the complete new one. The temporary file
+/// has a unique name, so concurrent writers never share it, and takes the permissions of the file
+/// it replaces.
+pub fn write_file_atomic(path: impl AsRef<Path>, bytes: &[u8]) -> io::Result<()> {
+    let path = path.as_ref();
+    let (temp, mut file) = create_temp_file(path)?;
+
+    let result = (|| {
+        if let Ok(metadata) = fs::metadata(path) {
+            file.set_permissions(metadata.permissions())?;
+        }
+        file.write_all(bytes)?;
+        file.sync_all()?;
+        drop(file);
+        fs::rename(&temp, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result?;
+
+    // Persist the rename itself
+    #[cfg(unix)]
+    if let Some(dir) = path.parent() {
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+        fs::File::open(dir)?.sync_all()?;
+    }
+
+    Ok(())
+}
+
+/// Creates a new file named after `path` with a random suffix, in the same directory
+fn create_temp_file(path: &Path) -> io::Result<(PathBuf, fs::File)> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+    let random = RandomState::new();
+    let mut attempt = 0u32;
+    loop {
+        let mut temp_name = std::ffi::OsString::from(".");
+        temp_name.push(name);
+        temp_name.push(format!(".{:016x}.tmp", random.hash_one(attempt)));
+        let temp = path.with_file_name(temp_name);
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp)
+        {
+            Ok(file) => return Ok((temp, file)),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists && attempt < 16 => attempt += 1,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Reads a backup file
+pub fn read_backup_file(path: impl AsRef<Path>) -> Result<Backup, BackupFileError> {
+    let bytes = fs::read(path).map_err(BackupFileError::Io)?;
+    decode_backup(&bytes).map_err(BackupFileError::Invalid)
+}
+
+/// Adds an encoded descriptor to the end of a backup file as a plain entry, returning its ID
+///
+/// The entry is given the next ID after the largest in the file. It replaces the old checksum,
+/// so existing bytes are kept and no entry is decoded, and the file is rewritten atomically as
+/// with [`write_file_atomic`]. Fails with [`BackupFileError::Invalid`] if
+/// the file is not a valid backup or `bytes` is not a valid encoding.
+pub fn append_descriptor(
+    path: impl AsRef<Path>,
+    bytes: &[u8],
+    label: Option<String>,
+) -> Result<u32, BackupFileError> {
+    decoder::decode_bytes_underived(bytes, decoder::DecodeContext::default())
+        .map_err(BackupFileError::Invalid)?;
+
+    let path = path.as_ref();
+    let mut contents = fs::read(path).map_err(BackupFileError::Io)?;
+    let (backup, _) = parse_backup(&contents).map_err(BackupFileError::Invalid)?;
+    let id = match backup.entries.iter().map(|entry| entry.id).max() {
+        Some(max) => max
+            .checked_add(1)
+            .ok_or(BackupFileError::Invalid(BackupError::Invalid.into()))?,
+        None => 0,
+    };
+
+    let end = contents.len() - CHECKSUM_LEN;
+    contents.truncate(end);
+    BackupEntry::plain(id, label, bytes).encode(&mut contents);
+    contents.extend(backup_checksum(&contents));
+    write_file_atomic(path, &contents).map_err(BackupFileError::Io)?;
+    Ok(id)
+}
+
+/// Removes the entry with the given ID from a backup file
+///
+/// No entry is decoded, and the file is rewritten atomically as with [`write_file_atomic`].
+pub fn remove_descriptor(path: impl AsRef<Path>, id: u32) -> Result<(), BackupFileError> {
+    let path = path.as_ref();
+    let mut contents = fs::read(path).map_err(BackupFileError::Io)?;
+    let (backup, ranges) = parse_backup(&contents).map_err(BackupFileError::Invalid)?;
+    let position = backup
+        .entries
+        .iter()
+        .position(|entry| entry.id == id)
+        .ok_or(BackupFileError::NoSuchEntry(id))?;
+    let range = ranges[position].clone();
+
+    contents.drain(range);
+    contents.truncate(contents.len() - CHECKSUM_LEN);
+    contents.extend(backup_checksum(&contents));
+    write_file_atomic(path, &contents).map_err(BackupFileError::Io)
+}
+
+fn read_varint(bytes: &[u8], index: &mut usize) -> Result<u64, Error> {
+    let start = *index;
+    let (value, size) =
+        varint::decode_as::<u64>(&bytes[start..]).map_err(|e| Error::InvalidVarInt(start, e))?;
+    *index += size;
+    Ok(value)
+}
+
+fn read_slice<'a>(bytes: &'a [u8], index: &mut usize) -> Result<&'a [u8], Error> {
+    let len = read_varint(bytes, index)?;
+    let start = *index;
+    let slice = usize::try_from(len)
+        .ok()
+        .and_then(|len| bytes.get(start..start.checked_add(len)?))
+        .ok_or(Error::MissingBytes)?;
+    *index += slice.len();
+    Ok(slice)
+}
+
+/// Returns the leading bytes of the SHA256 hash of a backup's contents
+fn backup_checksum(bytes: &[u8]) -> [u8; CHECKSUM_LEN] {
+    let hash = sha256::Hash::hash(bytes).to_byte_array();
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+/// Associated data of a key wrapped by a [`SoftwareCipher`]
+#[cfg(feature = "encryption")]
+const WRAP_AAD: &[u8] = b"descbak wrap";
+
+#[cfg(feature = "encryption")]
+fn hmac(key: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+    let mut engine = HmacEngine::<sha256::Hash>::new(key);
+    for part in parts {
+        engine.input(part);
+    }
+    Hmac::from_engine(engine).to_byte_array()
+}
+
+/// Returns the cipher sealing entries under an entry key
+#[cfg(feature = "encryption")]
+fn entry_cipher(key: &[u8; 32]) -> XChaCha20Poly1305 {
+    XChaCha20Poly1305::new(&hmac(key, &[b"descbak encryption"]).into())
+}
+
+/// Returns the associated data of a randomized entry, its ID and label
+#[cfg(feature = "encryption")]
+fn entry_aad(id: u32, label: Option<&str>) -> Vec<u8> {
+    let label = label.unwrap_or_default().as_bytes();
+    [
+        &id.to_be_bytes()[..],
+        &(label.len() as u64).to_be_bytes(),
+        label,
+    ]
+    .concat()
+}
+
+/// Returns the nonce of a deterministically encrypted entry, from the hash of its encoding
+#[cfg(feature = "encryption")]
+fn deterministic_nonce(key: &[u8; 32], bytes: &[u8]) -> [u8; NONCE_LEN] {
+    let nonce_key = hmac(key, &[b"descbak deterministic"]);
+    let hash = hmac(&nonce_key, &[sha256::Hash::hash(bytes).as_byte_array()]);
+    hash[..NONCE_LEN].try_into().expect("nonce fits in a hash")
+}
+
+/// Returns a nonce from the OS random number generator
+///
+/// Fails with [`BackupError::RandomnessUnavailable`] rather than falling back to a weaker source.
+#[cfg(feature = "encryption")]
+fn random_nonce() -> Result<[u8; NONCE_LEN], Error> {
+    let mut nonce = [0; NONCE_LEN];
+    getrandom::getrandom(&mut nonce).map_err(|_| BackupError::RandomnessUnavailable)?;
+    Ok(nonce)
+}
+
+/// Returns the nonce followed by the plaintext sealed under it
+#[cfg(feature = "encryption")]
+fn seal(cipher: &XChaCha20Poly1305, nonce: &[u8; NONCE_LEN], msg: &[u8], aad: &[u8]) -> Vec<u8> {
+    let sealed = cipher
+        .encrypt(XNonce::from_slice(nonce), Payload { msg, aad })
+        .expect("plaintext within the cipher's length limit");
+    [&nonce[..], &sealed].concat()
+}
+
+/// Opens data sealed by [`seal`]
+#[cfg(feature = "encryption")]
+fn open(cipher: &XChaCha20Poly1305, data: &[u8], aad: &[u8]) -> Result<Vec<u8>, Error> {
+    if data.len() < NONCE_LEN + TAG_LEN {
+        return Err(BackupError::DecryptionFailed.into());
+    }
+    let (nonce, msg) = data.split_at(NONCE_LEN);
+    cipher
+        .decrypt(XNonce::from_slice(nonce), Payload { msg, aad })
+        .map_err(|_| BackupError::DecryptionFailed.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DESCRIPTORS: [&str; 2] = [
+        "wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)#8zl0zxma",
+        "tr(c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5)#k6ze7ncu",
+    ];
+
+    #[test]
+    fn test_backup() {
+        let backup = Backup {
+            metadata: vec![Tlv {
+                kind: 2,
+                value: b"wallet".to_vec(),
+            }],
+            entries: DESCRIPTORS
+                .iter()
+                .enumerate()
+                .map(|(id, d)| BackupEntry::plain(id as u32, None, &encode(d).unwrap()))
+                .collect(),
+        };
+
+        let bytes = encode_backup(&backup);
+        assert!(bytes.starts_with(b"DESCBAK\x01"));
+        assert_eq!(decode_backup(&bytes).unwrap(), backup);
+        assert_eq!(
+            backup.entry(1).unwrap().bytes().unwrap(),
+            encode(DESCRIPTORS[1]).unwrap()
+        );
+
+        let empty = encode_backup(&Backup::default());
+        assert_eq!(empty.len(), MAGIC.len() + 2 + CHECKSUM_LEN);
+        assert_eq!(decode_backup(&empty).unwrap(), Backup::default());
+
+        let path = std::env::temp_dir().join("descriptor-codec-test_backup.descbak");
+        write_backup_file(&path, &Backup::default()).unwrap();
+        write_backup_file(&path, &backup).unwrap();
+        assert_eq!(read_backup_file(&path).unwrap(), backup);
+        assert!(
+            !path
+                .with_file_name(".descriptor-codec-test_backup.descbak.tmp")
+                .exists()
+        );
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            read_backup_file(&path),
+            Err(BackupFileError::Io(_))
+        ));
+
+        // Any change is caught by the checksum
+        let mut corrupted = bytes.clone();
+        corrupted[10] ^= 1;
+        assert_eq!(
+            decode_backup(&corrupted),
+            Err(Error::Backup(BackupError::Invalid))
+        );
+        assert_eq!(
+            decode_backup(&bytes[1..]),
+            Err(Error::Backup(BackupError::Invalid))
+        );
+        assert_eq!(
+            decode_backup(&bytes[..bytes.len() - 1]),
+            Err(Error::Backup(BackupError::Invalid))
+        );
+
+        // Newer versions are rejected
+        let mut newer = bytes[..bytes.len() - CHECKSUM_LEN].to_vec();
+        newer[MAGIC.len()] = VERSION + 1;
+        newer.extend(backup_checksum(&newer));
+        assert_eq!(
+            decode_backup(&newer),
+            Err(Error::Backup(BackupError::UnsupportedVersion(VERSION + 1)))
+        );
+
+        // IDs must be distinct
+        let mut repeated = backup.clone();
+        repeated.entries[1].id = 0;
+        assert_eq!(
+            decode_backup(&encode_backup(&repeated)),
+            Err(Error::Backup(BackupError::Invalid))
+        );
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_encrypted_entries() {
+        let hot_key = [1; 32];
+        let cold_key = [2; 32];
+        let hot = encode(DESCRIPTORS[0]).unwrap();
+        let cold = encode(DESCRIPTORS[1]).unwrap();
+        let backup = Backup {
+            metadata: Vec::new(),
+            entries: vec![
+                BackupEntry::encrypted(0, Some("hot wallet".to_string()), &hot, &hot_key).unwrap(),
+                BackupEntry::encrypted(1, Some("cold vault".to_string()), &cold, &cold_key)
+                    .unwrap(),
+            ],
+        };
+        let bytes = encode_backup(&backup);
+        let decoded = decode_backup(&bytes).unwrap();
+        assert_eq!(decoded, backup);
+
+        // Labels are readable without keys, encodings are not
+        assert_eq!(decoded.entries[1].label.as_deref(), Some("cold vault"));
+        assert!(decoded.entries[1].is_encrypted());
+        assert_eq!(decoded.entries[1].bytes(), None);
+        assert!(!bytes.windows(hot.len()).any(|window| window == hot));
+
+        // Each entry opens only with its own key
+        assert_eq!(decoded.entries[0].decrypt(&hot_key).unwrap(), hot);
+        assert_eq!(decoded.entries[1].decrypt(&cold_key).unwrap(), cold);
+        assert_eq!(
+            decoded.entries[1].decrypt(&hot_key),
+            Err(Error::Backup(BackupError::DecryptionFailed))
+        );
+
+        // The label is authenticated
+        let mut relabeled = decoded.entries[1].clone();
+        relabeled.label = Some("hot wallet".to_string());
+        assert_eq!(
+            relabeled.decrypt(&cold_key),
+            Err(Error::Backup(BackupError::DecryptionFailed))
+        );
+
+        // Plain entries need no key
+        let plain = BackupEntry::plain(2, None, &hot);
+        assert_eq!(plain.decrypt(&cold_key).unwrap(), hot);
+
+        // Encrypting again gives unrelated data, and the nonce is authenticated
+        let again =
+            BackupEntry::encrypted(0, Some("hot wallet".to_string()), &hot, &hot_key).unwrap();
+        assert_ne!(again.data, decoded.entries[0].data);
+        assert_ne!(
+            again.data[NONCE_LEN..],
+            decoded.entries[0].data[NONCE_LEN..]
+        );
+        assert_eq!(again.decrypt(&hot_key).unwrap(), hot);
+        let mut altered = again.clone();
+        altered.data[0] ^= 1;
+        assert_eq!(
+            altered.decrypt(&hot_key),
+            Err(Error::Backup(BackupError::DecryptionFailed))
+        );
+        altered.data.truncate(NONCE_LEN + TAG_LEN - 1);
+        assert_eq!(
+            altered.decrypt(&hot_key),
+            Err(Error::Backup(BackupError::DecryptionFailed))
+        );
+    }
+
+    /// Refuses to wrap or unwrap, as a locked secure element would
+    #[cfg(feature = "encryption")]
+    #[derive(Debug)]
+    struct LockedCipher;
+
+    #[cfg(feature = "encryption")]
+    impl BackupCipher for LockedCipher {
+        fn wrap_key(&self, _: &[u8; 32]) -> Result<Vec<u8>, Error> {
+            Err(Error::Backup(BackupError::KeyUnavailable))
+        }
+
+        fn unwrap_key(&self, _: &[u8]) -> Result<[u8; 32], Error> {
+            Err(Error::Backup(BackupError::KeyUnavailable))
+        }
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_backup_cipher() {
+        let cipher = SoftwareCipher::new([5; 32]);
+        let key = [6; 32];
+        let bytes = encode(DESCRIPTORS[0]).unwrap();
+        let mut backup = Backup {
+            metadata: Vec::new(),
+            entries: vec![BackupEntry::encrypted(0, None, &bytes, &key).unwrap()],
+        };
+        assert_eq!(backup.unwrap_key(&cipher), Ok(None));
+        backup.set_wrapped_key(&cipher, &[7; 32]).unwrap();
+        backup.set_wrapped_key(&cipher, &key).unwrap();
+        assert_eq!(backup.metadata.len(), 1);
+        assert!(
+            !backup.metadata[0]
+                .value
+                .windows(32)
+                .any(|window| window == key)
+        );
+
+        let decoded = decode_backup(&encode_backup(&backup)).unwrap();
+        let unwrapped = decoded.unwrap_key(&cipher).unwrap().unwrap();
+        assert_eq!(decoded.entries[0].decrypt(&unwrapped).unwrap(), bytes);
+
+        assert_eq!(
+            decoded.unwrap_key(&SoftwareCipher::new([8; 32])),
+            Err(Error::Backup(BackupError::DecryptionFailed))
+        );
+        assert_eq!(
+            decoded.unwrap_key(&LockedCipher),
+            Err(Error::Backup(BackupError::KeyUnavailable))
+        );
+        assert_eq!(
+            backup.set_wrapped_key(&LockedCipher, &key),
+            Err(Error::Backup(BackupError::KeyUnavailable))
+        );
+        assert_eq!(
+            cipher.unwrap_key(&[0; 10]),
+            Err(Error::Backup(BackupError::DecryptionFailed))
+        );
+        assert_eq!(format!("{cipher:?}"), "SoftwareCipher(..)");
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_passphrase_key() {
+        let params = KdfParams {
+            kdf: kdf::Kdf::Scrypt {
+                log_n: 4,
+                r: 8,
+                p: 1,
+            },
+            salt: vec![9; 16],
+        };
+        let bytes = encode(DESCRIPTORS[0]).unwrap();
+        let key = params.derive_key(b"passphrase").unwrap();
+        let mut backup = Backup {
+            metadata: Vec::new(),
+            entries: vec![BackupEntry::encrypted(0, None, &bytes, &key).unwrap()],
+        };
+        assert_eq!(backup.kdf_params(), Ok(None));
+        backup.set_kdf_params(&KdfParams {
+            kdf: kdf::Kdf::default(),
+            salt: vec![9; 16],
+        });
+        backup.set_kdf_params(&params);
+        assert_eq!(backup.metadata.len(), 1);
+
+        // A reader recovers the key from the passphrase and the stored parameters
+        let decoded = decode_backup(&encode_backup(&backup)).unwrap();
+        let stored = decoded.kdf_params().unwrap().unwrap();
+        assert_eq!(stored, params);
+        let key = stored.derive_key(b"passphrase").unwrap();
+        assert_eq!(decoded.entries[0].decrypt(&key).unwrap(), bytes);
+        let key = stored.derive_key(b"wrong").unwrap();
+        assert_eq!(
+            decoded.entries[0].decrypt(&key),
+            Err(Error::Backup(BackupError::DecryptionFailed))
+        );
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_deterministic_encryption() {
+        let key = [1; 32];
+        let bytes = encode(DESCRIPTORS[0]).unwrap();
+        let a = BackupEntry::encrypted_deterministic(0, Some("laptop".to_string()), &bytes, &key);
+        let b = BackupEntry::encrypted_deterministic(7, None, &bytes, &key);
+
+        // The same encoding and key give the same data whatever the ID and label
+        assert_eq!(a.data, b.data);
+        assert!(a.is_encrypted());
+        assert_eq!(a.bytes(), None);
+        assert_ne!(
+            a.data,
+            BackupEntry::encrypted_deterministic(0, None, &bytes, &[2; 32]).data
+        );
+
+        // Only the opt-in constructor repeats itself
+        let randomized = BackupEntry::encrypted(0, None, &bytes, &key).unwrap();
+        assert_ne!(a.data, randomized.data);
+        assert_ne!(
+            randomized.data,
+            BackupEntry::encrypted(0, None, &bytes, &key).unwrap().data
+        );
+
+        let backup = Backup {
+            metadata: Vec::new(),
+            entries: vec![a, b],
+        };
+        let decoded = decode_backup(&encode_backup(&backup)).unwrap();
+        assert_eq!(decoded, backup);
+        assert_eq!(decoded.entries[1].decrypt(&key).unwrap(), bytes);
+        assert_eq!(
+            decoded.entries[1].decrypt(&[2; 32]),
+            Err(Error::Backup(BackupError::DecryptionFailed))
+        );
+
+        let mut altered = decoded.entries[0].clone();
+        altered.data[NONCE_LEN] ^= 1;
+        assert_eq!(
+            altered.decrypt(&key),
+            Err(Error::Backup(BackupError::DecryptionFailed))
+        );
+    }
+
+    #[test]
+    fn test_append_remove() {
+        let entries: Vec<_> = DESCRIPTORS.iter().map(|d| encode(d).unwrap()).collect();
+        let path = std::env::temp_dir().join("descriptor-codec-test_append_remove.descbak");
+        let mut backup = Backup {
+            metadata: vec![Tlv {
+                kind: 2,
+                value: b"wallet".to_vec(),
+            }],
+            // An encrypted entry, which is moved around without its key
+            entries: vec![BackupEntry {
+                id: 3,
+                label: None,
+                data: vec![0xAA; 48],
+                kind: RANDOMIZED,
+            }],
+        };
+        write_backup_file(&path, &backup).unwrap();
+
+        let label = Some("spending".to_string());
+        assert_eq!(
+            append_descriptor(&path, &entries[1], label.clone()).unwrap(),
+            4
+        );
+        backup
+            .entries
+            .push(BackupEntry::plain(4, label, &entries[1]));
+        assert_eq!(fs::read(&path).unwrap(), encode_backup(&backup));
+
+        remove_descriptor(&path, 3).unwrap();
+        backup.entries.remove(0);
+        assert_eq!(fs::read(&path).unwrap(), encode_backup(&backup));
+
+        // Both go through a temporary file, which is gone once renamed into place
+        let temp_prefix = ".descriptor-codec-test_append_remove.descbak.";
+        assert!(!fs::read_dir(std::env::temp_dir()).unwrap().any(|entry| {
+            let name = entry.unwrap().file_name();
+            name.to_string_lossy().starts_with(temp_prefix)
+        }));
+
+        assert!(matches!(
+            remove_descriptor(&path, 3),
+            Err(BackupFileError::NoSuchEntry(3))
+        ));
+        assert!(matches!(
+            append_descriptor(&path, &[0xFF], None),
+            Err(BackupFileError::Invalid(_))
+        ));
+        assert_eq!(read_backup_file(&path).unwrap(), backup);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_write_file_atomic_concurrent() {
+        let path = std::env::temp_dir().join("descriptor-codec-test_write_file_atomic_concurrent");
+        let writers: Vec<_> = (0..8u8)
+            .map(|i| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..20 {
+                        write_file_atomic(&path, &[i; 4096]).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        // Writers never share a temporary file, so the result is one writer's bytes in full
+        let bytes = fs::read(&path).unwrap();
+        assert_eq!(bytes.len(), 4096);
+        assert!(bytes.iter().all(|&byte| byte == bytes[0]));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_file_atomic_keeps_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // Recovery sheets and backups are often restricted to their owner
+        let path = std::env::temp_dir().join("descriptor-codec-test_write_file_atomic_keeps_mode");
+        write_file_atomic(&path, b"first").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+
+        write_file_atomic(&path, b"second").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"second");
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,714 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! # Bundles
+//!
+//! Several encoded descriptors stored together, such as every account of a wallet. A bundle is
+//! a variable-length count of entries, each a variable-length length followed by an encoding.
+//!
+//! The entries may be followed by key annotations, such as a co-signer's name and how to reach
+//! them. Annotations sit outside the entries, so they never change an encoding or its template
+//! hash. They are a variable-length count, then for each annotation the entry index and key
+//! index as variable-length integers and the label, contact, and device as length-prefixed
+//! UTF-8 strings, empty when absent.
+//!
+//! A bundle may instead start with a manifest, so a reader can list its entries and pull out one
+//! of them without parsing the rest. Such a bundle starts with the bytes `0x00 0x01`, which no
+//! bundle without a manifest can, since it would hold an annotation on a missing entry. Then comes
+//! a variable-length count and, for each entry, the SHA256 hash of its encoding as its ID, its
+//! size as a variable-length integer, and its label as a length-prefixed UTF-8 string, empty when
+//! absent. The encodings follow back to back, then any annotations as above.
+
+use super::*;
+
+/// Bytes starting a bundle with a manifest
+const MANIFEST_PREFIX: [u8; 2] = [0x00, 0x01];
+
+/// Size of an entry ID in a manifest
+const ID_LEN: usize = 32;
+
+/// Recovery information about a key in a bundle
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct KeyAnnotation {
+    /// Index of the entry holding the key
+    pub entry: u32,
+    /// Index of the key, in order of appearance in the entry's descriptor
+    pub key_index: u32,
+    /// Name of the key or its holder
+    pub label: Option<String>,
+    /// How to reach the key's holder
+    pub contact: Option<String>,
+    /// Model of the signing device holding the key
+    pub device: Option<String>,
+}
+
+/// An entry listed in the manifest of a bundle
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    /// SHA256 hash of the entry's encoding
+    pub id: sha256::Hash,
+    /// Size of the entry's encoding in bytes
+    pub size: usize,
+    /// Name of the entry, such as the wallet it belongs to
+    pub label: Option<String>,
+}
+
+/// Concatenates encoded descriptors into a bundle
+pub fn encode_bundle<T: AsRef<[u8]>>(entries: &[T]) -> Vec<u8> {
+    encode_annotated_bundle(entries, &[])
+}
+
+/// Concatenates encoded descriptors into a bundle with key annotations
+pub fn encode_annotated_bundle<T: AsRef<[u8]>>(
+    entries: &[T],
+    annotations: &[KeyAnnotation],
+) -> Vec<u8> {
+    let mut bundle = varint::encode(entries.len() as u128);
+    for entry in entries {
+        let entry = entry.as_ref();
+        bundle.extend(varint::encode(entry.len() as u128));
+        bundle.extend_from_slice(entry);
+    }
+    encode_annotations(annotations, &mut bundle);
+    bundle
+}
+
+/// Concatenates labeled encoded descriptors into a bundle with a manifest and key annotations
+pub fn encode_manifest_bundle<T: AsRef<[u8]>>(
+    entries: &[(T, Option<&str>)],
+    annotations: &[KeyAnnotation],
+) -> Vec<u8> {
+    let mut bundle = MANIFEST_PREFIX.to_vec();
+    bundle.extend(varint::encode(entries.len() as u128));
+    for (entry, label) in entries {
+        let entry = entry.as_ref();
+        let label = label.unwrap_or_default();
+        bundle.extend_from_slice(sha256::Hash::hash(entry).as_byte_array());
+        bundle.extend(varint::encode(entry.len() as u128));
+        bundle.extend(varint::encode(label.len() as u128));
+        bundle.extend_from_slice(label.as_bytes());
+    }
+    for (entry, _) in entries {
+        bundle.extend_from_slice(entry.as_ref());
+    }
+    encode_annotations(annotations, &mut bundle);
+    bundle
+}
+
+fn encode_annotations(annotations: &[KeyAnnotation], bundle: &mut Vec<u8>) {
+    if !annotations.is_empty() {
+        bundle.extend(varint::encode(annotations.len() as u128));
+        for annotation in annotations {
+            bundle.extend(varint::encode(annotation.entry as u128));
+            bundle.extend(varint::encode(annotation.key_index as u128));
+            for field in [&annotation.label, &annotation.contact, &annotation.device] {
+                let field = field.as_deref().unwrap_or_default();
+                bundle.extend(varint::encode(field.len() as u128));
+                bundle.extend_from_slice(field.as_bytes());
+            }
+        }
+    }
+}
+
+/// Splits a bundle into its encoded descriptors without decoding them
+pub fn split_bundle(bytes: &[u8]) -> Result<Vec<&[u8]>, Error> {
+    Ok(parse_bundle(bytes)?.0)
+}
+
+/// Returns the key annotations of a bundle
+pub fn bundle_annotations(bytes: &[u8]) -> Result<Vec<KeyAnnotation>, Error> {
+    Ok(parse_bundle(bytes)?.1)
+}
+
+/// Returns the manifest of a bundle, without reading its entries
+///
+/// A bundle without a manifest is split to compute one, with no labels.
+pub fn bundle_manifest(bytes: &[u8]) -> Result<Vec<ManifestEntry>, Error> {
+    if !bytes.starts_with(&MANIFEST_PREFIX) {
+        return Ok(split_bundle(bytes)?
+            .into_iter()
+            .map(|entry| ManifestEntry {
+                id: sha256::Hash::hash(entry),
+                size: entry.len(),
+                label: None,
+            })
+            .collect());
+    }
+    let mut index = MANIFEST_PREFIX.len();
+    read_manifest(bytes, &mut index)
+}
+
+/// Returns the encoding with the given ID from a bundle with a manifest, without reading the
+/// other entries
+///
+/// Fails with [`Error::InvalidManifest`] if the entry does not match its ID.
+pub fn bundle_entry<'a>(bytes: &'a [u8], id: &sha256::Hash) -> Result<Option<&'a [u8]>, Error> {
+    if !bytes.starts_with(&MANIFEST_PREFIX) {
+        return Ok(split_bundle(bytes)?
+            .into_iter()
+            .find(|entry| sha256::Hash::hash(entry) == *id));
+    }
+
+    let mut index = MANIFEST_PREFIX.len();
+    let manifest = read_manifest(bytes, &mut index)?;
+    let Some(position) = manifest.iter().position(|entry| entry.id == *id) else {
+        return Ok(None);
+    };
+    let start = index + manifest[..position].iter().map(|e| e.size).sum::<usize>();
+    let entry = bytes
+        .get(start..start + manifest[position].size)
+        .ok_or(Error::MissingBytes)?;
+    if sha256::Hash::hash(entry) != *id {
+        return Err(Error::InvalidManifest(start));
+    }
+    Ok(Some(entry))
+}
+
+/// Decodes the descriptor with the given ID from a bundle or backup file, if present
+///
+/// The ID is the SHA256 hash of the entry's encoding, as listed by [`bundle_manifest`]. In a
+/// bundle with a manifest, the entry is found from the manifest offsets and no other entry is
+/// read. A backup file is checked whole against its checksum, and its encrypted entries are
+/// skipped.
+pub fn decode_entry(bytes: &[u8], id: &sha256::Hash) -> Result<Option<String>, Error> {
+    if bytes.starts_with(&backup::MAGIC) {
+        let backup = decode_backup(bytes)?;
+        return backup
+            .entries
+            .iter()
+            .filter_map(BackupEntry::bytes)
+            .find(|entry| sha256::Hash::hash(entry) == *id)
+            .map(decode)
+            .transpose();
+    }
+    bundle_entry(bytes, id)?.map(decode).transpose()
+}
+
+/// Reads the manifest of a bundle, leaving `index` at the first entry
+fn read_manifest(bytes: &[u8], index: &mut usize) -> Result<Vec<ManifestEntry>, Error> {
+    let count = read_len(bytes, index)?;
+
+    // Every record takes more than an ID, so a larger count is invalid
+    if count > (bytes.len() - *index) / ID_LEN {
+        return Err(Error::MissingBytes);
+    }
+
+    let mut manifest = Vec::with_capacity(count);
+    let mut total: usize = 0;
+    for _ in 0..count {
+        let start = *index;
+        let id = bytes
+            .get(*index..*index + ID_LEN)
+            .ok_or(Error::MissingBytes)?;
+        let id = sha256::Hash::from_slice(id).expect("32 bytes");
+        *index += ID_LEN;
+        let size = read_len(bytes, index)?;
+        let len = read_len(bytes, index)?;
+        let label = bytes
+            .get(*index..index.saturating_add(len))
+            .ok_or(Error::MissingBytes)?;
+        *index += len;
+        let label = std::str::from_utf8(label).map_err(|_| Error::InvalidManifest(start))?;
+
+        total = total
+            .checked_add(size)
+            .filter(|total| *total <= bytes.len())
+            .ok_or(Error::MissingBytes)?;
+        manifest.push(ManifestEntry {
+            id,
+            size,
+            label: (!label.is_empty()).then(|| label.to_string()),
+        });
+    }
+
+    if total > bytes.len() - *index {
+        return Err(Error::MissingBytes);
+    }
+    Ok(manifest)
+}
+
+/// Splits a bundle into its encoded descriptors and key annotations
+fn parse_bundle(bytes: &[u8]) -> Result<(Vec<&[u8]>, Vec<KeyAnnotation>), Error> {
+    let mut index = 0;
+    let mut entries;
+    if bytes.starts_with(&MANIFEST_PREFIX) {
+        index = MANIFEST_PREFIX.len();
+        let manifest = read_manifest(bytes, &mut index)?;
+        entries = Vec::with_capacity(manifest.len());
+        for entry in manifest {
+            let start = index;
+            index += entry.size;
+            if sha256::Hash::hash(&bytes[start..index]) != entry.id {
+                return Err(Error::InvalidManifest(start));
+            }
+            entries.push(&bytes[start..index]);
+        }
+    } else {
+        let count = read_len(bytes, &mut index)?;
+
+        // Every entry takes at least one byte, so a larger count is invalid
+        if count > bytes.len() - index {
+            return Err(Error::MissingBytes);
+        }
+
+        entries = Vec::with_capacity(count);
+        for _ in 0..count {
+            let len = read_len(bytes, &mut index)?;
+            let entry = bytes
+                .get(index..index.saturating_add(len))
+                .ok_or(Error::MissingBytes)?;
+            index += len;
+            entries.push(entry);
+        }
+    }
+
+    let mut annotations = Vec::new();
+    if index < bytes.len() {
+        let count = read_len(bytes, &mut index)?;
+        for _ in 0..count {
+            let start = index;
+            let entry = read_len(bytes, &mut index)?;
+            let key_index = read_len(bytes, &mut index)?;
+            let mut fields = [None, None, None];
+            for field in &mut fields {
+                let len = read_len(bytes, &mut index)?;
+                let value = bytes
+                    .get(index..index.saturating_add(len))
+                    .ok_or(Error::MissingBytes)?;
+                index += len;
+                let value =
+                    std::str::from_utf8(value).map_err(|_| Error::InvalidAnnotation(start))?;
+                if !value.is_empty() {
+                    *field = Some(value.to_string());
+                }
+            }
+
+            if entry >= entries.len() {
+                return Err(Error::InvalidAnnotation(start));
+            }
+            let [label, contact, device] = fields;
+            annotations.push(KeyAnnotation {
+                entry: entry as u32,
+                key_index: key_index
+                    .try_into()
+                    .map_err(|_| Error::InvalidAnnotation(start))?,
+                label,
+                contact,
+                device,
+            });
+        }
+    }
+
+    if index != bytes.len() {
+        return Err(Error::PayloadTooLarge(index, bytes.len()));
+    }
+
+    Ok((entries, annotations))
+}
+
+/// Decodes every descriptor in a bundle
+pub fn decode_bundle(bytes: &[u8]) -> Result<Vec<String>, Error> {
+    decode_bundle_cancellable(bytes, &CancelToken::new())
+}
+
+/// Decodes every descriptor in a bundle, stopping with [`Error::Cancelled`] once `cancel` is
+/// cancelled
+///
+/// The token is checked before each descriptor, so a restore can be aborted from another thread.
+pub fn decode_bundle_cancellable(bytes: &[u8], cancel: &CancelToken) -> Result<Vec<String>, Error> {
+    decode_bundle_with_progress(bytes, cancel, |_| {})
+}
+
+/// Number of entries of a bundle decoded so far, and the bundle bytes they span
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BundleProgress {
+    /// Number of entries decoded
+    pub decoded: usize,
+    /// Number of entries in the bundle
+    pub total: usize,
+    /// Number of bundle bytes up to the end of the last entry decoded
+    pub bytes_read: usize,
+    /// Number of bytes in the bundle
+    pub total_bytes: usize,
+}
+
+/// Decodes every descriptor in a bundle, calling `progress` after each one
+///
+/// Lets a UI render a progress bar during a large restore. Stops with [`Error::Cancelled`] once
+/// `cancel` is cancelled, as [`decode_bundle_cancellable`] does.
+pub fn decode_bundle_with_progress(
+    bytes: &[u8],
+    cancel: &CancelToken,
+    mut progress: impl FnMut(BundleProgress),
+) -> Result<Vec<String>, Error> {
+    let entries = split_bundle(bytes)?;
+    let mut decoded = Vec::with_capacity(entries.len());
+    for entry in &entries {
+        decoded.push(decode_unless_cancelled(entry, cancel)?);
+        progress(BundleProgress {
+            decoded: decoded.len(),
+            total: entries.len(),
+            bytes_read: entry.as_ptr() as usize + entry.len() - bytes.as_ptr() as usize,
+            total_bytes: bytes.len(),
+        });
+    }
+    Ok(decoded)
+}
+
+/// Decodes an entry, or fails with [`Error::Cancelled`] if `cancel` is cancelled
+fn decode_unless_cancelled(entry: &[u8], cancel: &CancelToken) -> Result<String, Error> {
+    match cancel.is_cancelled() {
+        true => Err(Error::Cancelled),
+        false => decode(entry),
+    }
+}
+
+/// Decodes every descriptor in a bundle on one thread per available core
+///
+/// Returns the same result as [`decode_bundle`], with descriptors in bundle order and the first
+/// error in bundle order, but cuts restore time for bundles of hundreds of descriptors.
+#[cfg(feature = "parallel")]
+pub fn decode_bundle_parallel(bytes: &[u8]) -> Result<Vec<String>, Error> {
+    decode_bundle_parallel_cancellable(bytes, &CancelToken::new())
+}
+
+/// Decodes every descriptor in a bundle on one thread per available core, stopping with
+/// [`Error::Cancelled`] once `cancel` is cancelled
+///
+/// Every thread checks the token before each descriptor. See [`decode_bundle_parallel`].
+#[cfg(feature = "parallel")]
+pub fn decode_bundle_parallel_cancellable(
+    bytes: &[u8],
+    cancel: &CancelToken,
+) -> Result<Vec<String>, Error> {
+    let entries = split_bundle(bytes)?;
+    let threads = std::thread::available_parallelism().map_or(1, usize::from);
+    let chunk_size = entries.len().div_ceil(threads).max(1);
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = entries
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(|| {
+                    chunk
+                        .iter()
+                        .map(|entry| decode_unless_cancelled(entry, cancel))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        let mut decoded = Vec::with_capacity(entries.len());
+        for handle in handles {
+            let chunk = handle
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+            decoded.extend(chunk);
+        }
+        decoded.into_iter().collect()
+    })
+}
+
+fn read_len(bytes: &[u8], index: &mut usize) -> Result<usize, Error> {
+    if *index >= bytes.len() {
+        return Err(Error::MissingBytes);
+    }
+
+    let current_index = *index;
+    let (len, size) = varint::decode_as::<usize>(&bytes[current_index..])
+        .map_err(|e| Error::InvalidVarInt(current_index, e))?;
+    *index += size;
+
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bundle() {
+        let descriptors = [
+            "wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)#8zl0zxma",
+            "tr(c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5)#k6ze7ncu",
+        ];
+        let entries: Vec<_> = descriptors.iter().map(|d| encode(d).unwrap()).collect();
+
+        let bundle = encode_bundle(&entries);
+        assert_eq!(split_bundle(&bundle).unwrap(), entries);
+        assert_eq!(decode_bundle(&bundle).unwrap(), descriptors);
+
+        let empty = encode_bundle::<Vec<u8>>(&[]);
+        assert_eq!(empty, vec![0]);
+        assert!(split_bundle(&empty).unwrap().is_empty());
+
+        assert_eq!(
+            split_bundle(&bundle[..bundle.len() - 1]),
+            Err(Error::MissingBytes)
+        );
+        assert_eq!(split_bundle(&[]), Err(Error::MissingBytes));
+        assert_eq!(split_bundle(&[0x05, 0x01]), Err(Error::MissingBytes));
+
+        let mut extended = bundle.clone();
+        extended.extend([0, 0]);
+        assert_eq!(
+            split_bundle(&extended),
+            Err(Error::PayloadTooLarge(bundle.len() + 1, bundle.len() + 2))
+        );
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_decode_bundle_parallel() {
+        let xpub = "xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V";
+        let descriptors: Vec<_> = (0..200).map(|i| format!("wpkh({xpub}/{i}/*)")).collect();
+        let entries: Vec<_> = descriptors.iter().map(|d| encode(d).unwrap()).collect();
+        let bundle = encode_bundle(&entries);
+        assert_eq!(
+            decode_bundle_parallel(&bundle).unwrap(),
+            decode_bundle(&bundle).unwrap()
+        );
+        assert!(
+            decode_bundle_parallel(&encode_bundle::<Vec<u8>>(&[]))
+                .unwrap()
+                .is_empty()
+        );
+
+        // The first invalid entry in bundle order is reported
+        let mut invalid = entries.clone();
+        invalid[150] = vec![0xFF];
+        invalid[5] = vec![0x00];
+        let bundle = encode_bundle(&invalid);
+        assert_eq!(
+            decode_bundle_parallel(&bundle),
+            Err(decode(&[0x00]).unwrap_err())
+        );
+        assert_eq!(decode_bundle_parallel(&bundle), decode_bundle(&bundle));
+    }
+
+    #[test]
+    fn test_decode_bundle_cancellable() {
+        let desc_str =
+            "wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)#8zl0zxma";
+        let bundle = encode_bundle(&[encode(desc_str).unwrap(), encode(desc_str).unwrap()]);
+
+        let cancel = CancelToken::new();
+        assert_eq!(
+            decode_bundle_cancellable(&bundle, &cancel).unwrap(),
+            [desc_str, desc_str]
+        );
+
+        cancel.clone().cancel();
+        assert_eq!(
+            decode_bundle_cancellable(&bundle, &cancel),
+            Err(Error::Cancelled)
+        );
+        #[cfg(feature = "parallel")]
+        assert_eq!(
+            decode_bundle_parallel_cancellable(&bundle, &cancel),
+            Err(Error::Cancelled)
+        );
+
+        // An empty bundle has nothing to cancel
+        let empty = encode_bundle::<Vec<u8>>(&[]);
+        assert!(
+            decode_bundle_cancellable(&empty, &cancel)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_decode_bundle_with_progress() {
+        let desc_str =
+            "wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)#8zl0zxma";
+        let entry = encode(desc_str).unwrap();
+        let bundle = encode_bundle(&[&entry, &entry, &entry]);
+
+        let mut reports = Vec::new();
+        let cancel = CancelToken::new();
+        let decoded = decode_bundle_with_progress(&bundle, &cancel, |p| reports.push(p)).unwrap();
+        assert_eq!(decoded, [desc_str; 3]);
+        assert_eq!(
+            reports
+                .iter()
+                .map(|p| (p.decoded, p.total))
+                .collect::<Vec<_>>(),
+            [(1, 3), (2, 3), (3, 3)]
+        );
+        // Each entry is a one-byte length followed by the encoding
+        assert_eq!(reports[0].bytes_read, 1 + 1 + entry.len());
+        assert_eq!(reports[2].bytes_read, bundle.len());
+        assert!(reports.iter().all(|p| p.total_bytes == bundle.len()));
+
+        // Cancelling from the callback stops before the next entry
+        let result = decode_bundle_with_progress(&bundle, &cancel, |p| {
+            if p.decoded == 2 {
+                cancel.cancel();
+            }
+        });
+        assert_eq!(result, Err(Error::Cancelled));
+    }
+
+    #[test]
+    fn test_bundle_annotations() {
+        let desc_str = "wsh(multi(1,03a0434d9e47f3c86235477c7b1ae6ae5d3442d49b1943c2b752a68e2a47e247c7,036d2b085e9e382ed10b69fc311a03f8641ccfff21574de0927513a49d9a688a00))";
+        let entries = [encode(desc_str).unwrap()];
+        let annotations = [
+            KeyAnnotation {
+                entry: 0,
+                key_index: 1,
+                label: Some("Alice".to_string()),
+                contact: Some("alice@example.com".to_string()),
+                device: Some("Coldcard Mk4".to_string()),
+            },
+            KeyAnnotation {
+                entry: 0,
+                key_index: 0,
+                label: Some("Bob".to_string()),
+                ..Default::default()
+            },
+        ];
+
+        let bundle = encode_annotated_bundle(&entries, &annotations);
+        assert_eq!(bundle_annotations(&bundle).unwrap(), annotations);
+
+        // Annotations leave the entries untouched
+        assert_eq!(split_bundle(&bundle).unwrap(), entries);
+        assert!(bundle.starts_with(&encode_bundle(&entries)));
+        assert!(
+            bundle_annotations(&encode_bundle(&entries))
+                .unwrap()
+                .is_empty()
+        );
+
+        // Annotations must refer to an existing entry
+        let orphan = KeyAnnotation {
+            entry: 1,
+            ..Default::default()
+        };
+        let bundle = encode_annotated_bundle(&entries, &[orphan]);
+        let start = encode_bundle(&entries).len() + 1;
+        assert_eq!(split_bundle(&bundle), Err(Error::InvalidAnnotation(start)));
+
+        let mut bundle = encode_bundle(&entries);
+        bundle.extend([1, 0, 0, 1, 0xFF, 0, 0]);
+        assert_eq!(
+            bundle_annotations(&bundle),
+            Err(Error::InvalidAnnotation(start))
+        );
+    }
+    #[test]
+    fn test_manifest() {
+        let descriptors = [
+            "wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)#8zl0zxma",
+            "tr(c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5)#k6ze7ncu",
+        ];
+        let entries: Vec<_> = descriptors.iter().map(|d| encode(d).unwrap()).collect();
+        let annotations = [KeyAnnotation {
+            entry: 1,
+            key_index: 0,
+            label: Some("Alice".to_string()),
+            ..Default::default()
+        }];
+        let bundle = encode_manifest_bundle(
+            &[(&entries[0], Some("spending")), (&entries[1], None)],
+            &annotations,
+        );
+
+        let manifest = bundle_manifest(&bundle).unwrap();
+        assert_eq!(
+            manifest,
+            vec![
+                ManifestEntry {
+                    id: sha256::Hash::hash(&entries[0]),
+                    size: entries[0].len(),
+                    label: Some("spending".to_string()),
+                },
+                ManifestEntry {
+                    id: sha256::Hash::hash(&entries[1]),
+                    size: entries[1].len(),
+                    label: None,
+                },
+            ]
+        );
+        assert_eq!(split_bundle(&bundle).unwrap(), entries);
+        assert_eq!(decode_bundle(&bundle).unwrap(), descriptors);
+        assert_eq!(bundle_annotations(&bundle).unwrap(), annotations);
+        assert_eq!(
+            decode_entry(&bundle, &manifest[1].id).unwrap(),
+            Some(descriptors[1].to_string())
+        );
+        assert_eq!(
+            bundle_entry(&bundle, &sha256::Hash::hash(b"missing")),
+            Ok(None)
+        );
+
+        // Bundles without a manifest list their entries as well
+        let plain = encode_bundle(&entries);
+        let unlabeled: Vec<_> = manifest
+            .iter()
+            .map(|entry| ManifestEntry {
+                label: None,
+                ..entry.clone()
+            })
+            .collect();
+        assert_eq!(bundle_manifest(&plain).unwrap(), unlabeled);
+        assert_eq!(
+            bundle_entry(&plain, &manifest[0].id).unwrap(),
+            Some(&entries[0][..])
+        );
+
+        // Only the requested entry is read, and it must match its ID
+        let mut corrupted = bundle.clone();
+        let start = bundle
+            .windows(entries[0].len())
+            .position(|window| window == entries[0])
+            .unwrap();
+        corrupted[start] ^= 1;
+        assert_eq!(
+            bundle_entry(&corrupted, &manifest[0].id),
+            Err(Error::InvalidManifest(start))
+        );
+        assert!(bundle_entry(&corrupted, &manifest[1].id).unwrap().is_some());
+        assert_eq!(split_bundle(&corrupted), Err(Error::InvalidManifest(start)));
+
+        assert_eq!(
+            bundle_manifest(&bundle[..start - 1]),
+            Err(Error::MissingBytes)
+        );
+    }
+
+    #[test]
+    fn test_decode_entry() {
+        let descriptors = [
+            "wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)#8zl0zxma",
+            "tr(c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5)#k6ze7ncu",
+        ];
+        let entries: Vec<_> = descriptors.iter().map(|d| encode(d).unwrap()).collect();
+        let ids: Vec<_> = entries.iter().map(|e| sha256::Hash::hash(e)).collect();
+
+        let bundle = encode_manifest_bundle(&[(&entries[0], None), (&entries[1], None)], &[]);
+        let backup = encode_backup(&Backup {
+            metadata: Vec::new(),
+            entries: vec![
+                #[cfg(feature = "encryption")]
+                BackupEntry::encrypted(0, None, &entries[0], &[1; 32]).unwrap(),
+                BackupEntry::plain(1, None, &entries[1]),
+            ],
+        });
+        for container in [bundle, encode_bundle(&entries), backup.clone()] {
+            assert_eq!(
+                decode_entry(&container, &ids[1]).unwrap(),
+                Some(descriptors[1].to_string())
+            );
+        }
+
+        // Encrypted backup entries cannot be matched
+        #[cfg(feature = "encryption")]
+        assert_eq!(decode_entry(&backup, &ids[0]), Ok(None));
+        let mut corrupted = backup.clone();
+        corrupted[10] ^= 1;
+        assert_eq!(
+            decode_entry(&corrupted, &ids[1]),
+            Err(Error::Backup(BackupError::Invalid))
+        );
+    }
+}
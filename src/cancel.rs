@@ -0,0 +1,46 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! # Cancellation
+//!
+//! A [`CancelToken`] lets a GUI wallet abort a long operation, such as decoding a bundle of
+//! hundreds of descriptors, from another thread. Cancellable operations check the token between
+//! descriptors and fail with [`Error::Cancelled`](crate::Error::Cancelled) once it is cancelled.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Shared flag requesting that an operation stop, cheap to clone across threads
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    /// Creates a token that is not cancelled
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requests that every operation holding a clone of this token stop
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns whether the token has been cancelled
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cancel_token() {
+        let token = CancelToken::new();
+        let clone = token.clone();
+        assert!(!clone.is_cancelled());
+
+        std::thread::spawn(move || token.cancel()).join().unwrap();
+        assert!(clone.is_cancelled());
+    }
+}
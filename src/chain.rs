@@ -0,0 +1,623 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! # Chain Lookups
+//!
+//! Queries a blockchain backend about a descriptor's scripts, so the first funding height can be
+//! stored as its birth height and restores skip scanning earlier blocks. Backends implement
+//! [`ChainBackend`], and [`ElectrumBackend`] speaks the Electrum protocol over plain TCP.
+
+use super::*;
+use bitcoin::{Script, ScriptBuf};
+use metadata::birth_height_record;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+use std::{error, fmt};
+
+/// Number of scripts per single-path descriptor checked by default
+pub const DEFAULT_GAP_LIMIT: u32 = 20;
+
+/// Largest response line read from an Electrum server, in bytes
+pub const MAX_RESPONSE_LEN: usize = 4 * 1024 * 1024;
+
+/// Number of requests sent to an Electrum server before reading their responses
+const PIPELINE_DEPTH: usize = 32;
+
+/// Deepest nesting of arrays and objects accepted in an Electrum response
+const MAX_JSON_DEPTH: usize = 32;
+
+/// Source of on-chain history for scripts
+pub trait ChainBackend {
+    /// Returns the heights of the confirmed transactions spending from or paying to a script
+    fn confirmed_heights(&self, script: &Script) -> io::Result<Vec<u32>>;
+
+    /// Returns the confirmed heights of each of several scripts, in order
+    ///
+    /// Queries one script at a time by default. Backends that can batch should override it.
+    fn confirmed_heights_batch(&self, scripts: &[ScriptBuf]) -> io::Result<Vec<Vec<u32>>> {
+        scripts
+            .iter()
+            .map(|script| self.confirmed_heights(script))
+            .collect()
+    }
+}
+
+/// A backend querying an Electrum server over plain TCP, such as `localhost:50001`
+#[derive(Debug, Clone)]
+pub struct ElectrumBackend {
+    address: String,
+    timeout: Duration,
+}
+
+impl ElectrumBackend {
+    /// Creates a backend for the server at a host and port
+    pub fn new(address: impl Into<String>) -> Self {
+        Self {
+            address: address.into(),
+            timeout: Duration::from_secs(30),
+        }
+    }
+
+    /// Sets how long to wait for the server, 30 seconds by default
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+impl ElectrumBackend {
+    /// Connects to the first address the server resolves to that accepts within the timeout
+    fn connect(&self) -> io::Result<TcpStream> {
+        let mut last_err = None;
+        for address in self.address.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&address, self.timeout) {
+                Ok(stream) => {
+                    stream.set_read_timeout(Some(self.timeout))?;
+                    stream.set_write_timeout(Some(self.timeout))?;
+                    return Ok(stream);
+                }
+                Err(err) => last_err = Some(err),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "electrum address did not resolve",
+            )
+        }))
+    }
+}
+
+impl ChainBackend for ElectrumBackend {
+    fn confirmed_heights(&self, script: &Script) -> io::Result<Vec<u32>> {
+        let mut heights = self.confirmed_heights_batch(&[script.to_owned()])?;
+        Ok(heights.remove(0))
+    }
+
+    fn confirmed_heights_batch(&self, scripts: &[ScriptBuf]) -> io::Result<Vec<Vec<u32>>> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        let stream = self.connect()?;
+        let mut reader = BufReader::new(&stream);
+        let mut history = Vec::with_capacity(scripts.len());
+
+        // Requests are pipelined over one connection, a chunk at a time so neither side's buffers
+        // fill while the other is still writing
+        for chunk in scripts.chunks(PIPELINE_DEPTH) {
+            let mut requests = String::new();
+            for (id, script) in chunk.iter().enumerate() {
+                requests.push_str(&format!(
+                    "{{\"jsonrpc\":\"2.0\",\"id\":{id},\"method\":\"blockchain.scripthash.get_history\",\"params\":[\"{}\"]}}\n",
+                    electrum_script_hash(script)
+                ));
+            }
+            (&stream).write_all(requests.as_bytes())?;
+
+            let mut responses = vec![None; chunk.len()];
+            for _ in 0..chunk.len() {
+                let mut line = Vec::new();
+                (&mut reader)
+                    .take(MAX_RESPONSE_LEN as u64)
+                    .read_until(b'\n', &mut line)?;
+                if line.last() != Some(&b'\n') {
+                    return Err(invalid(if line.len() == MAX_RESPONSE_LEN {
+                        "electrum response too large"
+                    } else {
+                        "electrum connection closed"
+                    }));
+                }
+                let line = std::str::from_utf8(&line)
+                    .map_err(|_| invalid("malformed electrum response"))?;
+                let (id, heights) = parse_history(line)?;
+                match usize::try_from(id)
+                    .ok()
+                    .and_then(|id| responses.get_mut(id))
+                {
+                    Some(slot @ None) => *slot = Some(heights),
+                    _ => return Err(invalid("unexpected electrum response id")),
+                }
+            }
+            history.extend(responses.into_iter().flatten());
+        }
+        Ok(history)
+    }
+}
+
+/// Reads the request id and confirmed heights from a `blockchain.scripthash.get_history`
+/// response
+///
+/// Unconfirmed transactions have a height of zero or less and are skipped.
+fn parse_history(response: &str) -> io::Result<(u64, Vec<u32>)> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+    let malformed = || invalid("malformed electrum response");
+    let Some(Json::Object(fields)) = Json::parse(response.trim_end()) else {
+        return Err(malformed());
+    };
+    let field = |name: &str| fields.iter().find(|(key, _)| key == name).map(|(_, v)| v);
+    if field("error").is_some() {
+        return Err(invalid("electrum server returned an error"));
+    }
+    if !matches!(field("jsonrpc"), Some(Json::String(version)) if version == "2.0") {
+        return Err(malformed());
+    }
+    let id = match field("id") {
+        Some(Json::Number(id)) => id.parse().map_err(|_| malformed())?,
+        _ => return Err(malformed()),
+    };
+    let Some(Json::Array(entries)) = field("result") else {
+        return Err(malformed());
+    };
+
+    let mut heights = Vec::new();
+    for entry in entries {
+        let Json::Object(entry) = entry else {
+            return Err(malformed());
+        };
+        let height: i64 = match entry.iter().find(|(key, _)| key == "height") {
+            Some((_, Json::Number(height))) => height
+                .parse()
+                .map_err(|_| invalid("invalid height in electrum response"))?,
+            _ => return Err(malformed()),
+        };
+        if let Ok(height @ 1..) = u32::try_from(height) {
+            heights.push(height);
+        }
+    }
+    Ok((id, heights))
+}
+
+/// JSON value, with numbers kept as their text
+#[derive(Debug, PartialEq)]
+enum Json<'a> {
+    Null,
+    Bool(bool),
+    Number(&'a str),
+    String(String),
+    Array(Vec<Json<'a>>),
+    Object(Vec<(String, Json<'a>)>),
+}
+
+impl<'a> Json<'a> {
+    /// Parses a complete JSON document, returning `None` if it is malformed
+    fn parse(input: &'a str) -> Option<Self> {
+        let mut pos = 0;
+        let value = Self::parse_value(input, &mut pos, 0)?;
+        input[pos..].trim_start().is_empty().then_some(value)
+    }
+
+    fn parse_value(input: &'a str, pos: &mut usize, depth: usize) -> Option<Self> {
+        if depth > MAX_JSON_DEPTH {
+            return None;
+        }
+        Self::skip_whitespace(input, pos);
+        let rest = &input[*pos..];
+        for (literal, value) in [
+            ("null", Json::Null),
+            ("true", Json::Bool(true)),
+            ("false", Json::Bool(false)),
+        ] {
+            if rest.starts_with(literal) {
+                *pos += literal.len();
+                return Some(value);
+            }
+        }
+        match rest.as_bytes().first()? {
+            b'"' => Self::parse_string(input, pos).map(Json::String),
+            b'-' | b'0'..=b'9' => {
+                let len = rest
+                    .find(|c: char| !matches!(c, '-' | '+' | '.' | 'e' | 'E' | '0'..='9'))
+                    .unwrap_or(rest.len());
+                rest[..len].parse::<f64>().ok()?;
+                *pos += len;
+                Some(Json::Number(&rest[..len]))
+            }
+            b'[' => {
+                *pos += 1;
+                let mut items = Vec::new();
+                if !Self::close(input, pos, b']') {
+                    loop {
+                        items.push(Self::parse_value(input, pos, depth + 1)?);
+                        if Self::close(input, pos, b']') {
+                            break;
+                        }
+                        Self::expect(input, pos, b',')?;
+                    }
+                }
+                Some(Json::Array(items))
+            }
+            b'{' => {
+                *pos += 1;
+                let mut fields = Vec::new();
+                if !Self::close(input, pos, b'}') {
+                    loop {
+                        Self::skip_whitespace(input, pos);
+                        if !input[*pos..].starts_with('"') {
+                            return None;
+                        }
+                        let key = Self::parse_string(input, pos)?;
+                        Self::expect(input, pos, b':')?;
+                        fields.push((key, Self::parse_value(input, pos, depth + 1)?));
+                        if Self::close(input, pos, b'}') {
+                            break;
+                        }
+                        Self::expect(input, pos, b',')?;
+                    }
+                }
+                Some(Json::Object(fields))
+            }
+            _ => None,
+        }
+    }
+
+    /// Parses a string starting at its opening quote
+    fn parse_string(input: &str, pos: &mut usize) -> Option<String> {
+        let mut string = String::new();
+        let mut chars = input[*pos + 1..].char_indices();
+        while let Some((offset, c)) = chars.next() {
+            match c {
+                '"' => {
+                    *pos += offset + 2;
+                    return Some(string);
+                }
+                '\\' => string.push(match chars.next()?.1 {
+                    'b' => '\u{8}',
+                    'f' => '\u{c}',
+                    'n' => '\n',
+                    'r' => '\r',
+                    't' => '\t',
+                    'u' => {
+                        let hex: String = chars.by_ref().take(4).map(|(_, c)| c).collect();
+                        let code = u32::from_str_radix(&hex, 16)
+                            .ok()
+                            .filter(|_| hex.len() == 4)?;
+                        char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER)
+                    }
+                    c @ ('"' | '\\' | '/') => c,
+                    _ => return None,
+                }),
+                '\0'..='\u{1f}' => return None,
+                c => string.push(c),
+            }
+        }
+        None
+    }
+
+    /// Consumes a closing bracket if it comes next, skipping whitespace
+    fn close(input: &str, pos: &mut usize, bracket: u8) -> bool {
+        Self::expect(input, pos, bracket).is_some()
+    }
+
+    /// Consumes a byte if it comes next, skipping whitespace
+    fn expect(input: &str, pos: &mut usize, byte: u8) -> Option<()> {
+        Self::skip_whitespace(input, pos);
+        (input.as_bytes().get(*pos) == Some(&byte)).then(|| *pos += 1)
+    }
+
+    fn skip_whitespace(input: &str, pos: &mut usize) {
+        *pos = input.len() - input[*pos..].trim_start().len();
+    }
+}
+
+/// Error looking up a descriptor on chain
+#[derive(Debug)]
+pub enum ChainError {
+    /// Descriptor cannot be encoded
+    Encode(encoder::Error),
+    /// Encoding cannot be decoded or its scripts derived
+    Decode(Error),
+    /// Backend cannot be queried
+    Backend(io::Error),
+}
+
+impl fmt::Display for ChainError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Encode(_) => write!(f, "invalid descriptor"),
+            Self::Decode(_) => write!(f, "cannot derive scripts"),
+            Self::Backend(_) => write!(f, "chain backend query failed"),
+        }
+    }
+}
+
+impl error::Error for ChainError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Encode(err) => Some(err),
+            Self::Decode(err) => Some(err),
+            Self::Backend(err) => Some(err),
+        }
+    }
+}
+
+/// Returns the height of the first block funding any of the first `gap_limit` scripts of each
+/// single-path descriptor in an encoding, or `None` if none has confirmed history
+pub fn estimate_birthday(
+    bytes: &[u8],
+    backend: &dyn ChainBackend,
+    gap_limit: u32,
+) -> Result<Option<u32>, ChainError> {
+    let scripts = derive_scripts(bytes, 0..gap_limit).map_err(ChainError::Decode)?;
+    let history = backend
+        .confirmed_heights_batch(&scripts)
+        .map_err(ChainError::Backend)?;
+    Ok(history.into_iter().flatten().min())
+}
+
+/// On-chain use of an encoding's first scripts, found by [`verify_funds`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FundsCheck {
+    /// Number of scripts checked
+    pub scripts_checked: usize,
+    /// Indices, in the order of [`derive_scripts`], of the scripts with confirmed history
+    pub used: Vec<usize>,
+}
+
+impl FundsCheck {
+    /// Returns whether any checked script has confirmed history
+    pub fn is_used(&self) -> bool {
+        !self.used.is_empty()
+    }
+}
+
+/// Checks whether any of the first `count` scripts of each single-path descriptor in an encoding
+/// has confirmed history
+///
+/// Lets a restore flow confirm that a backup belongs to a used wallet before anything is wiped.
+/// Every script is checked, so the result shows which ones were used. Encodings whose scripts
+/// cannot be derived, such as through a hardened step, fail with [`ChainError::Decode`].
+pub fn verify_funds(
+    bytes: &[u8],
+    backend: &dyn ChainBackend,
+    count: u32,
+) -> Result<FundsCheck, ChainError> {
+    let scripts = derive_scripts(bytes, 0..count).map_err(ChainError::Decode)?;
+    let history = backend
+        .confirmed_heights_batch(&scripts)
+        .map_err(ChainError::Backend)?;
+    let used = history
+        .iter()
+        .enumerate()
+        .filter(|(_, heights)| !heights.is_empty())
+        .map(|(index, _)| index)
+        .collect();
+    Ok(FundsCheck {
+        scripts_checked: scripts.len(),
+        used,
+    })
+}
+
+/// Encodes a descriptor with its estimated birth height, if it has on-chain history
+///
+/// See [`estimate_birthday`]. The height is stored as a record of type
+/// [`metadata::BIRTH_HEIGHT`], which [`birth_height`] reads back.
+pub fn encode_with_birthday(
+    s: &str,
+    backend: &dyn ChainBackend,
+    gap_limit: u32,
+) -> Result<Vec<u8>, ChainError> {
+    let encoded = encode(s).map_err(ChainError::Encode)?;
+    match estimate_birthday(&encoded, backend, gap_limit)? {
+        Some(height) => {
+            encode_with_metadata(s, &[birth_height_record(height)]).map_err(ChainError::Encode)
+        }
+        None => Ok(encoded),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::ScriptBuf;
+    use std::net::TcpListener;
+
+    const XPUB: &str = "[73c5da0a/84'/0'/0']xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V";
+
+    /// Serves history from a table, counting queries
+    #[derive(Default)]
+    struct TableBackend(BTreeMap<ScriptBuf, Vec<u32>>, std::cell::Cell<usize>);
+
+    impl ChainBackend for TableBackend {
+        fn confirmed_heights(&self, script: &Script) -> io::Result<Vec<u32>> {
+            self.1.set(self.1.get() + 1);
+            Ok(self.0.get(script).cloned().unwrap_or_default())
+        }
+    }
+
+    #[test]
+    fn test_estimate_birthday() {
+        let desc_str = format!("wpkh({XPUB}/<0;1>/*)");
+        let scripts = derive_scripts(&encode(&desc_str).unwrap(), 0..5).unwrap();
+        let mut backend = TableBackend::default();
+        backend.0.insert(scripts[3].clone(), vec![840_100, 800_000]);
+        backend.0.insert(scripts[7].clone(), vec![820_500]);
+
+        let encoded = encode_with_birthday(&desc_str, &backend, 5).unwrap();
+        assert_eq!(backend.1.get(), 10);
+        let (decoded, records) = decode_with_metadata(&encoded).unwrap();
+        assert_eq!(decoded, decode(&encode(&desc_str).unwrap()).unwrap());
+        assert_eq!(birth_height(&records), Ok(Some(800_000)));
+
+        // Scripts past the gap limit are not checked
+        assert_eq!(
+            estimate_birthday(&encode(&desc_str).unwrap(), &backend, 3).unwrap(),
+            Some(820_500)
+        );
+
+        // Unused descriptors are encoded without a birth height
+        let unused = TableBackend::default();
+        assert_eq!(
+            encode_with_birthday(&desc_str, &unused, 5).unwrap(),
+            encode(&desc_str).unwrap()
+        );
+        assert!(matches!(
+            encode_with_birthday("wpkh(invalid)", &unused, 5),
+            Err(ChainError::Encode(_))
+        ));
+
+        // Scripts behind a hardened step cannot be derived from the xpub
+        let untouched = TableBackend::default();
+        assert!(matches!(
+            encode_with_birthday(&format!("wpkh({XPUB}/1'/*)"), &untouched, 5),
+            Err(ChainError::Decode(_))
+        ));
+        assert_eq!(untouched.1.get(), 0);
+    }
+
+    #[test]
+    fn test_verify_funds() {
+        let encoded = encode(&format!("wpkh({XPUB}/<0;1>/*)")).unwrap();
+        let scripts = derive_scripts(&encoded, 0..3).unwrap();
+        let mut backend = TableBackend::default();
+
+        let check = verify_funds(&encoded, &backend, 3).unwrap();
+        assert_eq!(
+            check,
+            FundsCheck {
+                scripts_checked: 6,
+                used: Vec::new(),
+            }
+        );
+        assert!(!check.is_used());
+
+        backend.0.insert(scripts[4].clone(), vec![800_000]);
+        let check = verify_funds(&encoded, &backend, 3).unwrap();
+        assert!(check.is_used());
+        assert_eq!(check.used, [4]);
+
+        // A script with only unconfirmed history does not count
+        backend.0.insert(scripts[0].clone(), Vec::new());
+        assert_eq!(verify_funds(&encoded, &backend, 3).unwrap().used, [4]);
+
+        assert!(matches!(
+            verify_funds(&[0xFF], &backend, 3),
+            Err(ChainError::Decode(_))
+        ));
+
+        // Keys behind a hardened step fail instead of aborting the restore
+        for desc_str in [
+            format!("wpkh({XPUB}/1'/*)"),
+            format!("wsh(multi(1,{XPUB}/0/*,{XPUB}/<0;1>/5h/*))"),
+        ] {
+            let untouched = TableBackend::default();
+            assert!(matches!(
+                verify_funds(&encode(&desc_str).unwrap(), &untouched, 3),
+                Err(ChainError::Decode(_))
+            ));
+            assert_eq!(untouched.1.get(), 0);
+        }
+    }
+
+    #[test]
+    fn test_electrum_backend() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for responses in [
+                // Both requests arrive on one connection and are answered out of order
+                &[
+                    "{\"jsonrpc\":\"2.0\",\"id\":1,\"result\":[]}\n",
+                    "{\"jsonrpc\": \"2.0\", \"result\": [{\"height\":812345,\"tx_hash\":\"aa\"},{\"height\":0,\"tx_hash\":\"bb\",\"fee\":200},{\"height\": 800001,\"tx_hash\":\"cc\"}], \"id\": 0}\n",
+                ][..],
+                &["{\"jsonrpc\":\"2.0\",\"id\":0,\"error\":{\"code\":1,\"message\":\"bad\"}}\n"],
+                &["{\"jsonrpc\":\"2.0\",\"id\":0,\"result\":[{\"height\":1}]"],
+            ] {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(&stream);
+                for _ in responses {
+                    let mut request = String::new();
+                    reader.read_line(&mut request).unwrap();
+                    requests.push(request);
+                }
+                for response in responses {
+                    (&stream).write_all(response.as_bytes()).unwrap();
+                }
+            }
+            requests
+        });
+
+        let backend = ElectrumBackend::new(address).with_timeout(Duration::from_secs(5));
+        let scripts = [ScriptBuf::new_op_return([]), ScriptBuf::new_op_return([1])];
+        assert_eq!(
+            backend.confirmed_heights_batch(&scripts).unwrap(),
+            [vec![812345, 800001], vec![]]
+        );
+        assert_eq!(
+            backend.confirmed_heights(&scripts[0]).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        // A response cut off before its newline is rejected
+        assert_eq!(
+            backend.confirmed_heights(&scripts[0]).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].contains("blockchain.scripthash.get_history"));
+        assert!(requests[0].contains("\"id\":0"));
+        assert!(requests[0].contains(&electrum_script_hash(&scripts[0])));
+        assert!(requests[1].contains("\"id\":1"));
+        assert!(requests[1].contains(&electrum_script_hash(&scripts[1])));
+    }
+
+    #[test]
+    fn test_parse_history() {
+        assert_eq!(
+            parse_history("{\"jsonrpc\":\"2.0\",\"id\":7,\"result\":[{\"height\":-1,\"tx_hash\":\"a\\\"b\\u00e9\"}]}\n")
+                .unwrap(),
+            (7, vec![])
+        );
+
+        for response in [
+            // Error responses, even alongside a result
+            "{\"jsonrpc\":\"2.0\",\"id\":0,\"error\":{\"code\":1,\"message\":\"bad\"}}",
+            "{\"jsonrpc\":\"2.0\",\"id\":0,\"result\":[],\"error\":null}",
+            // Malformed responses
+            "",
+            "[]",
+            "{\"result\":[]}",
+            "{\"jsonrpc\":\"1.0\",\"id\":0,\"result\":[]}",
+            "{\"jsonrpc\":\"2.0\",\"id\":\"0\",\"result\":[]}",
+            "{\"jsonrpc\":\"2.0\",\"id\":0,\"result\":{\"height\":5}}",
+            "{\"jsonrpc\":\"2.0\",\"id\":0,\"result\":[{\"tx_hash\":\"aa\"}]}",
+            "{\"jsonrpc\":\"2.0\",\"id\":0,\"result\":[{\"height\":1.5}]}",
+            "{\"jsonrpc\":\"2.0\",\"id\":0,\"result\":[{\"height\":1}]} trailing",
+            "{\"jsonrpc\":\"2.0\",\"id\":0,\"result\":[{\"height\":1}",
+            "{\"jsonrpc\":\"2.0\",\"id\":0,\"result\":[],}",
+            "garbage \"result\" \"height\":5",
+        ] {
+            assert_eq!(
+                parse_history(response).unwrap_err().kind(),
+                io::ErrorKind::InvalidData,
+                "{response}"
+            );
+        }
+
+        // Deeply nested values are refused rather than overflowing the stack
+        let nested = format!(
+            "{{\"jsonrpc\":\"2.0\",\"id\":0,\"result\":[],\"x\":{}{}}}",
+            "[".repeat(10_000),
+            "]".repeat(10_000)
+        );
+        assert!(parse_history(&nested).is_err());
+    }
+}
@@ -0,0 +1,565 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! # Descriptor Codec Handle
+
+use super::*;
+use bitcoin::secp256k1::{All, Secp256k1};
+use decoder::DecodeContext;
+use std::cell::RefCell;
+use std::sync::Arc;
+
+/// A reusable handle for encoding and decoding descriptors
+///
+/// Holds the secp256k1 context used to derive public keys from decoded secret keys, which can be
+/// supplied with [`with_secp`](Self::with_secp). The handle is `Send + Sync`, so a long-running
+/// service can create one and share it across threads.
+#[derive(Debug, Clone)]
+pub struct Codec {
+    secp: Secp256k1<All>,
+    options: CodecOptions,
+}
+
+/// Options that change how descriptors are encoded and decoded
+#[derive(Debug, Clone)]
+pub(crate) struct CodecOptions {
+    pub allocation_factor: usize,
+    pub presets: PresetRegistry,
+    pub key_codec: Option<Arc<dyn KeyCodec>>,
+    pub tag_extension: Option<Arc<dyn TagExtension>>,
+    pub commit_payload: bool,
+    pub sort_multi: bool,
+    pub lenient_parse: bool,
+    pub strict_keys: bool,
+    pub max_path_len: usize,
+    pub original_string: bool,
+    pub path_numbers: PathNumbers,
+}
+
+/// How numbers written with leading zeros or a sign in key paths, such as `/007`, are encoded
+///
+/// Miniscript reads them by value, so decoding returns the canonical `/7`, whose checksum differs
+/// from that of the encoded string. Numbers elsewhere in a descriptor must already be canonical.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PathNumbers {
+    /// Encodes the canonical form, decoded with a checksum over the canonical string
+    #[default]
+    Canonicalize,
+    /// Also records the original string, as with
+    /// [`with_original_string`](Codec::with_original_string), so
+    /// [`decode_dual`](Codec::decode_dual) returns it with its own checksum
+    Preserve,
+    /// Fails with [`NonCanonicalNumber`](encoder::Error::NonCanonicalNumber)
+    Reject,
+}
+
+impl CodecOptions {
+    pub fn encode_context<'a>(&'a self, key_map: &'a KeyMap) -> EncodeContext<'a> {
+        EncodeContext {
+            key_map,
+            key_codec: self.key_codec.as_deref(),
+            tag_extension: self.tag_extension.as_deref(),
+            hashes: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn decode_context(&self) -> DecodeContext<'_> {
+        DecodeContext {
+            key_map: KeyMap::new(),
+            key_codec: self.key_codec.as_deref(),
+            tag_extension: self.tag_extension.as_deref(),
+            metadata: Vec::new(),
+            key_rotations: Vec::new(),
+            sorted_multi: false,
+            raw_tr: false,
+            max_path_len: self.max_path_len,
+            max_keys: usize::MAX,
+            key_count: 0,
+            original_edits: None,
+            hashes: Vec::new(),
+        }
+    }
+}
+
+impl Default for CodecOptions {
+    fn default() -> Self {
+        Self {
+            allocation_factor: decoder::DEFAULT_ALLOCATION_FACTOR,
+            presets: PresetRegistry::new(),
+            key_codec: None,
+            tag_extension: None,
+            commit_payload: false,
+            sort_multi: false,
+            lenient_parse: false,
+            strict_keys: false,
+            max_path_len: decoder::DEFAULT_MAX_PATH_LEN,
+            original_string: false,
+            path_numbers: PathNumbers::Canonicalize,
+        }
+    }
+}
+
+impl Codec {
+    /// Creates a new codec
+    pub fn new() -> Self {
+        Self {
+            secp: Secp256k1::new(),
+            options: CodecOptions::default(),
+        }
+    }
+
+    /// Sets the secp256k1 context used to derive public keys from decoded secret keys
+    ///
+    /// Lets a caller reuse a context it already holds, such as one that is randomized or built in
+    /// preallocated memory, instead of the codec creating its own.
+    pub fn with_secp(mut self, secp: Secp256k1<All>) -> Self {
+        self.secp = secp;
+        self
+    }
+
+    /// Sets the maximum number of bytes a decoded descriptor string may occupy per input byte
+    pub fn with_allocation_factor(mut self, factor: usize) -> Self {
+        self.options.allocation_factor = factor;
+        self
+    }
+
+    /// Sets the maximum number of steps in a derivation path
+    ///
+    /// Longer paths fail to encode or decode with a `PathTooLong` error. Defaults to
+    /// [`DEFAULT_MAX_PATH_LEN`](decoder::DEFAULT_MAX_PATH_LEN).
+    pub fn with_max_path_len(mut self, max_path_len: usize) -> Self {
+        self.options.max_path_len = max_path_len;
+        self
+    }
+
+    /// Sets the presets used to encode matching descriptors and decode preset IDs
+    pub fn with_presets(mut self, presets: PresetRegistry) -> Self {
+        self.options.presets = presets;
+        self
+    }
+
+    /// Sets the key codec used to serialize keys to and from the payload
+    ///
+    /// Bytes encoded with a key codec must be decoded with the same key codec.
+    pub fn with_key_codec(mut self, key_codec: Arc<dyn KeyCodec>) -> Self {
+        self.options.key_codec = Some(key_codec);
+        self
+    }
+
+    /// Sets the extension used to encode and decode descriptors with custom tags
+    ///
+    /// Bytes encoded with an extension must be decoded with the same extension.
+    pub fn with_tag_extension(mut self, tag_extension: Arc<dyn TagExtension>) -> Self {
+        self.options.tag_extension = Some(tag_extension);
+        self
+    }
+
+    /// Sets whether the template commits to a hash of the payload
+    ///
+    /// A committed template and payload stored separately can be checked to belong together with
+    /// [`verify_payload`](crate::verify_payload) before they are recombined.
+    pub fn with_payload_commitment(mut self, commit_payload: bool) -> Self {
+        self.options.commit_payload = commit_payload;
+        self
+    }
+
+    /// Sets whether a top-level `multi()` is encoded as `sortedmulti()`
+    ///
+    /// Only enable this when the order of keys does not matter, as it changes the script. Any key
+    /// order then gives the same encoding, which helps deduplicate backups across devices. The
+    /// conversion is recorded in the metadata and reported by [`inspect`](crate::inspect).
+    pub fn with_sorted_multi(mut self, sort_multi: bool) -> Self {
+        self.options.sort_multi = sort_multi;
+        self
+    }
+
+    /// Sets whether whitespace and invisible characters are removed before parsing
+    ///
+    /// Useful for descriptors pasted from PDFs or emails, which often contain line breaks and
+    /// zero-width characters. Descriptors never contain whitespace, so nothing meaningful is lost.
+    pub fn with_lenient_parse(mut self, lenient_parse: bool) -> Self {
+        self.options.lenient_parse = lenient_parse;
+        self
+    }
+
+    /// Sets whether every encoding is decoded again to check that its keys round-trip exactly
+    ///
+    /// Catches an x-only key decoding as a full key, or the reverse, which would silently change
+    /// the descriptor and its checksum. Encoding fails with
+    /// [`EncodeError::KeyMismatch`](crate::EncodeError::KeyMismatch) instead.
+    pub fn with_strict_keys(mut self, strict_keys: bool) -> Self {
+        self.options.strict_keys = strict_keys;
+        self
+    }
+
+    /// Sets whether the edits from the canonical string back to the encoded string are recorded
+    ///
+    /// [`decode_dual`](Self::decode_dual) then returns the original string byte for byte. Nothing
+    /// is recorded when the string is already canonical.
+    pub fn with_original_string(mut self, original_string: bool) -> Self {
+        self.options.original_string = original_string;
+        self
+    }
+
+    /// Sets how numbers written with leading zeros or a sign in key paths are encoded
+    ///
+    /// They are canonicalized by default. See [`PathNumbers`].
+    pub fn with_path_numbers(mut self, path_numbers: PathNumbers) -> Self {
+        self.options.path_numbers = path_numbers;
+        self
+    }
+
+    /// Parses and encodes a Bitcoin descriptor
+    pub fn encode(&self, s: &str) -> Result<Vec<u8>, encoder::Error> {
+        let mut encoded = Vec::new();
+        self.encode_into(s, &mut encoded)?;
+        Ok(encoded)
+    }
+
+    /// Parses and encodes a Bitcoin descriptor into `out`
+    pub fn encode_into(&self, s: &str, out: &mut Vec<u8>) -> Result<(), encoder::Error> {
+        encode_with(s, out, &[], &self.options)?;
+        Ok(())
+    }
+
+    /// Parses and encodes a Bitcoin descriptor, returning the sizes of its template and payload
+    pub fn encode_with_stats(&self, s: &str) -> Result<(Vec<u8>, Stats), encoder::Error> {
+        let mut encoded = Vec::new();
+        let stats = encode_with(s, &mut encoded, &[], &self.options)?;
+        Ok((encoded, stats))
+    }
+
+    /// Encodes a descriptor and key map, as returned by `Descriptor::parse_descriptor`
+    #[doc(alias = "encode_descriptor")]
+    pub fn encode_parsed(
+        &self,
+        descriptor: &Descriptor<DescriptorPublicKey>,
+        key_map: &KeyMap,
+    ) -> Result<Vec<u8>, encoder::Error> {
+        let mut encoded = Vec::new();
+        encode_parsed_with(descriptor, key_map, &mut encoded, &[], &self.options, false)?;
+        Ok(encoded)
+    }
+
+    /// Parses and encodes a Bitcoin descriptor with metadata records
+    pub fn encode_with_metadata(
+        &self,
+        s: &str,
+        records: &[Tlv],
+    ) -> Result<Vec<u8>, encoder::Error> {
+        let mut encoded = Vec::new();
+        encode_with(s, &mut encoded, records, &self.options)?;
+        Ok(encoded)
+    }
+
+    /// Decodes a Bitcoin descriptor
+    pub fn decode(&self, bytes: &[u8]) -> Result<String, Error> {
+        let mut decoded = String::new();
+        self.decode_into(bytes, &mut decoded)?;
+        Ok(decoded)
+    }
+
+    /// Decodes a Bitcoin descriptor into `out`
+    pub fn decode_into(&self, bytes: &[u8], out: &mut String) -> Result<(), Error> {
+        decode_with(bytes, out, &self.options)?;
+        Ok(())
+    }
+
+    /// Decodes a Bitcoin descriptor and the metadata records not understood by this decoder
+    pub fn decode_with_metadata(&self, bytes: &[u8]) -> Result<(String, Vec<Tlv>), Error> {
+        let mut decoded = String::new();
+        let (unknown, _) = decode_with(bytes, &mut decoded, &self.options)?;
+        Ok((decoded, unknown))
+    }
+
+    /// Decodes a Bitcoin descriptor in both its canonical and original string forms
+    pub fn decode_dual(&self, bytes: &[u8]) -> Result<DualString, Error> {
+        original::decode_dual_with(bytes, &self.options)
+    }
+
+    /// Rewrites an encoded descriptor with the given container options
+    ///
+    /// Preset IDs in `bytes` are expanded with the codec's presets.
+    pub fn transcode(&self, bytes: &[u8], opts: &TranscodeOptions) -> Result<Vec<u8>, Error> {
+        transcode::transcode_with(bytes, &self.options, opts)
+    }
+
+    /// Decodes a Bitcoin descriptor and a map from public keys to any secret keys
+    ///
+    /// Public keys of secret keys are derived using the codec's secp256k1 context.
+    pub fn decode_to_descriptor(
+        &self,
+        bytes: &[u8],
+    ) -> Result<(Descriptor<DescriptorPublicKey>, KeyMap), Error> {
+        let bytes = self.options.presets.expand(bytes)?;
+        let (descriptor, key_map) =
+            decoder::decode_bytes_underived(&bytes, self.options.decode_context())?;
+        decoder::derive_public_keys(&descriptor, &key_map, &self.secp)
+    }
+}
+
+impl Default for Codec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::XOnlyPublicKey;
+
+    #[test]
+    fn test_codec_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Codec>();
+
+        let codec = std::sync::Arc::new(Codec::new());
+        let desc_str =
+            "wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)#8zl0zxma";
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let codec = codec.clone();
+                std::thread::spawn(move || codec.decode(&codec.encode(desc_str).unwrap()).unwrap())
+            })
+            .collect();
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), desc_str);
+        }
+    }
+
+    #[test]
+    fn test_allocation_factor() {
+        let desc_str =
+            "wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)#8zl0zxma";
+        let encoded = encode(desc_str).unwrap();
+
+        assert_eq!(Codec::new().decode(&encoded).unwrap(), desc_str);
+        assert_eq!(
+            Codec::new().with_allocation_factor(1).decode(&encoded),
+            Err(Error::Oversized)
+        );
+    }
+
+    #[test]
+    fn test_presets() {
+        let desc_str =
+            "sh(wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9))#hyahcv3t";
+        let mut presets = PresetRegistry::new();
+        presets
+            .define(
+                1,
+                "sh(wpkh(02e493dbf1c10d80f3581e4904930b1404cc6c13900ee0758474fa94abe8c4cd13))",
+            )
+            .unwrap();
+        let codec = Codec::new().with_presets(presets);
+
+        let encoded = codec.encode(desc_str).unwrap();
+        assert_eq!(encoded.len(), 2 + 33);
+        assert_eq!(codec.decode(&encoded).unwrap(), desc_str);
+        assert_eq!(
+            codec.decode_to_descriptor(&encoded).unwrap().0.to_string(),
+            desc_str
+        );
+        assert_eq!(decode(&encoded), Err(Error::UnknownPreset(1)));
+
+        // Descriptors of other shapes are encoded in full
+        let desc_str =
+            "wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)#8zl0zxma";
+        assert_eq!(codec.encode(desc_str).unwrap(), encode(desc_str).unwrap());
+    }
+
+    #[test]
+    fn test_payload_commitment() {
+        let desc_str =
+            "wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)#8zl0zxma";
+        let other_str = "wpkh(02e493dbf1c10d80f3581e4904930b1404cc6c13900ee0758474fa94abe8c4cd13)";
+        let codec = Codec::new().with_payload_commitment(true);
+
+        let encoded = codec.encode(desc_str).unwrap();
+        assert_eq!(encoded.len(), encode(desc_str).unwrap().len() + 5);
+        assert_eq!(codec.decode(&encoded).unwrap(), desc_str);
+        assert_eq!(decode(&encoded).unwrap(), desc_str);
+
+        let (_, _, size) = decoder::decode_template(&encoded).unwrap();
+        let (template, payload) = encoded.split_at(size);
+        assert_eq!(verify_payload(template, payload), Ok(()));
+
+        // Payload of a different descriptor with the same shape
+        let other = codec.encode(other_str).unwrap();
+        let other_payload = &other[size..];
+        assert_eq!(
+            verify_payload(template, other_payload),
+            Err(Error::PayloadMismatch)
+        );
+        assert_eq!(
+            decode(&[template, other_payload].concat()),
+            Err(Error::PayloadMismatch)
+        );
+
+        let uncommitted = encode(desc_str).unwrap();
+        assert_eq!(
+            verify_payload(&uncommitted[..size - 5], payload),
+            Err(Error::MissingCommitment)
+        );
+
+        // Commitments wrap preset IDs
+        let mut presets = PresetRegistry::new();
+        presets.define(1, other_str).unwrap();
+        let codec = codec.with_presets(presets);
+        let encoded = codec.encode(desc_str).unwrap();
+        assert_eq!(encoded.len(), 5 + 2 + 33);
+        assert_eq!(codec.decode(&encoded).unwrap(), desc_str);
+    }
+
+    #[test]
+    fn test_sorted_multi() {
+        let pk1 = "03a0434d9e47f3c86235477c7b1ae6ae5d3442d49b1943c2b752a68e2a47e247c7";
+        let pk2 = "036d2b085e9e382ed10b69fc311a03f8641ccfff21574de0927513a49d9a688a00";
+        let codec = Codec::new().with_sorted_multi(true);
+
+        for (outer, inner) in [("wsh(", ")"), ("sh(", ")"), ("sh(wsh(", "))")] {
+            let forward = format!("{outer}multi(1,{pk1},{pk2}){inner}");
+            let reverse = format!("{outer}multi(1,{pk2},{pk1}){inner}");
+            let encoded = codec.encode(&forward).unwrap();
+            assert_eq!(encoded, codec.encode(&reverse).unwrap());
+            assert_ne!(encoded, encode(&forward).unwrap());
+
+            let decoded = codec.decode(&encoded).unwrap();
+            assert!(decoded.starts_with(&format!("{outer}sortedmulti(1,{pk2},{pk1}){inner}")));
+            assert!(inspect(&encoded).unwrap().sorted_multi);
+            assert!(!inspect(&encode(&forward).unwrap()).unwrap().sorted_multi);
+        }
+
+        // Other descriptors are unchanged
+        let desc_str = format!("wsh(or_d(multi(1,{pk1},{pk2}),pk({pk1})))");
+        assert_eq!(codec.encode(&desc_str).unwrap(), encode(&desc_str).unwrap());
+    }
+
+    #[test]
+    fn test_lenient_parse() {
+        let desc_str =
+            "wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)#8zl0zxma";
+        let pasted = " wpkh(02f9308a019258c31049344f8\r\n5f89d5229b531c845836f99b08601f113bce036f9)\u{200B}#8zl0\u{FEFF}zxma\n";
+
+        assert!(encode(pasted).is_err());
+        let codec = Codec::new().with_lenient_parse(true);
+        assert_eq!(codec.encode(pasted).unwrap(), encode(desc_str).unwrap());
+    }
+
+    #[test]
+    fn test_path_numbers() {
+        let xpub = "xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V";
+        let canonical = format!("wpkh([73c5da0a/84'/0'/0']{xpub}/<0;1>/7)");
+        let desc_str = format!("wpkh([73c5da0a/084'/0'/0']{xpub}/<00;1>/+7)");
+        let sum = checksum(&desc_str).unwrap();
+        let desc_str = format!("{desc_str}#{sum}");
+
+        // Canonicalized by default, with a checksum over the canonical string
+        let encoded = encode(&desc_str).unwrap();
+        assert_eq!(encoded, encode(&canonical).unwrap());
+        assert_eq!(
+            decode(&encoded).unwrap(),
+            format!("{canonical}#{}", checksum(&canonical).unwrap())
+        );
+
+        let codec = Codec::new().with_path_numbers(PathNumbers::Preserve);
+        let encoded = codec.encode(&desc_str).unwrap();
+        let dual = codec.decode_dual(&encoded).unwrap();
+        assert_eq!(dual.original, desc_str);
+        assert_eq!(
+            dual.canonical,
+            decode(&encode(&canonical).unwrap()).unwrap()
+        );
+        // Canonical numbers are not recorded
+        assert_eq!(
+            codec.encode(&canonical).unwrap(),
+            encode(&canonical).unwrap()
+        );
+
+        let codec = Codec::new().with_path_numbers(PathNumbers::Reject);
+        assert_eq!(
+            codec.encode(&desc_str),
+            Err(encoder::Error::NonCanonicalNumber("084".to_string()))
+        );
+        assert_eq!(
+            codec.encode(&format!("wpkh({xpub}/1/+7)")),
+            Err(encoder::Error::NonCanonicalNumber("+7".to_string()))
+        );
+        assert!(codec.encode(&canonical).is_ok());
+        assert!(codec.encode(&format!("wpkh({xpub}/10/0/*)")).is_ok());
+    }
+
+    #[test]
+    fn test_strict_keys() {
+        let codec = Codec::new().with_strict_keys(true);
+        let x_only = "c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+        let full = "02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9";
+        let wif = "L4rK1yDtCWekvXuE6oXD9jCYfFNV2cWRpVuPLBcCU2z8TrisoyY1";
+        for desc_str in [
+            format!("tr({x_only})"),
+            format!("tr({full})"),
+            format!("tr({x_only},{{pk({full}),multi_a(1,{x_only},{wif})}})"),
+            format!("tr({wif},pk({x_only}))"),
+        ] {
+            let encoded = codec.encode(&desc_str).unwrap();
+            assert_eq!(encoded, encode(&desc_str).unwrap());
+        }
+
+        /// Decodes every x-only key as the same key
+        #[derive(Debug)]
+        struct FixedXOnly;
+
+        impl KeyCodec for FixedXOnly {
+            fn decode_x_only(
+                &self,
+                _payload: &[u8],
+                payload_index: &mut usize,
+            ) -> Result<XOnlyPublicKey, Error> {
+                *payload_index += 32;
+                Ok(XOnlyPublicKey::from_str(
+                    "f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9",
+                )
+                .unwrap())
+            }
+        }
+
+        let codec = codec.with_key_codec(Arc::new(FixedXOnly));
+        let desc_str = format!("tr({full},{{pk({full}),pk({x_only})}})");
+        assert_eq!(codec.encode(&desc_str), Err(encoder::Error::KeyMismatch(1)));
+        assert!(
+            Codec::new()
+                .with_key_codec(Arc::new(FixedXOnly))
+                .encode(&desc_str)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_decode_to_descriptor() {
+        let mut secp = Secp256k1::new();
+        secp.seeded_randomize(&[7; 32]);
+        let codec = Codec::default().with_secp(secp);
+        let xprv = "xprv9s21ZrQH143K31xYSDQpPDxsXRTUcvj2iNHm5NUtrGiGG5e2DtALGdso3pGz6ssrdK4PFmM8NSpSBHNqPqm55Qn3LqFtT2emdEXVYsCzC2U";
+        let desc_str = format!("wsh(multi(1,{xprv}/0,{xprv}/<0;1>/*))");
+        let encoded = codec.encode(&desc_str).unwrap();
+
+        let (descriptor, key_map) = codec.decode_to_descriptor(&encoded).unwrap();
+        assert_eq!(key_map.len(), 2);
+        for (pk, sk) in key_map.iter() {
+            match sk {
+                // Multi-xprivs have no single public key, so the placeholder is kept
+                DescriptorSecretKey::MultiXPrv(_) => assert!(sk.to_public(&codec.secp).is_err()),
+                _ => assert_eq!(pk, &sk.to_public(&codec.secp).unwrap()),
+            }
+        }
+        assert_eq!(
+            descriptor.to_string_with_secret(&key_map),
+            codec.decode(&encoded).unwrap()
+        );
+
+        let (_, _, size) = decoder::decode_template(&encoded).unwrap();
+        let (decoded, _) =
+            decoder::decode_with_payload(&encoded[..size], &encoded[size..]).unwrap();
+        assert_eq!(decoded, descriptor);
+    }
+}
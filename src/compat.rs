@@ -0,0 +1,250 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! # Compatibility Checks
+//!
+//! Compares a stored encoding against the descriptor an app expects it to hold, such as its live
+//! wallet configuration, so drift after a library upgrade is caught before the encoding is relied
+//! on. A mismatch lists how the two differ without revealing any secret keys.
+
+use super::*;
+use std::{error, fmt};
+
+/// A way in which a decoded descriptor differs from the expected one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Difference {
+    /// Structure, key types, or derivation paths differ, with the template hash of each
+    Template {
+        /// Template hash of the decoded descriptor
+        decoded: sha256::Hash,
+        /// Template hash of the expected descriptor
+        expected: sha256::Hash,
+    },
+    /// Number of keys differs
+    KeyCount {
+        /// Number of keys in the decoded descriptor
+        decoded: usize,
+        /// Number of keys in the expected descriptor
+        expected: usize,
+    },
+    /// Public key at an index differs
+    Key {
+        /// Index of the key, in order of appearance
+        index: usize,
+        /// Public key in the decoded descriptor
+        decoded: String,
+        /// Public key in the expected descriptor
+        expected: String,
+    },
+    /// Only one of the descriptors holds secret keys
+    Secrets {
+        /// Whether the decoded descriptor holds secret keys
+        decoded: bool,
+    },
+    /// A hash, timelock, or other value differs
+    Other,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Template { decoded, expected } => {
+                write!(f, "template {decoded} differs from {expected}")
+            }
+            Self::KeyCount { decoded, expected } => {
+                write!(f, "{decoded} keys instead of {expected}")
+            }
+            Self::Key {
+                index,
+                decoded,
+                expected,
+            } => write!(f, "key {index} is {decoded} instead of {expected}"),
+            Self::Secrets { decoded: true } => write!(f, "holds secret keys that are not expected"),
+            Self::Secrets { decoded: false } => write!(f, "lacks expected secret keys"),
+            Self::Other => write!(f, "a hash, timelock, or other value differs"),
+        }
+    }
+}
+
+/// Error checking an encoding against an expected descriptor
+#[derive(Debug, PartialEq)]
+pub enum CompatError {
+    /// Encoding cannot be decoded
+    Decode(Error),
+    /// Expected descriptor cannot be encoded
+    InvalidExpected(encoder::Error),
+    /// Decoded descriptor differs from the expected one, in every way found
+    Mismatch(Vec<Difference>),
+}
+
+impl fmt::Display for CompatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Decode(_) => write!(f, "encoding cannot be decoded"),
+            Self::InvalidExpected(_) => write!(f, "invalid expected descriptor"),
+            Self::Mismatch(differences) => {
+                write!(f, "descriptor does not match")?;
+                for (i, difference) in differences.iter().enumerate() {
+                    let sep = if i == 0 { ": " } else { "; " };
+                    write!(f, "{sep}{difference}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl error::Error for CompatError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Decode(err) => Some(err),
+            Self::InvalidExpected(err) => Some(err),
+            Self::Mismatch(_) => None,
+        }
+    }
+}
+
+/// Checks that an encoding decodes to the expected descriptor
+///
+/// Both are compared in canonical form, so hardened markers and a missing checksum in
+/// `expected` do not matter. Metadata, payload commitments, and presets in `bytes` are ignored.
+/// Fails with [`CompatError::Mismatch`] listing every difference found.
+pub fn verify_compat(bytes: &[u8], expected: &str) -> Result<(), CompatError> {
+    let decoded = decode(bytes).map_err(CompatError::Decode)?;
+    let expected_bytes = encode(expected).map_err(CompatError::InvalidExpected)?;
+    if decoded == decode(&expected_bytes).map_err(CompatError::Decode)? {
+        return Ok(());
+    }
+
+    let mut differences = Vec::new();
+
+    // Encoding the decoded string again drops any container options from the template
+    if let Ok(decoded_bytes) = encode(&decoded) {
+        let (decoded, expected) = (
+            structure_hash(&decoded_bytes),
+            structure_hash(&expected_bytes),
+        );
+        if let (Ok(decoded), Ok(expected)) = (decoded, expected) {
+            if decoded != expected {
+                differences.push(Difference::Template { decoded, expected });
+            }
+        }
+    }
+
+    let decoded = inspect(bytes).map_err(CompatError::Decode)?;
+    let expected = inspect(&expected_bytes).map_err(CompatError::Decode)?;
+    if decoded.keys.len() != expected.keys.len() {
+        differences.push(Difference::KeyCount {
+            decoded: decoded.keys.len(),
+            expected: expected.keys.len(),
+        });
+    }
+    for (index, (decoded, expected)) in decoded.keys.iter().zip(&expected.keys).enumerate() {
+        if decoded != expected {
+            differences.push(Difference::Key {
+                index,
+                decoded: decoded.to_string(),
+                expected: expected.to_string(),
+            });
+        }
+    }
+    if decoded.has_secrets != expected.has_secrets {
+        differences.push(Difference::Secrets {
+            decoded: decoded.has_secrets,
+        });
+    }
+
+    if differences.is_empty() {
+        differences.push(Difference::Other);
+    }
+    Err(CompatError::Mismatch(differences))
+}
+
+/// Returns the SHA256 hash of the template bytes alone, which leave out hashes and timelocks
+fn structure_hash(bytes: &[u8]) -> Result<sha256::Hash, Error> {
+    let size = decoder::template_size(bytes, &decoder::DecodeContext::default())?;
+    Ok(sha256::Hash::hash(&bytes[..size]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_compat() {
+        let key_a = "03a0434d9e47f3c86235477c7b1ae6ae5d3442d49b1943c2b752a68e2a47e247c7";
+        let key_b = "036d2b085e9e382ed10b69fc311a03f8641ccfff21574de0927513a49d9a688a00";
+        let key_c = "02e8445082a72f29b75ca48748a914df60622a609cacfce8ed0e35804560741d29";
+        let desc_str = format!("wsh(multi(1,{key_a},{key_b}))");
+        let encoded = encode_with_metadata(
+            &desc_str,
+            &[Tlv {
+                kind: 2,
+                value: vec![1],
+            }],
+        )
+        .unwrap();
+
+        // A missing checksum does not matter, but a wrong one does
+        assert_eq!(verify_compat(&encoded, &desc_str), Ok(()));
+        assert_eq!(
+            verify_compat(&encoded, &format!("{desc_str}#rd2pu9rk")),
+            Ok(())
+        );
+        assert!(matches!(
+            verify_compat(&encoded, &format!("{desc_str}#aaaaaaaa")),
+            Err(CompatError::InvalidExpected(_))
+        ));
+
+        let err = verify_compat(&encoded, &format!("wsh(multi(1,{key_a},{key_c}))")).unwrap_err();
+        assert_eq!(
+            err,
+            CompatError::Mismatch(vec![Difference::Key {
+                index: 1,
+                decoded: key_b.to_string(),
+                expected: key_c.to_string(),
+            }])
+        );
+        assert!(
+            err.to_string()
+                .starts_with("descriptor does not match: key 1 is")
+        );
+
+        let err = verify_compat(&encoded, &format!("wsh(multi(1,{key_a}))")).unwrap_err();
+        let CompatError::Mismatch(differences) = err else {
+            panic!("expected a mismatch");
+        };
+        assert!(matches!(differences[0], Difference::Template { .. }));
+        assert_eq!(
+            differences[1],
+            Difference::KeyCount {
+                decoded: 2,
+                expected: 1
+            }
+        );
+
+        assert_eq!(
+            verify_compat(
+                &encode(&format!("wsh(and_v(v:pk({key_a}),older(144)))")).unwrap(),
+                &format!("wsh(and_v(v:pk({key_a}),older(145)))")
+            ),
+            Err(CompatError::Mismatch(vec![Difference::Other]))
+        );
+
+        // Secret keys are compared by their public keys, and never reported
+        let wif = "L4rK1yDtCWekvXuE6oXD9jCYfFNV2cWRpVuPLBcCU2z8TrisoyY1";
+        let encoded = encode(&format!("wpkh({wif})")).unwrap();
+        let public = inspect(&encoded).unwrap().keys[0].to_string();
+        let err = verify_compat(&encoded, &format!("wpkh({public})")).unwrap_err();
+        assert!(matches!(
+            err,
+            CompatError::Mismatch(ref differences)
+                if differences.contains(&Difference::Secrets { decoded: true })
+        ));
+        assert!(!err.to_string().contains(wif));
+
+        assert!(matches!(
+            verify_compat(&[0xFF], &desc_str),
+            Err(CompatError::Decode(_))
+        ));
+    }
+}
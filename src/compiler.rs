@@ -0,0 +1,167 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! # Policy Compilation
+//!
+//! Compiles a spending policy such as `or(pk(A),and(pk(B),older(144)))` to miniscript and encodes
+//! the resulting descriptor, going from spending intent to a compact backup in one step. Policies
+//! use the concrete policy language of miniscript, with public descriptor keys.
+
+use super::*;
+use miniscript::Segwitv0;
+use miniscript::policy::Concrete;
+use miniscript::policy::concrete::DescriptorCtx;
+
+/// Unspendable BIP341 key used as the taproot internal key when none can be taken from a policy
+const NUMS_KEY: &str = "50929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0";
+
+/// Descriptor a policy is compiled into
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CompileContext {
+    /// Segwit v0 script, `wsh(...)`
+    #[default]
+    Wsh,
+    /// Taproot, `tr(...)`, spending by key path with the most likely key
+    Tr,
+}
+
+/// Compiles a policy into a descriptor
+///
+/// For [`CompileContext::Tr`], the most likely key of a top-level `or` becomes the internal key,
+/// and the unspendable BIP341 key is used if there is none. Fails with
+/// [`InvalidDescriptor`](encoder::Error::InvalidDescriptor) if the policy cannot be parsed or
+/// compiled.
+pub fn compile_policy(policy: &str, context: CompileContext) -> Result<String, encoder::Error> {
+    let policy = Concrete::<DescriptorPublicKey>::from_str(policy)?;
+    Ok(compile(&policy, context)?.to_string())
+}
+
+/// Compiles a parsed policy into a descriptor
+fn compile(
+    policy: &Concrete<DescriptorPublicKey>,
+    context: CompileContext,
+) -> Result<Descriptor<DescriptorPublicKey>, encoder::Error> {
+    match context {
+        CompileContext::Wsh => Ok(policy.compile_to_descriptor::<Segwitv0>(DescriptorCtx::Wsh)?),
+        CompileContext::Tr => {
+            let nums = DescriptorPublicKey::from_str(NUMS_KEY)?;
+            Ok(policy.compile_tr(Some(nums))?)
+        }
+    }
+}
+
+/// Compiles a policy into a descriptor and encodes it
+///
+/// Returns the compiled descriptor along with its encoding. See [`compile_policy`].
+pub fn encode_policy(
+    policy: &str,
+    context: CompileContext,
+) -> Result<(String, Vec<u8>), encoder::Error> {
+    let descriptor = compile_policy(policy, context)?;
+    let encoded = encode(&descriptor)?;
+    Ok((descriptor, encoded))
+}
+
+/// A policy compiled into one context, with its costs, as returned by [`compare_policy`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Compilation {
+    /// Context the policy was compiled into
+    pub context: CompileContext,
+    /// Compiled descriptor
+    pub descriptor: String,
+    /// Encoded descriptor
+    pub encoded: Vec<u8>,
+    /// Largest witness weight of a spend, in weight units, assuming 73-byte ECDSA and 66-byte
+    /// Schnorr signatures
+    pub max_spend_weight: u64,
+}
+
+/// Compiles a policy into every context, so the cheaper backup and spending combination can be
+/// picked
+///
+/// Returns a `wsh` and a `tr` [`Compilation`], in that order. Fails if either cannot be compiled.
+pub fn compare_policy(policy: &str) -> Result<Vec<Compilation>, encoder::Error> {
+    let policy = Concrete::<DescriptorPublicKey>::from_str(policy)?;
+    [CompileContext::Wsh, CompileContext::Tr]
+        .into_iter()
+        .map(|context| {
+            let descriptor = compile(&policy, context)?;
+            Ok(Compilation {
+                context,
+                max_spend_weight: descriptor.max_weight_to_satisfy()?.to_wu(),
+                encoded: encode_parsed(&descriptor, &KeyMap::new())?,
+                descriptor: descriptor.to_string(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_policy() {
+        let a = "03a0434d9e47f3c86235477c7b1ae6ae5d3442d49b1943c2b752a68e2a47e247c7";
+        let b = "036d2b085e9e382ed10b69fc311a03f8641ccfff21574de0927513a49d9a688a00";
+        let policy = format!("or(99@pk({a}),1@and(pk({b}),older(144)))");
+
+        let (descriptor, encoded) = encode_policy(&policy, CompileContext::Wsh).unwrap();
+        assert!(descriptor.starts_with("wsh("));
+        assert_eq!(decode(&encoded).unwrap(), descriptor);
+
+        // The likely key spends by key path
+        let (descriptor, encoded) = encode_policy(&policy, CompileContext::Tr).unwrap();
+        assert!(descriptor.starts_with(&format!("tr({a},")));
+        assert_eq!(decode(&encoded).unwrap(), descriptor);
+
+        // Without a key to take, the internal key is unspendable
+        let descriptor =
+            compile_policy(&format!("and(pk({a}),pk({b}))"), CompileContext::Tr).unwrap();
+        assert!(descriptor.starts_with(&format!("tr({NUMS_KEY},")));
+
+        assert!(matches!(
+            compile_policy("or(pk(notakey),older(144))", CompileContext::Wsh),
+            Err(encoder::Error::InvalidDescriptor(_))
+        ));
+        // Compiles to a malleable script
+        assert!(matches!(
+            compile_policy("older(144)", CompileContext::Wsh),
+            Err(encoder::Error::InvalidDescriptor(_))
+        ));
+    }
+    #[test]
+    fn test_compare_policy() {
+        let keys = [
+            "03a0434d9e47f3c86235477c7b1ae6ae5d3442d49b1943c2b752a68e2a47e247c7",
+            "036d2b085e9e382ed10b69fc311a03f8641ccfff21574de0927513a49d9a688a00",
+            "02e8445082a72f29b75ca48748a914df60622a609cacfce8ed0e35804560741d29",
+        ];
+        let policy = format!(
+            "or(9@pk({}),1@and(thresh(2,pk({}),pk({})),older(144)))",
+            keys[0], keys[1], keys[2]
+        );
+
+        let compilations = compare_policy(&policy).unwrap();
+        let [wsh, tr] = &compilations[..] else {
+            panic!("expected two compilations");
+        };
+        assert_eq!(wsh.context, CompileContext::Wsh);
+        assert_eq!(tr.context, CompileContext::Tr);
+        for compilation in &compilations {
+            assert_eq!(
+                compilation.descriptor,
+                compile_policy(&policy, compilation.context).unwrap()
+            );
+            assert_eq!(
+                compilation.encoded,
+                encode(&compilation.descriptor).unwrap()
+            );
+        }
+
+        // Even its worst case, a script path spend, is cheaper with taproot
+        assert_eq!((wsh.max_spend_weight, tr.max_spend_weight), (308, 239));
+        assert_eq!((wsh.encoded.len(), tr.encoded.len()), (120, 118));
+
+        assert!(compare_policy("pk(notakey)").is_err());
+    }
+}
@@ -0,0 +1,155 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! # Decoded Descriptor
+
+use super::*;
+use secret::REDACTED;
+use std::fmt;
+
+/// A decoded descriptor that redacts its secret keys when formatted
+///
+/// `{}` shows each secret key as [`REDACTED`] and omits the checksum, which would not match the
+/// redacted string. `{:#}` shows the descriptor in full, with its secret keys and checksum, as
+/// does wrapping it in [`RevealSecrets`].
+#[derive(Clone, PartialEq, Eq)]
+pub struct DecodedDescriptor {
+    descriptor: Descriptor<DescriptorPublicKey>,
+    key_map: KeyMap,
+}
+
+impl DecodedDescriptor {
+    /// Returns the descriptor, with the public keys of any secret keys
+    pub fn descriptor(&self) -> &Descriptor<DescriptorPublicKey> {
+        &self.descriptor
+    }
+
+    /// Returns the map from public keys to secret keys
+    pub fn key_map(&self) -> &KeyMap {
+        &self.key_map
+    }
+
+    /// Returns whether the descriptor holds secret keys
+    pub fn has_secrets(&self) -> bool {
+        !self.key_map.is_empty()
+    }
+
+    /// Returns the descriptor and its map from public keys to secret keys
+    pub fn into_parts(self) -> (Descriptor<DescriptorPublicKey>, KeyMap) {
+        (self.descriptor, self.key_map)
+    }
+
+    /// Returns the descriptor with each secret key replaced by [`REDACTED`]
+    fn redacted(&self) -> Descriptor<String> {
+        struct Redactor<'a>(&'a KeyMap);
+
+        impl Translator<DescriptorPublicKey, String, ()> for Redactor<'_> {
+            fn pk(&mut self, pk: &DescriptorPublicKey) -> Result<String, ()> {
+                match self.0.contains_key(pk) {
+                    true => Ok(REDACTED.to_string()),
+                    false => Ok(pk.to_string()),
+                }
+            }
+
+            fn sha256(&mut self, hash: &sha256::Hash) -> Result<String, ()> {
+                Ok(hash.to_string())
+            }
+
+            fn hash256(&mut self, hash: &hash256::Hash) -> Result<String, ()> {
+                Ok(hash.to_string())
+            }
+
+            fn ripemd160(&mut self, hash: &ripemd160::Hash) -> Result<String, ()> {
+                Ok(hash.to_string())
+            }
+
+            fn hash160(&mut self, hash: &hash160::Hash) -> Result<String, ()> {
+                Ok(hash.to_string())
+            }
+        }
+
+        self.descriptor
+            .translate_pk(&mut Redactor(&self.key_map))
+            .expect("translating to strings cannot fail")
+    }
+}
+
+impl fmt::Display for DecodedDescriptor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if f.alternate() {
+            write!(f, "{}", RevealSecrets(self))
+        } else {
+            write!(f, "{:#}", self.redacted())
+        }
+    }
+}
+
+impl fmt::Display for RevealSecrets<'_, DecodedDescriptor> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0.descriptor.to_string_with_secret(&self.0.key_map))
+    }
+}
+
+impl fmt::Debug for DecodedDescriptor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("DecodedDescriptor")
+            .field(&format_args!("{self}"))
+            .finish()
+    }
+}
+
+impl From<(Descriptor<DescriptorPublicKey>, KeyMap)> for DecodedDescriptor {
+    fn from((descriptor, key_map): (Descriptor<DescriptorPublicKey>, KeyMap)) -> Self {
+        Self {
+            descriptor,
+            key_map,
+        }
+    }
+}
+
+impl TryFrom<&[u8]> for DecodedDescriptor {
+    type Error = Error;
+
+    /// Decodes a Bitcoin descriptor, deriving the public keys of any secret keys
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        let (descriptor, key_map) =
+            decoder::decode_bytes_underived(bytes, decoder::DecodeContext::default())?;
+        let secp = secp256k1::Secp256k1::signing_only();
+        Ok(decoder::derive_public_keys(&descriptor, &key_map, &secp)?.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let wif = "L4rK1yDtCWekvXuE6oXD9jCYfFNV2cWRpVuPLBcCU2z8TrisoyY1";
+        let desc_str = format!(
+            "wsh(and_v(v:pk({wif}),sha256(e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855)))"
+        );
+        let encoded = encode(&desc_str).unwrap();
+        let decoded = DecodedDescriptor::try_from(encoded.as_slice()).unwrap();
+        assert!(decoded.has_secrets());
+
+        assert_eq!(
+            decoded.to_string(),
+            "wsh(and_v(v:pk(<redacted>),sha256(e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855)))"
+        );
+        assert!(!format!("{decoded:?}").contains(wif));
+        assert_eq!(format!("{decoded:#}"), decode(&encoded).unwrap());
+        assert_eq!(
+            RevealSecrets(&decoded).to_string(),
+            decode(&encoded).unwrap()
+        );
+
+        // Public descriptors are shown in full, apart from the checksum
+        let desc_str =
+            "wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)#8zl0zxma";
+        let decoded = DecodedDescriptor::try_from(encode(desc_str).unwrap().as_slice()).unwrap();
+        assert!(!decoded.has_secrets());
+        assert_eq!(format!("{decoded:#}"), desc_str);
+        assert_eq!(decoded.to_string(), desc_str.split('#').next().unwrap());
+        assert_eq!(decoded.descriptor().to_string(), desc_str);
+    }
+}
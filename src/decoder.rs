@@ -2,18 +2,29 @@
 
 //! # Descriptor Decode
 
-use super::{tag::Tag, test_helpers, varint, *};
+use super::{
+    backup::BackupError,
+    extension::{CUSTOM_TAGS, TagExtension},
+    kdf::KdfError,
+    key_codec::{DefaultKeyCodec, KeyCodec},
+    metadata::{self, KeyRotation, Tlv},
+    qr::QrError,
+    tag::Tag,
+    varint, *,
+};
 use bitcoin::{
-    NetworkKind, PrivateKey, PublicKey, XOnlyPublicKey,
-    bip32::{ChildNumber, DerivationPath, Fingerprint, Xpriv, Xpub},
+    NetworkKind, PrivateKey, PublicKey, XOnlyPublicKey, base58,
+    bip32::{self, ChainCode, ChildNumber, DerivationPath, Fingerprint, Xpriv, Xpub},
     hashes::{
         Hash, hash160::Hash as Hash160, ripemd160::Hash as Ripemd160, sha256::Hash as Sha256,
         sha256d,
     },
+    key::FromSliceError,
     secp256k1::{Secp256k1, SecretKey, constants::SECRET_KEY_SIZE},
 };
 use miniscript::{
-    AbsLockTime, BareCtx, Legacy, Miniscript, RelLockTime, ScriptContext, Segwitv0, Tap, Threshold,
+    AbsLockTime, AbsLockTimeError, BareCtx, ForEachKey, Legacy, Miniscript, RelLockTime,
+    RelLockTimeError, ScriptContext, Segwitv0, Tap, Threshold,
     descriptor::{
         Bare, DerivPaths, Descriptor, DescriptorMultiXKey, DescriptorPublicKey,
         DescriptorSecretKey, DescriptorXKey, KeyMap, Pkh, Sh, SinglePriv, SinglePub, SinglePubKey,
@@ -23,12 +34,28 @@ use miniscript::{
     miniscript::decode::Terminal,
 };
 
+use std::convert::Infallible;
 use std::error;
 use std::fmt;
 use std::sync::Arc;
 
+/// Default maximum number of bytes a decoded descriptor string may occupy per input byte
+///
+/// Legitimate encodings expand by less than 10x, as every key, hash, and timelock is stored in
+/// full in the payload.
+pub const DEFAULT_ALLOCATION_FACTOR: usize = 32;
+
+/// Default maximum number of steps in a derivation path
+///
+/// Matches the largest depth an extended key can record.
+pub const DEFAULT_MAX_PATH_LEN: usize = 255;
+
 /// Error
+///
+/// Backup, QR scanning, and key derivation failures are grouped in their own error types, each
+/// wrapped by a single variant.
 #[derive(Debug, PartialEq)]
+#[non_exhaustive]
 pub enum Error {
     /// Input is missing bytes
     MissingBytes,
@@ -37,15 +64,65 @@ pub enum Error {
     /// Invalid tag
     InvalidTag(usize),
     /// Invalid miniscript
-    InvalidMiniscript(usize, String),
+    InvalidMiniscript(usize, miniscript::Error),
     /// Invalid var int
-    InvalidVarInt(usize, String),
+    InvalidVarInt(usize, varint::Error),
     /// Missing derivation paths
     MissingDerivPaths(usize),
     /// Invalid payload
-    InvalidPayload(usize, String),
+    InvalidPayload(usize, PayloadError),
+    /// Timelock is outside its consensus-valid range, by payload index
+    InvalidLocktime(usize, PayloadError),
     /// Payload too large
     PayloadTooLarge(usize, usize),
+    /// Decoded descriptor exceeds the allocation budget
+    Oversized,
+    /// Preset ID is not in the registry
+    UnknownPreset(u64),
+    /// Template does not match the expected template hash
+    TemplateMismatch,
+    /// Payload does not match the template's payload commitment
+    PayloadMismatch,
+    /// Template has no payload commitment
+    MissingCommitment,
+    /// Invalid base58check string
+    InvalidBase58(base58::Error),
+    /// Invalid digit or checksum on a line of grouped hex
+    InvalidHex(usize),
+    /// Invalid base32 character, by character index
+    InvalidBase32(usize),
+    /// Invalid line in a key mapping
+    InvalidMapping(usize),
+    /// Key is not in the key mapping
+    UnmappedKey,
+    /// Metadata record of odd type is not understood
+    UnknownRequiredTlv(u64),
+    /// Metadata record of known type is malformed
+    InvalidTlv(u64),
+    /// Bundle key annotation is malformed or refers to a missing entry
+    InvalidAnnotation(usize),
+    /// Secret key cannot be converted to a watch-only key
+    UnderivableKey,
+    /// Scanned QR part cannot be read or does not fit earlier parts
+    Qr(QrError),
+    /// Backup cannot be read, decrypted, or unlocked
+    Backup(BackupError),
+    /// Key derivation parameters cannot be used
+    Kdf(KdfError),
+    /// Multipath key's derivation paths differ in other than a single step, by key index
+    InvalidMultipath(usize),
+    /// Descriptor is a `rawtr()`, which miniscript cannot represent, so only decodes to a string
+    RawTr,
+    /// Derivation path has more steps than allowed, by index
+    PathTooLong(usize),
+    /// Descriptor has more keys than allowed, by template index of the first key over the limit
+    TooManyKeys(usize),
+    /// Hash reference does not refer to an earlier hash, by template index
+    InvalidHashRef(usize),
+    /// Bundle manifest is malformed or does not match an entry, by index
+    InvalidManifest(usize),
+    /// Operation was stopped through its [`CancelToken`](crate::CancelToken)
+    Cancelled,
 }
 
 impl fmt::Display for Error {
@@ -54,27 +131,149 @@ impl fmt::Display for Error {
             Self::MissingBytes => write!(f, "missing bytes"),
             Self::UnrecognizedTag(idx) => write!(f, "unrecognized tag (index: {idx})"),
             Self::InvalidTag(idx) => write!(f, "invalid tag (index: {idx})"),
-            Self::InvalidMiniscript(idx, err) => {
-                write!(f, "invalid miniscript (index: {idx}, error: {err})")
-            }
-            Self::InvalidVarInt(idx, err) => {
-                write!(f, "invalid varint (index: {idx}, error: {err})")
-            }
+            Self::InvalidMiniscript(idx, _) => write!(f, "invalid miniscript (index: {idx})"),
+            Self::InvalidVarInt(idx, _) => write!(f, "invalid varint (index: {idx})"),
             Self::MissingDerivPaths(idx) => write!(f, "missing derivation paths (index: {idx})"),
-            Self::InvalidPayload(idx, err) => {
-                write!(f, "invalid payload (payload index: {idx}, error: {err})")
-            }
+            Self::InvalidPayload(idx, _) => write!(f, "invalid payload (payload index: {idx})"),
+            Self::InvalidLocktime(idx, _) => write!(f, "invalid locktime (payload index: {idx})"),
             Self::PayloadTooLarge(expected, actual) => {
                 write!(
                     f,
                     "payload too large (expected {expected} bytes, found {actual} bytes)"
                 )
             }
+            Self::Oversized => write!(f, "decoded descriptor exceeds allocation budget"),
+            Self::UnknownPreset(id) => write!(f, "unknown preset (id: {id})"),
+            Self::TemplateMismatch => write!(f, "template does not match expected template"),
+            Self::PayloadMismatch => write!(f, "payload does not match commitment"),
+            Self::MissingCommitment => write!(f, "template has no payload commitment"),
+            Self::InvalidBase58(_) => write!(f, "invalid base58check string"),
+            Self::InvalidHex(line) => write!(f, "invalid grouped hex (line: {line})"),
+            Self::InvalidBase32(idx) => write!(f, "invalid base32 (character index: {idx})"),
+            Self::InvalidMapping(line) => write!(f, "invalid key mapping (line: {line})"),
+            Self::UnmappedKey => write!(f, "key is not in key mapping"),
+            Self::UnknownRequiredTlv(kind) => write!(f, "unknown required metadata (type: {kind})"),
+            Self::InvalidTlv(kind) => write!(f, "invalid metadata (type: {kind})"),
+            Self::InvalidAnnotation(idx) => write!(f, "invalid key annotation (index: {idx})"),
+            Self::UnderivableKey => write!(f, "secret key cannot be made watch-only"),
+            Self::Qr(err) => write!(f, "QR scan error: {err}"),
+            Self::Backup(err) => write!(f, "backup error: {err}"),
+            Self::Kdf(err) => write!(f, "key derivation error: {err}"),
+            Self::InvalidMultipath(idx) => write!(f, "invalid multipath key (key index: {idx})"),
+            Self::RawTr => write!(f, "rawtr descriptor only decodes to a string"),
+            Self::PathTooLong(idx) => write!(f, "derivation path too long (index: {idx})"),
+            Self::TooManyKeys(idx) => write!(f, "too many keys (index: {idx})"),
+            Self::InvalidHashRef(idx) => write!(f, "invalid hash reference (index: {idx})"),
+            Self::InvalidManifest(idx) => write!(f, "invalid bundle manifest (index: {idx})"),
+            Self::Cancelled => write!(f, "operation cancelled"),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::InvalidMiniscript(_, err) => Some(err),
+            Self::InvalidVarInt(_, err) => Some(err),
+            Self::InvalidPayload(_, err) => Some(err),
+            Self::InvalidLocktime(_, err) => Some(err),
+            Self::InvalidBase58(err) => Some(err),
+            Self::Qr(err) => Some(err),
+            Self::Backup(err) => Some(err),
+            Self::Kdf(err) => Some(err),
+            Self::MissingBytes
+            | Self::UnrecognizedTag(_)
+            | Self::InvalidTag(_)
+            | Self::MissingDerivPaths(_)
+            | Self::PayloadTooLarge(_, _)
+            | Self::Oversized
+            | Self::UnknownPreset(_)
+            | Self::TemplateMismatch
+            | Self::PayloadMismatch
+            | Self::MissingCommitment
+            | Self::InvalidHex(_)
+            | Self::InvalidBase32(_)
+            | Self::InvalidMapping(_)
+            | Self::UnmappedKey
+            | Self::UnknownRequiredTlv(_)
+            | Self::InvalidTlv(_)
+            | Self::InvalidAnnotation(_)
+            | Self::UnderivableKey
+            | Self::InvalidMultipath(_)
+            | Self::RawTr
+            | Self::PathTooLong(_)
+            | Self::TooManyKeys(_)
+            | Self::InvalidHashRef(_)
+            | Self::InvalidManifest(_)
+            | Self::Cancelled => None,
         }
     }
 }
 
-impl error::Error for Error {}
+impl From<QrError> for Error {
+    fn from(err: QrError) -> Self {
+        Self::Qr(err)
+    }
+}
+
+impl From<BackupError> for Error {
+    fn from(err: BackupError) -> Self {
+        Self::Backup(err)
+    }
+}
+
+impl From<KdfError> for Error {
+    fn from(err: KdfError) -> Self {
+        Self::Kdf(err)
+    }
+}
+
+/// Error encountered while reading a value from the payload
+#[derive(Debug, PartialEq)]
+pub enum PayloadError {
+    /// Invalid var int
+    VarInt(varint::Error),
+    /// Invalid secret key or x-only public key
+    Secp256k1(secp256k1::Error),
+    /// Invalid public key
+    PublicKey(FromSliceError),
+    /// Invalid extended key
+    Bip32(bip32::Error),
+    /// Invalid absolute timelock
+    AbsLockTime(AbsLockTimeError),
+    /// Invalid relative timelock
+    RelLockTime(RelLockTimeError),
+    /// Key reference not recognized by the key codec
+    UnknownKey,
+}
+
+impl fmt::Display for PayloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::VarInt(err) => write!(f, "invalid varint: {err}"),
+            Self::Secp256k1(err) => write!(f, "invalid key: {err}"),
+            Self::PublicKey(err) => write!(f, "invalid public key: {err}"),
+            Self::Bip32(err) => write!(f, "invalid extended key: {err}"),
+            Self::AbsLockTime(err) => write!(f, "invalid absolute timelock: {err}"),
+            Self::RelLockTime(err) => write!(f, "invalid relative timelock: {err}"),
+            Self::UnknownKey => write!(f, "unknown key reference"),
+        }
+    }
+}
+
+impl error::Error for PayloadError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::VarInt(err) => Some(err),
+            Self::Secp256k1(err) => Some(err),
+            Self::PublicKey(err) => Some(err),
+            Self::Bip32(err) => Some(err),
+            Self::AbsLockTime(err) => Some(err),
+            Self::RelLockTime(err) => Some(err),
+            Self::UnknownKey => None,
+        }
+    }
+}
 
 /// Returns a template descriptor with dummy keys, fingerprints, hashes, and timelocks and
 /// the number of decoded bytes.
@@ -82,8 +281,14 @@ pub fn decode_template(
     input: &[u8],
 ) -> Result<(Descriptor<DescriptorPublicKey>, KeyMap, usize), Error> {
     let mut index = 0;
-    let mut key_map = KeyMap::new();
-    let descriptor = Descriptor::from_template(input, &mut index, &[], &mut 0, &mut key_map)?;
+    let mut ctx = DecodeContext::default();
+    let descriptor = Descriptor::from_template(input, &mut index, &[], &mut 0, &mut ctx)?;
+    check_multipaths(&descriptor, &ctx.key_map)?;
+    if ctx.raw_tr {
+        return Err(Error::RawTr);
+    }
+    let (descriptor, key_map) =
+        derive_public_keys(&descriptor, &ctx.key_map, &Secp256k1::signing_only())?;
 
     Ok((descriptor, key_map, index))
 }
@@ -94,16 +299,325 @@ pub fn decode_with_payload(
     input: &[u8],
     payload: &[u8],
 ) -> Result<(Descriptor<DescriptorPublicKey>, KeyMap), Error> {
+    let mut ctx = DecodeContext::default();
+    let descriptor = decode_underived(input, payload, &mut ctx)?;
+    if ctx.raw_tr {
+        return Err(Error::RawTr);
+    }
+    derive_public_keys(&descriptor, &ctx.key_map, &Secp256k1::signing_only())
+}
+
+/// Default largest encoding a [`StreamingDecoder`] accepts, in bytes
+pub const DEFAULT_MAX_FRAME_LEN: usize = 65_536;
+
+/// Result of pushing bytes into a [`StreamingDecoder`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamStatus {
+    /// At least this many more bytes are needed to finish the current frame
+    NeedMore(usize),
+    /// A frame was received and decoded into a descriptor
+    Complete(String),
+}
+
+/// Decodes encodings pushed a chunk at a time, as they arrive over a serial or NFC link
+///
+/// An encoding does not record where it ends, so each is framed like a bundle entry: a
+/// variable-length length followed by the encoding, as returned by [`StreamingDecoder::frame`].
+/// Bytes past the end of a frame are kept for the next one, so frames may be sent back to back.
+///
+/// A frame that fails to decode is dropped. A malformed or oversized length drops every buffered
+/// byte, as the start of the next frame cannot be found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamingDecoder {
+    buffer: Vec<u8>,
+    max_len: usize,
+}
+
+impl Default for StreamingDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StreamingDecoder {
+    /// Creates a decoder accepting encodings of up to [`DEFAULT_MAX_FRAME_LEN`] bytes
+    pub fn new() -> Self {
+        Self::with_max_len(DEFAULT_MAX_FRAME_LEN)
+    }
+
+    /// Creates a decoder accepting encodings of up to `max_len` bytes
+    ///
+    /// Longer frames fail with [`Error::Oversized`] as soon as their length is read, so a
+    /// hostile sender cannot make the decoder buffer without bound.
+    pub fn with_max_len(max_len: usize) -> Self {
+        Self {
+            buffer: Vec::new(),
+            max_len,
+        }
+    }
+
+    /// Frames an encoding for sending to a [`StreamingDecoder`]
+    pub fn frame(encoded: &[u8]) -> Vec<u8> {
+        let mut frame = varint::encode(encoded.len() as u128);
+        frame.extend_from_slice(encoded);
+        frame
+    }
+
+    /// Appends received bytes and decodes the current frame if it is complete
+    pub fn push(&mut self, bytes: &[u8]) -> Result<StreamStatus, Error> {
+        self.buffer.extend_from_slice(bytes);
+        self.poll()
+    }
+
+    /// Decodes the current frame if it is complete, without appending bytes
+    ///
+    /// A single push may complete a frame and hold all of the next, which this decodes.
+    pub fn poll(&mut self) -> Result<StreamStatus, Error> {
+        let (len, size) = match varint::decode_as::<usize>(&self.buffer) {
+            Ok(header) => header,
+            Err(varint::Error::Unterminated) => return Ok(StreamStatus::NeedMore(1)),
+            Err(e) => {
+                self.buffer.clear();
+                return Err(Error::InvalidVarInt(0, e));
+            }
+        };
+        if len > self.max_len {
+            self.buffer.clear();
+            return Err(Error::Oversized);
+        }
+
+        let end = size + len;
+        if self.buffer.len() < end {
+            return Ok(StreamStatus::NeedMore(end - self.buffer.len()));
+        }
+        let decoded = decode(&self.buffer[size..end]);
+        self.buffer.drain(..end);
+        decoded.map(StreamStatus::Complete)
+    }
+
+    /// Returns the number of bytes received but not yet decoded
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Drops every buffered byte, such as after the link is interrupted mid-frame
+    pub fn reset(&mut self) {
+        self.buffer.clear();
+    }
+}
+
+/// State shared while decoding a descriptor
+pub(crate) struct DecodeContext<'a> {
+    /// Secret keys, by the placeholder public key that stands in for them
+    pub key_map: KeyMap,
+    /// Key serialization, if not the default
+    pub key_codec: Option<&'a dyn KeyCodec>,
+    /// Decoder for custom tags, if any
+    pub tag_extension: Option<&'a dyn TagExtension>,
+    /// Metadata records of unknown type
+    pub metadata: Vec<Tlv>,
+    /// Key rotation policies from the metadata
+    pub key_rotations: Vec<KeyRotation>,
+    /// Whether a top-level `multi()` was encoded as `sortedmulti()`
+    pub sorted_multi: bool,
+    /// Whether the descriptor is a `rawtr()`, decoded as a `tr()` without a tree
+    pub raw_tr: bool,
+    /// Maximum number of steps in a derivation path
+    pub max_path_len: usize,
+    /// Maximum number of keys
+    pub max_keys: usize,
+    /// Number of keys decoded so far
+    pub key_count: usize,
+    /// Edits from the canonical string to the original, if recorded
+    pub original_edits: Option<Vec<u8>>,
+    /// Tag and payload index of each distinct hash read so far, which `HashRef` tags refer back to
+    pub hashes: Vec<(Tag, usize)>,
+}
+
+impl Default for DecodeContext<'_> {
+    fn default() -> Self {
+        Self {
+            key_map: KeyMap::new(),
+            key_codec: None,
+            tag_extension: None,
+            metadata: Vec::new(),
+            key_rotations: Vec::new(),
+            sorted_multi: false,
+            raw_tr: false,
+            max_path_len: DEFAULT_MAX_PATH_LEN,
+            max_keys: usize::MAX,
+            key_count: 0,
+            original_edits: None,
+            hashes: Vec::new(),
+        }
+    }
+}
+
+impl DecodeContext<'_> {
+    /// Returns a context with the same hooks and an empty key map
+    fn fresh(&self) -> Self {
+        Self {
+            key_map: KeyMap::new(),
+            key_codec: self.key_codec,
+            tag_extension: self.tag_extension,
+            metadata: Vec::new(),
+            key_rotations: Vec::new(),
+            sorted_multi: false,
+            raw_tr: false,
+            max_path_len: self.max_path_len,
+            max_keys: self.max_keys,
+            key_count: 0,
+            original_edits: None,
+            hashes: Vec::new(),
+        }
+    }
+
+    /// Returns the key codec to read from `payload`, using the default for template-only
+    /// decoding so that dummy keys are returned
+    fn key_codec(&self, payload: &[u8]) -> &dyn KeyCodec {
+        match self.key_codec {
+            Some(key_codec) if !payload.is_empty() => key_codec,
+            _ => &DefaultKeyCodec,
+        }
+    }
+}
+
+/// Decodes a template and payload split at the end of the template, leaving the public keys
+/// of any secret keys as placeholders.
+pub(crate) fn decode_bytes_underived(
+    bytes: &[u8],
+    mut ctx: DecodeContext,
+) -> Result<(Descriptor<DescriptorPublicKey>, KeyMap), Error> {
+    let descriptor = decode_bytes_in(bytes, &mut ctx)?;
+    if ctx.raw_tr {
+        return Err(Error::RawTr);
+    }
+    Ok((descriptor, ctx.key_map))
+}
+
+/// Decodes a template and payload split at the end of the template, leaving secret keys and
+/// metadata in `ctx`.
+pub(crate) fn decode_bytes_in(
+    bytes: &[u8],
+    ctx: &mut DecodeContext,
+) -> Result<Descriptor<DescriptorPublicKey>, Error> {
+    let size = template_size(bytes, ctx)?;
+    decode_underived(&bytes[..size], &bytes[size..], ctx)
+}
+
+/// Returns the number of bytes in the template at the start of `bytes`
+pub(crate) fn template_size(bytes: &[u8], ctx: &DecodeContext) -> Result<usize, Error> {
+    let mut size = 0;
+    Descriptor::from_template(bytes, &mut size, &[], &mut 0, &mut ctx.fresh())?;
+    Ok(size)
+}
+
+/// Replaces the placeholder public keys of secret keys with their derived public keys.
+///
+/// Multi-xprivs have no single public key, so their placeholders are kept.
+pub(crate) fn derive_public_keys<C: secp256k1::Signing>(
+    descriptor: &Descriptor<DescriptorPublicKey>,
+    key_map: &KeyMap,
+    secp: &Secp256k1<C>,
+) -> Result<(Descriptor<DescriptorPublicKey>, KeyMap), Error> {
+    struct Deriver<'a, C: secp256k1::Signing> {
+        key_map: &'a KeyMap,
+        derived: KeyMap,
+        secp: &'a Secp256k1<C>,
+    }
+
+    impl<C: secp256k1::Signing> Translator<DescriptorPublicKey, DescriptorPublicKey, Infallible>
+        for Deriver<'_, C>
+    {
+        fn pk(&mut self, pk: &DescriptorPublicKey) -> Result<DescriptorPublicKey, Infallible> {
+            let Some(sk) = self.key_map.get(pk) else {
+                return Ok(pk.clone());
+            };
+            let derived = sk.to_public(self.secp).unwrap_or_else(|_| pk.clone());
+            self.derived.insert(derived.clone(), sk.clone());
+            Ok(derived)
+        }
+
+        miniscript::translate_hash_clone!(DescriptorPublicKey, DescriptorPublicKey, Infallible);
+    }
+
+    if key_map.is_empty() {
+        return Ok((descriptor.clone(), KeyMap::new()));
+    }
+
+    let mut deriver = Deriver {
+        key_map,
+        derived: KeyMap::new(),
+        secp,
+    };
+    let descriptor = descriptor.translate_pk(&mut deriver).map_err(|e| match e {
+        TranslateErr::TranslatorErr(e) => match e {},
+        TranslateErr::OuterError(e) => Error::InvalidMiniscript(0, e),
+    })?;
+
+    Ok((descriptor, deriver.derived))
+}
+
+fn decode_underived(
+    input: &[u8],
+    payload: &[u8],
+    ctx: &mut DecodeContext,
+) -> Result<Descriptor<DescriptorPublicKey>, Error> {
     let mut payload_index = 0;
-    let mut key_map = KeyMap::new();
-    let descriptor =
-        Descriptor::from_template(input, &mut 0, payload, &mut payload_index, &mut key_map)?;
+    let descriptor = Descriptor::from_template(input, &mut 0, payload, &mut payload_index, ctx)?;
 
     if payload_index < payload.len() {
         return Err(Error::PayloadTooLarge(payload_index, payload.len()));
     }
+    check_multipaths(&descriptor, &ctx.key_map)?;
+
+    Ok(descriptor)
+}
+
+/// Checks that the derivation paths of every multipath key can be written as one `<a;b;...>`
+/// step, the only form miniscript parses and prints correctly
+///
+/// The template holds each path in full, so it can express paths of different lengths or that
+/// differ in several steps. A single wildcard follows all paths, so it cannot be repeated.
+fn check_multipaths(
+    descriptor: &Descriptor<DescriptorPublicKey>,
+    key_map: &KeyMap,
+) -> Result<(), Error> {
+    let mut key_index = 0;
+    let valid = descriptor.for_each_key(|pk| {
+        let paths = match (key_map.get(pk), pk) {
+            (Some(DescriptorSecretKey::MultiXPrv(xprv)), _) => xprv.derivation_paths.paths(),
+            (None, DescriptorPublicKey::MultiXPub(xpub)) => xpub.derivation_paths.paths(),
+            _ => {
+                key_index += 1;
+                return true;
+            }
+        };
+        key_index += 1;
+        is_single_step(paths)
+    });
+
+    match valid {
+        true => Ok(()),
+        false => Err(Error::InvalidMultipath(key_index - 1)),
+    }
+}
 
-    Ok((descriptor, key_map))
+/// Returns whether at least two paths of equal length differ only in one step, where the first
+/// two paths differ
+fn is_single_step(paths: &[DerivationPath]) -> bool {
+    let Some((first, rest)) = paths.split_first() else {
+        return false;
+    };
+    if rest.is_empty() || rest.iter().any(|path| path.len() != first.len()) {
+        return false;
+    }
+
+    let mut steps = (0..first.len()).filter(|&i| rest.iter().any(|path| path[i] != first[i]));
+    match (steps.next(), steps.next()) {
+        (Some(step), None) => rest[0][step] != first[step],
+        _ => false,
+    }
 }
 
 trait FromTemplate: Sized {
@@ -112,15 +626,15 @@ trait FromTemplate: Sized {
         index: &mut usize,
         payload: &[u8],
         payload_index: &mut usize,
-        key_map: &mut KeyMap,
+        ctx: &mut DecodeContext,
     ) -> Result<Self, Error>;
 }
 
-trait FromPayload: Sized {
+pub(crate) trait FromPayload: Sized {
     fn from_payload(payload: &[u8], payload_index: &mut usize) -> Result<Self, Error>;
 }
 
-trait FromCompressablePayload: Sized {
+pub(crate) trait FromCompressablePayload: Sized {
     fn from_payload(
         compressed: bool,
         payload: &[u8],
@@ -134,56 +648,109 @@ impl FromTemplate for Descriptor<DescriptorPublicKey> {
         index: &mut usize,
         payload: &[u8],
         payload_index: &mut usize,
-        key_map: &mut KeyMap,
+        ctx: &mut DecodeContext,
     ) -> Result<Self, Error> {
         if *index >= input.len() {
             return Err(Error::MissingBytes);
         }
 
         let current_index = *index;
+        ctx.hashes.clear();
         let descriptor = match Tag::from(input[current_index]) {
-            Tag::Unrecognized => return Err(Error::UnrecognizedTag(current_index)),
+            Tag::Unrecognized => match ctx.tag_extension {
+                Some(tag_extension) if CUSTOM_TAGS.contains(&input[current_index]) => {
+                    tag_extension.decode(input, index, payload, payload_index)?
+                }
+                _ => return Err(Error::UnrecognizedTag(current_index)),
+            },
             Tag::Sh => Descriptor::Sh(Sh::<DescriptorPublicKey>::from_template(
                 input,
                 index,
                 payload,
                 payload_index,
-                key_map,
+                ctx,
             )?),
             Tag::Wsh => Descriptor::Wsh(Wsh::<DescriptorPublicKey>::from_template(
                 input,
                 index,
                 payload,
                 payload_index,
-                key_map,
+                ctx,
             )?),
             Tag::Tr => Descriptor::Tr(Tr::<DescriptorPublicKey>::from_template(
                 input,
                 index,
                 payload,
                 payload_index,
-                key_map,
+                ctx,
             )?),
             Tag::Wpkh => Descriptor::Wpkh(Wpkh::<DescriptorPublicKey>::from_template(
                 input,
                 index,
                 payload,
                 payload_index,
-                key_map,
+                ctx,
+            )?),
+            Tag::Preset => {
+                let (id, _) = varint::decode_as::<u64>(&input[current_index + 1..])
+                    .map_err(|e| Error::InvalidVarInt(current_index + 1, e))?;
+                return Err(Error::UnknownPreset(id));
+            }
+            Tag::PayloadCommitment if current_index == 0 => {
+                *index += 1;
+                let commitment = input
+                    .get(*index..*index + encoder::COMMITMENT_SIZE)
+                    .ok_or(Error::MissingBytes)?;
+                *index += encoder::COMMITMENT_SIZE;
+
+                if !payload.is_empty() && commitment != encoder::payload_commitment(payload) {
+                    return Err(Error::PayloadMismatch);
+                }
+
+                Descriptor::from_template(input, index, payload, payload_index, ctx)?
+            }
+            Tag::Metadata
+                if current_index == 0
+                    || (current_index == 1 + encoder::COMMITMENT_SIZE
+                        && input[0] == Tag::PayloadCommitment.value()) =>
+            {
+                *index += 1;
+                let descriptor =
+                    Descriptor::from_template(input, index, payload, payload_index, ctx)?;
+                if !payload.is_empty() {
+                    metadata::decode_records(payload, payload_index, ctx)?;
+                }
+                descriptor
+            }
+            Tag::RawTr => {
+                *index += 1;
+                let key =
+                    DescriptorPublicKey::from_template(input, index, payload, payload_index, ctx)?;
+                ctx.raw_tr = true;
+                Descriptor::Tr(
+                    Tr::new(key, None)
+                        .map_err(|err| Error::InvalidMiniscript(current_index, err))?,
+                )
+            }
+            Tag::SingleSigWpkh => Descriptor::Wpkh(single_sig_from_template(
+                input,
+                index,
+                payload,
+                payload_index,
             )?),
             Tag::Pkh => Descriptor::Pkh(Pkh::<DescriptorPublicKey>::from_template(
                 input,
                 index,
                 payload,
                 payload_index,
-                key_map,
+                ctx,
             )?),
             Tag::Bare => Descriptor::Bare(Bare::<DescriptorPublicKey>::from_template(
                 input,
                 index,
                 payload,
                 payload_index,
-                key_map,
+                ctx,
             )?),
             _ => return Err(Error::InvalidTag(current_index)),
         };
@@ -192,13 +759,74 @@ impl FromTemplate for Descriptor<DescriptorPublicKey> {
     }
 }
 
+/// Decodes the compact encoding of `wpkh([fp/84'/c'/a']xpub/<0;1>/*)`
+fn single_sig_from_template(
+    input: &[u8],
+    index: &mut usize,
+    payload: &[u8],
+    payload_index: &mut usize,
+) -> Result<Wpkh<DescriptorPublicKey>, Error> {
+    let current_index = *index;
+    *index += 1;
+
+    if *index >= input.len() {
+        return Err(Error::MissingBytes);
+    }
+
+    let (value, size) =
+        varint::decode_as::<u32>(&input[*index..]).map_err(|e| Error::InvalidVarInt(*index, e))?;
+    *index += size;
+
+    let (network, coin) = if value & 1 == 1 {
+        (NetworkKind::Test, 1)
+    } else {
+        (NetworkKind::Main, 0)
+    };
+    let account = ChildNumber::Hardened { index: value >> 1 };
+
+    let fingerprint = Fingerprint::from_payload(payload, payload_index)?;
+    let parent_fingerprint = Fingerprint::from_payload(payload, payload_index)?;
+    let chain_code = ChainCode::from_payload(payload, payload_index)?;
+    let public_key = PublicKey::from_payload(true, payload, payload_index)?.inner;
+
+    let xkey = Xpub {
+        network,
+        depth: 3,
+        parent_fingerprint,
+        child_number: account,
+        public_key,
+        chain_code,
+    };
+    let origin_path = DerivationPath::from(vec![
+        ChildNumber::Hardened { index: 84 },
+        ChildNumber::Hardened { index: coin },
+        account,
+    ]);
+    let derivation_paths = DerivPaths::new(vec![
+        DerivationPath::from(vec![ChildNumber::Normal { index: 0 }]),
+        DerivationPath::from(vec![ChildNumber::Normal { index: 1 }]),
+    ])
+    .expect("non-empty");
+
+    let key = DescriptorPublicKey::MultiXPub(DescriptorMultiXKey {
+        origin: Some((fingerprint, origin_path)),
+        xkey,
+        derivation_paths,
+        wildcard: Wildcard::Unhardened,
+    });
+
+    Wpkh::new(key).map_err(|err| {
+        Error::InvalidMiniscript(current_index, miniscript::Error::ContextError(err))
+    })
+}
+
 impl FromTemplate for Sh<DescriptorPublicKey> {
     fn from_template(
         input: &[u8],
         index: &mut usize,
         payload: &[u8],
         payload_index: &mut usize,
-        key_map: &mut KeyMap,
+        ctx: &mut DecodeContext,
     ) -> Result<Self, Error> {
         let current_index = *index;
         *index += 1;
@@ -215,7 +843,7 @@ impl FromTemplate for Sh<DescriptorPublicKey> {
                     index,
                     payload,
                     payload_index,
-                    key_map,
+                    ctx,
                 )?;
                 Sh::new_sortedmulti(sorted_multi.k(), sorted_multi.pks().to_vec())
             }
@@ -224,7 +852,7 @@ impl FromTemplate for Sh<DescriptorPublicKey> {
                 index,
                 payload,
                 payload_index,
-                key_map,
+                ctx,
             )?)),
             Tag::Wpkh => Ok(Sh::new_with_wpkh(
                 Wpkh::<DescriptorPublicKey>::from_template(
@@ -232,7 +860,7 @@ impl FromTemplate for Sh<DescriptorPublicKey> {
                     index,
                     payload,
                     payload_index,
-                    key_map,
+                    ctx,
                 )?,
             )),
             _ => Sh::new(Miniscript::<DescriptorPublicKey, Legacy>::from_template(
@@ -240,13 +868,13 @@ impl FromTemplate for Sh<DescriptorPublicKey> {
                 index,
                 payload,
                 payload_index,
-                key_map,
+                ctx,
             )?),
         };
 
         match sh {
             Ok(sh) => Ok(sh),
-            Err(err) => Err(Error::InvalidMiniscript(current_index, err.to_string())),
+            Err(err) => Err(Error::InvalidMiniscript(current_index, err)),
         }
     }
 }
@@ -257,7 +885,7 @@ impl FromTemplate for Wsh<DescriptorPublicKey> {
         index: &mut usize,
         payload: &[u8],
         payload_index: &mut usize,
-        key_map: &mut KeyMap,
+        ctx: &mut DecodeContext,
     ) -> Result<Self, Error> {
         let current_index = *index;
         *index += 1;
@@ -274,7 +902,7 @@ impl FromTemplate for Wsh<DescriptorPublicKey> {
                     index,
                     payload,
                     payload_index,
-                    key_map,
+                    ctx,
                 )?;
                 Wsh::new_sortedmulti(sorted_multi.k(), sorted_multi.pks().to_vec())
             }
@@ -283,13 +911,13 @@ impl FromTemplate for Wsh<DescriptorPublicKey> {
                 index,
                 payload,
                 payload_index,
-                key_map,
+                ctx,
             )?),
         };
 
         match wsh {
             Ok(wsh) => Ok(wsh),
-            Err(err) => Err(Error::InvalidMiniscript(current_index, err.to_string())),
+            Err(err) => Err(Error::InvalidMiniscript(current_index, err)),
         }
     }
 }
@@ -300,7 +928,7 @@ impl FromTemplate for Tr<DescriptorPublicKey> {
         index: &mut usize,
         payload: &[u8],
         payload_index: &mut usize,
-        key_map: &mut KeyMap,
+        ctx: &mut DecodeContext,
     ) -> Result<Self, Error> {
         let current_index = *index;
         *index += 1;
@@ -310,7 +938,7 @@ impl FromTemplate for Tr<DescriptorPublicKey> {
         }
 
         let internal_key =
-            DescriptorPublicKey::from_template(input, index, payload, payload_index, key_map)?;
+            DescriptorPublicKey::from_template(input, index, payload, payload_index, ctx)?;
 
         let tree = if *index < input.len() && Tag::from(input[*index]) == Tag::TapTree {
             Some(TapTree::<DescriptorPublicKey>::from_template(
@@ -318,7 +946,7 @@ impl FromTemplate for Tr<DescriptorPublicKey> {
                 index,
                 payload,
                 payload_index,
-                key_map,
+                ctx,
             )?)
         } else {
             None
@@ -326,7 +954,7 @@ impl FromTemplate for Tr<DescriptorPublicKey> {
 
         match Tr::new(internal_key, tree) {
             Ok(tr) => Ok(tr),
-            Err(err) => Err(Error::InvalidMiniscript(current_index, err.to_string())),
+            Err(err) => Err(Error::InvalidMiniscript(current_index, err)),
         }
     }
 }
@@ -337,7 +965,7 @@ impl FromTemplate for Wpkh<DescriptorPublicKey> {
         index: &mut usize,
         payload: &[u8],
         payload_index: &mut usize,
-        key_map: &mut KeyMap,
+        ctx: &mut DecodeContext,
     ) -> Result<Self, Error> {
         let current_index = *index;
         *index += 1;
@@ -347,10 +975,13 @@ impl FromTemplate for Wpkh<DescriptorPublicKey> {
             index,
             payload,
             payload_index,
-            key_map,
+            ctx,
         )?) {
             Ok(wpkh) => Ok(wpkh),
-            Err(err) => Err(Error::InvalidMiniscript(current_index, err.to_string())),
+            Err(err) => Err(Error::InvalidMiniscript(
+                current_index,
+                miniscript::Error::ContextError(err),
+            )),
         }
     }
 }
@@ -361,7 +992,7 @@ impl FromTemplate for Pkh<DescriptorPublicKey> {
         index: &mut usize,
         payload: &[u8],
         payload_index: &mut usize,
-        key_map: &mut KeyMap,
+        ctx: &mut DecodeContext,
     ) -> Result<Self, Error> {
         let current_index = *index;
         *index += 1;
@@ -371,10 +1002,13 @@ impl FromTemplate for Pkh<DescriptorPublicKey> {
             index,
             payload,
             payload_index,
-            key_map,
+            ctx,
         )?) {
             Ok(pkh) => Ok(pkh),
-            Err(err) => Err(Error::InvalidMiniscript(current_index, err.to_string())),
+            Err(err) => Err(Error::InvalidMiniscript(
+                current_index,
+                miniscript::Error::ContextError(err),
+            )),
         }
     }
 }
@@ -385,7 +1019,7 @@ impl FromTemplate for Bare<DescriptorPublicKey> {
         index: &mut usize,
         payload: &[u8],
         payload_index: &mut usize,
-        key_map: &mut KeyMap,
+        ctx: &mut DecodeContext,
     ) -> Result<Self, Error> {
         let current_index = *index;
         *index += 1;
@@ -395,12 +1029,12 @@ impl FromTemplate for Bare<DescriptorPublicKey> {
             index,
             payload,
             payload_index,
-            key_map,
+            ctx,
         )?;
         let bare = Bare::new(ms);
         match bare {
             Ok(bare) => Ok(bare),
-            Err(err) => Err(Error::InvalidMiniscript(current_index, err.to_string())),
+            Err(err) => Err(Error::InvalidMiniscript(current_index, err)),
         }
     }
 }
@@ -411,7 +1045,7 @@ impl FromTemplate for TapTree<DescriptorPublicKey> {
         index: &mut usize,
         payload: &[u8],
         payload_index: &mut usize,
-        key_map: &mut KeyMap,
+        ctx: &mut DecodeContext,
     ) -> Result<Self, Error> {
         let current_index = *index;
         *index += 1;
@@ -427,7 +1061,7 @@ impl FromTemplate for TapTree<DescriptorPublicKey> {
                 index,
                 payload,
                 payload_index,
-                key_map,
+                ctx,
             )?;
 
             if *index < input.len() && Tag::from(input[*index]) == Tag::TapTree {
@@ -436,7 +1070,7 @@ impl FromTemplate for TapTree<DescriptorPublicKey> {
                     index,
                     payload,
                     payload_index,
-                    key_map,
+                    ctx,
                 )?;
 
                 Ok(Self::combine(left, right))
@@ -450,7 +1084,7 @@ impl FromTemplate for TapTree<DescriptorPublicKey> {
                 index,
                 payload,
                 payload_index,
-                key_map,
+                ctx,
             )?;
 
             Ok(TapTree::Leaf(Arc::new(ms)))
@@ -464,7 +1098,7 @@ impl<Ctx: ScriptContext> FromTemplate for SortedMultiVec<DescriptorPublicKey, Ct
         index: &mut usize,
         payload: &[u8],
         payload_index: &mut usize,
-        key_map: &mut KeyMap,
+        ctx: &mut DecodeContext,
     ) -> Result<Self, Error> {
         let current_index = *index;
         *index += 1;
@@ -473,27 +1107,22 @@ impl<Ctx: ScriptContext> FromTemplate for SortedMultiVec<DescriptorPublicKey, Ct
             return Err(Error::MissingBytes);
         }
 
-        let (k, size_k) = varint::decode(&input[(current_index + 1)..])
-            .map_err(|e| Error::InvalidVarInt(current_index + 1, e.to_string()))?;
-        let (n, size_n) = varint::decode(&input[(current_index + 1 + size_k)..])
-            .map_err(|e| Error::InvalidVarInt(current_index + 1 + size_k, e.to_string()))?;
-
-        if k > usize::MAX as u128 {
-            return Err(Error::InvalidVarInt(current_index + 1, "overflow".into()));
-        }
+        let (k, size_k) = varint::decode_as::<usize>(&input[(current_index + 1)..])
+            .map_err(|e| Error::InvalidVarInt(current_index + 1, e))?;
+        let (n, size_n) = varint::decode_as::<usize>(&input[(current_index + 1 + size_k)..])
+            .map_err(|e| Error::InvalidVarInt(current_index + 1 + size_k, e))?;
 
         *index += size_k + size_n;
 
         let mut pks = Vec::new();
         for _ in 0..n {
-            let pk =
-                DescriptorPublicKey::from_template(input, index, payload, payload_index, key_map)?;
+            let pk = DescriptorPublicKey::from_template(input, index, payload, payload_index, ctx)?;
             pks.push(pk);
         }
 
-        match SortedMultiVec::<DescriptorPublicKey, Ctx>::new(k as usize, pks) {
+        match SortedMultiVec::<DescriptorPublicKey, Ctx>::new(k, pks) {
             Ok(sorted_multi) => Ok(sorted_multi),
-            Err(err) => Err(Error::InvalidMiniscript(current_index, err.to_string())),
+            Err(err) => Err(Error::InvalidMiniscript(current_index, err)),
         }
     }
 }
@@ -504,7 +1133,7 @@ impl<Ctx: ScriptContext> FromTemplate for Miniscript<DescriptorPublicKey, Ctx> {
         index: &mut usize,
         payload: &[u8],
         payload_index: &mut usize,
-        key_map: &mut KeyMap,
+        ctx: &mut DecodeContext,
     ) -> Result<Self, Error> {
         let current_index = *index;
         let ast = Terminal::<DescriptorPublicKey, Ctx>::from_template(
@@ -512,22 +1141,187 @@ impl<Ctx: ScriptContext> FromTemplate for Miniscript<DescriptorPublicKey, Ctx> {
             index,
             payload,
             payload_index,
-            key_map,
+            ctx,
         )?;
         match Self::from_ast(ast) {
             Ok(ms) => Ok(ms),
-            Err(err) => Err(Error::InvalidMiniscript(current_index, err.to_string())),
+            Err(err) => Err(Error::InvalidMiniscript(current_index, err)),
         }
     }
 }
 
+/// Reads a single miniscript fragment from a template
+type TerminalHandler<Ctx> = fn(
+    &[u8],
+    &mut usize,
+    &[u8],
+    &mut usize,
+    &mut DecodeContext,
+) -> Result<Terminal<DescriptorPublicKey, Ctx>, Error>;
+
+/// Lookup table from tag byte to the handler that reads the fragment it introduces
+struct TerminalTable<Ctx>(std::marker::PhantomData<Ctx>);
+
+impl<Ctx: ScriptContext> TerminalTable<Ctx> {
+    const HANDLERS: [Option<TerminalHandler<Ctx>>; 256] = {
+        let mut table: [Option<TerminalHandler<Ctx>>; 256] = [None; 256];
+        table[Tag::True as usize] = Self::handler(|_, _, _, _, _| Ok(Terminal::True));
+        table[Tag::False as usize] = Self::handler(|_, _, _, _, _| Ok(Terminal::False));
+        table[Tag::PkK as usize] = Self::handler(|i, x, p, px, c| {
+            Ok(Terminal::PkK(DescriptorPublicKey::from_template(
+                i, x, p, px, c,
+            )?))
+        });
+        table[Tag::PkH as usize] = Self::handler(|i, x, p, px, c| {
+            Ok(Terminal::PkH(DescriptorPublicKey::from_template(
+                i, x, p, px, c,
+            )?))
+        });
+        table[Tag::RawPkH as usize] =
+            Self::handler(|_, _, p, px, _| Ok(Terminal::RawPkH(Hash160::from_payload(p, px)?)));
+        table[Tag::After as usize] =
+            Self::handler(|_, _, p, px, _| Ok(Terminal::After(AbsLockTime::from_payload(p, px)?)));
+        table[Tag::Older as usize] =
+            Self::handler(|_, _, p, px, _| Ok(Terminal::Older(RelLockTime::from_payload(p, px)?)));
+        table[Tag::Sha256 as usize] =
+            Self::handler(|_, _, p, px, c| Self::hash(Tag::Sha256, p, px, c));
+        table[Tag::Hash256 as usize] =
+            Self::handler(|_, _, p, px, c| Self::hash(Tag::Hash256, p, px, c));
+        table[Tag::Ripemd160 as usize] =
+            Self::handler(|_, _, p, px, c| Self::hash(Tag::Ripemd160, p, px, c));
+        table[Tag::Hash160 as usize] =
+            Self::handler(|_, _, p, px, c| Self::hash(Tag::Hash160, p, px, c));
+        table[Tag::HashRef as usize] = Self::handler(|i, x, p, _, c| {
+            let current_index = *x - 1;
+            let (n, size) =
+                varint::decode_as::<usize>(&i[*x..]).map_err(|e| Error::InvalidVarInt(*x, e))?;
+            *x += size;
+            let (tag, mut hash_index) = *c
+                .hashes
+                .get(n)
+                .ok_or(Error::InvalidHashRef(current_index))?;
+            Self::read_hash(tag, p, &mut hash_index)
+        });
+        table[Tag::Alt as usize] =
+            Self::handler(|i, x, p, px, c| Ok(Terminal::Alt(Self::sub(i, x, p, px, c)?)));
+        table[Tag::Swap as usize] =
+            Self::handler(|i, x, p, px, c| Ok(Terminal::Swap(Self::sub(i, x, p, px, c)?)));
+        table[Tag::Check as usize] =
+            Self::handler(|i, x, p, px, c| Ok(Terminal::Check(Self::sub(i, x, p, px, c)?)));
+        table[Tag::DupIf as usize] =
+            Self::handler(|i, x, p, px, c| Ok(Terminal::DupIf(Self::sub(i, x, p, px, c)?)));
+        table[Tag::Verify as usize] =
+            Self::handler(|i, x, p, px, c| Ok(Terminal::Verify(Self::sub(i, x, p, px, c)?)));
+        table[Tag::NonZero as usize] =
+            Self::handler(|i, x, p, px, c| Ok(Terminal::NonZero(Self::sub(i, x, p, px, c)?)));
+        table[Tag::ZeroNotEqual as usize] =
+            Self::handler(|i, x, p, px, c| Ok(Terminal::ZeroNotEqual(Self::sub(i, x, p, px, c)?)));
+        table[Tag::AndV as usize] = Self::handler(|i, x, p, px, c| {
+            let left = Self::sub(i, x, p, px, c)?;
+            Ok(Terminal::AndV(left, Self::sub(i, x, p, px, c)?))
+        });
+        table[Tag::AndB as usize] = Self::handler(|i, x, p, px, c| {
+            let left = Self::sub(i, x, p, px, c)?;
+            Ok(Terminal::AndB(left, Self::sub(i, x, p, px, c)?))
+        });
+        table[Tag::AndOr as usize] = Self::handler(|i, x, p, px, c| {
+            let first = Self::sub(i, x, p, px, c)?;
+            let second = Self::sub(i, x, p, px, c)?;
+            Ok(Terminal::AndOr(first, second, Self::sub(i, x, p, px, c)?))
+        });
+        table[Tag::OrB as usize] = Self::handler(|i, x, p, px, c| {
+            let left = Self::sub(i, x, p, px, c)?;
+            Ok(Terminal::OrB(left, Self::sub(i, x, p, px, c)?))
+        });
+        table[Tag::OrC as usize] = Self::handler(|i, x, p, px, c| {
+            let left = Self::sub(i, x, p, px, c)?;
+            Ok(Terminal::OrC(left, Self::sub(i, x, p, px, c)?))
+        });
+        table[Tag::OrD as usize] = Self::handler(|i, x, p, px, c| {
+            let left = Self::sub(i, x, p, px, c)?;
+            Ok(Terminal::OrD(left, Self::sub(i, x, p, px, c)?))
+        });
+        table[Tag::OrI as usize] = Self::handler(|i, x, p, px, c| {
+            let left = Self::sub(i, x, p, px, c)?;
+            Ok(Terminal::OrI(left, Self::sub(i, x, p, px, c)?))
+        });
+        table[Tag::Thresh as usize] = Self::handler(|i, x, p, px, c| {
+            Ok(Terminal::Thresh(Threshold::<
+                Arc<Miniscript<DescriptorPublicKey, Ctx>>,
+                0,
+            >::from_template(
+                i, x, p, px, c
+            )?))
+        });
+        table[Tag::Multi as usize] = Self::handler(|i, x, p, px, c| {
+            Ok(Terminal::Multi(
+                Threshold::<DescriptorPublicKey, 20>::from_template(i, x, p, px, c)?,
+            ))
+        });
+        table[Tag::MultiA as usize] = Self::handler(|i, x, p, px, c| {
+            Ok(Terminal::MultiA(
+                Threshold::<DescriptorPublicKey, 125000>::from_template(i, x, p, px, c)?,
+            ))
+        });
+        table
+    };
+
+    /// Records where a hash is in the payload for later `HashRef` tags, then reads it
+    fn hash(
+        tag: Tag,
+        payload: &[u8],
+        payload_index: &mut usize,
+        ctx: &mut DecodeContext,
+    ) -> Result<Terminal<DescriptorPublicKey, Ctx>, Error> {
+        ctx.hashes.push((tag, *payload_index));
+        Self::read_hash(tag, payload, payload_index)
+    }
+
+    /// Reads the hash fragment for a hash tag
+    fn read_hash(
+        tag: Tag,
+        payload: &[u8],
+        payload_index: &mut usize,
+    ) -> Result<Terminal<DescriptorPublicKey, Ctx>, Error> {
+        Ok(match tag {
+            Tag::Sha256 => Terminal::Sha256(Sha256::from_payload(payload, payload_index)?),
+            Tag::Hash256 => Terminal::Hash256(Hash256::from_payload(payload, payload_index)?),
+            Tag::Ripemd160 => Terminal::Ripemd160(Ripemd160::from_payload(payload, payload_index)?),
+            _ => Terminal::Hash160(Hash160::from_payload(payload, payload_index)?),
+        })
+    }
+
+    /// Coerces a closure to a table entry
+    const fn handler(handler: TerminalHandler<Ctx>) -> Option<TerminalHandler<Ctx>> {
+        Some(handler)
+    }
+
+    /// Reads a sub-fragment
+    fn sub(
+        input: &[u8],
+        index: &mut usize,
+        payload: &[u8],
+        payload_index: &mut usize,
+        ctx: &mut DecodeContext,
+    ) -> Result<Arc<Miniscript<DescriptorPublicKey, Ctx>>, Error> {
+        Ok(Miniscript::<DescriptorPublicKey, Ctx>::from_template(
+            input,
+            index,
+            payload,
+            payload_index,
+            ctx,
+        )?
+        .into())
+    }
+}
+
 impl<Ctx: ScriptContext> FromTemplate for Terminal<DescriptorPublicKey, Ctx> {
     fn from_template(
         input: &[u8],
         index: &mut usize,
         payload: &[u8],
         payload_index: &mut usize,
-        key_map: &mut KeyMap,
+        ctx: &mut DecodeContext,
     ) -> Result<Self, Error> {
         if *index >= input.len() {
             return Err(Error::MissingBytes);
@@ -536,262 +1330,13 @@ impl<Ctx: ScriptContext> FromTemplate for Terminal<DescriptorPublicKey, Ctx> {
         let current_index = *index;
         *index += 1;
 
-        let terminal = match Tag::from(input[current_index]) {
-            Tag::Unrecognized => return Err(Error::UnrecognizedTag(current_index)),
-            Tag::True => Self::True,
-            Tag::False => Self::False,
-            Tag::PkK => Self::PkK(DescriptorPublicKey::from_template(
-                input,
-                index,
-                payload,
-                payload_index,
-                key_map,
-            )?),
-            Tag::PkH => Self::PkH(DescriptorPublicKey::from_template(
-                input,
-                index,
-                payload,
-                payload_index,
-                key_map,
-            )?),
-            Tag::RawPkH => Self::RawPkH(Hash160::from_payload(payload, payload_index)?),
-            Tag::After => Self::After(AbsLockTime::from_payload(payload, payload_index)?),
-            Tag::Older => Self::Older(RelLockTime::from_payload(payload, payload_index)?),
-            Tag::Sha256 => Self::Sha256(Sha256::from_payload(payload, payload_index)?),
-            Tag::Hash256 => Self::Hash256(Hash256::from_payload(payload, payload_index)?),
-            Tag::Ripemd160 => Self::Ripemd160(Ripemd160::from_payload(payload, payload_index)?),
-            Tag::Hash160 => Self::Hash160(Hash160::from_payload(payload, payload_index)?),
-            Tag::Alt => Self::Alt(
-                Miniscript::<DescriptorPublicKey, Ctx>::from_template(
-                    input,
-                    index,
-                    payload,
-                    payload_index,
-                    key_map,
-                )?
-                .into(),
-            ),
-            Tag::Swap => Self::Swap(
-                Miniscript::<DescriptorPublicKey, Ctx>::from_template(
-                    input,
-                    index,
-                    payload,
-                    payload_index,
-                    key_map,
-                )?
-                .into(),
-            ),
-            Tag::Check => Self::Check(
-                Miniscript::<DescriptorPublicKey, Ctx>::from_template(
-                    input,
-                    index,
-                    payload,
-                    payload_index,
-                    key_map,
-                )?
-                .into(),
-            ),
-            Tag::DupIf => Self::DupIf(
-                Miniscript::<DescriptorPublicKey, Ctx>::from_template(
-                    input,
-                    index,
-                    payload,
-                    payload_index,
-                    key_map,
-                )?
-                .into(),
-            ),
-            Tag::Verify => Self::Verify(
-                Miniscript::<DescriptorPublicKey, Ctx>::from_template(
-                    input,
-                    index,
-                    payload,
-                    payload_index,
-                    key_map,
-                )?
-                .into(),
-            ),
-            Tag::NonZero => Self::NonZero(
-                Miniscript::<DescriptorPublicKey, Ctx>::from_template(
-                    input,
-                    index,
-                    payload,
-                    payload_index,
-                    key_map,
-                )?
-                .into(),
-            ),
-            Tag::ZeroNotEqual => Self::ZeroNotEqual(
-                Miniscript::<DescriptorPublicKey, Ctx>::from_template(
-                    input,
-                    index,
-                    payload,
-                    payload_index,
-                    key_map,
-                )?
-                .into(),
-            ),
-            Tag::AndV => Self::AndV(
-                Miniscript::<DescriptorPublicKey, Ctx>::from_template(
-                    input,
-                    index,
-                    payload,
-                    payload_index,
-                    key_map,
-                )?
-                .into(),
-                Miniscript::<DescriptorPublicKey, Ctx>::from_template(
-                    input,
-                    index,
-                    payload,
-                    payload_index,
-                    key_map,
-                )?
-                .into(),
-            ),
-            Tag::AndB => Self::AndB(
-                Miniscript::<DescriptorPublicKey, Ctx>::from_template(
-                    input,
-                    index,
-                    payload,
-                    payload_index,
-                    key_map,
-                )?
-                .into(),
-                Miniscript::<DescriptorPublicKey, Ctx>::from_template(
-                    input,
-                    index,
-                    payload,
-                    payload_index,
-                    key_map,
-                )?
-                .into(),
-            ),
-            Tag::AndOr => Self::AndOr(
-                Miniscript::<DescriptorPublicKey, Ctx>::from_template(
-                    input,
-                    index,
-                    payload,
-                    payload_index,
-                    key_map,
-                )?
-                .into(),
-                Miniscript::<DescriptorPublicKey, Ctx>::from_template(
-                    input,
-                    index,
-                    payload,
-                    payload_index,
-                    key_map,
-                )?
-                .into(),
-                Miniscript::<DescriptorPublicKey, Ctx>::from_template(
-                    input,
-                    index,
-                    payload,
-                    payload_index,
-                    key_map,
-                )?
-                .into(),
-            ),
-            Tag::OrB => Self::OrB(
-                Miniscript::<DescriptorPublicKey, Ctx>::from_template(
-                    input,
-                    index,
-                    payload,
-                    payload_index,
-                    key_map,
-                )?
-                .into(),
-                Miniscript::<DescriptorPublicKey, Ctx>::from_template(
-                    input,
-                    index,
-                    payload,
-                    payload_index,
-                    key_map,
-                )?
-                .into(),
-            ),
-            Tag::OrC => Self::OrC(
-                Miniscript::<DescriptorPublicKey, Ctx>::from_template(
-                    input,
-                    index,
-                    payload,
-                    payload_index,
-                    key_map,
-                )?
-                .into(),
-                Miniscript::<DescriptorPublicKey, Ctx>::from_template(
-                    input,
-                    index,
-                    payload,
-                    payload_index,
-                    key_map,
-                )?
-                .into(),
-            ),
-            Tag::OrD => Self::OrD(
-                Miniscript::<DescriptorPublicKey, Ctx>::from_template(
-                    input,
-                    index,
-                    payload,
-                    payload_index,
-                    key_map,
-                )?
-                .into(),
-                Miniscript::<DescriptorPublicKey, Ctx>::from_template(
-                    input,
-                    index,
-                    payload,
-                    payload_index,
-                    key_map,
-                )?
-                .into(),
-            ),
-            Tag::OrI => Self::OrI(
-                Miniscript::<DescriptorPublicKey, Ctx>::from_template(
-                    input,
-                    index,
-                    payload,
-                    payload_index,
-                    key_map,
-                )?
-                .into(),
-                Miniscript::<DescriptorPublicKey, Ctx>::from_template(
-                    input,
-                    index,
-                    payload,
-                    payload_index,
-                    key_map,
-                )?
-                .into(),
-            ),
-            Tag::Thresh => Self::Thresh(
-                Threshold::<Arc<Miniscript<DescriptorPublicKey, Ctx>>, 0>::from_template(
-                    input,
-                    index,
-                    payload,
-                    payload_index,
-                    key_map,
-                )?,
-            ),
-            Tag::Multi => Self::Multi(Threshold::<DescriptorPublicKey, 20>::from_template(
-                input,
-                index,
-                payload,
-                payload_index,
-                key_map,
-            )?),
-            Tag::MultiA => Self::MultiA(Threshold::<DescriptorPublicKey, 125000>::from_template(
-                input,
-                index,
-                payload,
-                payload_index,
-                key_map,
-            )?),
-            _ => return Err(Error::InvalidTag(current_index)),
-        };
-
-        Ok(terminal)
+        match TerminalTable::<Ctx>::HANDLERS[input[current_index] as usize] {
+            Some(handler) => handler(input, index, payload, payload_index, ctx),
+            None if Tag::from(input[current_index]) == Tag::Unrecognized => {
+                Err(Error::UnrecognizedTag(current_index))
+            }
+            None => Err(Error::InvalidTag(current_index)),
+        }
     }
 }
 
@@ -801,14 +1346,14 @@ impl<T: FromTemplate> FromTemplate for Arc<T> {
         index: &mut usize,
         payload: &[u8],
         payload_index: &mut usize,
-        key_map: &mut KeyMap,
+        ctx: &mut DecodeContext,
     ) -> Result<Self, Error> {
         Ok(Arc::new(T::from_template(
             input,
             index,
             payload,
             payload_index,
-            key_map,
+            ctx,
         )?))
     }
 }
@@ -819,33 +1364,32 @@ impl<T: FromTemplate, const MAX: usize> FromTemplate for Threshold<T, MAX> {
         index: &mut usize,
         payload: &[u8],
         payload_index: &mut usize,
-        key_map: &mut KeyMap,
+        ctx: &mut DecodeContext,
     ) -> Result<Self, Error> {
         if *index >= input.len() {
             return Err(Error::MissingBytes);
         }
 
         let current_index = *index;
-        let (k, size_k) = varint::decode(&input[*index..])
-            .map_err(|e| Error::InvalidVarInt(*index, e.to_string()))?;
-        let (n, size_n) = varint::decode(&input[(*index + size_k)..])
-            .map_err(|e| Error::InvalidVarInt(*index + size_k, e.to_string()))?;
-
-        if k > usize::MAX as u128 {
-            return Err(Error::InvalidVarInt(*index, "overflow".into()));
-        }
+        let (k, size_k) = varint::decode_as::<usize>(&input[*index..])
+            .map_err(|e| Error::InvalidVarInt(*index, e))?;
+        let (n, size_n) = varint::decode_as::<usize>(&input[(*index + size_k)..])
+            .map_err(|e| Error::InvalidVarInt(*index + size_k, e))?;
 
         *index += size_k + size_n;
 
         let mut ts = Vec::new();
         for _ in 0..n {
-            let t = T::from_template(input, index, payload, payload_index, key_map)?;
+            let t = T::from_template(input, index, payload, payload_index, ctx)?;
             ts.push(t);
         }
 
-        match Threshold::<T, MAX>::new(k as usize, ts) {
+        match Threshold::<T, MAX>::new(k, ts) {
             Ok(thresh) => Ok(thresh),
-            Err(err) => Err(Error::InvalidMiniscript(current_index, err.to_string())),
+            Err(err) => Err(Error::InvalidMiniscript(
+                current_index,
+                miniscript::Error::Threshold(err),
+            )),
         }
     }
 }
@@ -856,11 +1400,15 @@ impl FromTemplate for DescriptorPublicKey {
         index: &mut usize,
         payload: &[u8],
         payload_index: &mut usize,
-        key_map: &mut KeyMap,
+        ctx: &mut DecodeContext,
     ) -> Result<Self, Error> {
         if *index + 1 >= input.len() {
             return Err(Error::MissingBytes);
         }
+        ctx.key_count += 1;
+        if ctx.key_count > ctx.max_keys {
+            return Err(Error::TooManyKeys(*index));
+        }
 
         // Check if this is a private key
         let tag = Tag::from(input[*index]);
@@ -869,24 +1417,16 @@ impl FromTemplate for DescriptorPublicKey {
             | Tag::MultiXPriv
             | Tag::CompressedSinglePriv
             | Tag::UncompressedSinglePriv => {
-                let secret_key = DescriptorSecretKey::from_template(
-                    input,
-                    index,
-                    payload,
-                    payload_index,
-                    key_map,
-                )?;
+                let secret_key =
+                    DescriptorSecretKey::from_template(input, index, payload, payload_index, ctx)?;
 
-                // Convert secret key to public key
-                let secp = Secp256k1::new();
-                let public_key = secret_key.to_public(&secp).unwrap_or(
-                    // multi-xpriv cannot be converted to a public key, so instead
-                    // use a dummy public key indexed to the map size
-                    test_helpers::create_dpk_single_compressed_no_origin(1 + key_map.len() as u32),
-                );
+                // Derivation is deferred, so use a placeholder public key indexed
+                // to the map size (see `derive_public_keys`)
+                let compressed = tag != Tag::UncompressedSinglePriv;
+                let public_key = dummy::placeholder_dpk(ctx.key_map.len() as u32, compressed);
 
                 // Insert key mapping
-                key_map.insert(public_key.clone(), secret_key);
+                ctx.key_map.insert(public_key.clone(), secret_key);
 
                 return Ok(public_key);
             }
@@ -901,7 +1441,7 @@ impl FromTemplate for DescriptorPublicKey {
             Tag::Origin => {
                 let fingerprint_dummy = Fingerprint::from_payload(payload, payload_index)?;
                 let derivation_path =
-                    DerivationPath::from_template(input, index, payload, payload_index, key_map)?;
+                    DerivationPath::from_template(input, index, payload, payload_index, ctx)?;
 
                 Some((fingerprint_dummy, derivation_path))
             }
@@ -912,40 +1452,51 @@ impl FromTemplate for DescriptorPublicKey {
         let template = match Tag::from(input[current_index]) {
             Tag::Unrecognized => return Err(Error::UnrecognizedTag(current_index)),
             Tag::UncompressedFullKey => DescriptorPublicKey::Single(SinglePub {
-                key: SinglePubKey::FullKey(PublicKey::from_payload(false, payload, payload_index)?),
+                key: SinglePubKey::FullKey(ctx.key_codec(payload).decode_full_key(
+                    false,
+                    payload,
+                    payload_index,
+                )?),
                 origin,
             }),
             Tag::CompressedFullKey => DescriptorPublicKey::Single(SinglePub {
-                key: SinglePubKey::FullKey(PublicKey::from_payload(true, payload, payload_index)?),
+                key: SinglePubKey::FullKey(ctx.key_codec(payload).decode_full_key(
+                    true,
+                    payload,
+                    payload_index,
+                )?),
                 origin,
             }),
             Tag::XOnly => DescriptorPublicKey::Single(SinglePub {
-                key: SinglePubKey::XOnly(XOnlyPublicKey::from_payload(payload, payload_index)?),
+                key: SinglePubKey::XOnly(
+                    ctx.key_codec(payload)
+                        .decode_x_only(payload, payload_index)?,
+                ),
                 origin,
             }),
             Tag::XPub => DescriptorPublicKey::XPub(DescriptorXKey {
                 origin,
-                xkey: Xpub::from_payload(payload, payload_index)?,
+                xkey: ctx.key_codec(payload).decode_xpub(payload, payload_index)?,
                 derivation_path: DerivationPath::from_template(
                     input,
                     index,
                     payload,
                     payload_index,
-                    key_map,
+                    ctx,
                 )?,
-                wildcard: Wildcard::from_template(input, index, payload, payload_index, key_map)?,
+                wildcard: Wildcard::from_template(input, index, payload, payload_index, ctx)?,
             }),
             Tag::MultiXPub => DescriptorPublicKey::MultiXPub(DescriptorMultiXKey {
                 origin,
-                xkey: Xpub::from_payload(payload, payload_index)?,
+                xkey: ctx.key_codec(payload).decode_xpub(payload, payload_index)?,
                 derivation_paths: DerivPaths::from_template(
                     input,
                     index,
                     payload,
                     payload_index,
-                    key_map,
+                    ctx,
                 )?,
-                wildcard: Wildcard::from_template(input, index, payload, payload_index, key_map)?,
+                wildcard: Wildcard::from_template(input, index, payload, payload_index, ctx)?,
             }),
             _ => return Err(Error::InvalidTag(current_index)),
         };
@@ -960,7 +1511,7 @@ impl FromTemplate for DescriptorSecretKey {
         index: &mut usize,
         payload: &[u8],
         payload_index: &mut usize,
-        key_map: &mut KeyMap,
+        ctx: &mut DecodeContext,
     ) -> Result<Self, Error> {
         if *index + 1 >= input.len() {
             return Err(Error::MissingBytes);
@@ -974,7 +1525,7 @@ impl FromTemplate for DescriptorSecretKey {
             Tag::Origin => {
                 let fingerprint = Fingerprint::from_payload(payload, payload_index)?;
                 let derivation_path =
-                    DerivationPath::from_template(input, index, payload, payload_index, key_map)?;
+                    DerivationPath::from_template(input, index, payload, payload_index, ctx)?;
 
                 Some((fingerprint, derivation_path))
             }
@@ -985,36 +1536,44 @@ impl FromTemplate for DescriptorSecretKey {
         let secret_key = match Tag::from(input[current_index]) {
             Tag::Unrecognized => return Err(Error::UnrecognizedTag(current_index)),
             Tag::CompressedSinglePriv => DescriptorSecretKey::Single(SinglePriv {
-                key: PrivateKey::from_payload(true, payload, payload_index)?,
+                key: ctx
+                    .key_codec(payload)
+                    .decode_secret_key(true, payload, payload_index)?,
                 origin,
             }),
             Tag::UncompressedSinglePriv => DescriptorSecretKey::Single(SinglePriv {
-                key: PrivateKey::from_payload(false, payload, payload_index)?,
+                key: ctx
+                    .key_codec(payload)
+                    .decode_secret_key(false, payload, payload_index)?,
                 origin,
             }),
             Tag::XPriv => DescriptorSecretKey::XPrv(DescriptorXKey {
                 origin,
-                xkey: Xpriv::from_payload(payload, payload_index)?,
+                xkey: ctx
+                    .key_codec(payload)
+                    .decode_xpriv(payload, payload_index)?,
                 derivation_path: DerivationPath::from_template(
                     input,
                     index,
                     payload,
                     payload_index,
-                    key_map,
+                    ctx,
                 )?,
-                wildcard: Wildcard::from_template(input, index, payload, payload_index, key_map)?,
+                wildcard: Wildcard::from_template(input, index, payload, payload_index, ctx)?,
             }),
             Tag::MultiXPriv => DescriptorSecretKey::MultiXPrv(DescriptorMultiXKey {
                 origin,
-                xkey: Xpriv::from_payload(payload, payload_index)?,
+                xkey: ctx
+                    .key_codec(payload)
+                    .decode_xpriv(payload, payload_index)?,
                 derivation_paths: DerivPaths::from_template(
                     input,
                     index,
                     payload,
                     payload_index,
-                    key_map,
+                    ctx,
                 )?,
-                wildcard: Wildcard::from_template(input, index, payload, payload_index, key_map)?,
+                wildcard: Wildcard::from_template(input, index, payload, payload_index, ctx)?,
             }),
             _ => return Err(Error::InvalidTag(current_index)),
         };
@@ -1045,7 +1604,7 @@ impl FromCompressablePayload for PrivateKey {
         }
 
         let sk = SecretKey::from_slice(&payload[current_index..current_index + SECRET_KEY_SIZE])
-            .map_err(|e| Error::InvalidPayload(current_index, e.to_string()))?;
+            .map_err(|e| Error::InvalidPayload(current_index, PayloadError::Secp256k1(e)))?;
 
         if compressed {
             Ok(PrivateKey::new(sk, NetworkKind::Main))
@@ -1070,7 +1629,10 @@ impl FromPayload for Xpriv {
 
         match Self::decode(&payload[current_index..current_index + 78]) {
             Ok(xpriv) => Ok(xpriv),
-            Err(err) => Err(Error::InvalidPayload(current_index, err.to_string())),
+            Err(err) => Err(Error::InvalidPayload(
+                current_index,
+                PayloadError::Bip32(err),
+            )),
         }
     }
 }
@@ -1119,7 +1681,10 @@ impl FromCompressablePayload for PublicKey {
 
         match Self::from_slice(&payload[current_index..current_index + size]) {
             Ok(pk) => Ok(pk),
-            Err(err) => Err(Error::InvalidPayload(current_index, err.to_string())),
+            Err(err) => Err(Error::InvalidPayload(
+                current_index,
+                PayloadError::PublicKey(err),
+            )),
         }
     }
 }
@@ -1139,7 +1704,10 @@ impl FromPayload for XOnlyPublicKey {
 
         match Self::from_slice(&payload[current_index..current_index + 32]) {
             Ok(x_only) => Ok(x_only),
-            Err(err) => Err(Error::InvalidPayload(current_index, err.to_string())),
+            Err(err) => Err(Error::InvalidPayload(
+                current_index,
+                PayloadError::Secp256k1(err),
+            )),
         }
     }
 }
@@ -1159,25 +1727,51 @@ impl FromPayload for Xpub {
 
         match Self::decode(&payload[current_index..current_index + 78]) {
             Ok(xpub) => Ok(xpub),
-            Err(err) => Err(Error::InvalidPayload(current_index, err.to_string())),
+            Err(err) => Err(Error::InvalidPayload(
+                current_index,
+                PayloadError::Bip32(err),
+            )),
         }
     }
 }
 
+impl FromPayload for ChainCode {
+    fn from_payload(payload: &[u8], payload_index: &mut usize) -> Result<Self, Error> {
+        if payload.is_empty() {
+            return Ok(dummy::xpub().chain_code);
+        }
+
+        let current_index = *payload_index;
+        *payload_index += 32;
+
+        if *payload_index > payload.len() {
+            return Err(Error::MissingBytes);
+        }
+
+        let mut data = [0u8; 32];
+        data.copy_from_slice(&payload[current_index..current_index + 32]);
+
+        Ok(ChainCode::from(data))
+    }
+}
+
 impl FromTemplate for DerivationPath {
     fn from_template(
         input: &[u8],
         index: &mut usize,
         payload: &[u8],
         payload_index: &mut usize,
-        key_map: &mut KeyMap,
+        ctx: &mut DecodeContext,
     ) -> Result<Self, Error> {
         if *index >= input.len() {
             return Err(Error::MissingBytes);
         }
 
-        let (len, size) = varint::decode(&input[*index..])
-            .map_err(|e| Error::InvalidVarInt(*index, e.to_string()))?;
+        let (len, size) = varint::decode_as::<usize>(&input[*index..])
+            .map_err(|e| Error::InvalidVarInt(*index, e))?;
+        if len > ctx.max_path_len {
+            return Err(Error::PathTooLong(*index));
+        }
 
         *index += size;
 
@@ -1188,7 +1782,7 @@ impl FromTemplate for DerivationPath {
                 index,
                 payload,
                 payload_index,
-                key_map,
+                ctx,
             )?);
         }
 
@@ -1202,22 +1796,20 @@ impl FromTemplate for ChildNumber {
         index: &mut usize,
         _payload: &[u8],
         _payload_index: &mut usize,
-        _key_map: &mut KeyMap,
+        _ctx: &mut DecodeContext,
     ) -> Result<Self, Error> {
         if *index >= input.len() {
             return Err(Error::MissingBytes);
         }
 
-        let (value, size) = varint::decode(&input[*index..])
-            .map_err(|e| Error::InvalidVarInt(*index, e.to_string()))?;
+        let (value, size) = varint::decode_as::<u64>(&input[*index..])
+            .map_err(|e| Error::InvalidVarInt(*index, e))?;
 
-        *index += size;
+        let child_index = u32::try_from(value >> 1)
+            .map_err(|_| Error::InvalidVarInt(*index, varint::Error::Overflow))?;
 
-        if value >> 1 > u32::MAX.into() {
-            return Err(Error::InvalidVarInt(*index, "overflow".into()));
-        }
+        *index += size;
 
-        let child_index = (value >> 1) as u32;
         let number = if value & 1 == 1 {
             ChildNumber::Hardened { index: child_index }
         } else {
@@ -1234,14 +1826,14 @@ impl FromTemplate for DerivPaths {
         index: &mut usize,
         payload: &[u8],
         payload_index: &mut usize,
-        key_map: &mut KeyMap,
+        ctx: &mut DecodeContext,
     ) -> Result<Self, Error> {
         if *index >= input.len() {
             return Err(Error::MissingBytes);
         }
 
-        let (len, size) = varint::decode(&input[*index..])
-            .map_err(|e| Error::InvalidVarInt(*index, e.to_string()))?;
+        let (len, size) = varint::decode_as::<usize>(&input[*index..])
+            .map_err(|e| Error::InvalidVarInt(*index, e))?;
 
         let current_index = *index;
         *index += size;
@@ -1253,7 +1845,7 @@ impl FromTemplate for DerivPaths {
                 index,
                 payload,
                 payload_index,
-                key_map,
+                ctx,
             )?);
         }
 
@@ -1271,7 +1863,7 @@ impl FromTemplate for Wildcard {
         index: &mut usize,
         _payload: &[u8],
         _payload_index: &mut usize,
-        _key_map: &mut KeyMap,
+        _ctx: &mut DecodeContext,
     ) -> Result<Self, Error> {
         if *index >= input.len() {
             return Err(Error::MissingBytes);
@@ -1303,18 +1895,17 @@ impl FromPayload for AbsLockTime {
         }
 
         let current_index = *payload_index;
-        let (after, size) = varint::decode(&payload[current_index..])
-            .map_err(|e| Error::InvalidPayload(current_index, e.to_string()))?;
+        let (after, size) = varint::decode_as::<u32>(&payload[current_index..])
+            .map_err(|e| Error::InvalidPayload(current_index, PayloadError::VarInt(e)))?;
 
         *payload_index += size;
 
-        if after > u32::MAX.into() {
-            return Err(Error::InvalidPayload(current_index, "overflow".into()));
-        }
-
-        match Self::from_consensus(after as u32) {
+        match Self::from_consensus(after) {
             Ok(after) => Ok(after),
-            Err(err) => Err(Error::InvalidPayload(current_index, err.to_string())),
+            Err(err) => Err(Error::InvalidLocktime(
+                current_index,
+                PayloadError::AbsLockTime(err),
+            )),
         }
     }
 }
@@ -1330,18 +1921,17 @@ impl FromPayload for RelLockTime {
         }
 
         let current_index = *payload_index;
-        let (older, size) = varint::decode(&payload[current_index..])
-            .map_err(|e| Error::InvalidPayload(current_index, e.to_string()))?;
+        let (older, size) = varint::decode_as::<u32>(&payload[current_index..])
+            .map_err(|e| Error::InvalidPayload(current_index, PayloadError::VarInt(e)))?;
 
         *payload_index += size;
 
-        if older > u32::MAX.into() {
-            return Err(Error::InvalidPayload(current_index, "overflow".into()));
-        }
-
-        match Self::from_consensus(older as u32) {
+        match Self::from_consensus(older) {
             Ok(older) => Ok(older),
-            Err(err) => Err(Error::InvalidPayload(current_index, err.to_string())),
+            Err(err) => Err(Error::InvalidLocktime(
+                current_index,
+                PayloadError::RelLockTime(err),
+            )),
         }
     }
 }
@@ -1452,7 +2042,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -1463,7 +2053,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -1474,7 +2064,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -1491,7 +2081,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -1505,7 +2095,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -1519,7 +2109,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -1533,10 +2123,32 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
+
+        // Path longer than the limit
+        let dp_long = DerivationPath::from(vec![ChildNumber::Normal { index: 0 }; 300]);
+        assert_eq!(
+            DerivationPath::from_template(
+                &template_of(dp_long.clone()),
+                &mut 0,
+                &[],
+                &mut 0,
+                &mut DecodeContext::default()
+            ),
+            Err(Error::PathTooLong(0))
+        );
+        let mut ctx = DecodeContext {
+            max_path_len: 300,
+            ..Default::default()
+        };
+        assert_eq!(
+            dp_long.clone(),
+            DerivationPath::from_template(&template_of(dp_long), &mut 0, &[], &mut 0, &mut ctx)
+                .unwrap()
+        );
     }
 
     #[test]
@@ -1551,7 +2163,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -1567,7 +2179,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -1584,7 +2196,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -1595,7 +2207,7 @@ mod tests {
                 &mut 0,
                 &payload_of(dpk1.clone()),
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -1609,7 +2221,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -1620,7 +2232,7 @@ mod tests {
                 &mut 0,
                 &payload_of(dpk2.clone()),
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -1634,7 +2246,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -1645,7 +2257,7 @@ mod tests {
                 &mut 0,
                 &payload_of(dpk_xonly.clone()),
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -1661,7 +2273,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -1672,7 +2284,7 @@ mod tests {
                 &mut 0,
                 &payload_of(dpk3.clone()),
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -1688,7 +2300,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -1699,7 +2311,7 @@ mod tests {
                 &mut 0,
                 &payload_of(dpk_xpub1.clone()),
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -1724,7 +2336,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -1735,7 +2347,7 @@ mod tests {
                 &mut 0,
                 &payload_of(dpk_xpub2.clone()),
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -1757,7 +2369,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -1768,7 +2380,7 @@ mod tests {
                 &mut 0,
                 &payload_of(dpk_multixpub1.clone()),
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -1778,24 +2390,42 @@ mod tests {
     fn test_descriptor_secret_key() {
         let secp = Secp256k1::new();
 
+        // Secret keys decode to placeholders, so derive them as the public decoders do
+        fn from_template_derived(
+            input: &[u8],
+            index: &mut usize,
+            payload: &[u8],
+            payload_index: &mut usize,
+            ctx: &mut DecodeContext,
+        ) -> Result<DescriptorPublicKey, Error> {
+            let placeholder =
+                DescriptorPublicKey::from_template(input, index, payload, payload_index, ctx)?;
+            let secret_key = ctx.key_map.remove(&placeholder).unwrap();
+            let public_key = secret_key
+                .to_public(&Secp256k1::new())
+                .unwrap_or(placeholder);
+            ctx.key_map.insert(public_key.clone(), secret_key);
+            Ok(public_key)
+        }
+
         // Single Key Compressed, No Origin
         let (_, dsk1) = create_dsk_single(true, None, 2);
         assert_eq!(
             create_dpk_single_full(true, None, 1).1,
-            DescriptorPublicKey::from_template(
+            from_template_derived(
                 &template_of(dsk1.clone()),
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
         let dpk1 = dsk1.to_public(&secp).unwrap();
-        let mut km = KeyMap::new();
+        let mut km = DecodeContext::default();
         assert_eq!(
             dpk1.clone(),
-            DescriptorPublicKey::from_template(
+            from_template_derived(
                 &template_of(dsk1.clone()),
                 &mut 0,
                 &payload_of(dsk1.clone()),
@@ -1804,26 +2434,26 @@ mod tests {
             )
             .unwrap()
         );
-        assert_eq!(&dsk1.clone(), km.get(&dpk1).unwrap());
+        assert_eq!(&dsk1.clone(), km.key_map.get(&dpk1).unwrap());
 
         // Single Key Uncompressed, No Origin
         let (_, dsk2) = create_dsk_single(false, None, 2);
         assert_eq!(
             create_dpk_single_full(false, None, 1).1,
-            DescriptorPublicKey::from_template(
+            from_template_derived(
                 &template_of(dsk2.clone()),
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
         let dpk2 = dsk2.to_public(&secp).unwrap();
-        let mut km = KeyMap::new();
+        let mut km = DecodeContext::default();
         assert_eq!(
             dpk2.clone(),
-            DescriptorPublicKey::from_template(
+            from_template_derived(
                 &template_of(dsk2.clone()),
                 &mut 0,
                 &payload_of(dsk2.clone()),
@@ -1832,7 +2462,7 @@ mod tests {
             )
             .unwrap()
         );
-        assert_eq!(&dsk2.clone(), km.get(&dpk2).unwrap());
+        assert_eq!(&dsk2.clone(), km.key_map.get(&dpk2).unwrap());
 
         // Single Key Compressed, With Origin
         let origin_fp = fp_from_str("12345678");
@@ -1840,20 +2470,20 @@ mod tests {
         let (_, dsk3) = create_dsk_single(true, Some((origin_fp, origin_path.clone())), 3);
         assert_eq!(
             create_dpk_single_full(true, Some((dummy::fp(), origin_path.clone())), 1).1,
-            DescriptorPublicKey::from_template(
+            from_template_derived(
                 &template_of(dsk3.clone()),
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
         let dpk3 = dsk3.to_public(&secp).unwrap();
-        let mut km = KeyMap::new();
+        let mut km = DecodeContext::default();
         assert_eq!(
             dpk3.clone(),
-            DescriptorPublicKey::from_template(
+            from_template_derived(
                 &template_of(dsk3.clone()),
                 &mut 0,
                 &payload_of(dsk3.clone()),
@@ -1862,7 +2492,7 @@ mod tests {
             )
             .unwrap()
         );
-        assert_eq!(&dsk3.clone(), km.get(&dpk3).unwrap());
+        assert_eq!(&dsk3.clone(), km.key_map.get(&dpk3).unwrap());
 
         // XPriv, No Origin, specific derivation path, NoWildcard
         let xpriv_path_str = "m/0/0";
@@ -1870,20 +2500,20 @@ mod tests {
         let (_, dsk_xpriv1) = create_dsk_xpriv(None, xpriv_path_str, xpriv, Wildcard::None);
         assert_eq!(
             create_dpk_xpub(None, xpriv_path_str, dummy::xpub(), Wildcard::None).1,
-            DescriptorPublicKey::from_template(
+            from_template_derived(
                 &template_of(dsk_xpriv1.clone()),
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
         let dpk_xpub1 = dsk_xpriv1.to_public(&secp).unwrap();
-        let mut km = KeyMap::new();
+        let mut km = DecodeContext::default();
         assert_eq!(
             dpk_xpub1.clone(),
-            DescriptorPublicKey::from_template(
+            from_template_derived(
                 &template_of(dsk_xpriv1.clone()),
                 &mut 0,
                 &payload_of(dsk_xpriv1.clone()),
@@ -1892,7 +2522,7 @@ mod tests {
             )
             .unwrap()
         );
-        assert_eq!(&dsk_xpriv1.clone(), km.get(&dpk_xpub1).unwrap());
+        assert_eq!(&dsk_xpriv1.clone(), km.key_map.get(&dpk_xpub1).unwrap());
 
         // XPub, With Origin, different derivation path, UnhardenedWildcard
         let (_, dsk_xpriv2) = create_dsk_xpriv(
@@ -1909,20 +2539,20 @@ mod tests {
                 Wildcard::Unhardened
             )
             .1,
-            DescriptorPublicKey::from_template(
+            from_template_derived(
                 &template_of(dsk_xpriv2.clone()),
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
         let dpk_xpub2 = dsk_xpriv2.to_public(&secp).unwrap();
-        let mut km = KeyMap::new();
+        let mut km = DecodeContext::default();
         assert_eq!(
             dpk_xpub2.clone(),
-            DescriptorPublicKey::from_template(
+            from_template_derived(
                 &template_of(dsk_xpriv2.clone()),
                 &mut 0,
                 &payload_of(dsk_xpriv2.clone()),
@@ -1931,27 +2561,27 @@ mod tests {
             )
             .unwrap()
         );
-        assert_eq!(&dsk_xpriv2.clone(), km.get(&dpk_xpub2).unwrap());
+        assert_eq!(&dsk_xpriv2.clone(), km.key_map.get(&dpk_xpub2).unwrap());
 
         // MultiXPub, No Origin, specific derivation paths, HardenedWildcard
         let multixpriv_paths_str = ["m/0/0", "m/0/1"];
         let (_, dsk_multixpriv1) =
             create_dsk_multixpriv(None, &multixpriv_paths_str, xpriv, Wildcard::Hardened);
         assert_eq!(
-            create_dpk_single_compressed_no_origin(1),
-            DescriptorPublicKey::from_template(
+            dummy::placeholder_dpk(0, true),
+            from_template_derived(
                 &template_of(dsk_multixpriv1.clone()),
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
-        let dpk_multixpub1 = create_dpk_single_compressed_no_origin(2);
+        let dpk_multixpub1 = dummy::placeholder_dpk(1, true);
         assert_eq!(
             dpk_multixpub1.clone(),
-            DescriptorPublicKey::from_template(
+            from_template_derived(
                 &template_of(dsk_multixpriv1.clone()),
                 &mut 0,
                 &payload_of(dsk_multixpriv1.clone()),
@@ -1960,7 +2590,10 @@ mod tests {
             )
             .unwrap()
         );
-        assert_eq!(&dsk_multixpriv1.clone(), km.get(&dpk_multixpub1).unwrap());
+        assert_eq!(
+            &dsk_multixpriv1.clone(),
+            km.key_map.get(&dpk_multixpub1).unwrap()
+        );
     }
 
     #[test]
@@ -1975,7 +2608,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -1988,7 +2621,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2001,7 +2634,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2014,7 +2647,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2029,7 +2662,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2040,7 +2673,7 @@ mod tests {
                 &mut 0,
                 &payload_of(ms_raw_pkh.clone()),
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2053,7 +2686,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2064,7 +2697,7 @@ mod tests {
                 &mut 0,
                 &payload_of(ms_hash160.clone()),
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2078,7 +2711,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2089,7 +2722,7 @@ mod tests {
                 &mut 0,
                 &payload_of(ms_after.clone()),
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2103,7 +2736,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2114,7 +2747,7 @@ mod tests {
                 &mut 0,
                 &payload_of(ms_older.clone()),
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2128,7 +2761,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2139,7 +2772,7 @@ mod tests {
                 &mut 0,
                 &payload_of(ms_sha256.clone()),
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2153,7 +2786,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2164,7 +2797,7 @@ mod tests {
                 &mut 0,
                 &payload_of(ms_hash256.clone()),
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2178,7 +2811,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2189,7 +2822,7 @@ mod tests {
                 &mut 0,
                 &payload_of(ms_ripemd160.clone()),
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2210,7 +2843,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2221,7 +2854,7 @@ mod tests {
                 &mut 0,
                 &payload_of(multi.clone()),
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2237,7 +2870,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2248,7 +2881,7 @@ mod tests {
                 &mut 0,
                 &payload_of(multi_a.clone()),
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2272,7 +2905,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2285,7 +2918,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2298,7 +2931,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2311,7 +2944,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2324,7 +2957,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2337,7 +2970,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2352,7 +2985,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2366,7 +2999,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2380,7 +3013,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2394,7 +3027,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2407,7 +3040,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2420,7 +3053,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2439,7 +3072,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2459,7 +3092,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2485,7 +3118,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2507,7 +3140,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2531,7 +3164,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2547,23 +3180,42 @@ mod tests {
         let bare = Bare::new(ms_bare_check_pkk.clone()).unwrap();
         assert_eq!(
             bare.clone(),
-            Bare::from_template(&template_of(bare), &mut 0, &[], &mut 0, &mut KeyMap::new())
-                .unwrap()
+            Bare::from_template(
+                &template_of(bare),
+                &mut 0,
+                &[],
+                &mut 0,
+                &mut DecodeContext::default()
+            )
+            .unwrap()
         );
 
         // Pkh
         let pkh = Pkh::new(pk_full.clone()).unwrap();
         assert_eq!(
             pkh.clone(),
-            Pkh::from_template(&template_of(pkh), &mut 0, &[], &mut 0, &mut KeyMap::new()).unwrap()
+            Pkh::from_template(
+                &template_of(pkh),
+                &mut 0,
+                &[],
+                &mut 0,
+                &mut DecodeContext::default()
+            )
+            .unwrap()
         );
 
         // Wpkh
         let wpkh = Wpkh::new(pk_full.clone()).unwrap();
         assert_eq!(
             wpkh.clone(),
-            Wpkh::from_template(&template_of(wpkh), &mut 0, &[], &mut 0, &mut KeyMap::new())
-                .unwrap()
+            Wpkh::from_template(
+                &template_of(wpkh),
+                &mut 0,
+                &[],
+                &mut 0,
+                &mut DecodeContext::default()
+            )
+            .unwrap()
         );
     }
 
@@ -2582,7 +3234,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2598,7 +3250,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2615,7 +3267,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2625,8 +3277,14 @@ mod tests {
         let sh_ms = Sh::new(ms_sh.clone()).unwrap();
         assert_eq!(
             sh_ms.clone(),
-            Sh::from_template(&template_of(sh_ms), &mut 0, &[], &mut 0, &mut KeyMap::new())
-                .unwrap()
+            Sh::from_template(
+                &template_of(sh_ms),
+                &mut 0,
+                &[],
+                &mut 0,
+                &mut DecodeContext::default()
+            )
+            .unwrap()
         );
     }
 
@@ -2647,7 +3305,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2662,7 +3320,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2681,7 +3339,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2697,7 +3355,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2725,7 +3383,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2736,7 +3394,7 @@ mod tests {
                 &mut 0,
                 &payload_of(descriptor2.clone()),
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2753,7 +3411,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2764,7 +3422,7 @@ mod tests {
                 &mut 0,
                 &payload_of(descriptor2.clone()),
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2779,7 +3437,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2796,7 +3454,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2807,7 +3465,7 @@ mod tests {
                 &mut 0,
                 &payload_of(descriptor2.clone()),
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2822,7 +3480,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2839,7 +3497,7 @@ mod tests {
                 &mut 0,
                 &[],
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2850,7 +3508,7 @@ mod tests {
                 &mut 0,
                 &payload_of(descriptor2.clone()),
                 &mut 0,
-                &mut KeyMap::new()
+                &mut DecodeContext::default()
             )
             .unwrap()
         );
@@ -2886,4 +3544,165 @@ mod tests {
             Ok((descriptor, KeyMap::new()))
         );
     }
+
+    #[test]
+    fn test_invalid_locktime() {
+        let decode_after =
+            |value: u32| AbsLockTime::from_payload(&varint::encode(value as u128), &mut 0);
+        let decode_older =
+            |value: u32| RelLockTime::from_payload(&varint::encode(value as u128), &mut 0);
+
+        // Height and time locks, including the CSV type flag and unused bits, are kept exactly
+        for value in [1, 499_999_999, 500_000_000, 0x8000_0000] {
+            assert_eq!(decode_after(value).unwrap().to_consensus_u32(), value);
+        }
+        for value in [1, 0xFFFF, 0x0040_0001, 0x7FFF_FFFF] {
+            assert_eq!(decode_older(value).unwrap().to_consensus_u32(), value);
+        }
+
+        for value in [0, 0x8000_0001, u32::MAX] {
+            assert!(matches!(
+                decode_after(value),
+                Err(Error::InvalidLocktime(0, PayloadError::AbsLockTime(_)))
+            ));
+        }
+        for value in [0, 0x8000_0000, u32::MAX] {
+            assert!(matches!(
+                decode_older(value),
+                Err(Error::InvalidLocktime(0, PayloadError::RelLockTime(_)))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_invalid_multipath() {
+        let multipath = |paths: &[&[u128]]| {
+            let mut template = vec![Tag::MultiXPub.value(), Tag::NoOrigin.value()];
+            template.extend(varint::encode(paths.len() as u128));
+            for path in paths {
+                template.extend(varint::encode(path.len() as u128));
+                for child in *path {
+                    template.extend(varint::encode(child << 1));
+                }
+            }
+            template.push(Tag::UnhardenedWildcard.value());
+            template
+        };
+        let xpub = dummy::xpub().encode();
+
+        let valid: [&[&[u128]]; 3] = [
+            &[&[0], &[1]],
+            &[&[7, 0], &[7, 1], &[7, 2]],
+            &[&[0, 5], &[1, 5]],
+        ];
+        for paths in valid {
+            let template = [vec![Tag::Wpkh.value()], multipath(paths)].concat();
+            let (descriptor, _) = decode_with_payload(&template, &xpub).unwrap();
+            let reparsed = Descriptor::<DescriptorPublicKey>::from_str(&descriptor.to_string());
+            assert_eq!(reparsed.unwrap(), descriptor);
+        }
+
+        let invalid: [&[&[u128]]; 5] = [
+            &[&[0]],
+            &[&[0], &[0]],
+            &[&[0], &[1, 1]],
+            &[&[0, 0], &[1, 1]],
+            &[&[0, 0], &[0, 0], &[0, 1]],
+        ];
+        for paths in invalid {
+            let template = [vec![Tag::Wpkh.value()], multipath(paths)].concat();
+            assert_eq!(
+                decode_with_payload(&template, &xpub),
+                Err(Error::InvalidMultipath(0))
+            );
+            assert_eq!(decode_template(&template), Err(Error::InvalidMultipath(0)));
+        }
+
+        // Reports the index of the offending key
+        let mut template = vec![Tag::Wsh.value(), Tag::Multi.value()];
+        template.extend(varint::encode(1));
+        template.extend(varint::encode(2));
+        template.extend([Tag::CompressedFullKey.value(), Tag::NoOrigin.value()]);
+        template.extend(multipath(&[&[0], &[1, 1]]));
+        let payload = [dummy::pk_at_index(1).serialize().to_vec(), xpub.to_vec()].concat();
+        assert_eq!(
+            decode_with_payload(&template, &payload),
+            Err(Error::InvalidMultipath(1))
+        );
+    }
+
+    #[test]
+    fn test_varint_overflow() {
+        // Timelock that does not fit in a u32
+        let template = [Tag::Wsh.value(), Tag::After.value()];
+        let payload = varint::encode(u128::from(u32::MAX) + 1);
+        assert_eq!(
+            decode_with_payload(&template, &payload),
+            Err(Error::InvalidPayload(
+                0,
+                PayloadError::VarInt(varint::Error::Overflow)
+            ))
+        );
+
+        // Child number that does not fit in a u32
+        let mut template = vec![Tag::Pkh.value(), Tag::CompressedFullKey.value()];
+        template.push(Tag::Origin.value());
+        template.extend(varint::encode(1));
+        template.extend(varint::encode((u128::from(u32::MAX) + 1) << 1));
+        assert_eq!(
+            decode_template(&template),
+            Err(Error::InvalidVarInt(4, varint::Error::Overflow))
+        );
+    }
+
+    #[test]
+    fn test_streaming_decoder() {
+        let single =
+            "wpkh(0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798)#ucxz0gak";
+        let taproot = "tr(79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798,pk(c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5))";
+        let taproot = crate::decode(&crate::encode(taproot).unwrap()).unwrap();
+        let frame = StreamingDecoder::frame(&crate::encode(single).unwrap());
+
+        // Bytes arrive a few at a time
+        let mut decoder = StreamingDecoder::new();
+        assert_eq!(decoder.push(&[]), Ok(StreamStatus::NeedMore(1)));
+        let (last, chunks) = frame.split_last().unwrap();
+        for chunk in chunks.chunks(5) {
+            assert!(matches!(decoder.push(chunk), Ok(StreamStatus::NeedMore(_))));
+        }
+        assert_eq!(decoder.push(&[]), Ok(StreamStatus::NeedMore(1)));
+        assert_eq!(
+            decoder.push(&[*last]),
+            Ok(StreamStatus::Complete(single.to_string()))
+        );
+        assert_eq!(decoder.buffered(), 0);
+
+        // A prefix of a taproot encoding is valid, so only the frame tells where it ends
+        let mut frames = StreamingDecoder::frame(&crate::encode(&taproot).unwrap());
+        frames.extend(&frame);
+        assert_eq!(
+            decoder.push(&frames),
+            Ok(StreamStatus::Complete(taproot.clone()))
+        );
+        assert_eq!(
+            decoder.poll(),
+            Ok(StreamStatus::Complete(single.to_string()))
+        );
+        assert_eq!(decoder.poll(), Ok(StreamStatus::NeedMore(1)));
+
+        // An invalid frame is dropped, keeping the next
+        let mut frames = StreamingDecoder::frame(&[0xFF]);
+        frames.extend(&frame);
+        assert!(decoder.push(&frames).is_err());
+        assert_eq!(decoder.buffered(), frame.len());
+        assert_eq!(
+            decoder.poll(),
+            Ok(StreamStatus::Complete(single.to_string()))
+        );
+
+        // An oversized frame drops everything
+        let mut decoder = StreamingDecoder::with_max_len(frame.len() - 2);
+        assert_eq!(decoder.push(&frame), Err(Error::Oversized));
+        assert_eq!(decoder.buffered(), 0);
+    }
 }
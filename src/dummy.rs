@@ -3,10 +3,21 @@
 use bitcoin::{
     NetworkKind,
     bip32::{Fingerprint, Xpriv, Xpub},
-    hashes::{Hash, hash160, ripemd160, sha256, sha256d},
-    secp256k1::{PublicKey as SecpPublicKey, Secp256k1, SecretKey},
+    hashes::{Hash, HashEngine, hash160, ripemd160, sha256, sha256d},
+    secp256k1::{PublicKey as SecpPublicKey, SecretKey},
 };
-use miniscript::{AbsLockTime, RelLockTime, hash256};
+use miniscript::{
+    AbsLockTime, RelLockTime,
+    descriptor::{DescriptorPublicKey, SinglePub, SinglePubKey},
+    hash256,
+};
+use std::str::FromStr;
+
+/// Public key of the secret key 1, which is the generator point
+const PK: &str = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+
+/// Extended public key of [`xpriv`], so dummy keys need no secp256k1 context
+const XPUB: &str = "xpub661MyMwAqRbcFhCvdhTAfpEEDV58oqDvv65YNHC686NNs4KbH8YZQJWVmrfbve7aAVHzxw8bKFxA7MLeDK6BbLfkE3bqkvHLPgaGHHtYGeY";
 
 pub fn sk_at_index(index: u32) -> SecretKey {
     let mut sk_bytes = [0u8; 32];
@@ -14,22 +25,55 @@ pub fn sk_at_index(index: u32) -> SecretKey {
     SecretKey::from_slice(&sk_bytes).unwrap()
 }
 
+#[cfg(test)]
 pub fn pk_at_index(index: u32) -> SecpPublicKey {
-    let secp = Secp256k1::new();
+    let secp = bitcoin::secp256k1::Secp256k1::new();
     SecpPublicKey::from_secret_key(&secp, &sk_at_index(index))
 }
 
+/// Returns a public key with an unknown discrete log, used in place of the public key of a secret
+/// key whose derivation has been deferred. Found by hashing to a valid x-coordinate, so no EC
+/// multiplication is needed and collisions with real keys are infeasible.
+pub fn placeholder_pk(index: u32) -> SecpPublicKey {
+    let mut counter = 0u32;
+    loop {
+        let mut engine = sha256::Hash::engine();
+        engine.input(b"descriptor-codec/placeholder");
+        engine.input(&index.to_be_bytes());
+        engine.input(&counter.to_be_bytes());
+        let x = sha256::Hash::from_engine(engine);
+
+        let mut bytes = [0x02; 33];
+        bytes[1..].copy_from_slice(x.as_byte_array());
+        if let Ok(pk) = SecpPublicKey::from_slice(&bytes) {
+            return pk;
+        }
+
+        counter += 1;
+    }
+}
+
+/// Returns a placeholder descriptor public key, see [`placeholder_pk`]
+pub fn placeholder_dpk(index: u32, compressed: bool) -> DescriptorPublicKey {
+    DescriptorPublicKey::Single(SinglePub {
+        key: SinglePubKey::FullKey(bitcoin::PublicKey {
+            inner: placeholder_pk(index),
+            compressed,
+        }),
+        origin: None,
+    })
+}
+
 pub fn sk() -> SecretKey {
     sk_at_index(1)
 }
 
 pub fn pk() -> SecpPublicKey {
-    pk_at_index(1)
+    SecpPublicKey::from_str(PK).expect("valid public key")
 }
 
 pub fn xpub() -> Xpub {
-    let secp = Secp256k1::new();
-    Xpub::from_priv(&secp, &xpriv())
+    Xpub::from_str(XPUB).expect("valid xpub")
 }
 
 pub fn xpriv() -> Xpriv {
@@ -63,3 +107,17 @@ pub fn after() -> AbsLockTime {
 pub fn older() -> RelLockTime {
     RelLockTime::from_consensus(1).unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_constants() {
+        assert_eq!(pk(), pk_at_index(1));
+        assert_eq!(
+            xpub(),
+            Xpub::from_priv(&bitcoin::secp256k1::Secp256k1::new(), &xpriv())
+        );
+    }
+}
@@ -0,0 +1,49 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! # Typed Encoding
+//!
+//! Entry points for wallets that already hold a parsed descriptor and key map, so encoding never
+//! serializes and re-parses a string.
+
+use super::*;
+
+/// Encodes a descriptor and key map, as returned by `Descriptor::parse_descriptor`
+///
+/// Gives the same bytes as [`encode`](crate::encode) on the descriptor's string form. Fails only
+/// with [`PathTooLong`](encoder::Error::PathTooLong), if a path has more steps than a key can
+/// record.
+pub fn encode_descriptor(
+    descriptor: &Descriptor<DescriptorPublicKey>,
+    key_map: &KeyMap,
+) -> Result<Vec<u8>, encoder::Error> {
+    encode_parsed(descriptor, key_map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_descriptor() {
+        let secp = secp256k1::Secp256k1::new();
+        let desc_str = "wpkh([73c5da0a/84'/0'/0']xprv9s21ZrQH143K31xYSDQpPDxsXRTUcvj2iNHm5NUtrGiGG5e2DtALGdso3pGz6ssrdK4PFmM8NSpSBHNqPqm55Qn3LqFtT2emdEXVYsCzC2U/0/*)";
+        let (descriptor, key_map) = Descriptor::parse_descriptor(&secp, desc_str).unwrap();
+        let encoded = encode_descriptor(&descriptor, &key_map).unwrap();
+        assert_eq!(encoded, crate::encode(desc_str).unwrap());
+        assert_eq!(encoded, encode_parsed(&descriptor, &key_map).unwrap());
+    }
+
+    #[test]
+    fn test_encode_descriptor_path_too_long() {
+        let secp = secp256k1::Secp256k1::new();
+        let path = "/0".repeat(decoder::DEFAULT_MAX_PATH_LEN + 1);
+        let desc_str = format!(
+            "wpkh(xpub661MyMwAqRbcGczjuMoRm6dXaLDEhW1u34gKenbeYqAix21mdUKJyuyu5F1rzYGVxyL6tmgBUAEPrEz92mBXjByMRiJdba9wpnN37RLLAXa{path})"
+        );
+        let (descriptor, key_map) = Descriptor::parse_descriptor(&secp, &desc_str).unwrap();
+        assert!(matches!(
+            encode_descriptor(&descriptor, &key_map),
+            Err(encoder::Error::PathTooLong(0))
+        ));
+    }
+}
@@ -0,0 +1,75 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! # Encoded Descriptor
+
+use super::*;
+
+/// A validated encoded descriptor
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct EncodedDescriptor(Vec<u8>);
+
+impl EncodedDescriptor {
+    /// Returns the encoded bytes
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Decodes the descriptor
+    pub fn decode(&self) -> Result<String, Error> {
+        decode(&self.0)
+    }
+}
+
+impl TryFrom<&str> for EncodedDescriptor {
+    type Error = encoder::Error;
+
+    /// Parses and encodes a Bitcoin descriptor
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        encode(s).map(Self)
+    }
+}
+
+impl TryFrom<&[u8]> for EncodedDescriptor {
+    type Error = Error;
+
+    /// Checks that the bytes hold a valid encoded descriptor, without deriving keys
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        decoder::decode_bytes_underived(bytes, decoder::DecodeContext::default())?;
+        Ok(Self(bytes.to_vec()))
+    }
+}
+
+impl From<EncodedDescriptor> for Vec<u8> {
+    fn from(encoded: EncodedDescriptor) -> Self {
+        encoded.0
+    }
+}
+
+impl AsRef<[u8]> for EncodedDescriptor {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_try_from() {
+        let desc_str =
+            "wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)#8zl0zxma";
+        let encoded = EncodedDescriptor::try_from(desc_str).unwrap();
+        assert_eq!(encoded.as_bytes(), encode(desc_str).unwrap());
+        assert_eq!(encoded.decode().unwrap(), desc_str);
+
+        let bytes: Vec<u8> = encoded.clone().into();
+        assert_eq!(EncodedDescriptor::try_from(bytes.as_slice()), Ok(encoded));
+
+        assert!(EncodedDescriptor::try_from("wpkh(invalid)").is_err());
+        assert_eq!(
+            EncodedDescriptor::try_from(&bytes[..bytes.len() - 1]),
+            Err(Error::MissingBytes)
+        );
+    }
+}
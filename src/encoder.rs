@@ -2,295 +2,854 @@
 
 //! # Descriptor Encode
 
+use super::extension::{CUSTOM_TAGS, TagExtension};
+use super::key_codec::{DefaultKeyCodec, KeyCodec};
 use super::tag::Tag;
 use super::varint;
 
 use bitcoin::{
-    bip32::{ChildNumber, DerivationPath, Xpriv, Xpub},
-    hashes::Hash,
+    NetworkKind,
+    bip32::{ChildNumber, DerivationPath, Fingerprint, Xpriv, Xpub},
+    hashes::{Hash, sha256},
+    hex::HexToArrayError,
 };
 use miniscript::{
     Miniscript, ScriptContext, Threshold,
     descriptor::{
-        Bare, DerivPaths, Descriptor, DescriptorMultiXKey, DescriptorPublicKey,
-        DescriptorSecretKey, DescriptorXKey, KeyMap, Pkh, Sh, ShInner, SinglePubKey,
-        SortedMultiVec, TapTree, Tr, Wildcard, Wpkh, Wsh, WshInner,
+        Bare, DerivPaths, Descriptor, DescriptorKeyParseError, DescriptorMultiXKey,
+        DescriptorPublicKey, DescriptorSecretKey, DescriptorXKey, KeyMap, Pkh, Sh, ShInner,
+        SinglePubKey, SortedMultiVec, TapTree, Tr, Wildcard, Wpkh, Wsh, WshInner,
     },
     miniscript::decode::Terminal,
 };
-use std::fmt::Debug;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::error;
+use std::fmt::{self, Debug};
+use std::io;
 use std::sync::Arc;
 
+/// Error
+#[derive(Debug, PartialEq)]
+#[non_exhaustive]
+pub enum Error {
+    /// Invalid descriptor
+    InvalidDescriptor(miniscript::Error),
+    /// Invalid key
+    InvalidKey(DescriptorKeyParseError),
+    /// Invalid hash
+    InvalidHash(HexToArrayError),
+    /// Key is not a master extended private key
+    NotMasterKey,
+    /// Checksum is missing or wrong, with the expected checksum
+    InvalidChecksum(String),
+    /// Uncompressed key in a segwit context, by key index, which would make the backup unspendable
+    UncompressedKey(usize),
+    /// Key decodes differently than it was encoded, by key index
+    KeyMismatch(usize),
+    /// Derivation path has more steps than allowed, by key index
+    PathTooLong(usize),
+    /// Template uses a name with no bound key
+    UnboundName(String),
+    /// Bound key is not used by the template, by name
+    UnusedBinding(String),
+    /// Number in a key path written with leading zeros or a sign, such as `007`
+    NonCanonicalNumber(String),
+    /// Output buffer is smaller than the encoding, with the size of the encoding
+    BufferTooSmall(usize),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::InvalidDescriptor(_) => write!(f, "invalid descriptor"),
+            Self::InvalidKey(_) => write!(f, "invalid key"),
+            Self::InvalidHash(_) => write!(f, "invalid hash"),
+            Self::NotMasterKey => write!(f, "not a master extended private key"),
+            Self::InvalidChecksum(expected) => write!(f, "invalid checksum (expected: {expected})"),
+            Self::KeyMismatch(idx) => write!(f, "key does not round-trip (key index: {idx})"),
+            Self::PathTooLong(idx) => write!(f, "derivation path too long (key index: {idx})"),
+            Self::UnboundName(name) => write!(f, "no key bound to ${name}"),
+            Self::UnusedBinding(name) => write!(f, "key bound to ${name} is not used"),
+            Self::NonCanonicalNumber(number) => {
+                write!(f, "non-canonical number in key path: {number}")
+            }
+            Self::BufferTooSmall(len) => write!(f, "buffer too small (needed: {len})"),
+            Self::UncompressedKey(idx) => {
+                write!(
+                    f,
+                    "uncompressed key not allowed in segwit (key index: {idx})"
+                )
+            }
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::InvalidDescriptor(err) => Some(err),
+            Self::InvalidKey(err) => Some(err),
+            Self::InvalidHash(err) => Some(err),
+            Self::NotMasterKey
+            | Self::InvalidChecksum(_)
+            | Self::UncompressedKey(_)
+            | Self::KeyMismatch(_)
+            | Self::PathTooLong(_)
+            | Self::UnboundName(_)
+            | Self::UnusedBinding(_)
+            | Self::NonCanonicalNumber(_)
+            | Self::BufferTooSmall(_) => None,
+        }
+    }
+}
+
+impl From<miniscript::Error> for Error {
+    fn from(err: miniscript::Error) -> Self {
+        Self::InvalidDescriptor(err)
+    }
+}
+
+impl From<DescriptorKeyParseError> for Error {
+    fn from(err: DescriptorKeyParseError) -> Self {
+        Self::InvalidKey(err)
+    }
+}
+
+impl From<HexToArrayError> for Error {
+    fn from(err: HexToArrayError) -> Self {
+        Self::InvalidHash(err)
+    }
+}
+
 /// Encodes a descriptor as a template with a separate payload, containing the keys,
 /// fingerprints, hashes, and timelocks.
 pub fn encode(descriptor: Descriptor<DescriptorPublicKey>, key_map: &KeyMap) -> (Vec<u8>, Vec<u8>) {
-    let mut template = Vec::new();
-    let mut payload = Vec::new();
-    descriptor.encode_template(&mut template, &mut payload, key_map);
+    let ctx = EncodeContext::new(key_map);
+    let stats = measure(&descriptor, &ctx);
+
+    let mut template = Vec::with_capacity(stats.template_len);
+    let mut payload = Vec::with_capacity(stats.payload_len);
+    descriptor.encode_template(&mut template, &mut payload, &ctx);
 
     (template, payload)
 }
 
+/// Encodes a descriptor and key map into a fixed buffer, returning the number of bytes written
+///
+/// Writes the same bytes as [`encode_parsed`](crate::encode_parsed) to the start of `out`,
+/// without allocating an output vector. Fails with [`Error::BufferTooSmall`] before writing
+/// anything if the encoding does not fit.
+pub fn encode_parsed_into_slice(
+    descriptor: &Descriptor<DescriptorPublicKey>,
+    key_map: &KeyMap,
+    out: &mut [u8],
+) -> Result<usize, Error> {
+    super::check_path_lens(descriptor, key_map, super::decoder::DEFAULT_MAX_PATH_LEN)?;
+
+    let ctx = EncodeContext::new(key_map);
+    let stats = measure(descriptor, &ctx);
+    if stats.len() > out.len() {
+        return Err(Error::BufferTooSmall(stats.len()));
+    }
+
+    let (template, payload) = out[..stats.len()].split_at_mut(stats.template_len);
+    let mut template = SliceSink::new(template);
+    let mut payload = SliceSink::new(payload);
+    descriptor.encode_template(&mut template, &mut payload, &ctx);
+
+    Ok(stats.len())
+}
+
+/// Sink that writes into a buffer sized by [`measure`]
+struct SliceSink<'b> {
+    buf: &'b mut [u8],
+    len: usize,
+    /// Reused buffer for key codec output
+    scratch: Vec<u8>,
+}
+
+impl<'b> SliceSink<'b> {
+    fn new(buf: &'b mut [u8]) -> Self {
+        Self {
+            buf,
+            len: 0,
+            scratch: Vec::new(),
+        }
+    }
+}
+
+impl Sink for SliceSink<'_> {
+    fn push_tag(&mut self, tag: Tag) {
+        self.write(&[tag.value()]);
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.buf[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+    }
+
+    fn write_varint(&mut self, mut n: u128) {
+        while n >> 7 > 0 {
+            self.write(&[n.to_le_bytes()[0] | 0b1000_0000]);
+            n >>= 7;
+        }
+        self.write(&[n.to_le_bytes()[0]]);
+    }
+
+    fn write_with(&mut self, f: impl FnOnce(&mut Vec<u8>)) {
+        let mut scratch = std::mem::take(&mut self.scratch);
+        scratch.clear();
+        f(&mut scratch);
+        self.write(&scratch);
+        self.scratch = scratch;
+    }
+}
+
+/// Error encoding a descriptor into an [`io::Write`]
+#[derive(Debug)]
+pub enum WriteError {
+    /// Descriptor cannot be encoded
+    Encode(Error),
+    /// Writer failed
+    Io(io::Error),
+}
+
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Encode(_) => write!(f, "cannot encode descriptor"),
+            Self::Io(_) => write!(f, "cannot write encoding"),
+        }
+    }
+}
+
+impl error::Error for WriteError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Encode(err) => Some(err),
+            Self::Io(err) => Some(err),
+        }
+    }
+}
+
+impl From<Error> for WriteError {
+    fn from(err: Error) -> Self {
+        Self::Encode(err)
+    }
+}
+
+impl From<io::Error> for WriteError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+/// Encodes a descriptor and key map into a writer, returning the number of bytes written
+///
+/// Writes the same bytes as [`encode_parsed`](crate::encode_parsed). The template is written as
+/// it is encoded and only the payload is buffered, so no vector of the whole encoding is built.
+/// Writes are small, so a file or socket should be wrapped in an [`io::BufWriter`]. If the
+/// writer fails, encoding stops and part of the encoding may have been written.
+pub fn encode_to_writer<W: io::Write>(
+    descriptor: &Descriptor<DescriptorPublicKey>,
+    key_map: &KeyMap,
+    writer: &mut W,
+) -> Result<usize, WriteError> {
+    super::check_path_lens(descriptor, key_map, super::decoder::DEFAULT_MAX_PATH_LEN)?;
+
+    let ctx = EncodeContext::new(key_map);
+    let mut payload = Vec::new();
+    let mut template = WriterSink::new(writer);
+    let mut payload_sink = WriterSink::new(&mut payload);
+    descriptor.encode_template(&mut template, &mut payload_sink, &ctx);
+    let template_len = template.finish()?;
+    payload_sink.finish()?;
+
+    writer.write_all(&payload)?;
+    Ok(template_len + payload.len())
+}
+
+/// Sink that writes through to an [`io::Write`], stopping at the first error
+struct WriterSink<'w> {
+    writer: &'w mut dyn io::Write,
+    len: usize,
+    result: io::Result<()>,
+    /// Reused buffer for key codec output
+    scratch: Vec<u8>,
+}
+
+impl<'w> WriterSink<'w> {
+    fn new(writer: &'w mut dyn io::Write) -> Self {
+        Self {
+            writer,
+            len: 0,
+            result: Ok(()),
+            scratch: Vec::new(),
+        }
+    }
+
+    /// Returns the number of bytes written, or the first error
+    fn finish(self) -> io::Result<usize> {
+        self.result.map(|_| self.len)
+    }
+}
+
+impl Sink for WriterSink<'_> {
+    fn push_tag(&mut self, tag: Tag) {
+        self.write(&[tag.value()]);
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        if self.result.is_ok() {
+            self.result = self.writer.write_all(bytes);
+            self.len += bytes.len();
+        }
+    }
+
+    fn write_varint(&mut self, n: u128) {
+        self.write(&varint::encode(n));
+    }
+
+    fn write_with(&mut self, f: impl FnOnce(&mut Vec<u8>)) {
+        let mut scratch = std::mem::take(&mut self.scratch);
+        scratch.clear();
+        f(&mut scratch);
+        self.write(&scratch);
+        self.scratch = scratch;
+    }
+}
+
+/// Sizes and tags of an encoded descriptor
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Length of the template, including any header tags
+    pub template_len: usize,
+    /// Length of the payload, including any metadata records
+    pub payload_len: usize,
+    /// Number of times each tag appears in the descriptor's template, by name, before any
+    /// preset compression and excluding header tags
+    pub tags: BTreeMap<&'static str, usize>,
+}
+
+impl Stats {
+    /// Returns the total length of the encoding
+    pub fn len(&self) -> usize {
+        self.template_len + self.payload_len
+    }
+
+    /// Returns whether the encoding is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Measures the template and payload of a descriptor without writing them
+pub(crate) fn measure(descriptor: &Descriptor<DescriptorPublicKey>, ctx: &EncodeContext) -> Stats {
+    let mut template = Counter::default();
+    let mut payload = Counter::default();
+    descriptor.encode_template(&mut template, &mut payload, ctx);
+
+    let tags = (0..=u8::MAX)
+        .zip(template.tags)
+        .filter(|(_, count)| *count > 0)
+        .map(|(value, count)| (Tag::from(value).name(), count))
+        .collect();
+
+    Stats {
+        template_len: template.len,
+        payload_len: payload.len,
+        tags,
+    }
+}
+
+/// Destination of encoded bytes
+pub(crate) trait Sink {
+    /// Appends a tag
+    fn push_tag(&mut self, tag: Tag);
+
+    /// Appends bytes
+    fn write(&mut self, bytes: &[u8]);
+
+    /// Appends the LEB128 encoding of `n`
+    fn write_varint(&mut self, n: u128);
+
+    /// Appends the bytes written to a vector by `f`, for key codecs
+    fn write_with(&mut self, f: impl FnOnce(&mut Vec<u8>));
+}
+
+impl Sink for Vec<u8> {
+    fn push_tag(&mut self, tag: Tag) {
+        self.push(tag.value());
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.extend_from_slice(bytes);
+    }
+
+    fn write_varint(&mut self, n: u128) {
+        varint::encode_to_vec(n, self);
+    }
+
+    fn write_with(&mut self, f: impl FnOnce(&mut Vec<u8>)) {
+        f(self);
+    }
+}
+
+/// Sink that only counts the bytes and tags written to it
+struct Counter {
+    len: usize,
+    /// Number of each tag, by value
+    tags: [usize; 256],
+    /// Reused buffer for key codec output
+    scratch: Vec<u8>,
+}
+
+impl Default for Counter {
+    fn default() -> Self {
+        Self {
+            len: 0,
+            tags: [0; 256],
+            scratch: Vec::new(),
+        }
+    }
+}
+
+impl Sink for Counter {
+    fn push_tag(&mut self, tag: Tag) {
+        self.len += 1;
+        self.tags[tag.value() as usize] += 1;
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.len += bytes.len();
+    }
+
+    fn write_varint(&mut self, n: u128) {
+        self.len += varint::encoded_len(n);
+    }
+
+    fn write_with(&mut self, f: impl FnOnce(&mut Vec<u8>)) {
+        self.scratch.clear();
+        f(&mut self.scratch);
+        self.len += self.scratch.len();
+    }
+}
+
+/// Number of payload hash bytes committed to by the `PayloadCommitment` tag
+pub(crate) const COMMITMENT_SIZE: usize = 4;
+
+/// Returns the leading bytes of the SHA256 hash of a payload
+pub(crate) fn payload_commitment(payload: &[u8]) -> [u8; COMMITMENT_SIZE] {
+    let hash = sha256::Hash::hash(payload).to_byte_array();
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+/// Prefixes a template with a commitment to its payload
+pub(crate) fn commit_payload(template: &mut Vec<u8>, payload: &[u8]) {
+    let header = std::iter::once(Tag::PayloadCommitment.value()).chain(payload_commitment(payload));
+    template.splice(0..0, header);
+}
+
+/// Returns the length of the header tags at the start of a template, which precede the
+/// descriptor or preset ID
+pub(crate) fn header_len(template: &[u8]) -> usize {
+    let mut len = 0;
+    if template.first() == Some(&Tag::PayloadCommitment.value()) {
+        len += 1 + COMMITMENT_SIZE;
+    }
+    if template.get(len) == Some(&Tag::Metadata.value()) {
+        len += 1;
+    }
+    len
+}
+
+/// State shared while encoding a descriptor
+pub(crate) struct EncodeContext<'a> {
+    /// Secret keys, by the public key that stands in for them
+    pub key_map: &'a KeyMap,
+    /// Key serialization, if not the default
+    pub key_codec: Option<&'a dyn KeyCodec>,
+    /// Encoder for custom tags, if any
+    pub tag_extension: Option<&'a dyn TagExtension>,
+    /// Distinct hashes written so far, in order, which later copies refer back to
+    pub hashes: RefCell<Vec<(Tag, Vec<u8>)>>,
+}
+
+impl<'a> EncodeContext<'a> {
+    pub fn new(key_map: &'a KeyMap) -> Self {
+        Self {
+            key_map,
+            key_codec: None,
+            tag_extension: None,
+            hashes: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Writes a hash fragment, or a `HashRef` to an earlier copy of the same hash
+    fn encode_hash<S: Sink>(&self, tag: Tag, hash: &[u8], template: &mut S, payload: &mut S) {
+        let mut hashes = self.hashes.borrow_mut();
+        match hashes.iter().position(|(t, h)| *t == tag && h == hash) {
+            Some(index) => {
+                template.push_tag(Tag::HashRef);
+                template.write_varint(index as u128);
+            }
+            None => {
+                hashes.push((tag, hash.to_vec()));
+                template.push_tag(tag);
+                payload.write(hash);
+            }
+        }
+    }
+
+    fn key_codec(&self) -> &dyn KeyCodec {
+        self.key_codec.unwrap_or(&DefaultKeyCodec)
+    }
+}
+
 /// A trait to create an encoded template
 pub(crate) trait EncodeTemplate: Debug + PartialEq {
-    fn encode_template(&self, template: &mut Vec<u8>, payload: &mut Vec<u8>, key_map: &KeyMap);
+    fn encode_template<S: Sink>(&self, template: &mut S, payload: &mut S, ctx: &EncodeContext);
 }
 
 impl EncodeTemplate for Descriptor<DescriptorPublicKey> {
-    fn encode_template(&self, template: &mut Vec<u8>, payload: &mut Vec<u8>, key_map: &KeyMap) {
+    fn encode_template<S: Sink>(&self, template: &mut S, payload: &mut S, ctx: &EncodeContext) {
+        // Each pass over a descriptor, measuring or writing, starts with no hashes seen
+        ctx.hashes.borrow_mut().clear();
+        if let Some(tag_extension) = ctx.tag_extension {
+            let (mut custom_template, mut custom_payload) = (Vec::new(), Vec::new());
+            if ctx.key_map.is_empty()
+                && tag_extension.encode(self, &mut custom_template, &mut custom_payload)
+            {
+                assert!(
+                    custom_template
+                        .first()
+                        .is_some_and(|tag| CUSTOM_TAGS.contains(tag)),
+                    "tag extension wrote a template not starting with a custom tag"
+                );
+                template.write(&custom_template);
+                payload.write(&custom_payload);
+                return;
+            }
+        }
+
         match self {
-            Descriptor::Sh(sh) => sh.encode_template(template, payload, key_map),
-            Descriptor::Wsh(wsh) => wsh.encode_template(template, payload, key_map),
-            Descriptor::Tr(tr) => tr.encode_template(template, payload, key_map),
-            Descriptor::Wpkh(wpkh) => wpkh.encode_template(template, payload, key_map),
-            Descriptor::Pkh(pk) => pk.encode_template(template, payload, key_map),
-            Descriptor::Bare(bare) => bare.encode_template(template, payload, key_map),
+            Descriptor::Sh(sh) => sh.encode_template(template, payload, ctx),
+            Descriptor::Wsh(wsh) => wsh.encode_template(template, payload, ctx),
+            Descriptor::Tr(tr) => tr.encode_template(template, payload, ctx),
+            Descriptor::Wpkh(wpkh) => {
+                if let Some((xpub, fingerprint)) = single_sig_shape(wpkh, ctx) {
+                    encode_single_sig(xpub, fingerprint, template, payload);
+                } else {
+                    wpkh.encode_template(template, payload, ctx)
+                }
+            }
+            Descriptor::Pkh(pk) => pk.encode_template(template, payload, ctx),
+            Descriptor::Bare(bare) => bare.encode_template(template, payload, ctx),
         };
     }
 }
 
+/// Returns the xpub and origin fingerprint of a `wpkh([fp/84'/c'/a']xpub/<0;1>/*)` descriptor,
+/// where the coin type `c` is 0 on mainnet and 1 on testnet, and the xpub is at depth 3.
+///
+/// Custom key codecs serialize xpubs themselves, so they never match.
+fn single_sig_shape<'a>(
+    wpkh: &'a Wpkh<DescriptorPublicKey>,
+    ctx: &EncodeContext,
+) -> Option<(&'a DescriptorMultiXKey<Xpub>, Fingerprint)> {
+    let key = wpkh.as_inner();
+    if ctx.key_codec.is_some() || ctx.key_map.contains_key(key) {
+        return None;
+    }
+
+    let DescriptorPublicKey::MultiXPub(xpub) = key else {
+        return None;
+    };
+    let (fingerprint, origin_path) = xpub.origin.as_ref()?;
+
+    let coin = match xpub.xkey.network {
+        NetworkKind::Main => 0,
+        NetworkKind::Test => 1,
+    };
+    let expected_origin = [
+        ChildNumber::Hardened { index: 84 },
+        ChildNumber::Hardened { index: coin },
+        xpub.xkey.child_number,
+    ];
+    let expected_paths = [
+        DerivationPath::from(vec![ChildNumber::Normal { index: 0 }]),
+        DerivationPath::from(vec![ChildNumber::Normal { index: 1 }]),
+    ];
+
+    let matches = origin_path.as_ref() == expected_origin
+        && xpub.xkey.child_number.is_hardened()
+        && xpub.xkey.depth == 3
+        && xpub.derivation_paths.paths() == &expected_paths
+        && xpub.wildcard == Wildcard::Unhardened;
+
+    matches.then_some((xpub, *fingerprint))
+}
+
+/// Encodes a single-sig descriptor matched by `single_sig_shape`, omitting the parts of the
+/// xpub implied by the origin
+fn encode_single_sig<S: Sink>(
+    xpub: &DescriptorMultiXKey<Xpub>,
+    fingerprint: Fingerprint,
+    template: &mut S,
+    payload: &mut S,
+) {
+    let account = match xpub.xkey.child_number {
+        ChildNumber::Hardened { index } | ChildNumber::Normal { index } => index,
+    };
+    let testnet = xpub.xkey.network == NetworkKind::Test;
+
+    template.push_tag(Tag::SingleSigWpkh);
+    template.write_varint(((account as u128) << 1) + testnet as u128);
+
+    payload.write(fingerprint.as_bytes());
+    payload.write(xpub.xkey.parent_fingerprint.as_bytes());
+    payload.write(xpub.xkey.chain_code.as_bytes());
+    payload.write(&xpub.xkey.public_key.serialize());
+}
+
 impl EncodeTemplate for Sh<DescriptorPublicKey> {
-    fn encode_template(&self, template: &mut Vec<u8>, payload: &mut Vec<u8>, key_map: &KeyMap) {
-        template.push(Tag::Sh.value());
+    fn encode_template<S: Sink>(&self, template: &mut S, payload: &mut S, ctx: &EncodeContext) {
+        template.push_tag(Tag::Sh);
 
         match self.as_inner() {
             ShInner::SortedMulti(sortedmulti) => {
-                sortedmulti.encode_template(template, payload, key_map)
+                sortedmulti.encode_template(template, payload, ctx)
             }
-            ShInner::Wsh(wsh) => wsh.encode_template(template, payload, key_map),
-            ShInner::Wpkh(wpkh) => wpkh.encode_template(template, payload, key_map),
-            ShInner::Ms(ms) => ms.encode_template(template, payload, key_map),
+            ShInner::Wsh(wsh) => wsh.encode_template(template, payload, ctx),
+            ShInner::Wpkh(wpkh) => wpkh.encode_template(template, payload, ctx),
+            ShInner::Ms(ms) => ms.encode_template(template, payload, ctx),
         }
     }
 }
 
 impl EncodeTemplate for Wsh<DescriptorPublicKey> {
-    fn encode_template(&self, template: &mut Vec<u8>, payload: &mut Vec<u8>, key_map: &KeyMap) {
-        template.push(Tag::Wsh.value());
+    fn encode_template<S: Sink>(&self, template: &mut S, payload: &mut S, ctx: &EncodeContext) {
+        template.push_tag(Tag::Wsh);
 
         match self.as_inner() {
             WshInner::SortedMulti(sortedmulti) => {
-                sortedmulti.encode_template(template, payload, key_map)
+                sortedmulti.encode_template(template, payload, ctx)
             }
-            WshInner::Ms(ms) => ms.encode_template(template, payload, key_map),
+            WshInner::Ms(ms) => ms.encode_template(template, payload, ctx),
         };
     }
 }
 
 impl EncodeTemplate for Tr<DescriptorPublicKey> {
-    fn encode_template(&self, template: &mut Vec<u8>, payload: &mut Vec<u8>, key_map: &KeyMap) {
-        template.push(Tag::Tr.value());
+    fn encode_template<S: Sink>(&self, template: &mut S, payload: &mut S, ctx: &EncodeContext) {
+        template.push_tag(Tag::Tr);
 
-        self.internal_key()
-            .encode_template(template, payload, key_map);
+        self.internal_key().encode_template(template, payload, ctx);
 
         if let Some(tap_tree) = self.tap_tree() {
-            tap_tree.encode_template(template, payload, key_map);
+            tap_tree.encode_template(template, payload, ctx);
         }
     }
 }
 
 impl EncodeTemplate for Wpkh<DescriptorPublicKey> {
-    fn encode_template(&self, template: &mut Vec<u8>, payload: &mut Vec<u8>, key_map: &KeyMap) {
-        template.push(Tag::Wpkh.value());
+    fn encode_template<S: Sink>(&self, template: &mut S, payload: &mut S, ctx: &EncodeContext) {
+        template.push_tag(Tag::Wpkh);
 
-        self.as_inner().encode_template(template, payload, key_map);
+        self.as_inner().encode_template(template, payload, ctx);
     }
 }
 
 impl EncodeTemplate for Pkh<DescriptorPublicKey> {
-    fn encode_template(&self, template: &mut Vec<u8>, payload: &mut Vec<u8>, key_map: &KeyMap) {
-        template.push(Tag::Pkh.value());
+    fn encode_template<S: Sink>(&self, template: &mut S, payload: &mut S, ctx: &EncodeContext) {
+        template.push_tag(Tag::Pkh);
 
-        self.as_inner().encode_template(template, payload, key_map);
+        self.as_inner().encode_template(template, payload, ctx);
     }
 }
 
 impl EncodeTemplate for Bare<DescriptorPublicKey> {
-    fn encode_template(&self, template: &mut Vec<u8>, payload: &mut Vec<u8>, key_map: &KeyMap) {
-        template.push(Tag::Bare.value());
+    fn encode_template<S: Sink>(&self, template: &mut S, payload: &mut S, ctx: &EncodeContext) {
+        template.push_tag(Tag::Bare);
 
-        self.as_inner().encode_template(template, payload, key_map);
+        self.as_inner().encode_template(template, payload, ctx);
     }
 }
 
 impl EncodeTemplate for TapTree<DescriptorPublicKey> {
-    fn encode_template(&self, template: &mut Vec<u8>, payload: &mut Vec<u8>, key_map: &KeyMap) {
-        template.push(Tag::TapTree.value());
+    fn encode_template<S: Sink>(&self, template: &mut S, payload: &mut S, ctx: &EncodeContext) {
+        template.push_tag(Tag::TapTree);
 
         match self {
             TapTree::Tree { left, right, .. } => {
-                left.encode_template(template, payload, key_map);
-                right.encode_template(template, payload, key_map);
+                left.encode_template(template, payload, ctx);
+                right.encode_template(template, payload, ctx);
             }
-            TapTree::Leaf(ms) => ms.encode_template(template, payload, key_map),
+            TapTree::Leaf(ms) => ms.encode_template(template, payload, ctx),
         }
     }
 }
 
 impl<Ctx: ScriptContext> EncodeTemplate for SortedMultiVec<DescriptorPublicKey, Ctx> {
-    fn encode_template(&self, template: &mut Vec<u8>, payload: &mut Vec<u8>, key_map: &KeyMap) {
-        template.push(Tag::SortedMulti.value());
-        template.extend(varint::encode(self.k() as u128));
-        template.extend(varint::encode(self.n() as u128));
+    fn encode_template<S: Sink>(&self, template: &mut S, payload: &mut S, ctx: &EncodeContext) {
+        template.push_tag(Tag::SortedMulti);
+        template.write_varint(self.k() as u128);
+        template.write_varint(self.n() as u128);
 
         self.pks()
             .iter()
-            .for_each(|pk| pk.encode_template(template, payload, key_map));
+            .for_each(|pk| pk.encode_template(template, payload, ctx));
     }
 }
 
 impl<Ctx: ScriptContext> EncodeTemplate for Miniscript<DescriptorPublicKey, Ctx> {
-    fn encode_template(&self, template: &mut Vec<u8>, payload: &mut Vec<u8>, key_map: &KeyMap) {
-        self.node.encode_template(template, payload, key_map);
+    fn encode_template<S: Sink>(&self, template: &mut S, payload: &mut S, ctx: &EncodeContext) {
+        self.node.encode_template(template, payload, ctx);
     }
 }
 
 impl<Ctx: ScriptContext> EncodeTemplate for Terminal<DescriptorPublicKey, Ctx> {
-    fn encode_template(&self, template: &mut Vec<u8>, payload: &mut Vec<u8>, key_map: &KeyMap) {
+    fn encode_template<S: Sink>(&self, template: &mut S, payload: &mut S, ctx: &EncodeContext) {
         match self {
             Terminal::True => {
-                template.push(Tag::True.value());
+                template.push_tag(Tag::True);
             }
             Terminal::False => {
-                template.push(Tag::False.value());
+                template.push_tag(Tag::False);
             }
             Terminal::PkK(pk) => {
-                template.push(Tag::PkK.value());
-                pk.encode_template(template, payload, key_map);
+                template.push_tag(Tag::PkK);
+                pk.encode_template(template, payload, ctx);
             }
             Terminal::PkH(pk) => {
-                template.push(Tag::PkH.value());
-                pk.encode_template(template, payload, key_map);
+                template.push_tag(Tag::PkH);
+                pk.encode_template(template, payload, ctx);
             }
             Terminal::RawPkH(hash) => {
-                template.push(Tag::RawPkH.value());
-                payload.extend(hash.as_byte_array().to_vec());
+                template.push_tag(Tag::RawPkH);
+                payload.write(hash.as_byte_array());
             }
             Terminal::After(after) => {
-                template.push(Tag::After.value());
-                payload.extend(varint::encode(after.to_consensus_u32().into()));
+                template.push_tag(Tag::After);
+                payload.write_varint(after.to_consensus_u32().into());
             }
             Terminal::Older(older) => {
-                template.push(Tag::Older.value());
-                payload.extend(varint::encode(older.to_consensus_u32().into()));
+                template.push_tag(Tag::Older);
+                payload.write_varint(older.to_consensus_u32().into());
             }
             Terminal::Sha256(sha256) => {
-                template.push(Tag::Sha256.value());
-                payload.extend(sha256.as_byte_array().to_vec());
+                ctx.encode_hash(Tag::Sha256, sha256.as_byte_array(), template, payload)
             }
-            Terminal::Hash256(hash156) => {
-                template.push(Tag::Hash256.value());
-                payload.extend(hash156.as_byte_array().to_vec());
+            Terminal::Hash256(hash256) => {
+                ctx.encode_hash(Tag::Hash256, hash256.as_byte_array(), template, payload)
             }
             Terminal::Ripemd160(ripemd160) => {
-                template.push(Tag::Ripemd160.value());
-                payload.extend(ripemd160.as_byte_array().to_vec());
+                ctx.encode_hash(Tag::Ripemd160, ripemd160.as_byte_array(), template, payload)
             }
             Terminal::Hash160(hash160) => {
-                template.push(Tag::Hash160.value());
-                payload.extend(hash160.as_byte_array().to_vec());
+                ctx.encode_hash(Tag::Hash160, hash160.as_byte_array(), template, payload)
             }
             Terminal::Alt(ms) => {
-                template.push(Tag::Alt.value());
-                ms.encode_template(template, payload, key_map);
+                template.push_tag(Tag::Alt);
+                ms.encode_template(template, payload, ctx);
             }
             Terminal::Swap(ms) => {
-                template.push(Tag::Swap.value());
-                ms.encode_template(template, payload, key_map);
+                template.push_tag(Tag::Swap);
+                ms.encode_template(template, payload, ctx);
             }
             Terminal::Check(ms) => {
-                template.push(Tag::Check.value());
-                ms.encode_template(template, payload, key_map);
+                template.push_tag(Tag::Check);
+                ms.encode_template(template, payload, ctx);
             }
             Terminal::DupIf(ms) => {
-                template.push(Tag::DupIf.value());
-                ms.encode_template(template, payload, key_map);
+                template.push_tag(Tag::DupIf);
+                ms.encode_template(template, payload, ctx);
             }
             Terminal::Verify(ms) => {
-                template.push(Tag::Verify.value());
-                ms.encode_template(template, payload, key_map);
+                template.push_tag(Tag::Verify);
+                ms.encode_template(template, payload, ctx);
             }
             Terminal::NonZero(ms) => {
-                template.push(Tag::NonZero.value());
-                ms.encode_template(template, payload, key_map);
+                template.push_tag(Tag::NonZero);
+                ms.encode_template(template, payload, ctx);
             }
             Terminal::ZeroNotEqual(ms) => {
-                template.push(Tag::ZeroNotEqual.value());
-                ms.encode_template(template, payload, key_map);
+                template.push_tag(Tag::ZeroNotEqual);
+                ms.encode_template(template, payload, ctx);
             }
             Terminal::AndV(ms0, ms1) => {
-                template.push(Tag::AndV.value());
-                ms0.encode_template(template, payload, key_map);
-                ms1.encode_template(template, payload, key_map);
+                template.push_tag(Tag::AndV);
+                ms0.encode_template(template, payload, ctx);
+                ms1.encode_template(template, payload, ctx);
             }
             Terminal::AndB(ms0, ms1) => {
-                template.push(Tag::AndB.value());
-                ms0.encode_template(template, payload, key_map);
-                ms1.encode_template(template, payload, key_map);
+                template.push_tag(Tag::AndB);
+                ms0.encode_template(template, payload, ctx);
+                ms1.encode_template(template, payload, ctx);
             }
             Terminal::AndOr(ms0, ms1, ms2) => {
-                template.push(Tag::AndOr.value());
-                ms0.encode_template(template, payload, key_map);
-                ms1.encode_template(template, payload, key_map);
-                ms2.encode_template(template, payload, key_map);
+                template.push_tag(Tag::AndOr);
+                ms0.encode_template(template, payload, ctx);
+                ms1.encode_template(template, payload, ctx);
+                ms2.encode_template(template, payload, ctx);
             }
             Terminal::OrB(ms0, ms1) => {
-                template.push(Tag::OrB.value());
-                ms0.encode_template(template, payload, key_map);
-                ms1.encode_template(template, payload, key_map);
+                template.push_tag(Tag::OrB);
+                ms0.encode_template(template, payload, ctx);
+                ms1.encode_template(template, payload, ctx);
             }
             Terminal::OrC(ms0, ms1) => {
-                template.push(Tag::OrC.value());
-                ms0.encode_template(template, payload, key_map);
-                ms1.encode_template(template, payload, key_map);
+                template.push_tag(Tag::OrC);
+                ms0.encode_template(template, payload, ctx);
+                ms1.encode_template(template, payload, ctx);
             }
             Terminal::OrD(ms0, ms1) => {
-                template.push(Tag::OrD.value());
-                ms0.encode_template(template, payload, key_map);
-                ms1.encode_template(template, payload, key_map);
+                template.push_tag(Tag::OrD);
+                ms0.encode_template(template, payload, ctx);
+                ms1.encode_template(template, payload, ctx);
             }
             Terminal::OrI(ms0, ms1) => {
-                template.push(Tag::OrI.value());
-                ms0.encode_template(template, payload, key_map);
-                ms1.encode_template(template, payload, key_map);
+                template.push_tag(Tag::OrI);
+                ms0.encode_template(template, payload, ctx);
+                ms1.encode_template(template, payload, ctx);
             }
             Terminal::Thresh(thresh) => {
-                template.push(Tag::Thresh.value());
-                thresh.encode_template(template, payload, key_map);
+                template.push_tag(Tag::Thresh);
+                thresh.encode_template(template, payload, ctx);
             }
             Terminal::Multi(thresh) => {
-                template.push(Tag::Multi.value());
-                thresh.encode_template(template, payload, key_map);
+                template.push_tag(Tag::Multi);
+                thresh.encode_template(template, payload, ctx);
             }
             Terminal::MultiA(thresh) => {
-                template.push(Tag::MultiA.value());
-                thresh.encode_template(template, payload, key_map);
+                template.push_tag(Tag::MultiA);
+                thresh.encode_template(template, payload, ctx);
             }
         }
     }
 }
 
 impl<T: EncodeTemplate> EncodeTemplate for Arc<T> {
-    fn encode_template(&self, template: &mut Vec<u8>, payload: &mut Vec<u8>, key_map: &KeyMap) {
-        (**self).encode_template(template, payload, key_map);
+    fn encode_template<S: Sink>(&self, template: &mut S, payload: &mut S, ctx: &EncodeContext) {
+        (**self).encode_template(template, payload, ctx);
     }
 }
 
 impl<T: EncodeTemplate, const MAX: usize> EncodeTemplate for Threshold<T, MAX> {
-    fn encode_template(&self, template: &mut Vec<u8>, payload: &mut Vec<u8>, key_map: &KeyMap) {
-        template.extend(varint::encode(self.k() as u128));
-        template.extend(varint::encode(self.n() as u128));
+    fn encode_template<S: Sink>(&self, template: &mut S, payload: &mut S, ctx: &EncodeContext) {
+        template.write_varint(self.k() as u128);
+        template.write_varint(self.n() as u128);
 
         self.iter()
-            .for_each(|t| t.encode_template(template, payload, key_map));
+            .for_each(|t| t.encode_template(template, payload, ctx));
     }
 }
 
 impl EncodeTemplate for DescriptorPublicKey {
-    fn encode_template(&self, template: &mut Vec<u8>, payload: &mut Vec<u8>, key_map: &KeyMap) {
-        if let Some(secret_key) = key_map.get(self) {
-            secret_key.encode_template(template, payload, key_map);
+    fn encode_template<S: Sink>(&self, template: &mut S, payload: &mut S, ctx: &EncodeContext) {
+        if let Some(secret_key) = ctx.key_map.get(self) {
+            secret_key.encode_template(template, payload, ctx);
             return;
         }
 
@@ -312,31 +871,29 @@ impl EncodeTemplate for DescriptorPublicKey {
             }
         };
 
-        template.push(tag.value());
+        template.push_tag(tag);
 
         if let Some((fingerprint, derivation_path)) = origin {
-            template.push(Tag::Origin.value());
-            payload.extend(fingerprint.as_bytes().to_vec());
+            template.push_tag(Tag::Origin);
+            payload.write(fingerprint.as_bytes());
 
-            derivation_path.encode_template(template, payload, key_map);
+            derivation_path.encode_template(template, payload, ctx);
         } else {
-            template.push(Tag::NoOrigin.value());
+            template.push_tag(Tag::NoOrigin);
         }
 
         match self {
-            DescriptorPublicKey::XPub(xpub) => xpub.encode_template(template, payload, key_map),
-            DescriptorPublicKey::MultiXPub(xpub) => {
-                xpub.encode_template(template, payload, key_map)
-            }
+            DescriptorPublicKey::XPub(xpub) => xpub.encode_template(template, payload, ctx),
+            DescriptorPublicKey::MultiXPub(xpub) => xpub.encode_template(template, payload, ctx),
             DescriptorPublicKey::Single(single) => {
-                single.key.encode_template(template, payload, key_map)
+                single.key.encode_template(template, payload, ctx)
             }
         }
     }
 }
 
 impl EncodeTemplate for DescriptorSecretKey {
-    fn encode_template(&self, template: &mut Vec<u8>, payload: &mut Vec<u8>, key_map: &KeyMap) {
+    fn encode_template<S: Sink>(&self, template: &mut S, payload: &mut S, ctx: &EncodeContext) {
         let (tag, origin) = match self.clone() {
             DescriptorSecretKey::XPrv(xprv) => (Tag::XPriv, xprv.origin),
             DescriptorSecretKey::MultiXPrv(xprv) => (Tag::MultiXPriv, xprv.origin),
@@ -350,121 +907,118 @@ impl EncodeTemplate for DescriptorSecretKey {
             }
         };
 
-        template.push(tag.value());
+        template.push_tag(tag);
 
         if let Some((fingerprint, derivation_path)) = origin {
-            template.push(Tag::Origin.value());
-            payload.extend(fingerprint.as_bytes().to_vec());
+            template.push_tag(Tag::Origin);
+            payload.write(fingerprint.as_bytes());
 
-            derivation_path.encode_template(template, payload, key_map);
+            derivation_path.encode_template(template, payload, ctx);
         } else {
-            template.push(Tag::NoOrigin.value());
+            template.push_tag(Tag::NoOrigin);
         }
 
         match self {
-            DescriptorSecretKey::XPrv(xprv) => xprv.encode_template(template, payload, key_map),
-            DescriptorSecretKey::MultiXPrv(xprv) => {
-                xprv.encode_template(template, payload, key_map)
-            }
+            DescriptorSecretKey::XPrv(xprv) => xprv.encode_template(template, payload, ctx),
+            DescriptorSecretKey::MultiXPrv(xprv) => xprv.encode_template(template, payload, ctx),
             DescriptorSecretKey::Single(single) => {
-                payload.extend(single.key.to_bytes());
+                payload
+                    .write_with(|payload| ctx.key_codec().encode_secret_key(&single.key, payload));
             }
         }
     }
 }
 
 impl EncodeTemplate for DerivationPath {
-    fn encode_template(&self, template: &mut Vec<u8>, payload: &mut Vec<u8>, key_map: &KeyMap) {
-        template.extend(varint::encode(self.len() as u128));
+    fn encode_template<S: Sink>(&self, template: &mut S, payload: &mut S, ctx: &EncodeContext) {
+        template.write_varint(self.len() as u128);
 
         self.into_iter()
-            .for_each(|child| child.encode_template(template, payload, key_map));
+            .for_each(|child| child.encode_template(template, payload, ctx));
     }
 }
 
 impl EncodeTemplate for ChildNumber {
-    fn encode_template(&self, template: &mut Vec<u8>, _payload: &mut Vec<u8>, _key_map: &KeyMap) {
+    fn encode_template<S: Sink>(&self, template: &mut S, _payload: &mut S, _ctx: &EncodeContext) {
         let value = match *self {
             ChildNumber::Normal { index } => (index as u128) << 1,
             ChildNumber::Hardened { index } => 1 + ((index as u128) << 1),
         };
 
-        template.extend(varint::encode(value));
+        template.write_varint(value);
     }
 }
 
 impl EncodeTemplate for SinglePubKey {
-    fn encode_template(&self, _template: &mut Vec<u8>, payload: &mut Vec<u8>, _key_map: &KeyMap) {
+    fn encode_template<S: Sink>(&self, _template: &mut S, payload: &mut S, ctx: &EncodeContext) {
         match self {
             SinglePubKey::FullKey(pk) => {
-                payload.extend(pk.to_bytes());
+                payload.write_with(|payload| ctx.key_codec().encode_full_key(pk, payload))
             }
             SinglePubKey::XOnly(x_only) => {
-                payload.extend(x_only.serialize().to_vec());
+                payload.write_with(|payload| ctx.key_codec().encode_x_only(x_only, payload))
             }
         }
     }
 }
 
 impl EncodeTemplate for DescriptorXKey<Xpub> {
-    fn encode_template(&self, template: &mut Vec<u8>, payload: &mut Vec<u8>, key_map: &KeyMap) {
-        self.derivation_path
-            .encode_template(template, payload, key_map);
-        self.wildcard.encode_template(template, payload, key_map);
+    fn encode_template<S: Sink>(&self, template: &mut S, payload: &mut S, ctx: &EncodeContext) {
+        self.derivation_path.encode_template(template, payload, ctx);
+        self.wildcard.encode_template(template, payload, ctx);
 
-        payload.extend(self.xkey.encode().to_vec());
+        payload.write_with(|payload| ctx.key_codec().encode_xpub(&self.xkey, payload));
     }
 }
 
 impl EncodeTemplate for DescriptorMultiXKey<Xpub> {
-    fn encode_template(&self, template: &mut Vec<u8>, payload: &mut Vec<u8>, key_map: &KeyMap) {
+    fn encode_template<S: Sink>(&self, template: &mut S, payload: &mut S, ctx: &EncodeContext) {
         self.derivation_paths
-            .encode_template(template, payload, key_map);
-        self.wildcard.encode_template(template, payload, key_map);
+            .encode_template(template, payload, ctx);
+        self.wildcard.encode_template(template, payload, ctx);
 
-        payload.extend(self.xkey.encode().to_vec());
+        payload.write_with(|payload| ctx.key_codec().encode_xpub(&self.xkey, payload));
     }
 }
 
 impl EncodeTemplate for DescriptorXKey<Xpriv> {
-    fn encode_template(&self, template: &mut Vec<u8>, payload: &mut Vec<u8>, key_map: &KeyMap) {
-        self.derivation_path
-            .encode_template(template, payload, key_map);
-        self.wildcard.encode_template(template, payload, key_map);
+    fn encode_template<S: Sink>(&self, template: &mut S, payload: &mut S, ctx: &EncodeContext) {
+        self.derivation_path.encode_template(template, payload, ctx);
+        self.wildcard.encode_template(template, payload, ctx);
 
-        payload.extend(self.xkey.encode().to_vec());
+        payload.write_with(|payload| ctx.key_codec().encode_xpriv(&self.xkey, payload));
     }
 }
 
 impl EncodeTemplate for DescriptorMultiXKey<Xpriv> {
-    fn encode_template(&self, template: &mut Vec<u8>, payload: &mut Vec<u8>, key_map: &KeyMap) {
+    fn encode_template<S: Sink>(&self, template: &mut S, payload: &mut S, ctx: &EncodeContext) {
         self.derivation_paths
-            .encode_template(template, payload, key_map);
-        self.wildcard.encode_template(template, payload, key_map);
+            .encode_template(template, payload, ctx);
+        self.wildcard.encode_template(template, payload, ctx);
 
-        payload.extend(self.xkey.encode().to_vec());
+        payload.write_with(|payload| ctx.key_codec().encode_xpriv(&self.xkey, payload));
     }
 }
 
 impl EncodeTemplate for DerivPaths {
-    fn encode_template(&self, template: &mut Vec<u8>, payload: &mut Vec<u8>, key_map: &KeyMap) {
-        template.extend(varint::encode(self.paths().len() as u128));
+    fn encode_template<S: Sink>(&self, template: &mut S, payload: &mut S, ctx: &EncodeContext) {
+        template.write_varint(self.paths().len() as u128);
 
         self.paths()
             .iter()
-            .for_each(|path| path.encode_template(template, payload, key_map));
+            .for_each(|path| path.encode_template(template, payload, ctx));
     }
 }
 
 impl EncodeTemplate for Wildcard {
-    fn encode_template(&self, template: &mut Vec<u8>, _payload: &mut Vec<u8>, _key_map: &KeyMap) {
+    fn encode_template<S: Sink>(&self, template: &mut S, _payload: &mut S, _ctx: &EncodeContext) {
         let tag = match self {
             Wildcard::None => Tag::NoWildcard,
             Wildcard::Unhardened => Tag::UnhardenedWildcard,
             Wildcard::Hardened => Tag::HardenedWildcard,
         };
 
-        template.push(tag.value());
+        template.push_tag(tag);
     }
 }
 
@@ -503,10 +1057,21 @@ mod tests {
     ) {
         let mut template = Vec::new();
         let mut payload = Vec::new();
-        t.encode_template(&mut template, &mut payload, key_map);
+        t.encode_template(&mut template, &mut payload, &EncodeContext::new(key_map));
 
         assert_eq!(template, expected_template);
         assert_eq!(payload, expected_payload);
+
+        let mut template_len = Counter::default();
+        let mut payload_len = Counter::default();
+        t.encode_template(
+            &mut template_len,
+            &mut payload_len,
+            &EncodeContext::new(key_map),
+        );
+
+        assert_eq!(template_len.len, template.len());
+        assert_eq!(payload_len.len, payload.len());
     }
 
     // Generic Miniscript helpers
@@ -1278,4 +1843,103 @@ mod tests {
         assert_eq!(template, template_of(tr.clone()));
         assert_eq!(payload, payload_of(tr.clone()));
     }
+
+    #[test]
+    fn test_single_sig_wpkh() {
+        let secp = bitcoin::secp256k1::Secp256k1::new();
+        let origin_fp = fp_from_str("12345678");
+        let master = Xpriv::new_master(NetworkKind::Main, &[1u8; 32]).unwrap();
+        let account = master
+            .derive_priv(&secp, &dp_from_str("m/84h/0h/5h"))
+            .unwrap();
+        let xpub = Xpub::from_priv(&secp, &account);
+
+        let (_, dpk) = create_dpk_multixpub(
+            Some((origin_fp, dp_from_str("m/84h/0h/5h"))),
+            &["m/0", "m/1"],
+            xpub,
+            Wildcard::Unhardened,
+        );
+        let descriptor = Descriptor::Wpkh(Wpkh::new(dpk).unwrap());
+        let mut expected_payload = origin_fp.as_bytes().to_vec();
+        expected_payload.extend(xpub.parent_fingerprint.as_bytes());
+        expected_payload.extend(xpub.chain_code.as_bytes());
+        expected_payload.extend(xpub.public_key.serialize());
+        assert_eq_template_and_payload(
+            descriptor,
+            vec![Tag::SingleSigWpkh.value(), 10],
+            expected_payload,
+        );
+
+        // Origin paths that do not match the xpub fall back to the full encoding
+        for origin_path in ["m/84h/1h/5h", "m/84h/0h/4h", "m/49h/0h/5h", "m/84h/0h"] {
+            let (_, dpk) = create_dpk_multixpub(
+                Some((origin_fp, dp_from_str(origin_path))),
+                &["m/0", "m/1"],
+                xpub,
+                Wildcard::Unhardened,
+            );
+            let wpkh = Wpkh::new(dpk).unwrap();
+            let descriptor = Descriptor::Wpkh(wpkh.clone());
+            assert_eq!(template_of(descriptor), template_of(wpkh));
+        }
+
+        // As do other derivation paths and wildcards
+        let (_, dpk) = create_dpk_multixpub(
+            Some((origin_fp, dp_from_str("m/84h/0h/5h"))),
+            &["m/0", "m/1"],
+            xpub,
+            Wildcard::Hardened,
+        );
+        let wpkh = Wpkh::new(dpk).unwrap();
+        assert_eq!(
+            template_of(Descriptor::Wpkh(wpkh.clone())),
+            template_of(wpkh)
+        );
+    }
+
+    #[test]
+    fn test_encode_to_writer() {
+        let secp = bitcoin::secp256k1::Secp256k1::new();
+        let (descriptor, key_map) = Descriptor::parse_descriptor(
+            &secp,
+            "tr(xprv9s21ZrQH143K2LBWUUQRFXhucrQqBpKdRRxNVq2zBqsx8HVqFk2uYo8kmbaLLHRdqtQpUm98uKfu3vca1LqdGhUtyoFnCNkfmXRyPXLjbKb/0/*,pk(03a0434d9e47f3c86235477c7b1ae6ae5d3442d49b1943c2b752a68e2a47e247c7))",
+        )
+        .unwrap();
+        let expected = crate::encode_parsed(&descriptor, &key_map).unwrap();
+
+        let mut written = Vec::new();
+        let len = encode_to_writer(&descriptor, &key_map, &mut written).unwrap();
+        assert_eq!(written, expected);
+        assert_eq!(len, expected.len());
+
+        // A writer that runs out of room fails
+        let mut buf = vec![0; expected.len() - 1];
+        let result = encode_to_writer(&descriptor, &key_map, &mut &mut buf[..]);
+        assert!(matches!(result, Err(WriteError::Io(e)) if e.kind() == io::ErrorKind::WriteZero));
+    }
+
+    #[test]
+    fn test_encode_parsed_into_slice() {
+        let secp = bitcoin::secp256k1::Secp256k1::new();
+        let (descriptor, key_map) = Descriptor::parse_descriptor(
+            &secp,
+            "wsh(or_d(pk(xprv9s21ZrQH143K2LBWUUQRFXhucrQqBpKdRRxNVq2zBqsx8HVqFk2uYo8kmbaLLHRdqtQpUm98uKfu3vca1LqdGhUtyoFnCNkfmXRyPXLjbKb/0/*),and_v(v:pk(03a0434d9e47f3c86235477c7b1ae6ae5d3442d49b1943c2b752a68e2a47e247c7),older(1000))))",
+        )
+        .unwrap();
+        let expected = crate::encode_parsed(&descriptor, &key_map).unwrap();
+
+        let mut out = [0xAA; 256];
+        let len = encode_parsed_into_slice(&descriptor, &key_map, &mut out).unwrap();
+        assert_eq!(&out[..len], expected);
+        assert!(out[len..].iter().all(|b| *b == 0xAA));
+
+        // A buffer one byte short is left untouched
+        let mut out = vec![0xAA; expected.len() - 1];
+        assert_eq!(
+            encode_parsed_into_slice(&descriptor, &key_map, &mut out),
+            Err(Error::BufferTooSmall(expected.len()))
+        );
+        assert!(out.iter().all(|b| *b == 0xAA));
+    }
 }
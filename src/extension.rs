@@ -0,0 +1,166 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! # Custom Tag Extensions
+//!
+//! Tags in [`CUSTOM_TAGS`] are reserved for application-specific encodings and will never be
+//! assigned by this library, so forks and experiments can use them without colliding with
+//! future official tags.
+
+use super::decoder::Error;
+use miniscript::descriptor::{Descriptor, DescriptorPublicKey};
+use std::fmt;
+use std::ops::RangeInclusive;
+
+/// Tag values reserved for application-specific use
+pub const CUSTOM_TAGS: RangeInclusive<u8> = 0xC0..=0xFF;
+
+/// Encodes and decodes descriptors using tags from [`CUSTOM_TAGS`]
+///
+/// The encoder offers every descriptor without secret keys to the extension before using the
+/// standard encoding, and the decoder hands any descriptor starting with a custom tag to the
+/// extension.
+pub trait TagExtension: fmt::Debug + Send + Sync {
+    /// Encodes a descriptor, returning `false` to fall back to the standard encoding
+    ///
+    /// The template written must start with a tag from [`CUSTOM_TAGS`], or encoding panics, so an
+    /// extension can never produce bytes that decode as an official tag.
+    fn encode(
+        &self,
+        descriptor: &Descriptor<DescriptorPublicKey>,
+        template: &mut Vec<u8>,
+        payload: &mut Vec<u8>,
+    ) -> bool;
+
+    /// Decodes a descriptor starting with the custom tag at `input[*index]`, advancing `index`
+    /// and `payload_index` past the bytes read
+    ///
+    /// `payload` is empty when only the template is decoded, in which case dummy values should
+    /// be used.
+    fn decode(
+        &self,
+        input: &[u8],
+        index: &mut usize,
+        payload: &[u8],
+        payload_index: &mut usize,
+    ) -> Result<Descriptor<DescriptorPublicKey>, Error>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Codec, decode, encode};
+    use bitcoin::XOnlyPublicKey;
+    use miniscript::descriptor::{SinglePub, SinglePubKey, Tr};
+    use std::sync::Arc;
+
+    /// Encodes key-path-only taproot descriptors without origin as a tag and x-only key
+    #[derive(Debug)]
+    struct KeyPathOnly;
+
+    impl TagExtension for KeyPathOnly {
+        fn encode(
+            &self,
+            descriptor: &Descriptor<DescriptorPublicKey>,
+            template: &mut Vec<u8>,
+            payload: &mut Vec<u8>,
+        ) -> bool {
+            let Descriptor::Tr(tr) = descriptor else {
+                return false;
+            };
+            let DescriptorPublicKey::Single(SinglePub {
+                origin: None,
+                key: SinglePubKey::XOnly(key),
+            }) = tr.internal_key()
+            else {
+                return false;
+            };
+            if tr.tap_tree().is_some() {
+                return false;
+            }
+
+            template.push(0xC0);
+            payload.extend(key.serialize());
+            true
+        }
+
+        fn decode(
+            &self,
+            input: &[u8],
+            index: &mut usize,
+            payload: &[u8],
+            payload_index: &mut usize,
+        ) -> Result<Descriptor<DescriptorPublicKey>, Error> {
+            assert_eq!(input[*index], 0xC0);
+            *index += 1;
+
+            let key = if payload.is_empty() {
+                XOnlyPublicKey::from(crate::dummy::pk())
+            } else {
+                let bytes = payload
+                    .get(*payload_index..*payload_index + 32)
+                    .ok_or(Error::MissingBytes)?;
+                *payload_index += 32;
+                XOnlyPublicKey::from_slice(bytes).unwrap()
+            };
+
+            let internal_key = DescriptorPublicKey::Single(SinglePub {
+                origin: None,
+                key: SinglePubKey::XOnly(key),
+            });
+            Ok(Descriptor::Tr(Tr::new(internal_key, None).unwrap()))
+        }
+    }
+
+    #[test]
+    fn test_tag_extension() {
+        let desc_str =
+            "tr(c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5)#k6ze7ncu";
+        let codec = Codec::new().with_tag_extension(Arc::new(KeyPathOnly));
+
+        let encoded = codec.encode(desc_str).unwrap();
+        assert_eq!(encoded.len(), 1 + 32);
+        assert!(CUSTOM_TAGS.contains(&encoded[0]));
+        assert_eq!(codec.decode(&encoded).unwrap(), desc_str);
+        assert_eq!(decode(&encoded), Err(Error::UnrecognizedTag(0)));
+
+        // Other descriptors use the standard encoding
+        let desc_str =
+            "wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)#8zl0zxma";
+        assert_eq!(codec.encode(desc_str).unwrap(), encode(desc_str).unwrap());
+    }
+
+    /// Claims every descriptor but writes an official tag
+    #[derive(Debug)]
+    struct OfficialTag;
+
+    impl TagExtension for OfficialTag {
+        fn encode(
+            &self,
+            _: &Descriptor<DescriptorPublicKey>,
+            template: &mut Vec<u8>,
+            _: &mut Vec<u8>,
+        ) -> bool {
+            template.push(0x01);
+            true
+        }
+
+        fn decode(
+            &self,
+            _: &[u8],
+            _: &mut usize,
+            _: &[u8],
+            _: &mut usize,
+        ) -> Result<Descriptor<DescriptorPublicKey>, Error> {
+            Err(Error::UnrecognizedTag(0))
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "tag extension wrote a template not starting with a custom tag")]
+    fn test_tag_extension_official_tag() {
+        let codec = Codec::new().with_tag_extension(Arc::new(OfficialTag));
+        let _ = codec.encode(
+            "tr(c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5)#k6ze7ncu",
+        );
+    }
+}
@@ -0,0 +1,291 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! # Format Features
+//!
+//! Reports which optional parts of the format an encoding uses, so an application can tell the
+//! user which version it needs instead of reporting a generic decode failure.
+
+use super::decoder::{DecodeContext, Error};
+use super::encoder::{self, COMMITMENT_SIZE};
+use super::extension::CUSTOM_TAGS;
+use super::metadata;
+use super::tag::Tag;
+use super::walk;
+use std::fmt;
+
+/// Revision of the encoding format written by this version of the library
+///
+/// Encodings carry no version number. Instead, each revision only adds tags and headers, so an
+/// encoding written at any revision up to [`MAX_SUPPORTED_VERSION`] decodes, and
+/// [`required_features`] names the additions an older decoder would reject.
+///
+/// Version 3 added `HashRef`, which stores a repeated hash once. It can appear anywhere in the
+/// template, so [`required_features`] does not report it.
+pub const FORMAT_VERSION: u32 = 3;
+
+/// Newest revision of the encoding format this version of the library can decode
+pub const MAX_SUPPORTED_VERSION: u32 = FORMAT_VERSION;
+
+/// Optional format features this version of the library can encode and decode
+///
+/// Custom tags also need the matching [`crate::TagExtension`].
+pub const SUPPORTED_FEATURES: FeatureSet = FeatureSet {
+    payload_commitment: true,
+    metadata: true,
+    preset: true,
+    single_sig: true,
+    raw_tr: true,
+    custom_tag: true,
+    unrecognized_tag: None,
+};
+
+/// Optional format features used by an encoding
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FeatureSet {
+    /// The template starts with a payload commitment
+    pub payload_commitment: bool,
+    /// The payload ends with TLV metadata records
+    pub metadata: bool,
+    /// The descriptor is a registered preset
+    pub preset: bool,
+    /// The descriptor uses the single-sig fast path
+    pub single_sig: bool,
+    /// The descriptor is a `rawtr()`, added in format version 2
+    pub raw_tr: bool,
+    /// The descriptor starts with a custom tag, which needs the matching [`crate::TagExtension`]
+    pub custom_tag: bool,
+    /// The descriptor starts with a tag unknown to this version of the library
+    pub unrecognized_tag: Option<u8>,
+}
+
+impl FeatureSet {
+    /// Returns whether the encoding uses only the base format
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Returns whether every feature in the set is in [`SUPPORTED_FEATURES`]
+    pub fn is_supported(&self) -> bool {
+        let supported = SUPPORTED_FEATURES;
+        (!self.payload_commitment || supported.payload_commitment)
+            && (!self.metadata || supported.metadata)
+            && (!self.preset || supported.preset)
+            && (!self.single_sig || supported.single_sig)
+            && (!self.raw_tr || supported.raw_tr)
+            && (!self.custom_tag || supported.custom_tag)
+            && self.unrecognized_tag.is_none()
+    }
+}
+
+impl fmt::Display for FeatureSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names = [
+            (self.payload_commitment, "payload commitment"),
+            (self.metadata, "metadata"),
+            (self.preset, "preset"),
+            (self.single_sig, "single-sig fast path"),
+            (self.raw_tr, "rawtr"),
+            (self.custom_tag, "custom tag"),
+        ];
+        let mut names: Vec<String> = names
+            .iter()
+            .filter(|(used, _)| *used)
+            .map(|(_, name)| name.to_string())
+            .collect();
+        if let Some(tag) = self.unrecognized_tag {
+            names.push(format!("unrecognized tag {tag:#04x}"));
+        }
+
+        if names.is_empty() {
+            f.write_str("none")
+        } else {
+            f.write_str(&names.join(", "))
+        }
+    }
+}
+
+/// Returns the optional format features used by an encoding
+///
+/// Only the header and first tag of the template are read, so this succeeds on encodings this
+/// version cannot decode.
+pub fn required_features(bytes: &[u8]) -> Result<FeatureSet, Error> {
+    let mut features = FeatureSet::default();
+
+    let mut index = 0;
+    if bytes.first() == Some(&Tag::PayloadCommitment.value()) {
+        features.payload_commitment = true;
+        index += 1 + COMMITMENT_SIZE;
+    }
+    if bytes.get(index) == Some(&Tag::Metadata.value()) {
+        features.metadata = true;
+        index += 1;
+    }
+
+    let value = *bytes.get(index).ok_or(Error::MissingBytes)?;
+    match Tag::from(value) {
+        Tag::Preset => features.preset = true,
+        Tag::SingleSigWpkh => features.single_sig = true,
+        Tag::RawTr => features.raw_tr = true,
+        Tag::Unrecognized if CUSTOM_TAGS.contains(&value) => features.custom_tag = true,
+        Tag::Unrecognized => features.unrecognized_tag = Some(value),
+        _ => {}
+    }
+
+    Ok(features)
+}
+
+/// Summary of an encoding that passed [`can_decode`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FormatReport {
+    /// Optional format features used by the encoding
+    pub features: FeatureSet,
+    /// Number of bytes in the template
+    pub template_len: usize,
+    /// Number of bytes in the payload, including any metadata
+    pub payload_len: usize,
+    /// Number of metadata records
+    pub metadata_records: usize,
+}
+
+/// Checks that an encoding can be decoded, without building the descriptor
+///
+/// Walks the tags of the template, then checks that the payload holds every value they call
+/// for, that timelocks and metadata records are valid, and that the payload is fully consumed.
+/// Keys are not parsed and miniscript types are not checked, so a decode can still fail on an
+/// invalid key or fragment. Intended as a fast pre-flight check on untrusted input.
+pub fn can_decode(bytes: &[u8]) -> Result<FormatReport, Error> {
+    let features = required_features(bytes)?;
+
+    let walk = walk::walk(bytes)?;
+    let template_len = walk.template_len;
+    let payload = &bytes[template_len..];
+    let mut ctx = DecodeContext::default();
+    // A template without a payload decodes to dummy values
+    if !payload.is_empty() {
+        if walk.commitment && bytes[1..=COMMITMENT_SIZE] != encoder::payload_commitment(payload) {
+            return Err(Error::PayloadMismatch);
+        }
+        let mut payload_index = walk.read_payload(payload, |_, _| {})?;
+        if walk.metadata {
+            metadata::decode_records(payload, &mut payload_index, &mut ctx)?;
+        }
+        if payload_index < payload.len() {
+            return Err(Error::PayloadTooLarge(payload_index, payload.len()));
+        }
+    }
+
+    Ok(FormatReport {
+        features,
+        template_len,
+        payload_len: bytes.len() - template_len,
+        metadata_records: ctx.metadata.len() + ctx.key_rotations.len(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Codec, PresetRegistry, Tlv, encode};
+
+    #[test]
+    fn test_required_features() {
+        let desc_str = "wsh(multi(1,03a0434d9e47f3c86235477c7b1ae6ae5d3442d49b1943c2b752a68e2a47e247c7,036d2b085e9e382ed10b69fc311a03f8641ccfff21574de0927513a49d9a688a00))";
+        let encoded = encode(desc_str).unwrap();
+        let features = required_features(&encoded).unwrap();
+        assert!(features.is_empty());
+        assert_eq!(features.to_string(), "none");
+
+        let record = Tlv {
+            kind: 2,
+            value: vec![1],
+        };
+        let codec = Codec::new().with_payload_commitment(true);
+        let encoded = codec.encode_with_metadata(desc_str, &[record]).unwrap();
+        let features = required_features(&encoded).unwrap();
+        assert_eq!(
+            features,
+            FeatureSet {
+                payload_commitment: true,
+                metadata: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(features.to_string(), "payload commitment, metadata");
+
+        let mut presets = PresetRegistry::new();
+        presets.define(1, desc_str).unwrap();
+        let codec = Codec::new().with_presets(presets);
+        let features = required_features(&codec.encode(desc_str).unwrap()).unwrap();
+        assert!(features.preset);
+
+        let wpkh = "wpkh([73c5da0a/84'/0'/0']xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V/<0;1>/*)";
+        let features = required_features(&encode(wpkh).unwrap()).unwrap();
+        assert!(features.single_sig);
+
+        let raw_tr = "rawtr(c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5)";
+        let features = required_features(&encode(raw_tr).unwrap()).unwrap();
+        assert!(features.raw_tr);
+        assert!(features.is_supported());
+
+        let features = required_features(&[0xC0, 1, 2]).unwrap();
+        assert!(features.custom_tag);
+        assert!(features.is_supported());
+
+        let features = required_features(&[0x80]).unwrap();
+        assert_eq!(features.unrecognized_tag, Some(0x80));
+        assert_eq!(features.to_string(), "unrecognized tag 0x80");
+        assert!(!features.is_supported());
+        assert!(SUPPORTED_FEATURES.is_supported());
+
+        assert_eq!(required_features(&[]), Err(Error::MissingBytes));
+        assert_eq!(
+            required_features(&[Tag::PayloadCommitment.value(), 0, 0, 0, 0]),
+            Err(Error::MissingBytes)
+        );
+    }
+
+    #[test]
+    fn test_can_decode() {
+        let desc_str =
+            "wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)#8zl0zxma";
+        let record = Tlv {
+            kind: 2,
+            value: vec![1],
+        };
+        let encoded = crate::encode_with_metadata(desc_str, &[record]).unwrap();
+
+        let report = can_decode(&encoded).unwrap();
+        assert!(report.features.metadata);
+        assert_eq!(report.template_len, 4);
+        assert_eq!(report.payload_len, 33 + 4);
+        assert_eq!(report.template_len + report.payload_len, encoded.len());
+        assert_eq!(report.metadata_records, 1);
+
+        let encoded = encode(desc_str).unwrap();
+        assert_eq!(
+            can_decode(&encoded[..encoded.len() - 1]),
+            Err(Error::MissingBytes)
+        );
+        let mut extended = encoded.clone();
+        extended.push(0);
+        assert_eq!(can_decode(&extended), Err(Error::PayloadTooLarge(33, 34)));
+        assert_eq!(can_decode(&[0xC0]), Err(Error::UnrecognizedTag(0)));
+
+        // A template without a payload decodes to dummy values
+        let report = can_decode(&encoded[..3]).unwrap();
+        assert_eq!((report.template_len, report.payload_len), (3, 0));
+
+        let codec = Codec::new().with_payload_commitment(true);
+        let mut committed = codec.encode(desc_str).unwrap();
+        assert!(can_decode(&committed).unwrap().features.payload_commitment);
+        *committed.last_mut().unwrap() ^= 1;
+        assert_eq!(can_decode(&committed), Err(Error::PayloadMismatch));
+
+        let timelock = "wsh(and_v(v:pk(03a0434d9e47f3c86235477c7b1ae6ae5d3442d49b1943c2b752a68e2a47e247c7),older(144)))";
+        let mut encoded = encode(timelock).unwrap();
+        encoded.truncate(encoded.len() - 2);
+        encoded.push(0);
+        let report = can_decode(&encoded);
+        assert!(matches!(report, Err(Error::InvalidLocktime(33, _))));
+    }
+}
@@ -0,0 +1,316 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! # Flat API
+//!
+//! The codec through functions taking and returning only strings, byte slices, and integer error
+//! codes, for binding generators such as UniFFI or Kotlin Multiplatform. Every FFI target shares
+//! this one entry surface.
+//!
+//! Error codes are stable: encoding errors are below 100 and decoding errors from 100. Positions
+//! carried by the Rust errors are dropped; [`error_message`] describes each code.
+
+use super::*;
+
+/// Descriptor cannot be parsed
+pub const INVALID_DESCRIPTOR: i32 = 1;
+/// Key cannot be parsed
+pub const INVALID_KEY: i32 = 2;
+/// Hash cannot be parsed
+pub const INVALID_HASH: i32 = 3;
+/// Key is not a master extended private key
+pub const NOT_MASTER_KEY: i32 = 4;
+/// Checksum is missing or wrong
+pub const INVALID_CHECKSUM: i32 = 5;
+/// Uncompressed key in a segwit context
+pub const UNCOMPRESSED_KEY: i32 = 6;
+/// Key decodes differently than it was encoded
+pub const KEY_MISMATCH: i32 = 7;
+/// Template uses a name with no bound key
+pub const UNBOUND_NAME: i32 = 8;
+/// Bound key is not used by the template
+pub const UNUSED_BINDING: i32 = 9;
+/// Number in a key path is written with leading zeros or a sign
+pub const NON_CANONICAL_NUMBER: i32 = 10;
+/// Output buffer is smaller than the encoding
+pub const BUFFER_TOO_SMALL: i32 = 11;
+/// Derivation path has more steps than a key can record
+pub const PATH_TOO_LONG_TO_ENCODE: i32 = 12;
+/// Input is missing bytes
+pub const MISSING_BYTES: i32 = 100;
+/// Unrecognized tag
+pub const UNRECOGNIZED_TAG: i32 = 101;
+/// Invalid tag
+pub const INVALID_TAG: i32 = 102;
+/// Invalid miniscript
+pub const INVALID_MINISCRIPT: i32 = 103;
+/// Invalid var int
+pub const INVALID_VAR_INT: i32 = 104;
+/// Missing derivation paths
+pub const MISSING_DERIV_PATHS: i32 = 105;
+/// Invalid payload
+pub const INVALID_PAYLOAD: i32 = 106;
+/// Payload too large
+pub const PAYLOAD_TOO_LARGE: i32 = 107;
+/// Decoded descriptor exceeds the allocation budget
+pub const OVERSIZED: i32 = 108;
+/// Preset ID is not in the registry
+pub const UNKNOWN_PRESET: i32 = 109;
+/// Template does not match the expected template hash
+pub const TEMPLATE_MISMATCH: i32 = 110;
+/// Payload does not match the template's payload commitment
+pub const PAYLOAD_MISMATCH: i32 = 111;
+/// Template has no payload commitment
+pub const MISSING_COMMITMENT: i32 = 112;
+/// Invalid base58check string
+pub const INVALID_BASE58: i32 = 113;
+/// Invalid digit or checksum on a line of grouped hex
+pub const INVALID_HEX: i32 = 114;
+/// Invalid line in a key mapping
+pub const INVALID_MAPPING: i32 = 115;
+/// Key is not in the key mapping
+pub const UNMAPPED_KEY: i32 = 116;
+/// Metadata record of odd type is not understood
+pub const UNKNOWN_REQUIRED_TLV: i32 = 117;
+/// Metadata record of known type is malformed
+pub const INVALID_TLV: i32 = 118;
+/// Bundle key annotation is malformed or refers to a missing entry
+pub const INVALID_ANNOTATION: i32 = 119;
+/// Secret key cannot be converted to a watch-only key
+pub const UNDERIVABLE_KEY: i32 = 120;
+/// Scanned QR payload cannot be read
+pub const INVALID_QR_PART: i32 = 121;
+/// Scanned QR part belongs to a different sequence than earlier parts
+pub const QR_PART_MISMATCH: i32 = 122;
+/// Backup file has a wrong magic or checksum
+pub const INVALID_BACKUP: i32 = 123;
+/// Backup file version is not supported
+pub const UNSUPPORTED_BACKUP_VERSION: i32 = 124;
+/// Key is wrong or encrypted data was altered
+pub const DECRYPTION_FAILED: i32 = 125;
+/// Multipath key's derivation paths differ in other than a single step
+pub const INVALID_MULTIPATH: i32 = 126;
+/// Descriptor is a `rawtr()`, which only decodes to a string
+pub const RAW_TR: i32 = 127;
+/// Derivation path has more steps than allowed while decoding
+pub const PATH_TOO_LONG: i32 = 128;
+/// Timelock is outside its consensus-valid range
+pub const INVALID_LOCKTIME: i32 = 129;
+/// Invalid base32 character
+pub const INVALID_BASE32: i32 = 130;
+/// Descriptor has more keys than allowed
+pub const TOO_MANY_KEYS: i32 = 131;
+/// Hash reference does not refer to an earlier hash
+pub const INVALID_HASH_REF: i32 = 132;
+/// Bundle manifest is malformed or does not match an entry
+pub const INVALID_MANIFEST: i32 = 133;
+/// Backup cipher cannot wrap or unwrap a key
+pub const KEY_UNAVAILABLE: i32 = 134;
+/// Operation was cancelled
+pub const CANCELLED: i32 = 135;
+/// Key derivation parameters record is malformed
+pub const MALFORMED_KDF_PARAMS: i32 = 136;
+/// Key derivation function is not known
+pub const UNKNOWN_KDF: i32 = 137;
+/// Key derivation costs are invalid or too high
+pub const INVALID_KDF_PARAMS: i32 = 138;
+/// OS random number generator cannot be read
+pub const RANDOMNESS_UNAVAILABLE: i32 = 139;
+
+/// Parses and encodes a Bitcoin descriptor
+pub fn encode(descriptor: &str) -> Result<Vec<u8>, i32> {
+    crate::encode(descriptor).map_err(|e| encode_error_code(&e))
+}
+
+/// Decodes an encoded Bitcoin descriptor
+pub fn decode(bytes: &[u8]) -> Result<String, i32> {
+    crate::decode(bytes).map_err(|e| decode_error_code(&e))
+}
+
+/// Parses and encodes a Bitcoin descriptor as base58check
+pub fn encode_base58(descriptor: &str) -> Result<String, i32> {
+    format::encode_base58(descriptor).map_err(|e| encode_error_code(&e))
+}
+
+/// Decodes a base58check-encoded Bitcoin descriptor
+pub fn decode_base58(s: &str) -> Result<String, i32> {
+    format::decode_base58(s).map_err(|e| decode_error_code(&e))
+}
+
+/// Returns the 32-byte policy hash of an encoded descriptor, see [`crate::template_hash`]
+pub fn template_hash(bytes: &[u8]) -> Result<Vec<u8>, i32> {
+    crate::template_hash(bytes)
+        .map(|hash| hash.to_byte_array().to_vec())
+        .map_err(|e| decode_error_code(&e))
+}
+
+/// Parses a descriptor and returns its canonical string
+pub fn normalize(descriptor: &str) -> Result<String, i32> {
+    crate::normalize(descriptor).map_err(|e| encode_error_code(&e))
+}
+
+/// Returns the checksum of a descriptor, ignoring any checksum it already has
+pub fn checksum(descriptor: &str) -> Result<String, i32> {
+    crate::checksum(descriptor).map_err(|e| encode_error_code(&e))
+}
+
+/// Returns the message of an error code
+pub fn error_message(code: i32) -> &'static str {
+    match code {
+        INVALID_DESCRIPTOR => "invalid descriptor",
+        INVALID_KEY => "invalid key",
+        INVALID_HASH => "invalid hash",
+        NOT_MASTER_KEY => "not a master extended private key",
+        INVALID_CHECKSUM => "invalid checksum",
+        UNCOMPRESSED_KEY => "uncompressed key not allowed in segwit",
+        KEY_MISMATCH => "key does not round-trip",
+        UNBOUND_NAME => "template name has no bound key",
+        UNUSED_BINDING => "bound key is not used",
+        NON_CANONICAL_NUMBER => "non-canonical number in key path",
+        BUFFER_TOO_SMALL => "buffer too small",
+        PATH_TOO_LONG_TO_ENCODE => "derivation path too long to encode",
+        MISSING_BYTES => "missing bytes",
+        UNRECOGNIZED_TAG => "unrecognized tag",
+        INVALID_TAG => "invalid tag",
+        INVALID_MINISCRIPT => "invalid miniscript",
+        INVALID_VAR_INT => "invalid varint",
+        MISSING_DERIV_PATHS => "missing derivation paths",
+        INVALID_PAYLOAD => "invalid payload",
+        PAYLOAD_TOO_LARGE => "payload too large",
+        OVERSIZED => "decoded descriptor exceeds allocation budget",
+        UNKNOWN_PRESET => "unknown preset",
+        TEMPLATE_MISMATCH => "template does not match expected template",
+        PAYLOAD_MISMATCH => "payload does not match commitment",
+        MISSING_COMMITMENT => "template has no payload commitment",
+        INVALID_BASE58 => "invalid base58check string",
+        INVALID_HEX => "invalid grouped hex",
+        INVALID_MAPPING => "invalid key mapping",
+        UNMAPPED_KEY => "key is not in key mapping",
+        UNKNOWN_REQUIRED_TLV => "unknown required metadata",
+        INVALID_TLV => "invalid metadata",
+        INVALID_ANNOTATION => "invalid key annotation",
+        UNDERIVABLE_KEY => "secret key cannot be made watch-only",
+        INVALID_QR_PART => "invalid QR part",
+        QR_PART_MISMATCH => "QR part belongs to a different sequence",
+        INVALID_BACKUP => "invalid backup magic or checksum",
+        UNSUPPORTED_BACKUP_VERSION => "unsupported backup version",
+        DECRYPTION_FAILED => "wrong key or altered encrypted data",
+        INVALID_MULTIPATH => "invalid multipath key",
+        RAW_TR => "rawtr descriptor only decodes to a string",
+        PATH_TOO_LONG => "derivation path too long",
+        INVALID_LOCKTIME => "invalid locktime",
+        INVALID_BASE32 => "invalid base32",
+        TOO_MANY_KEYS => "too many keys",
+        INVALID_HASH_REF => "invalid hash reference",
+        INVALID_MANIFEST => "invalid bundle manifest",
+        KEY_UNAVAILABLE => "backup cipher key unavailable",
+        CANCELLED => "operation cancelled",
+        MALFORMED_KDF_PARAMS => "malformed key derivation parameters",
+        UNKNOWN_KDF => "unknown key derivation function",
+        INVALID_KDF_PARAMS => "invalid or too costly key derivation parameters",
+        RANDOMNESS_UNAVAILABLE => "OS random number generator unavailable",
+        _ => "unknown error",
+    }
+}
+
+fn encode_error_code(error: &encoder::Error) -> i32 {
+    match error {
+        encoder::Error::InvalidDescriptor(_) => INVALID_DESCRIPTOR,
+        encoder::Error::InvalidKey(_) => INVALID_KEY,
+        encoder::Error::InvalidHash(_) => INVALID_HASH,
+        encoder::Error::NotMasterKey => NOT_MASTER_KEY,
+        encoder::Error::InvalidChecksum(_) => INVALID_CHECKSUM,
+        encoder::Error::UncompressedKey(_) => UNCOMPRESSED_KEY,
+        encoder::Error::KeyMismatch(_) => KEY_MISMATCH,
+        encoder::Error::PathTooLong(_) => PATH_TOO_LONG_TO_ENCODE,
+        encoder::Error::UnboundName(_) => UNBOUND_NAME,
+        encoder::Error::UnusedBinding(_) => UNUSED_BINDING,
+        encoder::Error::NonCanonicalNumber(_) => NON_CANONICAL_NUMBER,
+        encoder::Error::BufferTooSmall(_) => BUFFER_TOO_SMALL,
+    }
+}
+
+fn decode_error_code(error: &Error) -> i32 {
+    match error {
+        Error::MissingBytes => MISSING_BYTES,
+        Error::UnrecognizedTag(_) => UNRECOGNIZED_TAG,
+        Error::InvalidTag(_) => INVALID_TAG,
+        Error::InvalidMiniscript(_, _) => INVALID_MINISCRIPT,
+        Error::InvalidVarInt(_, _) => INVALID_VAR_INT,
+        Error::MissingDerivPaths(_) => MISSING_DERIV_PATHS,
+        Error::InvalidPayload(_, _) => INVALID_PAYLOAD,
+        Error::PayloadTooLarge(_, _) => PAYLOAD_TOO_LARGE,
+        Error::Oversized => OVERSIZED,
+        Error::UnknownPreset(_) => UNKNOWN_PRESET,
+        Error::TemplateMismatch => TEMPLATE_MISMATCH,
+        Error::PayloadMismatch => PAYLOAD_MISMATCH,
+        Error::MissingCommitment => MISSING_COMMITMENT,
+        Error::InvalidBase58(_) => INVALID_BASE58,
+        Error::InvalidHex(_) => INVALID_HEX,
+        Error::InvalidMapping(_) => INVALID_MAPPING,
+        Error::UnmappedKey => UNMAPPED_KEY,
+        Error::UnknownRequiredTlv(_) => UNKNOWN_REQUIRED_TLV,
+        Error::InvalidTlv(_) => INVALID_TLV,
+        Error::InvalidAnnotation(_) => INVALID_ANNOTATION,
+        Error::UnderivableKey => UNDERIVABLE_KEY,
+        Error::Qr(QrError::InvalidPart) => INVALID_QR_PART,
+        Error::Qr(QrError::PartMismatch) => QR_PART_MISMATCH,
+        Error::Backup(BackupError::Invalid) => INVALID_BACKUP,
+        Error::Backup(BackupError::UnsupportedVersion(_)) => UNSUPPORTED_BACKUP_VERSION,
+        Error::Backup(BackupError::DecryptionFailed) => DECRYPTION_FAILED,
+        Error::Backup(BackupError::KeyUnavailable) => KEY_UNAVAILABLE,
+        Error::Backup(BackupError::RandomnessUnavailable) => RANDOMNESS_UNAVAILABLE,
+        Error::Kdf(KdfError::Malformed) => MALFORMED_KDF_PARAMS,
+        Error::Kdf(KdfError::UnknownFunction(_)) => UNKNOWN_KDF,
+        Error::Kdf(KdfError::InvalidParams) => INVALID_KDF_PARAMS,
+        Error::InvalidMultipath(_) => INVALID_MULTIPATH,
+        Error::RawTr => RAW_TR,
+        Error::PathTooLong(_) => PATH_TOO_LONG,
+        Error::InvalidLocktime(..) => INVALID_LOCKTIME,
+        Error::InvalidBase32(_) => INVALID_BASE32,
+        Error::TooManyKeys(_) => TOO_MANY_KEYS,
+        Error::InvalidHashRef(_) => INVALID_HASH_REF,
+        Error::InvalidManifest(_) => INVALID_MANIFEST,
+        Error::Cancelled => CANCELLED,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flat_api() {
+        let desc_str =
+            "wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)#8zl0zxma";
+        let encoded = encode(desc_str).unwrap();
+        assert_eq!(decode(&encoded).unwrap(), desc_str);
+        assert_eq!(
+            decode_base58(&encode_base58(desc_str).unwrap()).unwrap(),
+            desc_str
+        );
+        assert_eq!(template_hash(&encoded).unwrap().len(), 32);
+
+        assert_eq!(encode("wpkh(invalid)"), Err(INVALID_KEY));
+        assert_eq!(decode(&encoded[..encoded.len() - 1]), Err(MISSING_BYTES));
+        assert_eq!(decode(&[0xFF]), Err(UNRECOGNIZED_TAG));
+        assert_eq!(error_message(MISSING_BYTES), "missing bytes");
+        assert_eq!(error_message(0), "unknown error");
+
+        // Paths too long fail below 100 when encoding and from 100 when decoding
+        let path = "/0".repeat(decoder::DEFAULT_MAX_PATH_LEN + 1);
+        let xpub = "xpub6EigxozzGaNVWUwEFnbyX6oHPdpWTKgJgbfpRbAcdiGpGMrdpPinCoHBXehu35sqJHpgLDTxigAnFQG3opKjXQoSmGMrMNHz81ALZSBRCWw";
+        assert_eq!(
+            encode(&format!("wpkh({xpub}{path})")),
+            Err(PATH_TOO_LONG_TO_ENCODE)
+        );
+        let codec = Codec::new().with_max_path_len(decoder::DEFAULT_MAX_PATH_LEN + 1);
+        let encoded = codec.encode(&format!("wpkh({xpub}{path})")).unwrap();
+        assert_eq!(decode(&encoded), Err(PATH_TOO_LONG));
+
+        // Backup, QR, and key derivation errors map to their own codes
+        let backup = Error::Backup(BackupError::KeyUnavailable);
+        assert_eq!(decode_error_code(&backup), KEY_UNAVAILABLE);
+        let kdf = Error::Kdf(KdfError::InvalidParams);
+        assert_eq!(decode_error_code(&kdf), INVALID_KDF_PARAMS);
+    }
+}
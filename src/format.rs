@@ -0,0 +1,377 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! # Text Formats
+//!
+//! Text representations of encoded descriptors for tooling and paper workflows.
+
+use super::*;
+use bitcoin::{base58, hashes::HashEngine, hex::FromHex};
+use std::fmt::Write;
+
+/// Number of checksum characters ending each line of grouped hex
+const LINE_CHECKSUM_LEN: usize = 4;
+
+/// Characters of the base58 alphabet
+const BASE58_ALPHABET: &str = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// Characters of the RFC 4648 base32 alphabet
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// A single-character transcription error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edit {
+    /// Character at `position` was mistyped as `found` instead of `expected`
+    Substitution {
+        /// Character index
+        position: usize,
+        /// Character in the input
+        found: char,
+        /// Character in the correction
+        expected: char,
+    },
+    /// Characters at `position` and `position + 1` were swapped
+    Transposition {
+        /// Character index of the first swapped character
+        position: usize,
+    },
+}
+
+/// A candidate correction of a damaged base58check string
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Correction {
+    /// Transcription error undone by the correction
+    pub edit: Edit,
+    /// Corrected base58check string
+    pub corrected: String,
+    /// Descriptor decoded from the corrected string
+    pub descriptor: String,
+}
+
+/// Layout of grouped hex for human transcription
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HexStyle {
+    /// Whether to use uppercase digits
+    pub uppercase: bool,
+    /// Number of hex characters per group
+    pub group_len: usize,
+    /// Number of groups per line, excluding the checksum group
+    pub groups_per_line: usize,
+}
+
+impl Default for HexStyle {
+    fn default() -> Self {
+        Self {
+            uppercase: false,
+            group_len: 4,
+            groups_per_line: 8,
+        }
+    }
+}
+
+/// Parses and encodes a Bitcoin descriptor as base58check
+pub fn encode_base58(s: &str) -> Result<String, encoder::Error> {
+    Ok(base58::encode_check(&encode(s)?))
+}
+
+/// Decodes a base58check-encoded Bitcoin descriptor, validating the 4-byte checksum
+pub fn decode_base58(s: &str) -> Result<String, Error> {
+    let bytes = base58::decode_check(s.trim()).map_err(Error::InvalidBase58)?;
+    decode(&bytes)
+}
+
+/// Suggests corrections of a base58check string that fails to decode
+///
+/// Tries every single-character substitution and every swap of adjacent characters, keeping
+/// those that pass the 4-byte checksum and decode. The checksum detects far more errors than it
+/// can correct, so only single errors are tried, and a candidate should be confirmed by the user,
+/// such as by comparing addresses. Returns no candidates if the string already decodes.
+pub fn repair_base58(s: &str) -> Vec<Correction> {
+    let s = s.trim();
+    if decode_base58(s).is_ok() {
+        return Vec::new();
+    }
+
+    let chars: Vec<char> = s.chars().collect();
+    let mut corrections = Vec::new();
+    let mut try_candidate = |candidate: &[char], edit: Edit| {
+        let corrected: String = candidate.iter().collect();
+        if let Ok(descriptor) = decode_base58(&corrected) {
+            corrections.push(Correction {
+                edit,
+                corrected,
+                descriptor,
+            });
+        }
+    };
+
+    let mut candidate = chars.clone();
+    for (position, &found) in chars.iter().enumerate() {
+        for expected in BASE58_ALPHABET.chars().filter(|c| *c != found) {
+            candidate[position] = expected;
+            let edit = Edit::Substitution {
+                position,
+                found,
+                expected,
+            };
+            try_candidate(&candidate, edit);
+        }
+        candidate[position] = found;
+    }
+
+    for position in 0..chars.len().saturating_sub(1) {
+        if chars[position] != chars[position + 1] {
+            candidate.swap(position, position + 1);
+            try_candidate(&candidate, Edit::Transposition { position });
+            candidate.swap(position, position + 1);
+        }
+    }
+
+    corrections
+}
+
+/// Formats bytes as grouped hex, ending each line with a checksum group
+///
+/// The checksum covers the line number and the line's bytes, so a mistyped character or a
+/// swapped line is caught by [`parse_grouped_hex`].
+pub fn grouped_hex(bytes: &[u8], style: HexStyle) -> String {
+    let group_len = style.group_len.max(2) & !1;
+    let line_len = group_len / 2 * style.groups_per_line.max(1);
+
+    let mut out = String::new();
+    for (i, line) in bytes.chunks(line_len).enumerate() {
+        if i > 0 {
+            out.push('\n');
+        }
+
+        let mut digits = String::new();
+        for byte in line {
+            write!(digits, "{byte:02x}").expect("writing to a string");
+        }
+        for (j, group) in digits.as_bytes().chunks(group_len).enumerate() {
+            if j > 0 {
+                out.push(' ');
+            }
+            out.push_str(std::str::from_utf8(group).expect("hex is ascii"));
+        }
+        out.push_str(" - ");
+        out.push_str(&line_checksum(i, line));
+    }
+
+    if style.uppercase {
+        out.make_ascii_uppercase();
+    }
+    out
+}
+
+/// Parses grouped hex produced by [`grouped_hex`], validating each line's checksum
+///
+/// Whitespace and dashes within a line are ignored, as is the case of each digit. Blank lines
+/// are skipped.
+pub fn parse_grouped_hex(s: &str) -> Result<Vec<u8>, Error> {
+    let mut bytes = Vec::new();
+    let lines = s.lines().filter(|line| !line.trim().is_empty());
+    for (i, line) in lines.enumerate() {
+        let digits: String = line
+            .chars()
+            .filter(|c| !c.is_whitespace() && *c != '-')
+            .map(|c| c.to_ascii_lowercase())
+            .collect();
+        if digits.len() < LINE_CHECKSUM_LEN
+            || !digits.is_char_boundary(digits.len() - LINE_CHECKSUM_LEN)
+        {
+            return Err(Error::InvalidHex(i));
+        }
+
+        let (data, checksum) = digits.split_at(digits.len() - LINE_CHECKSUM_LEN);
+        let data = Vec::<u8>::from_hex(data).map_err(|_| Error::InvalidHex(i))?;
+        if checksum != line_checksum(i, &data) {
+            return Err(Error::InvalidHex(i));
+        }
+        bytes.extend(data);
+    }
+
+    Ok(bytes)
+}
+
+/// Formats bytes as unpadded RFC 4648 base32
+///
+/// Base32 uses only uppercase letters and digits, avoiding the similar-looking `0`, `1`, and
+/// `8`, so it suits handwritten backups.
+pub fn base32(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(5) * 8);
+    let (mut buffer, mut bits) = (0u16, 0);
+    for &byte in bytes {
+        buffer = (buffer << 8) | byte as u16;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32_ALPHABET[(buffer >> bits) as usize & 31] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32_ALPHABET[(buffer << (5 - bits)) as usize & 31] as char);
+    }
+    out
+}
+
+/// Parses base32 produced by [`base32`]
+///
+/// Whitespace, dashes, and trailing padding are ignored, as is the case of each character.
+pub fn parse_base32(s: &str) -> Result<Vec<u8>, Error> {
+    let s = s.trim_end().trim_end_matches('=');
+    let mut out = Vec::with_capacity(s.len() * 5 / 8);
+    let (mut buffer, mut bits) = (0u16, 0);
+    for (i, c) in s.chars().enumerate() {
+        if c.is_whitespace() || c == '-' {
+            continue;
+        }
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|&b| b as char == c.to_ascii_uppercase())
+            .ok_or(Error::InvalidBase32(i))?;
+        buffer = (buffer << 5) | value as u16;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    // Leftover bits are padding, which must be zero
+    if bits >= 5 || buffer & ((1 << bits) - 1) != 0 {
+        return Err(Error::InvalidBase32(s.chars().count().saturating_sub(1)));
+    }
+
+    Ok(out)
+}
+
+/// Returns the checksum of a line of grouped hex as lowercase hex
+fn line_checksum(line: usize, data: &[u8]) -> String {
+    let mut engine = sha256::Hash::engine();
+    engine.input(&(line as u32).to_le_bytes());
+    engine.input(data);
+    let hash = sha256::Hash::from_engine(engine).to_byte_array();
+    format!("{:02x}{:02x}", hash[0], hash[1])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base58() {
+        let desc_str =
+            "wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)#8zl0zxma";
+        let encoded = encode_base58(desc_str).unwrap();
+        assert_eq!(
+            base58::decode_check(&encoded).unwrap(),
+            encode(desc_str).unwrap()
+        );
+        assert_eq!(decode_base58(&encoded).unwrap(), desc_str);
+
+        // Corrupting a character breaks the checksum
+        let mut corrupted = encoded.into_bytes();
+        corrupted[5] = if corrupted[5] == b'2' { b'3' } else { b'2' };
+        let corrupted = String::from_utf8(corrupted).unwrap();
+        assert!(matches!(
+            decode_base58(&corrupted),
+            Err(Error::InvalidBase58(_))
+        ));
+    }
+
+    #[test]
+    fn test_repair_base58() {
+        let desc_str =
+            "wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)#8zl0zxma";
+        let encoded = encode_base58(desc_str).unwrap();
+        assert!(repair_base58(&encoded).is_empty());
+
+        // A mistyped character, including one outside the alphabet
+        let mut chars: Vec<char> = encoded.chars().collect();
+        let original = chars[10];
+        chars[10] = '0';
+        let mistyped: String = chars.iter().collect();
+        let corrections = repair_base58(&mistyped);
+        assert_eq!(corrections.len(), 1);
+        assert_eq!(
+            corrections[0].edit,
+            Edit::Substitution {
+                position: 10,
+                found: '0',
+                expected: original
+            }
+        );
+        assert_eq!(corrections[0].corrected, encoded);
+        assert_eq!(corrections[0].descriptor, desc_str);
+
+        // Swapped characters
+        let mut chars: Vec<char> = encoded.chars().collect();
+        let position = (0..chars.len() - 1)
+            .find(|&i| chars[i] != chars[i + 1])
+            .unwrap();
+        chars.swap(position, position + 1);
+        let swapped: String = chars.iter().collect();
+        let corrections = repair_base58(&swapped);
+        assert!(
+            corrections
+                .iter()
+                .any(|c| c.edit == Edit::Transposition { position } && c.corrected == encoded)
+        );
+    }
+
+    #[test]
+    fn test_grouped_hex() {
+        let bytes: Vec<u8> = (0..40).collect();
+        let grouped = grouped_hex(&bytes, HexStyle::default());
+        let lines: Vec<_> = grouped.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("0001 0203 0405 0607 0809 0a0b 0c0d 0e0f - "));
+        assert!(lines[2].starts_with("2021 2223 2425 2627 - "));
+        assert_eq!(parse_grouped_hex(&grouped).unwrap(), bytes);
+
+        // Casing, spacing, dashes, and blank lines are tolerated
+        let style = HexStyle {
+            uppercase: true,
+            group_len: 2,
+            groups_per_line: 5,
+        };
+        let grouped = grouped_hex(&bytes, style);
+        assert!(grouped.starts_with("00 01 02 03 04 - "));
+        let retyped = grouped.replace(' ', "-").replace('\n', "\n\n  ");
+        assert_eq!(parse_grouped_hex(&retyped).unwrap(), bytes);
+
+        // A mistyped digit or swapped lines fail the line checksum
+        let grouped = grouped_hex(&bytes, HexStyle::default());
+        let mistyped = grouped.replacen("0a0b", "0a0c", 1);
+        assert_eq!(parse_grouped_hex(&mistyped), Err(Error::InvalidHex(0)));
+        let lines: Vec<_> = grouped.lines().collect();
+        let swapped = [lines[1], lines[0], lines[2]].join("\n");
+        assert_eq!(parse_grouped_hex(&swapped), Err(Error::InvalidHex(0)));
+        assert_eq!(parse_grouped_hex("zz - 0000"), Err(Error::InvalidHex(0)));
+    }
+
+    #[test]
+    fn test_base32() {
+        // RFC 4648 test vectors, without padding
+        let vectors = [
+            ("", ""),
+            ("f", "MY"),
+            ("fo", "MZXQ"),
+            ("foo", "MZXW6"),
+            ("foob", "MZXW6YQ"),
+            ("fooba", "MZXW6YTB"),
+            ("foobar", "MZXW6YTBOI"),
+        ];
+        for (bytes, text) in vectors {
+            assert_eq!(base32(bytes.as_bytes()), text);
+            assert_eq!(parse_base32(text).unwrap(), bytes.as_bytes());
+        }
+
+        let bytes: Vec<u8> = (0..=255).collect();
+        assert_eq!(parse_base32(&base32(&bytes)).unwrap(), bytes);
+        assert_eq!(parse_base32("mzxw-6ytb\noi======").unwrap(), b"foobar");
+        assert_eq!(parse_base32("MZXW0"), Err(Error::InvalidBase32(4)));
+        // Nonzero padding bits or a dangling character
+        assert_eq!(parse_base32("MZ"), Err(Error::InvalidBase32(1)));
+        assert_eq!(parse_base32("MZXW6Y"), Err(Error::InvalidBase32(5)));
+    }
+}
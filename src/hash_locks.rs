@@ -0,0 +1,114 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! # Hash Locks
+//!
+//! Extracts the hash-lock digests of an encoded descriptor, for services that index
+//! preimage-based contracts and need neither keys nor structure. Only the template's tags and the
+//! hashes in the payload are read, so keys are never parsed or derived.
+
+use super::decoder::Error;
+use super::tag::Tag;
+use super::walk::{self, Item};
+use bitcoin::hashes::{Hash, hash160, ripemd160, sha256};
+use miniscript::hash256;
+use std::fmt;
+
+/// A hash-lock digest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HashLock {
+    /// `sha256(h)`
+    Sha256(sha256::Hash),
+    /// `hash256(h)`
+    Hash256(hash256::Hash),
+    /// `ripemd160(h)`
+    Ripemd160(ripemd160::Hash),
+    /// `hash160(h)`
+    Hash160(hash160::Hash),
+}
+
+impl HashLock {
+    /// Reads the digest of a hash-lock fragment from its payload bytes
+    fn from_slice(tag: Tag, bytes: &[u8]) -> Self {
+        let expect = "walk reads a digest of the hash's size";
+        match tag {
+            Tag::Sha256 => HashLock::Sha256(sha256::Hash::from_slice(bytes).expect(expect)),
+            Tag::Hash256 => HashLock::Hash256(hash256::Hash::from_slice(bytes).expect(expect)),
+            Tag::Ripemd160 => {
+                HashLock::Ripemd160(ripemd160::Hash::from_slice(bytes).expect(expect))
+            }
+            _ => HashLock::Hash160(hash160::Hash::from_slice(bytes).expect(expect)),
+        }
+    }
+}
+
+impl fmt::Display for HashLock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HashLock::Sha256(h) => write!(f, "sha256({h})"),
+            HashLock::Hash256(h) => write!(f, "hash256({h})"),
+            HashLock::Ripemd160(h) => write!(f, "ripemd160({h})"),
+            HashLock::Hash160(h) => write!(f, "hash160({h})"),
+        }
+    }
+}
+
+/// Returns the distinct hash locks of an encoded descriptor, in order of appearance
+///
+/// Walks the template's tags and reads each hash from the payload, without building the
+/// descriptor or reading its keys. Fails with [`Error::MissingBytes`] if the encoding has no
+/// payload.
+pub fn decode_hash_locks(bytes: &[u8]) -> Result<Vec<HashLock>, Error> {
+    let walk = walk::walk(bytes)?;
+
+    let mut hash_locks = Vec::new();
+    walk.read_payload(&bytes[walk.template_len..], |item, value| {
+        if let Item::Hash(tag) = item {
+            let hash_lock = HashLock::from_slice(tag, value);
+            if !hash_locks.contains(&hash_lock) {
+                hash_locks.push(hash_lock);
+            }
+        }
+    })?;
+
+    Ok(hash_locks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_decode_hash_locks() {
+        let sha256 = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        let hash160 = "b472a266d0bd89c13706a4132ccfb16f7c3b9fcb";
+        let desc_str = format!(
+            "wsh(or_d(pk(03a0434d9e47f3c86235477c7b1ae6ae5d3442d49b1943c2b752a68e2a47e247c7),and_v(v:sha256({sha256}),or_d(hash160({hash160}),sha256({sha256})))))"
+        );
+        let encoded = encode(&desc_str).unwrap();
+
+        let hash_locks = decode_hash_locks(&encoded).unwrap();
+        assert_eq!(
+            hash_locks,
+            vec![
+                HashLock::Sha256(sha256::Hash::from_str(sha256).unwrap()),
+                HashLock::Hash160(hash160::Hash::from_str(hash160).unwrap()),
+            ]
+        );
+        assert_eq!(hash_locks[1].to_string(), format!("hash160({hash160})"));
+
+        let encoded =
+            encode("wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)")
+                .unwrap();
+        assert!(decode_hash_locks(&encoded).unwrap().is_empty());
+
+        // Every hash is read from the payload, so it must be present
+        let encoded = encode(&desc_str).unwrap();
+        assert_eq!(
+            decode_hash_locks(&encoded[..encoded.len() - 1]),
+            Err(Error::MissingBytes)
+        );
+        assert_eq!(decode_hash_locks(&[0xFF]), Err(Error::UnrecognizedTag(0)));
+    }
+}
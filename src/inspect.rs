@@ -0,0 +1,174 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! # Inspection
+//!
+//! Summarizes an encoded descriptor for display: its public form, the format features it uses,
+//! its keys, and its metadata. Secret keys are never shown.
+
+use super::*;
+use decoder::DecodeContext;
+use features::FeatureSet;
+use metadata::{KEY_ROTATION, KeyRotation};
+use miniscript::ForEachKey;
+use std::fmt;
+
+/// Summary of an encoded descriptor
+#[derive(Debug, Clone, PartialEq)]
+pub struct Inspection {
+    /// Descriptor with the public keys of any secret keys
+    pub descriptor: String,
+    /// Optional format features used by the encoding
+    pub features: FeatureSet,
+    /// Keys, in order of appearance
+    pub keys: Vec<DescriptorPublicKey>,
+    /// Key rotation policies
+    pub key_rotations: Vec<KeyRotation>,
+    /// Metadata records of unknown type
+    pub metadata: Vec<Tlv>,
+    /// Whether a top-level `multi()` was encoded as `sortedmulti()`
+    pub sorted_multi: bool,
+    /// Whether the encoding holds secret keys
+    pub has_secrets: bool,
+}
+
+impl fmt::Display for Inspection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "descriptor: {}", self.descriptor)?;
+        writeln!(f, "features: {}", self.features)?;
+        for (index, key) in self.keys.iter().enumerate() {
+            writeln!(f, "key {index}: {key}")?;
+        }
+        for rotation in &self.key_rotations {
+            write!(f, "rotation of key {}:", rotation.key_index)?;
+            if let Some(time) = rotation.valid_from {
+                write!(f, " valid from {time}")?;
+            }
+            if let Some(time) = rotation.valid_until {
+                write!(f, " valid until {time}")?;
+            }
+            if let Some(height) = rotation.rotate_after_height {
+                write!(f, " rotate after height {height}")?;
+            }
+            writeln!(f)?;
+        }
+        if self.has_secrets {
+            writeln!(f, "warning: the encoding holds secret keys")?;
+        }
+        if self.sorted_multi {
+            writeln!(
+                f,
+                "warning: multi() was encoded as sortedmulti(), so the script may differ from the original"
+            )?;
+        }
+        for record in &self.metadata {
+            writeln!(
+                f,
+                "metadata: type {}, {} bytes",
+                record.kind,
+                record.value.len()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Decodes a Bitcoin descriptor into a summary for display
+///
+/// Fails with [`Error::InvalidTlv`] if a key rotation refers to a key that does not exist.
+pub fn inspect(bytes: &[u8]) -> Result<Inspection, Error> {
+    let features = features::required_features(bytes)?;
+
+    let mut ctx = DecodeContext::default();
+    let descriptor = decoder::decode_bytes_in(bytes, &mut ctx)?;
+    let (descriptor, _) =
+        decoder::derive_public_keys(&descriptor, &ctx.key_map, &secp256k1::Secp256k1::new())?;
+
+    let mut keys = Vec::new();
+    descriptor.for_each_key(|key| {
+        keys.push(key.clone());
+        true
+    });
+    if ctx
+        .key_rotations
+        .iter()
+        .any(|rotation| rotation.key_index as usize >= keys.len())
+    {
+        return Err(Error::InvalidTlv(KEY_ROTATION));
+    }
+
+    Ok(Inspection {
+        descriptor: match ctx.raw_tr {
+            true => raw_tr_string(&descriptor, &KeyMap::new()),
+            false => descriptor.to_string(),
+        },
+        features,
+        keys,
+        key_rotations: ctx.key_rotations,
+        metadata: ctx.metadata,
+        sorted_multi: ctx.sorted_multi,
+        has_secrets: !ctx.key_map.is_empty(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inspect() {
+        let desc_str = "wsh(multi(1,03a0434d9e47f3c86235477c7b1ae6ae5d3442d49b1943c2b752a68e2a47e247c7,036d2b085e9e382ed10b69fc311a03f8641ccfff21574de0927513a49d9a688a00))#rd2pu9rk";
+        let rotation = KeyRotation {
+            key_index: 1,
+            valid_until: Some(1_900_000_000),
+            rotate_after_height: Some(1_000_000),
+            ..Default::default()
+        };
+        let records = [
+            rotation.into(),
+            Tlv {
+                kind: 2,
+                value: vec![1, 2],
+            },
+        ];
+        let encoded = encode_with_metadata(desc_str, &records).unwrap();
+
+        let inspection = inspect(&encoded).unwrap();
+        assert_eq!(inspection.descriptor, desc_str);
+        assert!(inspection.features.metadata);
+        assert_eq!(inspection.keys.len(), 2);
+        assert_eq!(inspection.key_rotations, vec![rotation]);
+        assert_eq!(inspection.metadata, records[1..]);
+        assert!(!inspection.has_secrets);
+        assert_eq!(
+            inspection.to_string(),
+            format!(
+                "descriptor: {desc_str}\n\
+                features: metadata\n\
+                key 0: 03a0434d9e47f3c86235477c7b1ae6ae5d3442d49b1943c2b752a68e2a47e247c7\n\
+                key 1: 036d2b085e9e382ed10b69fc311a03f8641ccfff21574de0927513a49d9a688a00\n\
+                rotation of key 1: valid until 1900000000 rotate after height 1000000\n\
+                metadata: type 2, 2 bytes\n"
+            )
+        );
+
+        // Key rotations must refer to an existing key
+        let rotation = KeyRotation {
+            key_index: 2,
+            ..Default::default()
+        };
+        let encoded = encode_with_metadata(desc_str, &[rotation.into()]).unwrap();
+        assert_eq!(inspect(&encoded), Err(Error::InvalidTlv(KEY_ROTATION)));
+
+        // Secret keys are shown as public keys
+        let wif = "L4rK1yDtCWekvXuE6oXD9jCYfFNV2cWRpVuPLBcCU2z8TrisoyY1";
+        let inspection = inspect(&encode(&format!("wpkh({wif})")).unwrap()).unwrap();
+        assert!(!inspection.descriptor.contains(wif));
+        assert!(!inspection.to_string().contains(wif));
+        assert!(inspection.has_secrets);
+        assert!(
+            inspection
+                .to_string()
+                .contains("warning: the encoding holds secret keys")
+        );
+    }
+}
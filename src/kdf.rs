@@ -0,0 +1,477 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! # Key Derivation
+//!
+//! Derives the 32-byte key of an encrypted backup entry from a passphrase. The function and its
+//! costs are stored in the backup's metadata as a record of type [`KDF_PARAMS`], so costs can be
+//! raised for new backups while old ones still decrypt.
+//!
+//! The record value is a variable-length function ID, `0` for Argon2id and `1` for scrypt, its
+//! three cost parameters as variable-length integers, then the salt. Argon2id takes memory in
+//! KiB, iterations, and lanes, as in RFC 9106. Scrypt takes the base-2 logarithm of `N`, `r`, and
+//! `p`, as in RFC 7914.
+//!
+//! Every cost is capped, so a hostile header can neither exhaust memory nor stall decryption:
+//! memory by [`MAX_MEMORY`], passes and lanes by [`MAX_ITERATIONS`] and [`MAX_PARALLELISM`],
+//! scrypt's costs by [`MAX_LOG_N`], [`MAX_R`], and [`MAX_P`], and memory times passes by
+//! [`MAX_WORK`].
+//!
+//! Deriving a key needs the `encryption` feature, which brings in the `argon2` and `scrypt`
+//! crates. Parameters can be read and written without it.
+
+use super::*;
+#[cfg(feature = "encryption")]
+use argon2::{Algorithm, Argon2, Version};
+use metadata::KDF_PARAMS;
+use std::{error, fmt};
+
+/// Most memory a key derivation may use, in bytes, so a hostile header cannot exhaust memory
+pub const MAX_MEMORY: u64 = 1 << 30;
+
+/// Most bytes of memory a key derivation may process, its memory times its passes
+pub const MAX_WORK: u64 = 1 << 33;
+
+/// Most passes over memory Argon2id may make
+pub const MAX_ITERATIONS: u32 = 16;
+
+/// Most lanes Argon2id may use
+pub const MAX_PARALLELISM: u32 = 16;
+
+/// Largest base-2 logarithm of scrypt's `N`
+pub const MAX_LOG_N: u8 = 20;
+
+/// Largest scrypt block size
+pub const MAX_R: u32 = 32;
+
+/// Largest scrypt parallelization
+pub const MAX_P: u32 = 16;
+
+/// Function ID of Argon2id
+const ARGON2ID: u64 = 0;
+
+/// Function ID of scrypt
+const SCRYPT: u64 = 1;
+
+/// Fewest salt bytes either function accepts
+pub const MIN_SALT_LEN: usize = 8;
+
+/// Error reading or applying key derivation parameters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KdfError {
+    /// Parameters record is truncated or of another type
+    Malformed,
+    /// Function ID is not known
+    UnknownFunction(u32),
+    /// Costs are invalid or exceed a cap, or the salt is too short
+    InvalidParams,
+}
+
+impl fmt::Display for KdfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Malformed => write!(f, "malformed key derivation parameters"),
+            Self::UnknownFunction(id) => write!(f, "unknown key derivation function (id: {id})"),
+            Self::InvalidParams => write!(f, "invalid or too costly key derivation parameters"),
+        }
+    }
+}
+
+impl error::Error for KdfError {}
+
+/// A password-based key derivation function and its costs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kdf {
+    /// Argon2id, version 0x13
+    Argon2id {
+        /// Memory in KiB, at least 8 per lane
+        memory_kib: u32,
+        /// Number of passes over memory
+        iterations: u32,
+        /// Number of lanes
+        parallelism: u32,
+    },
+    /// Scrypt
+    Scrypt {
+        /// Base-2 logarithm of the CPU and memory cost `N`
+        log_n: u8,
+        /// Block size
+        r: u32,
+        /// Parallelization
+        p: u32,
+    },
+}
+
+impl Default for Kdf {
+    /// Argon2id with 64 MiB, 3 passes, and 4 lanes, as recommended by RFC 9106
+    fn default() -> Self {
+        Kdf::Argon2id {
+            memory_kib: 1 << 16,
+            iterations: 3,
+            parallelism: 4,
+        }
+    }
+}
+
+impl Kdf {
+    /// Returns the memory the derivation uses, in bytes
+    pub fn memory(&self) -> u64 {
+        match *self {
+            Kdf::Argon2id { memory_kib, .. } => u64::from(memory_kib) * 1024,
+            Kdf::Scrypt { log_n, r, .. } => {
+                (128 * u64::from(r)).saturating_mul(1u64.checked_shl(log_n.into()).unwrap_or(0))
+            }
+        }
+    }
+
+    /// Returns the memory processed by the derivation, in bytes
+    pub fn work(&self) -> u64 {
+        let passes = match *self {
+            Kdf::Argon2id { iterations, .. } => iterations,
+            Kdf::Scrypt { p, .. } => p,
+        };
+        self.memory().saturating_mul(passes.into())
+    }
+
+    /// Returns whether the parameters are valid and within every cost cap
+    #[cfg(feature = "encryption")]
+    fn is_valid(&self) -> bool {
+        let valid = match *self {
+            Kdf::Argon2id {
+                memory_kib,
+                iterations,
+                parallelism,
+            } => {
+                (1..=MAX_PARALLELISM).contains(&parallelism)
+                    && (1..=MAX_ITERATIONS).contains(&iterations)
+                    && u64::from(memory_kib) >= 8 * u64::from(parallelism)
+            }
+            Kdf::Scrypt { log_n, r, p } => {
+                (1..=MAX_LOG_N).contains(&log_n)
+                    && (1..=MAX_R).contains(&r)
+                    && (1..=MAX_P).contains(&p)
+            }
+        };
+        valid && self.memory() <= MAX_MEMORY && self.work() <= MAX_WORK
+    }
+}
+
+/// Key derivation function, costs, and salt of a backup
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KdfParams {
+    /// Function and costs
+    pub kdf: Kdf,
+    /// Random salt, at least 16 bytes are recommended
+    pub salt: Vec<u8>,
+}
+
+impl KdfParams {
+    /// Derives a 32-byte entry key from a passphrase
+    ///
+    /// Fails with [`KdfError::InvalidParams`] if the parameters are invalid or exceed a cost cap,
+    /// or the salt is shorter than [`MIN_SALT_LEN`], before any memory is allocated.
+    #[cfg(feature = "encryption")]
+    pub fn derive_key(&self, passphrase: &[u8]) -> Result<[u8; 32], Error> {
+        if !self.kdf.is_valid() || self.salt.len() < MIN_SALT_LEN {
+            return Err(KdfError::InvalidParams.into());
+        }
+
+        let mut key = [0; 32];
+        let derived = match self.kdf {
+            Kdf::Argon2id {
+                memory_kib,
+                iterations,
+                parallelism,
+            } => argon2::Params::new(memory_kib, iterations, parallelism, Some(key.len()))
+                .and_then(|params| {
+                    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+                        .hash_password_into(passphrase, &self.salt, &mut key)
+                })
+                .is_ok(),
+            Kdf::Scrypt { log_n, r, p } => scrypt::Params::new(log_n, r, p, key.len())
+                .ok()
+                .and_then(|params| scrypt::scrypt(passphrase, &self.salt, &params, &mut key).ok())
+                .is_some(),
+        };
+        match derived {
+            true => Ok(key),
+            false => Err(KdfError::InvalidParams.into()),
+        }
+    }
+
+    /// Returns the metadata record holding the parameters
+    pub fn to_tlv(&self) -> Tlv {
+        let (id, costs) = match self.kdf {
+            Kdf::Argon2id {
+                memory_kib,
+                iterations,
+                parallelism,
+            } => (ARGON2ID, [memory_kib, iterations, parallelism]),
+            Kdf::Scrypt { log_n, r, p } => (SCRYPT, [log_n.into(), r, p]),
+        };
+        let mut value = varint::encode(id as u128);
+        for cost in costs {
+            value.extend(varint::encode(cost as u128));
+        }
+        value.extend_from_slice(&self.salt);
+        Tlv {
+            kind: KDF_PARAMS,
+            value,
+        }
+    }
+
+    /// Parses a metadata record of type [`KDF_PARAMS`]
+    pub fn from_tlv(record: &Tlv) -> Result<Self, Error> {
+        let invalid = |_| KdfError::Malformed;
+        if record.kind != KDF_PARAMS {
+            return Err(KdfError::Malformed.into());
+        }
+
+        let value = &record.value;
+        let mut index = 0;
+        let mut read = || -> Result<u32, Error> {
+            let (n, size) = varint::decode_as::<u32>(&value[index..]).map_err(invalid)?;
+            index += size;
+            Ok(n)
+        };
+        let id = read()?;
+        let (a, b, c) = (read()?, read()?, read()?);
+        let kdf = match u64::from(id) {
+            ARGON2ID => Kdf::Argon2id {
+                memory_kib: a,
+                iterations: b,
+                parallelism: c,
+            },
+            SCRYPT => Kdf::Scrypt {
+                log_n: u8::try_from(a).map_err(|_| KdfError::InvalidParams)?,
+                r: b,
+                p: c,
+            },
+            _ => return Err(KdfError::UnknownFunction(id).into()),
+        };
+
+        Ok(Self {
+            kdf,
+            salt: value[index..].to_vec(),
+        })
+    }
+}
+
+#[cfg(all(test, feature = "encryption"))]
+mod tests {
+    use super::*;
+    use bitcoin::hex::DisplayHex;
+
+    #[test]
+    fn test_derive_key() {
+        // Known keys, so stored parameters keep deriving the same key
+        let vectors = [
+            (
+                Kdf::Argon2id {
+                    memory_kib: 64,
+                    iterations: 2,
+                    parallelism: 2,
+                },
+                "af9ecb59597da4d56e432c0ccd2d237a0053185a802c0de95e7ccf7c01fa15fe",
+            ),
+            (
+                Kdf::Scrypt {
+                    log_n: 6,
+                    r: 8,
+                    p: 1,
+                },
+                "55101ba34a88dbf347d62022390e258a757c935a02d31e2d48eedea7b564f0d8",
+            ),
+        ];
+        for (kdf, key) in vectors {
+            let params = KdfParams {
+                kdf,
+                salt: b"descriptor-codec".to_vec(),
+            };
+            assert_eq!(
+                params
+                    .derive_key(b"correct horse")
+                    .unwrap()
+                    .to_lower_hex_string(),
+                key
+            );
+
+            // Both functions refuse short salts
+            let short_salt = KdfParams {
+                kdf,
+                salt: vec![7; MIN_SALT_LEN - 1],
+            };
+            assert_eq!(
+                short_salt.derive_key(b""),
+                Err(Error::Kdf(KdfError::InvalidParams))
+            );
+        }
+    }
+
+    #[test]
+    fn test_kdf_params() {
+        let params = [
+            KdfParams {
+                kdf: Kdf::Argon2id {
+                    memory_kib: 64,
+                    iterations: 2,
+                    parallelism: 2,
+                },
+                salt: vec![7; 16],
+            },
+            KdfParams {
+                kdf: Kdf::Scrypt {
+                    log_n: 6,
+                    r: 8,
+                    p: 1,
+                },
+                salt: vec![7; 16],
+            },
+        ];
+        let mut keys = Vec::new();
+        for params in &params {
+            let record = params.to_tlv();
+            assert_eq!(record.kind, KDF_PARAMS);
+            assert!(!record.is_required());
+            assert_eq!(&KdfParams::from_tlv(&record).unwrap(), params);
+
+            let key = params.derive_key(b"correct horse").unwrap();
+            assert_eq!(params.derive_key(b"correct horse").unwrap(), key);
+            assert_ne!(params.derive_key(b"wrong horse").unwrap(), key);
+            keys.push(key);
+        }
+        assert_ne!(keys[0], keys[1]);
+
+        // Higher costs give a different key
+        let mut costlier = params[1].clone();
+        costlier.kdf = Kdf::Scrypt {
+            log_n: 7,
+            r: 8,
+            p: 1,
+        };
+        assert_ne!(costlier.derive_key(b"correct horse").unwrap(), keys[1]);
+
+        let invalid = [
+            Kdf::Argon2id {
+                memory_kib: 8,
+                iterations: 1,
+                parallelism: 2,
+            },
+            Kdf::Argon2id {
+                memory_kib: u32::MAX,
+                iterations: 1,
+                parallelism: 1,
+            },
+            Kdf::Scrypt {
+                log_n: 0,
+                r: 8,
+                p: 1,
+            },
+            Kdf::Scrypt {
+                log_n: 40,
+                r: 8,
+                p: 1,
+            },
+            // Each cost one past its cap
+            Kdf::Argon2id {
+                memory_kib: 64,
+                iterations: MAX_ITERATIONS + 1,
+                parallelism: 1,
+            },
+            Kdf::Argon2id {
+                memory_kib: 1 << 10,
+                iterations: 1,
+                parallelism: MAX_PARALLELISM + 1,
+            },
+            Kdf::Argon2id {
+                memory_kib: (1 << 20) + 1,
+                iterations: 1,
+                parallelism: 1,
+            },
+            Kdf::Argon2id {
+                memory_kib: 1 << 20,
+                iterations: 9,
+                parallelism: 1,
+            },
+            Kdf::Scrypt {
+                log_n: MAX_LOG_N + 1,
+                r: 1,
+                p: 1,
+            },
+            Kdf::Scrypt {
+                log_n: 1,
+                r: MAX_R + 1,
+                p: 1,
+            },
+            Kdf::Scrypt {
+                log_n: 1,
+                r: 1,
+                p: MAX_P + 1,
+            },
+            Kdf::Scrypt {
+                log_n: MAX_LOG_N,
+                r: 8,
+                p: 9,
+            },
+        ];
+        for kdf in invalid {
+            let params = KdfParams {
+                kdf,
+                salt: vec![7; 16],
+            };
+            assert_eq!(
+                params.derive_key(b""),
+                Err(Error::Kdf(KdfError::InvalidParams))
+            );
+        }
+        let short_salt = KdfParams {
+            kdf: Kdf::default(),
+            salt: vec![7; 4],
+        };
+        assert_eq!(
+            short_salt.derive_key(b""),
+            Err(Error::Kdf(KdfError::InvalidParams))
+        );
+
+        // Headers that parse but exceed a cap are rejected before deriving
+        let hostile = [
+            [0, 8, u32::MAX, 1],
+            [0, 1 << 10, 1, u32::MAX],
+            [1, 1, 1, u32::MAX],
+            [1, 1, u32::MAX, 1],
+            [1, 63, 1, 1],
+        ];
+        for fields in hostile {
+            let mut value: Vec<u8> = fields
+                .iter()
+                .flat_map(|&n| varint::encode(n.into()))
+                .collect();
+            value.extend_from_slice(&[7; 16]);
+            let record = Tlv {
+                kind: KDF_PARAMS,
+                value,
+            };
+            let params = KdfParams::from_tlv(&record).unwrap();
+            assert_eq!(
+                params.derive_key(b""),
+                Err(Error::Kdf(KdfError::InvalidParams))
+            );
+        }
+
+        let large_log_n = [varint::encode(1), varint::encode(300), vec![1, 1]].concat();
+        let malformed = [
+            (vec![], KdfError::Malformed),
+            (vec![2, 1, 1, 1], KdfError::UnknownFunction(2)),
+            (vec![0, 1, 1], KdfError::Malformed),
+            (large_log_n, KdfError::InvalidParams),
+        ];
+        for (value, err) in malformed {
+            assert_eq!(
+                KdfParams::from_tlv(&Tlv {
+                    kind: KDF_PARAMS,
+                    value
+                }),
+                Err(Error::Kdf(err))
+            );
+        }
+    }
+}
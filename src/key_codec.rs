@@ -0,0 +1,150 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! # Key Codec
+
+use super::decoder::{Error, FromCompressablePayload, FromPayload};
+use bitcoin::{
+    PrivateKey, PublicKey, XOnlyPublicKey,
+    bip32::{Xpriv, Xpub},
+};
+use std::fmt;
+
+/// Serializes keys to and from the payload
+///
+/// Every method defaults to the standard serialization. Implementations may override any of
+/// them, for example to store a handle into an HSM in place of a key, while the template
+/// machinery encodes the rest of the descriptor. Decoding methods are only called with a
+/// non-empty payload.
+pub trait KeyCodec: fmt::Debug + Send + Sync {
+    /// Appends a compressed or uncompressed public key to the payload
+    fn encode_full_key(&self, key: &PublicKey, payload: &mut Vec<u8>) {
+        payload.extend(key.to_bytes());
+    }
+
+    /// Reads a compressed or uncompressed public key from the payload
+    fn decode_full_key(
+        &self,
+        compressed: bool,
+        payload: &[u8],
+        payload_index: &mut usize,
+    ) -> Result<PublicKey, Error> {
+        PublicKey::from_payload(compressed, payload, payload_index)
+    }
+
+    /// Appends an x-only public key to the payload
+    fn encode_x_only(&self, key: &XOnlyPublicKey, payload: &mut Vec<u8>) {
+        payload.extend(key.serialize());
+    }
+
+    /// Reads an x-only public key from the payload
+    fn decode_x_only(
+        &self,
+        payload: &[u8],
+        payload_index: &mut usize,
+    ) -> Result<XOnlyPublicKey, Error> {
+        XOnlyPublicKey::from_payload(payload, payload_index)
+    }
+
+    /// Appends an extended public key to the payload
+    fn encode_xpub(&self, xpub: &Xpub, payload: &mut Vec<u8>) {
+        payload.extend(xpub.encode());
+    }
+
+    /// Reads an extended public key from the payload
+    fn decode_xpub(&self, payload: &[u8], payload_index: &mut usize) -> Result<Xpub, Error> {
+        Xpub::from_payload(payload, payload_index)
+    }
+
+    /// Appends a private key to the payload
+    fn encode_secret_key(&self, key: &PrivateKey, payload: &mut Vec<u8>) {
+        payload.extend(key.to_bytes());
+    }
+
+    /// Reads a private key from the payload
+    fn decode_secret_key(
+        &self,
+        compressed: bool,
+        payload: &[u8],
+        payload_index: &mut usize,
+    ) -> Result<PrivateKey, Error> {
+        PrivateKey::from_payload(compressed, payload, payload_index)
+    }
+
+    /// Appends an extended private key to the payload
+    fn encode_xpriv(&self, xpriv: &Xpriv, payload: &mut Vec<u8>) {
+        payload.extend(xpriv.encode());
+    }
+
+    /// Reads an extended private key from the payload
+    fn decode_xpriv(&self, payload: &[u8], payload_index: &mut usize) -> Result<Xpriv, Error> {
+        Xpriv::from_payload(payload, payload_index)
+    }
+}
+
+/// The standard key serialization
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DefaultKeyCodec;
+
+impl KeyCodec for DefaultKeyCodec {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Codec, decode, decoder::PayloadError, encode};
+    use std::str::FromStr;
+    use std::sync::Arc;
+
+    /// Stores xpubs by their index in a table, as an HSM might by handle
+    #[derive(Debug)]
+    struct HandleKeyCodec(Vec<Xpub>);
+
+    impl KeyCodec for HandleKeyCodec {
+        fn encode_xpub(&self, xpub: &Xpub, payload: &mut Vec<u8>) {
+            let handle = self.0.iter().position(|x| x == xpub).unwrap();
+            payload.push(handle as u8);
+        }
+
+        fn decode_xpub(&self, payload: &[u8], payload_index: &mut usize) -> Result<Xpub, Error> {
+            let current_index = *payload_index;
+            let handle = *payload.get(current_index).ok_or(Error::MissingBytes)?;
+            *payload_index += 1;
+
+            self.0
+                .get(handle as usize)
+                .copied()
+                .ok_or(Error::InvalidPayload(
+                    current_index,
+                    PayloadError::UnknownKey,
+                ))
+        }
+    }
+
+    #[test]
+    fn test_key_codec() {
+        let xpubs = [
+            "xpub6EigxozzGaNVWUwEFnbyX6oHPdpWTKgJgbfpRbAcdiGpGMrdpPinCoHBXehu35sqJHpgLDTxigAnFQG3opKjXQoSmGMrMNHz81ALZSBRCWw",
+            "xpub6EAtA5XJ6pwFQ7L32iAJMgiWQEcrwU75NNWQ6H6eavwznDFeGFzTbSFdDKNdbG2HQdZvzrXuCyEYSSJ4cGsmfoPkKUKQ6haNKMRqG4pD4xi",
+        ];
+        let desc_str = format!(
+            "wsh(sortedmulti(1,[2c49202a/45'/0'/0'/0]{}/0/*,[55b43a50/45'/0'/0'/0]{}/0/*))#fm96q43m",
+            xpubs[0], xpubs[1]
+        );
+        let table = xpubs.iter().map(|s| Xpub::from_str(s).unwrap()).collect();
+        let codec = Codec::new().with_key_codec(Arc::new(HandleKeyCodec(table)));
+
+        let encoded = codec.encode(&desc_str).unwrap();
+        assert_eq!(encoded.len(), encode(&desc_str).unwrap().len() - 2 * 77);
+        assert_eq!(codec.decode(&encoded).unwrap(), desc_str);
+
+        let empty = Codec::new().with_key_codec(Arc::new(HandleKeyCodec(Vec::new())));
+        assert!(matches!(
+            empty.decode(&encoded),
+            Err(Error::InvalidPayload(_, PayloadError::UnknownKey))
+        ));
+        assert!(decode(&encoded).is_err());
+
+        // The default key codec keeps the standard serialization
+        let codec = Codec::new().with_key_codec(Arc::new(DefaultKeyCodec));
+        assert_eq!(codec.encode(&desc_str).unwrap(), encode(&desc_str).unwrap());
+    }
+}
@@ -51,72 +51,824 @@
 #[cfg(not(any(feature = "std")))]
 compile_error!("`std` must be enabled");
 
+pub mod accounts;
+pub mod anonymize;
+pub mod backup;
+pub mod bundle;
+pub mod cancel;
+#[cfg(feature = "chain")]
+pub mod chain;
+mod codec;
+pub mod compat;
+#[cfg(feature = "compiler")]
+pub mod compiler;
+pub mod decoded;
 pub mod decoder;
 mod dummy;
+pub mod encode;
+pub mod encoded;
 pub mod encoder;
+pub mod extension;
+pub mod features;
+pub mod flat_api;
+pub mod format;
+#[cfg(feature = "hashes-only")]
+pub mod hash_locks;
+pub mod inspect;
+pub mod kdf;
+pub mod key_codec;
+#[cfg(feature = "lowmem")]
+pub mod lowmem;
+pub mod metadata;
+pub mod original;
+pub mod prelude;
+pub mod preset;
+pub mod profile;
+pub mod qr;
+pub mod qr_code;
+#[cfg(feature = "qr-image")]
+mod qr_image;
+#[cfg(feature = "scanner")]
+pub mod scanner;
+pub mod scripts;
+pub mod secret;
+#[cfg(feature = "sheet")]
+pub mod sheet;
+pub mod stream;
+pub mod support;
 mod tag;
+pub mod template;
+#[cfg(test)]
 mod test_helpers;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod transcode;
+pub mod unsupported;
+#[cfg(feature = "internals")]
+pub mod varint;
+#[cfg(not(feature = "internals"))]
 mod varint;
+mod walk;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub mod watch_only;
 
-pub use decoder::Error;
+pub use accounts::{Purpose, account_descriptors, encode_accounts};
+pub use anonymize::{KeyMapping, anonymize, deanonymize};
+#[cfg(feature = "encryption")]
+pub use backup::SoftwareCipher;
+pub use backup::{
+    Backup, BackupCipher, BackupEntry, BackupError, BackupFileError, append_descriptor,
+    decode_backup, encode_backup, read_backup_file, remove_descriptor, write_backup_file,
+    write_file_atomic,
+};
+pub use bundle::{
+    BundleProgress, KeyAnnotation, ManifestEntry, bundle_annotations, bundle_entry,
+    bundle_manifest, decode_bundle, decode_bundle_cancellable, decode_bundle_with_progress,
+    decode_entry, encode_annotated_bundle, encode_bundle, encode_manifest_bundle, split_bundle,
+};
+#[cfg(feature = "parallel")]
+pub use bundle::{decode_bundle_parallel, decode_bundle_parallel_cancellable};
+pub use cancel::CancelToken;
+#[cfg(feature = "chain")]
+pub use chain::{
+    ChainBackend, ChainError, ElectrumBackend, FundsCheck, encode_with_birthday, estimate_birthday,
+    verify_funds,
+};
+use codec::CodecOptions;
+pub use codec::{Codec, PathNumbers};
+pub use compat::{CompatError, Difference, verify_compat};
+#[cfg(feature = "compiler")]
+pub use compiler::{Compilation, CompileContext, compare_policy, compile_policy, encode_policy};
+pub use decoded::DecodedDescriptor;
+pub use decoder::{Error, PayloadError, StreamStatus, StreamingDecoder};
+pub use encoded::EncodedDescriptor;
+pub use encoder::{
+    Error as EncodeError, Stats, WriteError, encode_parsed_into_slice, encode_to_writer,
+};
+pub use extension::{CUSTOM_TAGS, TagExtension};
+pub use features::{
+    FORMAT_VERSION, FeatureSet, FormatReport, MAX_SUPPORTED_VERSION, SUPPORTED_FEATURES,
+    can_decode, required_features,
+};
+pub use format::{
+    Correction, Edit, HexStyle, base32, decode_base58, encode_base58, grouped_hex, parse_base32,
+    parse_grouped_hex, repair_base58,
+};
+pub use inspect::{Inspection, inspect};
+pub use kdf::{Kdf, KdfError, KdfParams};
+pub use key_codec::{DefaultKeyCodec, KeyCodec};
+#[cfg(feature = "lowmem")]
+pub use lowmem::{FixedKeyMap, decode_fixed};
+pub use metadata::{
+    KeyRotation, Tlv, birth_height, birth_height_record, decode_with_metadata, encode_with_metadata,
+};
+pub use original::{DualString, decode_dual};
+pub use preset::PresetRegistry;
+pub use profile::{FitError, TransportProfile, assert_fits, max_encoded_size_for};
+pub use qr::{QrError, QrPlan, animated_frames, qr_capacity_report};
+pub use qr_code::QrCode;
+#[cfg(feature = "scanner")]
+pub use scanner::{Progress, Scanner, ur_parts};
+pub use scripts::{derive_scripts, electrum_script_hash, electrum_script_hashes};
+pub use secret::RevealSecrets;
+#[cfg(feature = "sheet")]
+pub use sheet::{RecoverySheet, recovery_sheet};
+pub use stream::{Transport, decode_stream, encode_stream};
+pub use support::{FragmentEncoding, FragmentInfo, FragmentKind, supported_fragments};
+pub use template::{encode_template_str, fill_template};
+pub use transcode::{TranscodeOptions, transcode};
+pub use unsupported::{Unsupported, UnsupportedError, check_encodable, encode_with_reproduction};
+#[cfg(feature = "wasm")]
+pub use wasm::ScannerSession;
+pub use watch_only::derive_watch_only;
 
 use bitcoin::{
-    hashes::{hash160, ripemd160, sha256},
+    bip32,
+    hashes::{Hash, HashEngine, hash160, ripemd160, sha256},
     secp256k1,
 };
+use encoder::{EncodeContext, EncodeTemplate};
 use miniscript::{
-    Descriptor, TranslatePk, Translator,
-    descriptor::{DescriptorPublicKey, DescriptorSecretKey, KeyMap},
+    Descriptor, ForEachKey, MiniscriptKey, TranslateErr, TranslatePk, Translator,
+    descriptor::{DescriptorPublicKey, DescriptorSecretKey, KeyMap, SinglePubKey},
     hash256,
 };
 use std::collections::BTreeMap;
+use std::fmt::Write;
 use std::str::FromStr;
 
 /// Parses and encodes a Bitcoin descriptor
-pub fn encode(s: &str) -> Result<Vec<u8>, miniscript::Error> {
-    let secp = secp256k1::Secp256k1::new();
-    let (descriptor, key_map) = parse_descriptor(&secp, s)?;
-    let (mut template, mut payload) = encoder::encode(descriptor, &key_map);
-    template.append(&mut payload);
-    Ok(template)
+pub fn encode(s: &str) -> Result<Vec<u8>, encoder::Error> {
+    let mut encoded = Vec::new();
+    encode_into(s, &mut encoded)?;
+    Ok(encoded)
+}
+
+/// Parses and encodes a Bitcoin descriptor into `out`
+///
+/// `out` is cleared first and its capacity reused, so a single buffer can serve many calls.
+pub fn encode_into(s: &str, out: &mut Vec<u8>) -> Result<(), encoder::Error> {
+    encode_with(s, out, &[], &CodecOptions::default())?;
+    Ok(())
+}
+
+/// Parses and encodes a Bitcoin descriptor, returning the sizes of its template and payload
+pub fn encode_with_stats(s: &str) -> Result<(Vec<u8>, Stats), encoder::Error> {
+    let mut encoded = Vec::new();
+    let stats = encode_with(s, &mut encoded, &[], &CodecOptions::default())?;
+    Ok((encoded, stats))
+}
+
+/// Parses and encodes a Bitcoin descriptor as a separate template and payload
+///
+/// The template holds the structure of the descriptor and its derivation paths, like a BIP388
+/// wallet policy, and the payload holds its keys, fingerprints, hashes, and timelocks. The two
+/// concatenated are the bytes returned by [`encode`], and [`decode_parts`] decodes them apart.
+pub fn encode_parts(s: &str) -> Result<(Vec<u8>, Vec<u8>), encoder::Error> {
+    let (mut template, stats) = encode_with_stats(s)?;
+    let payload = template.split_off(stats.template_len);
+    Ok((template, payload))
+}
+
+/// Encodes a descriptor and key map, as returned by `Descriptor::parse_descriptor`
+///
+/// Gives the same bytes as [`encode`] on the descriptor's string form, without re-parsing it,
+/// for wallets that already hold a parsed descriptor. Fails only with
+/// [`PathTooLong`](encoder::Error::PathTooLong), if a path has more steps than a key can record.
+pub fn encode_parsed(
+    descriptor: &Descriptor<DescriptorPublicKey>,
+    key_map: &KeyMap,
+) -> Result<Vec<u8>, encoder::Error> {
+    let mut encoded = Vec::new();
+    encode_parsed_with(
+        descriptor,
+        key_map,
+        &mut encoded,
+        &[],
+        &CodecOptions::default(),
+        false,
+    )?;
+    Ok(encoded)
+}
+
+/// Parses and encodes a Bitcoin descriptor into `out` with the given options
+fn encode_with(
+    s: &str,
+    out: &mut Vec<u8>,
+    records: &[Tlv],
+    options: &CodecOptions,
+) -> Result<Stats, encoder::Error> {
+    let original = s;
+    let cleaned;
+    let s = match options.lenient_parse {
+        true => {
+            cleaned = clean_descriptor(s);
+            cleaned.as_str()
+        }
+        false => s,
+    };
+    let preserve = match noncanonical_path_number(s) {
+        Some(number) => match options.path_numbers {
+            PathNumbers::Canonicalize => false,
+            PathNumbers::Preserve => true,
+            PathNumbers::Reject => {
+                return Err(encoder::Error::NonCanonicalNumber(number.to_string()));
+            }
+        },
+        None => false,
+    };
+    let raw_tr = raw_tr_as_tr(s)?;
+    let (descriptor, key_map) = parse_descriptor(raw_tr.as_deref().unwrap_or(s))?;
+    let stats = encode_parsed_with(
+        &descriptor,
+        &key_map,
+        out,
+        records,
+        options,
+        raw_tr.is_some(),
+    )?;
+    if !options.original_string && !preserve {
+        return Ok(stats);
+    }
+
+    // The canonical string is whatever decoding gives, so it is decoded rather than predicted
+    let mut canonical = String::new();
+    if decode_with(out, &mut canonical, options).is_err() {
+        return Ok(stats);
+    }
+    let Some(edits) = original::edits(&canonical, original) else {
+        return Ok(stats);
+    };
+    let mut records = records.to_vec();
+    records.push(Tlv {
+        kind: metadata::ORIGINAL_STRING,
+        value: edits,
+    });
+    encode_parsed_with(
+        &descriptor,
+        &key_map,
+        out,
+        &records,
+        options,
+        raw_tr.is_some(),
+    )
+}
+
+/// Returns the `tr()` equivalent of a `rawtr()` descriptor, or `None` for any other descriptor
+///
+/// A `rawtr()` is encoded as a `tr()` without a tree under its own tag, as miniscript cannot
+/// parse it. Its checksum, if any, is checked here, as it differs from that of the `tr()`.
+fn raw_tr_as_tr(s: &str) -> Result<Option<String>, encoder::Error> {
+    let (body, actual) = match s.split_once('#') {
+        Some((body, actual)) => (body, Some(actual)),
+        None => (s, None),
+    };
+    let Some(key) = body
+        .strip_prefix("rawtr(")
+        .and_then(|rest| rest.strip_suffix(')'))
+    else {
+        return Ok(None);
+    };
+    if key.contains([',', '(', ')']) {
+        return Err(encoder::Error::InvalidDescriptor(
+            miniscript::Error::Unexpected("rawtr() takes a single key".to_string()),
+        ));
+    }
+    if let Some(actual) = actual {
+        let expected = checksum(body)?;
+        if actual != expected {
+            return Err(encoder::Error::InvalidChecksum(expected));
+        }
+    }
+    Ok(Some(format!("tr({key})")))
+}
+
+/// Returns the string form of a `rawtr()` decoded as a `tr()`, with secret keys from `key_map`
+pub(crate) fn raw_tr_string(
+    descriptor: &Descriptor<DescriptorPublicKey>,
+    key_map: &KeyMap,
+) -> String {
+    let Descriptor::Tr(tr) = descriptor else {
+        return descriptor.to_string_with_secret(key_map);
+    };
+    let key = tr.internal_key();
+    let body = match key_map.get(key) {
+        Some(secret_key) => format!("rawtr({secret_key})"),
+        None => format!("rawtr({key})"),
+    };
+    match checksum(&body) {
+        Ok(checksum) => format!("{body}#{checksum}"),
+        Err(_) => body,
+    }
+}
+
+/// Encodes a parsed Bitcoin descriptor into `out` with the given options
+///
+/// The descriptor is measured first, so that `out` is allocated at most once.
+fn encode_parsed_with(
+    descriptor: &Descriptor<DescriptorPublicKey>,
+    key_map: &KeyMap,
+    out: &mut Vec<u8>,
+    records: &[Tlv],
+    options: &CodecOptions,
+    raw_tr: bool,
+) -> Result<Stats, encoder::Error> {
+    check_path_lens(descriptor, key_map, options.max_path_len)?;
+
+    let mut ctx = options.encode_context(key_map);
+    if raw_tr {
+        ctx.tag_extension = None;
+    }
+
+    let mut records = records.to_vec();
+    let sorted;
+    let mut descriptor = descriptor;
+    if options.sort_multi {
+        if let Some(multi) = sort_multi(descriptor, key_map)? {
+            sorted = multi;
+            descriptor = &sorted;
+            records.push(Tlv {
+                kind: metadata::SORTED_MULTI,
+                value: Vec::new(),
+            });
+        }
+    }
+
+    let measured = encoder::measure(descriptor, &ctx);
+    let records_len = match records.is_empty() {
+        true => 0,
+        false => metadata::records_len(&records),
+    };
+    let header_len = usize::from(!records.is_empty())
+        + usize::from(options.commit_payload) * (1 + encoder::COMMITMENT_SIZE);
+
+    out.clear();
+    out.reserve(header_len + measured.len() + records_len);
+    let mut payload = Vec::with_capacity(measured.payload_len + records_len);
+    descriptor.encode_template(out, &mut payload, &ctx);
+    let mut tags = measured.tags;
+    if raw_tr {
+        out[0] = tag::Tag::RawTr.value();
+        tags.remove(tag::Tag::Tr.name());
+        tags.insert(tag::Tag::RawTr.name(), 1);
+    }
+    options.presets.compress(out);
+    if !records.is_empty() {
+        out.insert(0, tag::Tag::Metadata.value());
+        metadata::encode_records(&records, &mut payload);
+    }
+    if options.commit_payload {
+        encoder::commit_payload(out, &payload);
+    }
+
+    let stats = Stats {
+        template_len: out.len(),
+        payload_len: payload.len(),
+        tags,
+    };
+    out.append(&mut payload);
+
+    if options.strict_keys {
+        check_keys(descriptor, out, options)?;
+    }
+
+    Ok(stats)
+}
+
+/// Checks that no derivation path, including key origins, has more than `max_len` steps
+fn check_path_lens(
+    descriptor: &Descriptor<DescriptorPublicKey>,
+    key_map: &KeyMap,
+    max_len: usize,
+) -> Result<(), encoder::Error> {
+    fn longest<'a>(
+        origin: &'a Option<(bip32::Fingerprint, bip32::DerivationPath)>,
+        paths: impl IntoIterator<Item = &'a bip32::DerivationPath>,
+    ) -> usize {
+        let origin = origin.iter().map(|(_, path)| path);
+        origin
+            .chain(paths)
+            .map(|path| path.len())
+            .max()
+            .unwrap_or(0)
+    }
+
+    let mut idx = 0;
+    let valid = descriptor.for_each_key(|key| {
+        let len = match (key_map.get(key), key) {
+            (Some(DescriptorSecretKey::Single(single)), _) => longest(&single.origin, []),
+            (Some(DescriptorSecretKey::XPrv(xprv)), _) => {
+                longest(&xprv.origin, [&xprv.derivation_path])
+            }
+            (Some(DescriptorSecretKey::MultiXPrv(xprv)), _) => {
+                longest(&xprv.origin, xprv.derivation_paths.paths())
+            }
+            (None, DescriptorPublicKey::Single(single)) => longest(&single.origin, []),
+            (None, DescriptorPublicKey::XPub(xpub)) => {
+                longest(&xpub.origin, [&xpub.derivation_path])
+            }
+            (None, DescriptorPublicKey::MultiXPub(xpub)) => {
+                longest(&xpub.origin, xpub.derivation_paths.paths())
+            }
+        };
+        idx += 1;
+        len <= max_len
+    });
+
+    match valid {
+        true => Ok(()),
+        false => Err(encoder::Error::PathTooLong(idx - 1)),
+    }
+}
+
+/// Decodes `bytes` and checks that every key matches the corresponding key in `descriptor`
+///
+/// A failure to decode is reported as a mismatch of the first key, as no key can be recovered.
+fn check_keys(
+    descriptor: &Descriptor<DescriptorPublicKey>,
+    bytes: &[u8],
+    options: &CodecOptions,
+) -> Result<(), encoder::Error> {
+    let decoded = options
+        .presets
+        .expand(bytes)
+        .and_then(|bytes| decoder::decode_bytes_in(&bytes, &mut options.decode_context()))
+        .map_err(|_| encoder::Error::KeyMismatch(0))?;
+
+    let mut keys = Vec::new();
+    descriptor.for_each_key(|key| {
+        keys.push(key);
+        true
+    });
+    let mut idx = 0;
+    let matched = decoded.for_each_key(|key| {
+        let matched = keys.get(idx) == Some(&key);
+        idx += usize::from(matched);
+        matched
+    });
+    match matched && idx == keys.len() {
+        true => Ok(()),
+        false => Err(encoder::Error::KeyMismatch(idx)),
+    }
+}
+
+/// Returns the first number in a key path written with leading zeros or a sign, such as `007`
+///
+/// Path steps and multipath alternatives are the only places a number follows `/`, `<`, or `;`.
+fn noncanonical_path_number(s: &str) -> Option<&str> {
+    let body = s.split('#').next().unwrap_or(s);
+    body.match_indices(['/', '<', ';']).find_map(|(i, _)| {
+        let rest = &body[i + 1..];
+        let len = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '+')
+            .unwrap_or(rest.len());
+        let number = &rest[..len];
+        let noncanonical = number.starts_with('+') || (number.len() > 1 && number.starts_with('0'));
+        noncanonical.then_some(number)
+    })
+}
+
+/// Removes whitespace and invisible characters, such as zero-width spaces and soft hyphens
+fn clean_descriptor(s: &str) -> String {
+    const INVISIBLE: [char; 6] = [
+        '\u{00AD}', '\u{200B}', '\u{200C}', '\u{200D}', '\u{2060}', '\u{FEFF}',
+    ];
+    s.chars()
+        .filter(|c| !c.is_whitespace() && !INVISIBLE.contains(c))
+        .collect()
+}
+
+/// Returns the descriptor with a top-level `multi()` replaced by `sortedmulti()`, if it has one
+///
+/// Keys are sorted by their string form, which for single public keys is the order used by
+/// `sortedmulti()`, so that any key order gives the same descriptor.
+fn sort_multi(
+    descriptor: &Descriptor<DescriptorPublicKey>,
+    key_map: &KeyMap,
+) -> Result<Option<Descriptor<DescriptorPublicKey>>, encoder::Error> {
+    use miniscript::descriptor::{Sh, ShInner, Wsh, WshInner};
+    use miniscript::{Miniscript, ScriptContext, Terminal};
+
+    fn multi<Ctx: ScriptContext>(
+        ms: &Miniscript<DescriptorPublicKey, Ctx>,
+        key_map: &KeyMap,
+    ) -> Option<(usize, Vec<DescriptorPublicKey>)> {
+        let Terminal::Multi(thresh) = &ms.node else {
+            return None;
+        };
+        let mut pks = thresh.data().to_vec();
+        pks.sort_by_cached_key(|pk| match key_map.get(pk) {
+            Some(sk) => sk.to_string(),
+            None => pk.to_string(),
+        });
+        Some((thresh.k(), pks))
+    }
+
+    let sorted =
+        match descriptor {
+            Descriptor::Wsh(wsh) => match wsh.as_inner() {
+                WshInner::Ms(ms) => multi(ms, key_map)
+                    .map(|(k, pks)| Wsh::new_sortedmulti(k, pks).map(Descriptor::Wsh)),
+                WshInner::SortedMulti(_) => None,
+            },
+            Descriptor::Sh(sh) => match sh.as_inner() {
+                ShInner::Ms(ms) => multi(ms, key_map)
+                    .map(|(k, pks)| Sh::new_sortedmulti(k, pks).map(Descriptor::Sh)),
+                ShInner::Wsh(wsh) => match wsh.as_inner() {
+                    WshInner::Ms(ms) => multi(ms, key_map)
+                        .map(|(k, pks)| Sh::new_wsh_sortedmulti(k, pks).map(Descriptor::Sh)),
+                    WshInner::SortedMulti(_) => None,
+                },
+                _ => None,
+            },
+            _ => None,
+        };
+
+    Ok(sorted.transpose()?)
 }
 
 /// Decodes a Bitcoin descriptor
 pub fn decode(bytes: &[u8]) -> Result<String, Error> {
-    let (_, _, size) = decoder::decode_template(bytes)?;
-    let (descriptor, key_map) = decoder::decode_with_payload(&bytes[..size], &bytes[size..])?;
+    let mut decoded = String::new();
+    decode_into(bytes, &mut decoded)?;
+    Ok(decoded)
+}
+
+/// Decodes a Bitcoin descriptor from a template and payload stored separately
+///
+/// Returns the same string as [`decode`] on the two concatenated, as split by [`encode_parts`].
+/// Fails with [`Error::InvalidTag`], at the first extra byte, if `template` holds more than a
+/// template. Templates encoded with a payload commitment can be checked against the payload with
+/// [`verify_payload`] first.
+pub fn decode_parts(template: &[u8], payload: &[u8]) -> Result<String, Error> {
+    let size = decoder::template_size(template, &decoder::DecodeContext::default())?;
+    if size < template.len() {
+        return Err(Error::InvalidTag(size));
+    }
+    decode(&[template, payload].concat())
+}
+
+/// Decodes a Bitcoin descriptor to a parsed descriptor and the key map of its secret keys
+///
+/// Saves re-parsing the string returned by [`decode`]. Creates a secp256k1 context on each call
+/// to derive public keys from secret keys, so services decoding many descriptors should share a
+/// [`Codec`] instead. Fails with [`Error::RawTr`] for a `rawtr()` descriptor.
+pub fn decode_to_descriptor(
+    bytes: &[u8],
+) -> Result<(Descriptor<DescriptorPublicKey>, KeyMap), Error> {
+    Codec::new().decode_to_descriptor(bytes)
+}
+
+/// Decodes a Bitcoin descriptor into `out`
+///
+/// `out` is cleared first and its capacity reused, so a single buffer can serve many calls.
+///
+/// Fails with [`Error::Oversized`] if the decoded string would exceed
+/// [`decoder::DEFAULT_ALLOCATION_FACTOR`] times the length of `bytes`.
+pub fn decode_into(bytes: &[u8], out: &mut String) -> Result<(), Error> {
+    decode_with(bytes, out, &CodecOptions::default())?;
+    Ok(())
+}
+
+/// Decodes a Bitcoin descriptor into `out` with the given options
+///
+/// Returns the metadata records not understood by this decoder, and the edits to the original
+/// string, if recorded.
+fn decode_with(
+    bytes: &[u8],
+    out: &mut String,
+    options: &CodecOptions,
+) -> Result<(Vec<Tlv>, Option<Vec<u8>>), Error> {
+    let bytes = options.presets.expand(bytes)?;
+
+    // The string form of a secret key does not depend on its public key, so skip derivation
+    let mut ctx = options.decode_context();
+    let descriptor = decoder::decode_bytes_in(&bytes, &mut ctx)?;
+    let key_map = ctx.key_map;
+
+    // The decoded descriptor is bounded by the input length, as every node consumes at least one
+    // byte, so only its string form needs a budget.
+    let limit = options.allocation_factor.saturating_mul(bytes.len());
+    out.clear();
+    if ctx.raw_tr {
+        let raw_tr = raw_tr_string(&descriptor, &key_map);
+        if raw_tr.len() > limit {
+            return Err(Error::Oversized);
+        }
+        out.push_str(&raw_tr);
+        return Ok((ctx.metadata, ctx.original_edits));
+    }
+    if key_map.is_empty() {
+        let mut limited = LimitedWriter { out, limit };
+        write!(limited, "{descriptor}").map_err(|_| Error::Oversized)?;
+        return Ok((ctx.metadata, ctx.original_edits));
+    }
+
+    // The secret form is the public form with each key replaced by its secret key, so its length
+    // is measured without allocating, and checked before it is built.
+    let mut counter = LenCounter(0);
+    write!(counter, "{descriptor}").map_err(|_| Error::Oversized)?;
+    let (mut longer, mut shorter) = (0usize, 0usize);
+    descriptor.for_each_key(|pk| {
+        if let Some(sk) = key_map.get(pk) {
+            let (secret, public) = (sk.to_string().len(), pk.to_string().len());
+            longer = longer.saturating_add(secret.saturating_sub(public));
+            shorter = shorter.saturating_add(public.saturating_sub(secret));
+        }
+        true
+    });
+    if counter.0.saturating_add(longer).saturating_sub(shorter) > limit {
+        return Err(Error::Oversized);
+    }
+    out.push_str(&descriptor.to_string_with_secret(&key_map));
+
+    Ok((ctx.metadata, ctx.original_edits))
+}
+
+/// Returns the canonical string form of a descriptor, as produced by [`decode`]
+///
+/// Hex is lowercased, hardened markers follow miniscript's canonical form, and the checksum is
+/// recomputed, so descriptors from different tools can be compared directly.
+pub fn normalize(s: &str) -> Result<String, encoder::Error> {
+    let (descriptor, key_map) = parse_descriptor(s)?;
     Ok(descriptor.to_string_with_secret(&key_map))
 }
 
+/// Returns the checksum of a descriptor, ignoring any checksum it already has
+///
+/// Appending `#` and the result to the descriptor repairs a damaged checksum.
+pub fn checksum(s: &str) -> Result<String, encoder::Error> {
+    let body = s.split_once('#').map_or(s, |(body, _)| body);
+    miniscript::descriptor::checksum::desc_checksum(body).map_err(encoder::Error::InvalidDescriptor)
+}
+
+/// Checks that a descriptor ends with its correct checksum
+///
+/// Fails with [`encoder::Error::InvalidChecksum`] holding the expected checksum if it is missing
+/// or wrong. The rest of the descriptor is not validated.
+pub fn verify_checksum(s: &str) -> Result<(), encoder::Error> {
+    let expected = checksum(s)?;
+    match s.split_once('#') {
+        Some((_, actual)) if actual == expected => Ok(()),
+        _ => Err(encoder::Error::InvalidChecksum(expected)),
+    }
+}
+
+/// Returns the SHA256 hash of the policy of an encoded descriptor
+///
+/// The policy is the template, which holds the structure of the descriptor and its derivation
+/// paths, together with its timelocks, hash locks, and thresholds. Keys and fingerprints are
+/// left out, except for which keys are reused, so the same policy over other keys has the same
+/// hash.
+pub fn template_hash(bytes: &[u8]) -> Result<sha256::Hash, Error> {
+    let size = decoder::template_size(bytes, &decoder::DecodeContext::default())?;
+    let policy = match decode_to_descriptor(bytes) {
+        Ok((descriptor, _)) => policy_string(&descriptor),
+        // A `rawtr()` holds nothing but its key
+        Err(Error::RawTr) => String::new(),
+        Err(err) => return Err(err),
+    };
+
+    let mut engine = sha256::Hash::engine();
+    engine.input(&bytes[..size]);
+    engine.input(policy.as_bytes());
+    Ok(sha256::Hash::from_engine(engine))
+}
+
+/// Returns the key of a descriptor key without its origin or derivation path, which is the same
+/// for every use of an extended key
+pub(crate) fn key_material(pk: &DescriptorPublicKey) -> String {
+    match pk {
+        DescriptorPublicKey::Single(single) => match single.key {
+            SinglePubKey::FullKey(key) => key.to_string(),
+            SinglePubKey::XOnly(key) => key.to_string(),
+        },
+        DescriptorPublicKey::XPub(xpub) => xpub.xkey.to_string(),
+        DescriptorPublicKey::MultiXPub(xpub) => xpub.xkey.to_string(),
+    }
+}
+
+/// Returns a descriptor with each distinct key replaced by its index, such as `@0`, so reuse of
+/// a key along other paths is kept
+fn policy_string(descriptor: &Descriptor<DescriptorPublicKey>) -> String {
+    struct Blanker(Vec<String>);
+
+    impl Translator<DescriptorPublicKey, String, ()> for Blanker {
+        fn pk(&mut self, pk: &DescriptorPublicKey) -> Result<String, ()> {
+            let material = key_material(pk);
+            let index = match self.0.iter().position(|key| *key == material) {
+                Some(index) => index,
+                None => {
+                    self.0.push(material);
+                    self.0.len() - 1
+                }
+            };
+            Ok(format!("@{index}"))
+        }
+
+        fn sha256(&mut self, hash: &sha256::Hash) -> Result<String, ()> {
+            Ok(hash.to_string())
+        }
+
+        fn hash256(&mut self, hash: &hash256::Hash) -> Result<String, ()> {
+            Ok(hash.to_string())
+        }
+
+        fn ripemd160(&mut self, hash: &ripemd160::Hash) -> Result<String, ()> {
+            Ok(hash.to_string())
+        }
+
+        fn hash160(&mut self, hash: &hash160::Hash) -> Result<String, ()> {
+            Ok(hash.to_string())
+        }
+    }
+
+    descriptor
+        .translate_pk(&mut Blanker(Vec::new()))
+        .expect("translating to strings cannot fail")
+        .to_string()
+}
+
+/// Decodes a Bitcoin descriptor, failing unless its template hash matches `expected`
+///
+/// Protects restore flows from substituted backups that keep the user's keys but change the
+/// policy.
+pub fn decode_expecting(bytes: &[u8], expected: &sha256::Hash) -> Result<String, Error> {
+    if template_hash(bytes)? != *expected {
+        return Err(Error::TemplateMismatch);
+    }
+
+    decode(bytes)
+}
+
+/// Checks that a template and payload stored separately belong together
+///
+/// Fails with [`Error::MissingCommitment`] if the template was encoded without a payload
+/// commitment, and with [`Error::PayloadMismatch`] if the payload does not match it.
+pub fn verify_payload(template: &[u8], payload: &[u8]) -> Result<(), Error> {
+    if template.first() != Some(&tag::Tag::PayloadCommitment.value()) {
+        return Err(Error::MissingCommitment);
+    }
+    let commitment = template
+        .get(1..1 + encoder::COMMITMENT_SIZE)
+        .ok_or(Error::MissingBytes)?;
+    if commitment != encoder::payload_commitment(payload) {
+        return Err(Error::PayloadMismatch);
+    }
+
+    Ok(())
+}
+
+/// Writes to a string, failing rather than growing it beyond a limit
+struct LimitedWriter<'a> {
+    out: &'a mut String,
+    limit: usize,
+}
+
+impl Write for LimitedWriter<'_> {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        if self.out.len() + s.len() > self.limit {
+            return Err(std::fmt::Error);
+        }
+        self.out.push_str(s);
+        Ok(())
+    }
+}
+
+/// Counts the bytes written to it, without storing them
+struct LenCounter(usize);
+
+impl Write for LenCounter {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        self.0 += s.len();
+        Ok(())
+    }
+}
+
 /// Parse a descriptor that may contain secret keys
 ///
-/// Internally turns every secret key found into the corresponding public key and then returns a
-/// a descriptor that only contains public keys and a map to lookup the secret key given a public key.
+/// Internally turns every secret key found into a placeholder public key and then returns a
+/// descriptor that only contains public keys and a map to lookup the secret key given a public key.
 ///
-/// Re-implements `parse_descriptor` from `miniscript/descriptor` to handle MultiXPrivs by replacing
-/// each MultiXPriv with an indexed dummy SinglePub and adding the MultiXpriv to the key map.
-fn parse_descriptor<C: secp256k1::Signing>(
-    secp: &secp256k1::Secp256k1<C>,
-    s: &str,
-) -> Result<(Descriptor<DescriptorPublicKey>, KeyMap), miniscript::Error> {
-    fn parse_key<C: secp256k1::Signing>(
-        s: &str,
-        key_map: &mut KeyMap,
-        secp: &secp256k1::Secp256k1<C>,
-    ) -> Result<DescriptorPublicKey, miniscript::Error> {
+/// Deriving the actual public key of an xpriv is expensive and the encoding only needs the secret
+/// key, so derivation is deferred until a caller needs the public form. Placeholders preserve
+/// compression so that script context checks behave as they would for the derived key.
+///
+/// Re-implements `parse_descriptor` from `miniscript/descriptor`, which derives every public key
+/// and cannot handle MultiXPrivs.
+fn parse_descriptor(s: &str) -> Result<(Descriptor<DescriptorPublicKey>, KeyMap), encoder::Error> {
+    fn parse_key(s: &str, key_map: &mut KeyMap) -> Result<DescriptorPublicKey, encoder::Error> {
         let (public_key, secret_key) = match DescriptorSecretKey::from_str(s) {
-            Ok(sk) => (
-                sk.to_public(secp)
-                    .unwrap_or(test_helpers::create_dpk_single_compressed_no_origin(
-                        1 + key_map.len() as u32,
-                    )),
-                Some(sk),
-            ),
-            Err(_) => (
-                DescriptorPublicKey::from_str(s)
-                    .map_err(|e| miniscript::Error::Unexpected(e.to_string()))?,
-                None,
-            ),
+            Ok(sk) => {
+                let compressed = match &sk {
+                    DescriptorSecretKey::Single(single) => single.key.compressed,
+                    DescriptorSecretKey::XPrv(_) | DescriptorSecretKey::MultiXPrv(_) => true,
+                };
+                let pk = dummy::placeholder_dpk(key_map.len() as u32, compressed);
+                (pk, Some(sk))
+            }
+            Err(_) => (DescriptorPublicKey::from_str(s)?, None),
         };
 
         if let Some(secret_key) = secret_key {
@@ -126,54 +878,584 @@ fn parse_descriptor<C: secp256k1::Signing>(
         Ok(public_key)
     }
 
-    let mut keymap_pk = KeyMapWrapper(BTreeMap::new(), secp);
-
-    struct KeyMapWrapper<'a, C: secp256k1::Signing>(KeyMap, &'a secp256k1::Secp256k1<C>);
+    /// Counts keys so an uncompressed key in a segwit context can be reported by index
+    struct KeyMapWrapper {
+        key_map: KeyMap,
+        index: usize,
+        segwit: bool,
+    }
 
-    impl<C: secp256k1::Signing> Translator<String, DescriptorPublicKey, miniscript::Error>
-        for KeyMapWrapper<'_, C>
-    {
-        fn pk(&mut self, pk: &String) -> Result<DescriptorPublicKey, miniscript::Error> {
-            parse_key(pk, &mut self.0, self.1)
+    impl Translator<String, DescriptorPublicKey, encoder::Error> for KeyMapWrapper {
+        fn pk(&mut self, pk: &String) -> Result<DescriptorPublicKey, encoder::Error> {
+            let key = parse_key(pk, &mut self.key_map)?;
+            if self.segwit && key.is_uncompressed() {
+                return Err(encoder::Error::UncompressedKey(self.index));
+            }
+            self.index += 1;
+            Ok(key)
         }
 
-        fn sha256(&mut self, sha256: &String) -> Result<sha256::Hash, miniscript::Error> {
-            let hash = sha256::Hash::from_str(sha256)
-                .map_err(|e| miniscript::Error::Unexpected(e.to_string()))?;
-            Ok(hash)
+        fn sha256(&mut self, sha256: &String) -> Result<sha256::Hash, encoder::Error> {
+            Ok(sha256::Hash::from_str(sha256)?)
         }
 
-        fn hash256(&mut self, hash256: &String) -> Result<hash256::Hash, miniscript::Error> {
-            let hash = hash256::Hash::from_str(hash256)
-                .map_err(|e| miniscript::Error::Unexpected(e.to_string()))?;
-            Ok(hash)
+        fn hash256(&mut self, hash256: &String) -> Result<hash256::Hash, encoder::Error> {
+            Ok(hash256::Hash::from_str(hash256)?)
         }
 
-        fn ripemd160(&mut self, ripemd160: &String) -> Result<ripemd160::Hash, miniscript::Error> {
-            let hash = ripemd160::Hash::from_str(ripemd160)
-                .map_err(|e| miniscript::Error::Unexpected(e.to_string()))?;
-            Ok(hash)
+        fn ripemd160(&mut self, ripemd160: &String) -> Result<ripemd160::Hash, encoder::Error> {
+            Ok(ripemd160::Hash::from_str(ripemd160)?)
         }
 
-        fn hash160(&mut self, hash160: &String) -> Result<hash160::Hash, miniscript::Error> {
-            let hash = hash160::Hash::from_str(hash160)
-                .map_err(|e| miniscript::Error::Unexpected(e.to_string()))?;
-            Ok(hash)
+        fn hash160(&mut self, hash160: &String) -> Result<hash160::Hash, encoder::Error> {
+            Ok(hash160::Hash::from_str(hash160)?)
         }
     }
 
     let descriptor = Descriptor::<String>::from_str(s)?;
+    let mut keymap_pk = KeyMapWrapper {
+        key_map: BTreeMap::new(),
+        index: 0,
+        segwit: descriptor.desc_type().segwit_version().is_some(),
+    };
     let descriptor = descriptor
         .translate_pk(&mut keymap_pk)
-        .map_err(miniscript::TranslateErr::flatten)?;
+        .map_err(|e| match e {
+            TranslateErr::TranslatorErr(e) => e,
+            TranslateErr::OuterError(e) => e.into(),
+        })?;
 
-    Ok((descriptor, keymap_pk.0))
+    Ok((descriptor, keymap_pk.key_map))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_error_source() {
+        use std::error::Error as _;
+
+        let err = encode("wsh(pk(invalid))").unwrap_err();
+        assert!(matches!(err, encoder::Error::InvalidKey(_)));
+        assert!(err.source().is_some());
+
+        let err = encode("wsh(sha256(00))").unwrap_err();
+        assert!(matches!(err, encoder::Error::InvalidHash(_)));
+
+        let err = encode("wsh(pk(").unwrap_err();
+        assert!(matches!(err, encoder::Error::InvalidDescriptor(_)));
+
+        let descriptor = "wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)";
+        let mut encoded = encode(descriptor).unwrap();
+        encoded[3] = 0x05; // corrupt the public key prefix
+        let err = decode(&encoded).unwrap_err();
+        assert!(matches!(err, Error::InvalidPayload(0, _)));
+        assert!(err.source().is_some());
+    }
+
+    #[test]
+    fn test_raw_tr() {
+        let descriptors = [
+            "rawtr(c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5)",
+            "rawtr(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)",
+            "rawtr([deadbeef/86'/0'/0']xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/0/*)",
+            "rawtr(L4rK1yDtCWekvXuE6oXD9jCYfFNV2cWRpVuPLBcCU2z8TrisoyY1)",
+        ];
+        for desc_str in descriptors {
+            let with_checksum = format!("{desc_str}#{}", checksum(desc_str).unwrap());
+            let encoded = encode(desc_str).unwrap();
+            assert_eq!(encoded[0], tag::Tag::RawTr.value());
+            assert_eq!(encode(&with_checksum).unwrap(), encoded);
+            assert_eq!(decode(&encoded).unwrap(), with_checksum);
+            assert_eq!(
+                Codec::new().decode_to_descriptor(&encoded),
+                Err(Error::RawTr)
+            );
+        }
+
+        let desc_str = descriptors[0];
+        let (_, stats) = encode_with_stats(desc_str).unwrap();
+        assert_eq!(stats.tags.get("rawtr"), Some(&1));
+        assert_eq!(stats.tags.get("tr"), None);
+        assert!(matches!(
+            encode(&format!("{desc_str}#00000000")),
+            Err(encoder::Error::InvalidChecksum(_))
+        ));
+        let with_tree = desc_str.replace(
+            ')',
+            ",pk(c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5))",
+        );
+        assert!(matches!(
+            encode(&with_tree),
+            Err(encoder::Error::InvalidDescriptor(_))
+        ));
+    }
+
+    #[test]
+    fn test_repeated_hashes() {
+        let key_a = "03a0434d9e47f3c86235477c7b1ae6ae5d3442d49b1943c2b752a68e2a47e247c7";
+        let key_b = "036d2b085e9e382ed10b69fc311a03f8641ccfff21574de0927513a49d9a688a00";
+        let h = "6c60f404f8167a38fc70eaf8aa17ac351023bef86bcb9d1086a19afe95bd5333";
+        let g = "7c60f404f8167a38fc70eaf8aa17ac351023bef86bcb9d1086a19afe95bd5333";
+        let desc_str = format!(
+            "wsh(or_i(and_v(v:pk({key_a}),sha256({h})),or_i(and_v(v:pk({key_b}),sha256({h})),and_v(v:hash256({h}),sha256({g})))))"
+        );
+        let encoded = encode(&desc_str).unwrap();
+        assert_eq!(decode(&encoded).unwrap(), normalize(&desc_str).unwrap());
+
+        // The repeated sha256 is stored once, but a hash256 of the same bytes is not a repeat
+        let (_, stats) = encode_with_stats(&desc_str).unwrap();
+        assert_eq!(stats.tags.get("hash ref"), Some(&1));
+        assert_eq!(stats.tags.get("sha256"), Some(&2));
+        assert_eq!(stats.tags.get("hash256"), Some(&1));
+
+        // A reference to a hash that has not been read yet fails
+        let mut bad = encoded.clone();
+        let index = bad
+            .iter()
+            .position(|b| *b == tag::Tag::HashRef.value())
+            .unwrap();
+        bad[index + 1] = 3;
+        assert_eq!(decode(&bad), Err(Error::InvalidHashRef(index)));
+    }
+
+    #[test]
+    fn test_path_too_long() {
+        let xpub = "xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL";
+        let path = "/0".repeat(300);
+        let desc_str = format!("wsh(multi(1,{xpub}/1,[deadbeef{path}]{xpub}))");
+        assert_eq!(encode(&desc_str), Err(encoder::Error::PathTooLong(1)));
+
+        let codec = Codec::new().with_max_path_len(300);
+        let encoded = codec.encode(&desc_str).unwrap();
+        assert!(matches!(decode(&encoded), Err(Error::PathTooLong(_))));
+        assert_eq!(
+            codec.decode(&encoded).unwrap(),
+            normalize(&desc_str).unwrap()
+        );
+
+        let codec = Codec::new().with_max_path_len(1);
+        assert!(
+            codec
+                .encode("wpkh(L4rK1yDtCWekvXuE6oXD9jCYfFNV2cWRpVuPLBcCU2z8TrisoyY1)")
+                .is_ok()
+        );
+        let desc_str = format!("wpkh([deadbeef/1/2]{xpub}/0)");
+        assert_eq!(codec.encode(&desc_str), Err(encoder::Error::PathTooLong(0)));
+    }
+
+    #[test]
+    fn test_wrapper_matrix() {
+        const WRAPPERS: [char; 10] = ['a', 's', 'c', 't', 'd', 'v', 'j', 'n', 'l', 'u'];
+        let key = "02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9";
+        let other = "03a34b99f22c790c4e36b2b3c2c35a36db06226e41c692fc82b8b56ac1c540c5bd";
+        let hash = "11".repeat(32);
+        let fragments = [
+            format!("pk_k({key})"),
+            format!("pk_h({key})"),
+            format!("multi(1,{key})"),
+            format!("sha256({hash})"),
+            "older(10)".to_string(),
+        ];
+
+        let mut prefixes: Vec<String> = WRAPPERS.iter().map(|w| format!("{w}:")).collect();
+        for outer in WRAPPERS {
+            for inner in WRAPPERS {
+                prefixes.push(format!("{outer}{inner}:"));
+            }
+        }
+
+        let mut encodings = BTreeMap::new();
+        let mut used = std::collections::BTreeSet::new();
+        for prefix in &prefixes {
+            for fragment in &fragments {
+                let wrapped = format!("{prefix}{fragment}");
+                for desc_str in [
+                    format!("wsh({wrapped})"),
+                    format!("wsh(and_b(pk({other}),{wrapped}))"),
+                    format!("wsh(and_v({wrapped},pk({other})))"),
+                ] {
+                    let Ok(normalized) = normalize(&desc_str) else {
+                        continue;
+                    };
+                    let encoded = encode(&desc_str).unwrap();
+                    assert_eq!(decode(&encoded).unwrap(), normalized, "{desc_str}");
+                    if let Some(previous) = encodings.insert(encoded, normalized.clone()) {
+                        assert_eq!(previous, normalized, "{desc_str}");
+                    }
+                    used.extend(prefix.chars().filter(|c| *c != ':'));
+                }
+            }
+        }
+        assert_eq!(used.len(), WRAPPERS.len());
+    }
+
+    #[test]
+    fn test_encode_decode_into() {
+        let descriptors = [
+            "wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)#8zl0zxma",
+            "pkh(xprv9s21ZrQH143K31xYSDQpPDxsXRTUcvj2iNHm5NUtrGiGG5e2DtALGdso3pGz6ssrdK4PFmM8NSpSBHNqPqm55Qn3LqFtT2emdEXVYsCzC2U/0)#m6s0eyht",
+        ];
+
+        let mut encoded = Vec::with_capacity(256);
+        let mut decoded = String::with_capacity(256);
+        for desc_str in descriptors {
+            encode_into(desc_str, &mut encoded).unwrap();
+            assert_eq!(encoded, encode(desc_str).unwrap());
+            assert_eq!(encoded.capacity(), 256);
+
+            decode_into(&encoded, &mut decoded).unwrap();
+            assert_eq!(decoded, desc_str);
+            assert_eq!(decoded.capacity(), 256);
+        }
+    }
+
+    #[test]
+    fn test_allocation_budget() {
+        let desc_str =
+            "wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)#8zl0zxma";
+        let encoded = encode(desc_str).unwrap();
+
+        let mut decoded = String::new();
+        let factor = desc_str.len().div_ceil(encoded.len());
+        let options = CodecOptions {
+            allocation_factor: factor,
+            ..Default::default()
+        };
+        decode_with(&encoded, &mut decoded, &options).unwrap();
+        assert_eq!(decoded, desc_str);
+
+        let options = CodecOptions {
+            allocation_factor: factor - 1,
+            ..Default::default()
+        };
+        let err = decode_with(&encoded, &mut decoded, &options).unwrap_err();
+        assert_eq!(err, Error::Oversized);
+        assert!(decoded.len() <= (factor - 1) * encoded.len());
+
+        // Secret forms are measured before they are built, so the budget applies to them alone
+        // even when the public form, with its longer key, would exceed it
+        let desc_str = "tr(L4rK1yDtCWekvXuE6oXD9jCYfFNV2cWRpVuPLBcCU2z8TrisoyY1)";
+        let desc_str = format!("{desc_str}#{}", checksum(desc_str).unwrap());
+        let encoded = encode(&desc_str).unwrap();
+        let factor = desc_str.len().div_ceil(encoded.len());
+        let (descriptor, _) = decode_to_descriptor(&encoded).unwrap();
+        assert!(descriptor.to_string().len() > factor * encoded.len());
+
+        let options = CodecOptions {
+            allocation_factor: factor,
+            ..Default::default()
+        };
+        decode_with(&encoded, &mut decoded, &options).unwrap();
+        assert_eq!(decoded, desc_str);
+
+        let options = CodecOptions {
+            allocation_factor: factor - 1,
+            ..Default::default()
+        };
+        let err = decode_with(&encoded, &mut decoded, &options).unwrap_err();
+        assert_eq!(err, Error::Oversized);
+        assert!(decoded.is_empty());
+    }
+
+    #[test]
+    fn test_decode_expecting() {
+        let xpub = "xpub6EigxozzGaNVWUwEFnbyX6oHPdpWTKgJgbfpRbAcdiGpGMrdpPinCoHBXehu35sqJHpgLDTxigAnFQG3opKjXQoSmGMrMNHz81ALZSBRCWw";
+        let policy = format!("wsh(and_v(v:pk({xpub}/0/*),older(144)))");
+        let encoded = encode(&policy).unwrap();
+        let expected = template_hash(&encoded).unwrap();
+        assert_eq!(
+            decode_expecting(&encoded, &expected).unwrap(),
+            decode(&encoded).unwrap()
+        );
+
+        // Same key and timelock with a different policy
+        let substituted = encode(&format!("wsh(or_d(pk({xpub}/0/*),older(144)))")).unwrap();
+        assert_eq!(
+            decode_expecting(&substituted, &expected),
+            Err(Error::TemplateMismatch)
+        );
+
+        // Different keys with the same policy
+        let other = "xpub6EAtA5XJ6pwFQ7L32iAJMgiWQEcrwU75NNWQ6H6eavwznDFeGFzTbSFdDKNdbG2HQdZvzrXuCyEYSSJ4cGsmfoPkKUKQ6haNKMRqG4pD4xi";
+        let rekeyed = encode(&format!("wsh(and_v(v:pk({other}/0/*),older(144)))")).unwrap();
+        assert!(decode_expecting(&rekeyed, &expected).is_ok());
+
+        // Same policy with a shorter timelock
+        let shortened = encode(&format!("wsh(and_v(v:pk({xpub}/0/*),older(1)))")).unwrap();
+        assert_eq!(
+            decode_expecting(&shortened, &expected),
+            Err(Error::TemplateMismatch)
+        );
+
+        // Same policy with another hash lock
+        let hash_lock = |hash: &str| {
+            encode(&format!(
+                "wsh(and_v(v:pk({xpub}/0/*),sha256({})))",
+                hash.repeat(32)
+            ))
+            .unwrap()
+        };
+        let expected = template_hash(&hash_lock("01")).unwrap();
+        assert!(decode_expecting(&hash_lock("01"), &expected).is_ok());
+        assert_eq!(
+            decode_expecting(&hash_lock("02"), &expected),
+            Err(Error::TemplateMismatch)
+        );
+
+        // Same policy with a key reused in place of a distinct one
+        let multi = |a: &str, b: &str| encode(&format!("wsh(multi(1,{a}/0/*,{b}/1/*))")).unwrap();
+        let expected = template_hash(&multi(xpub, other)).unwrap();
+        assert_eq!(
+            decode_expecting(&multi(xpub, xpub), &expected),
+            Err(Error::TemplateMismatch)
+        );
+    }
+
+    #[test]
+    fn test_encode_parsed() {
+        let secp = secp256k1::Secp256k1::new();
+        for desc_str in [
+            "wpkh(L4rK1yDtCWekvXuE6oXD9jCYfFNV2cWRpVuPLBcCU2z8TrisoyY1)",
+            "wsh(multi(1,03a0434d9e47f3c86235477c7b1ae6ae5d3442d49b1943c2b752a68e2a47e247c7,036d2b085e9e382ed10b69fc311a03f8641ccfff21574de0927513a49d9a688a00))",
+        ] {
+            let (descriptor, key_map) = Descriptor::parse_descriptor(&secp, desc_str).unwrap();
+            assert_eq!(
+                encode_parsed(&descriptor, &key_map).unwrap(),
+                encode(desc_str).unwrap()
+            );
+
+            let codec = Codec::new().with_sorted_multi(true);
+            assert_eq!(
+                codec.encode_parsed(&descriptor, &key_map).unwrap(),
+                codec.encode(desc_str).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_encode_parts() {
+        let xpub = "[73c5da0a/48'/0'/0'/2']xpub6DkFAXWQ2dHxq2vatrt9qyA3bXYU4ToWQwCHbf5XB2mSTexcHZCeKS1VZYcPoBd5X8yVcbXFHJR9R8UCVpt82VX1VhR28mCyxUFL4r6KFrf/<0;1>/*";
+        let key_a = "036d2b085e9e382ed10b69fc311a03f8641ccfff21574de0927513a49d9a688a00";
+        let key_b = "02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9";
+        let desc_str = format!("wsh(multi(1,{xpub},{key_a}))");
+        let encoded = encode(&desc_str).unwrap();
+
+        let (template, payload) = encode_parts(&desc_str).unwrap();
+        assert_eq!([&template[..], &payload].concat(), encoded);
+        assert_eq!(
+            decoder::template_size(&encoded, &decoder::DecodeContext::default()).unwrap(),
+            template.len()
+        );
+        assert_eq!(
+            decode_parts(&template, &payload).unwrap(),
+            decode(&encoded).unwrap()
+        );
+
+        // The same policy with other keys has the same template
+        let (other_template, other_payload) =
+            encode_parts(&format!("wsh(multi(1,{xpub},{key_b}))")).unwrap();
+        assert_eq!(other_template, template);
+        assert_ne!(other_payload, payload);
+
+        assert_eq!(
+            decode_parts(&template[..template.len() - 1], &payload),
+            Err(Error::MissingBytes)
+        );
+        let (longer, shorter) = encoded.split_at(template.len() + 1);
+        assert_eq!(
+            decode_parts(longer, shorter),
+            Err(Error::InvalidTag(template.len()))
+        );
+    }
+
+    #[test]
+    fn test_decode_to_descriptor() {
+        let desc_str = "wsh(multi(1,L4rK1yDtCWekvXuE6oXD9jCYfFNV2cWRpVuPLBcCU2z8TrisoyY1,036d2b085e9e382ed10b69fc311a03f8641ccfff21574de0927513a49d9a688a00))";
+        let encoded = encode(desc_str).unwrap();
+        let (descriptor, key_map) = decode_to_descriptor(&encoded).unwrap();
+        assert_eq!(key_map.len(), 1);
+        assert_eq!(
+            descriptor.to_string_with_secret(&key_map),
+            decode(&encoded).unwrap()
+        );
+        let secp = secp256k1::Secp256k1::new();
+        assert_eq!(
+            (descriptor, key_map),
+            Descriptor::parse_descriptor(&secp, desc_str).unwrap()
+        );
+
+        let raw_tr =
+            encode("rawtr(c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5)")
+                .unwrap();
+        assert_eq!(decode_to_descriptor(&raw_tr), Err(Error::RawTr));
+    }
+
+    #[test]
+    fn test_encode_with_stats() {
+        let desc_str = "wsh(multi(1,03a0434d9e47f3c86235477c7b1ae6ae5d3442d49b1943c2b752a68e2a47e247c7,036d2b085e9e382ed10b69fc311a03f8641ccfff21574de0927513a49d9a688a00))";
+        let (encoded, stats) = encode_with_stats(desc_str).unwrap();
+        assert_eq!(encoded, encode(desc_str).unwrap());
+        assert_eq!(stats.len(), encoded.len());
+        assert_eq!(stats.payload_len, 66);
+        assert_eq!(encoded.capacity(), encoded.len());
+        assert_eq!(
+            stats.tags.into_iter().collect::<Vec<_>>(),
+            [
+                ("compressed key", 2),
+                ("multi", 1),
+                ("no origin", 2),
+                ("wsh", 1)
+            ]
+        );
+
+        let codec = Codec::new()
+            .with_payload_commitment(true)
+            .with_sorted_multi(true);
+        let (encoded, stats) = codec.encode_with_stats(desc_str).unwrap();
+        assert_eq!(encoded, codec.encode(desc_str).unwrap());
+        assert_eq!(stats.len(), encoded.len());
+        assert_eq!(encoded.capacity(), encoded.len());
+    }
+
+    #[test]
+    fn test_checksum() {
+        let desc_str = "wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)";
+        assert_eq!(checksum(desc_str).unwrap(), "8zl0zxma");
+        assert_eq!(
+            checksum(&format!("{desc_str}#damaged")).unwrap(),
+            "8zl0zxma"
+        );
+        assert_eq!(verify_checksum(&format!("{desc_str}#8zl0zxma")), Ok(()));
+
+        let expected = Err(encoder::Error::InvalidChecksum("8zl0zxma".to_string()));
+        assert_eq!(verify_checksum(&format!("{desc_str}#8zl0zxmq")), expected);
+        assert_eq!(verify_checksum(desc_str), expected);
+        assert!(matches!(
+            checksum("wpkh(\u{e9})"),
+            Err(encoder::Error::InvalidDescriptor(_))
+        ));
+    }
+
+    #[test]
+    fn test_normalize() {
+        let canonical = "wpkh([73c5da0a/84'/0'/0']xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V/0/*h)#kz36cx3w";
+        let variants = [
+            "wpkh([73C5DA0A/84h/0h/0h]xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V/0/*')",
+            "wpkh([73c5da0a/84'/0'/0']xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V/0/*h)",
+        ];
+        for variant in variants {
+            let normalized = normalize(variant).unwrap();
+            assert_eq!(normalized, canonical);
+            assert_eq!(normalized, decode(&encode(variant).unwrap()).unwrap());
+        }
+
+        let desc_str =
+            "wsh(pk(03A0434D9E47F3C86235477C7B1AE6AE5D3442D49B1943C2B752A68E2A47E247C7))";
+        assert_eq!(
+            normalize(desc_str).unwrap(),
+            normalize(&desc_str.to_lowercase()).unwrap()
+        );
+
+        let xprv = "xprv9s21ZrQH143K31xYSDQpPDxsXRTUcvj2iNHm5NUtrGiGG5e2DtALGdso3pGz6ssrdK4PFmM8NSpSBHNqPqm55Qn3LqFtT2emdEXVYsCzC2U";
+        assert!(
+            normalize(&format!("tr({xprv}/86h/0h/0h/<0;1>/*)"))
+                .unwrap()
+                .contains(xprv)
+        );
+
+        assert!(normalize("wsh(pk(invalid))").is_err());
+    }
+
+    #[test]
+    fn test_single_sig_fast_path() {
+        let descriptors = [
+            "wpkh([73c5da0a/84'/0'/0']xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V/<0;1>/*)#hpg6d6w2",
+            "wpkh([e2867bb6/84'/1'/0']tpubDDPRy5xWxJTuVmsh7YRzK8o2EdMWgn4t41fTLxXRgyRN7EKvN2L8BKCFC1gUfPu8Xp6rr667Yc26zrXsiBZsgBc8dQiYnhPNk2Q7CsBrer5/<0;1>/*)#p50pwxgw",
+        ];
+
+        for desc_str in descriptors {
+            let encoded = encode(desc_str).unwrap();
+            assert_eq!(encoded[0], tag::Tag::SingleSigWpkh.value());
+            assert_eq!(encoded.len(), 75);
+            assert_eq!(decode(&encoded).unwrap(), desc_str);
+        }
+
+        // Secret keys use the full encoding
+        let xprv = "xprv9s21ZrQH143K31xYSDQpPDxsXRTUcvj2iNHm5NUtrGiGG5e2DtALGdso3pGz6ssrdK4PFmM8NSpSBHNqPqm55Qn3LqFtT2emdEXVYsCzC2U";
+        let desc_str = format!("wpkh([73c5da0a/84'/0'/0']{xprv}/<0;1>/*)");
+        let encoded = encode(&desc_str).unwrap();
+        assert_eq!(encoded[0], tag::Tag::Wpkh.value());
+    }
+
+    #[test]
+    fn test_deferred_derivation() {
+        let xprv = "xprv9s21ZrQH143K31xYSDQpPDxsXRTUcvj2iNHm5NUtrGiGG5e2DtALGdso3pGz6ssrdK4PFmM8NSpSBHNqPqm55Qn3LqFtT2emdEXVYsCzC2U";
+        let (descriptor, key_map) =
+            parse_descriptor(&format!("wsh(multi(1,{xprv}/0,{xprv}/1))")).unwrap();
+
+        // Secret keys are mapped to distinct placeholders rather than derived
+        assert_eq!(key_map.len(), 2);
+        let secp = secp256k1::Secp256k1::new();
+        for (pk, sk) in key_map.iter() {
+            assert_ne!(pk, &sk.to_public(&secp).unwrap());
+        }
+        assert_eq!(
+            descriptor.to_string_with_secret(&key_map),
+            format!("wsh(multi(1,{xprv}/0,{xprv}/1))#ncpvs5cd")
+        );
+
+        // Placeholders cannot collide with low-index public keys, such as the generator
+        let desc_str = format!(
+            "wsh(multi(1,0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798,{xprv}/<0;1>/*))"
+        );
+        let decoded = decode(&encode(&desc_str).unwrap()).unwrap();
+        assert_eq!(decoded.split('#').next().unwrap(), desc_str);
+    }
+
+    #[test]
+    fn test_uncompressed_key_index() {
+        let compressed = "02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9";
+        let uncompressed = "04a34b99f22c790c4e36b2b3c2c35a36db06226e41c692fc82b8b56ac1c540c5bd5b8dec5235a0fa8722476c7709c02559e3aa73aa03918ba2d492eea75abea235";
+
+        // Segwit contexts reject uncompressed keys, reporting the index of the first
+        let err = encode(&format!("wsh(multi(1,{compressed},{uncompressed}))")).unwrap_err();
+        assert_eq!(err, encoder::Error::UncompressedKey(1));
+        let err = encode(&format!("sh(wpkh({uncompressed}))")).unwrap_err();
+        assert_eq!(err, encoder::Error::UncompressedKey(0));
+
+        // Legacy contexts accept them
+        assert!(encode(&format!("sh(multi(1,{compressed},{uncompressed}))")).is_ok());
+    }
+
+    #[test]
+    fn test_legacy_descriptors() {
+        let compressed = "03a0434d9e47f3c86235477c7b1ae6ae5d3442d49b1943c2b752a68e2a47e247c7";
+        let uncompressed = "04a0434d9e47f3c86235477c7b1ae6ae5d3442d49b1943c2b752a68e2a47e247c7893aba425419bc27a3b6c7e693a24c696f794c2ed877a1593cbee53b037368d7";
+        let wif = "5HueCGU8rMjxEXxiPuD5BDku4MkFqeZyd4dZ1jvhTVqvbTLvyTJ";
+
+        for key in [compressed, uncompressed, wif] {
+            let shapes = [
+                (format!("pk({key})"), tag::Tag::Bare),
+                (format!("pkh({key})"), tag::Tag::Pkh),
+                (format!("sh(pk({key}))"), tag::Tag::Sh),
+                (format!("sh(pkh({key}))"), tag::Tag::Sh),
+                (format!("multi(1,{key},{compressed})"), tag::Tag::Bare),
+                (format!("sh(multi(1,{key},{compressed}))"), tag::Tag::Sh),
+                (
+                    format!("sh(sortedmulti(1,{key},{compressed}))"),
+                    tag::Tag::Sh,
+                ),
+            ];
+            for (desc_str, tag) in shapes {
+                let encoded = encode(&desc_str).unwrap();
+                assert_eq!(encoded[0], tag.value());
+                assert_eq!(decode(&encoded).unwrap(), normalize(&desc_str).unwrap());
+            }
+        }
+    }
+
     #[test]
     fn test_integration() {
         let descriptors = vec![
@@ -0,0 +1,136 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! # Low-Memory Decoding
+//!
+//! Decodes descriptors with at most `N` keys, keeping secret keys in a caller-provided
+//! [`FixedKeyMap`] instead of a `BTreeMap`. Decoding stops at the first key over the limit, so a
+//! hostile encoding cannot make the decoder hold more keys than the caller budgeted for.
+//!
+//! The descriptor itself is still built by miniscript on the heap, so this bounds memory use
+//! rather than removing allocation.
+
+use super::*;
+use decoder::DecodeContext;
+
+/// A map from public keys to secret keys with room for at most `N` entries
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FixedKeyMap<const N: usize> {
+    entries: [Option<(DescriptorPublicKey, DescriptorSecretKey)>; N],
+    len: usize,
+}
+
+impl<const N: usize> FixedKeyMap<N> {
+    /// Creates an empty map
+    pub fn new() -> Self {
+        Self {
+            entries: std::array::from_fn(|_| None),
+            len: 0,
+        }
+    }
+
+    /// Returns the number of entries
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns whether the map is empty
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the secret key of a public key
+    pub fn get(&self, public_key: &DescriptorPublicKey) -> Option<&DescriptorSecretKey> {
+        self.iter()
+            .find(|(key, _)| *key == public_key)
+            .map(|(_, secret)| secret)
+    }
+
+    /// Returns the entries in the order they were decoded
+    pub fn iter(&self) -> impl Iterator<Item = (&DescriptorPublicKey, &DescriptorSecretKey)> {
+        self.entries[..self.len]
+            .iter()
+            .flatten()
+            .map(|(public, secret)| (public, secret))
+    }
+
+    /// Removes every entry
+    pub fn clear(&mut self) {
+        self.entries.iter_mut().for_each(|entry| *entry = None);
+        self.len = 0;
+    }
+}
+
+impl<const N: usize> Default for FixedKeyMap<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Decodes a Bitcoin descriptor with at most `N` keys, placing its secret keys in `keys`
+///
+/// Any entries already in `keys` are removed, so one map can be reused across calls. Public keys
+/// of secret keys are derived with `secp`, which may be built in preallocated memory. Fails with
+/// [`Error::TooManyKeys`] as soon as the template holds more than `N` keys.
+pub fn decode_fixed<const N: usize, C: secp256k1::Signing>(
+    bytes: &[u8],
+    keys: &mut FixedKeyMap<N>,
+    secp: &secp256k1::Secp256k1<C>,
+) -> Result<Descriptor<DescriptorPublicKey>, Error> {
+    keys.clear();
+    let mut ctx = DecodeContext {
+        max_keys: N,
+        ..Default::default()
+    };
+    let descriptor = decoder::decode_bytes_in(bytes, &mut ctx)?;
+    if ctx.raw_tr {
+        return Err(Error::RawTr);
+    }
+    let (descriptor, key_map) = decoder::derive_public_keys(&descriptor, &ctx.key_map, secp)?;
+
+    // Each secret key is also a key of the descriptor, so there is room for all of them
+    for (slot, entry) in keys.entries.iter_mut().zip(key_map) {
+        *slot = Some(entry);
+        keys.len += 1;
+    }
+
+    Ok(descriptor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_fixed() {
+        let secp = secp256k1::Secp256k1::signing_only();
+        let desc_str = "wsh(multi(1,03a0434d9e47f3c86235477c7b1ae6ae5d3442d49b1943c2b752a68e2a47e247c7,036d2b085e9e382ed10b69fc311a03f8641ccfff21574de0927513a49d9a688a00))#rd2pu9rk";
+        let encoded = encode(desc_str).unwrap();
+
+        let mut keys = FixedKeyMap::<2>::new();
+        let descriptor = decode_fixed(&encoded, &mut keys, &secp).unwrap();
+        assert_eq!(descriptor.to_string(), desc_str);
+        assert!(keys.is_empty());
+
+        let mut keys = FixedKeyMap::<1>::new();
+        assert_eq!(
+            decode_fixed(&encoded, &mut keys, &secp),
+            Err(Error::TooManyKeys(6))
+        );
+
+        // Secret keys are held in the fixed map, replacing earlier entries
+        let wif = "L4rK1yDtCWekvXuE6oXD9jCYfFNV2cWRpVuPLBcCU2z8TrisoyY1";
+        let encoded = encode(&format!("wpkh({wif})")).unwrap();
+        let mut keys = FixedKeyMap::<1>::new();
+        for _ in 0..2 {
+            let descriptor = decode_fixed(&encoded, &mut keys, &secp).unwrap();
+            assert_eq!(keys.len(), 1);
+            let (public_key, secret_key) = keys.iter().next().unwrap();
+            assert_eq!(secret_key.to_string(), wif);
+            assert_eq!(keys.get(public_key), Some(secret_key));
+            assert_eq!(
+                descriptor.to_string(),
+                decode(&derive_watch_only(&encoded).unwrap()).unwrap()
+            );
+        }
+    }
+}
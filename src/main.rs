@@ -4,7 +4,9 @@
 #[cfg(feature = "cli")]
 use anyhow::{Context, Result};
 #[cfg(feature = "cli")]
-use clap::{Args, Parser, Subcommand};
+use bitcoin::hex::{DisplayHex, FromHex};
+#[cfg(feature = "cli")]
+use clap::{Args, Parser, Subcommand, ValueEnum};
 
 #[cfg(feature = "cli")]
 #[derive(Parser)]
@@ -24,29 +26,289 @@ enum Commands {
     Encode(EncodeArgs),
     /// Decodes a hex-encoded descriptor
     Decode(DecodeArgs),
+    /// Summarizes a hex-encoded descriptor, its keys, and its metadata
+    Inspect(DecodeArgs),
+    /// Recomputes or verifies the checksum of a descriptor
+    Checksum(ChecksumArgs),
+    /// Writes descriptors to a backup file
+    Export(ExportArgs),
+    /// Reads descriptors from a backup file
+    Import(ImportArgs),
+    /// Encodes a file of descriptors and prints size and tag histograms
+    Stats(StatsArgs),
+    /// Prints a recovery sheet for a hex-encoded descriptor
+    Sheet(SheetArgs),
+    /// Prints the Electrum script hashes of a hex-encoded descriptor over a range of indices
+    ScriptHashes(ScriptHashesArgs),
+    /// Compiles a spending policy to a descriptor and encodes it
+    #[cfg(feature = "compiler")]
+    Compile(CompileArgs),
+    /// Checks every backup file in a directory and prints a summary
+    Audit(AuditArgs),
+    /// Converts encoded bytes on stdin to or from text on stdout, a chunk at a time
+    Convert(ConvertArgs),
+    /// Starts an interactive session to encode and decode descriptors
+    #[cfg(feature = "tui")]
+    Tui,
+}
+
+#[cfg(feature = "cli")]
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    /// Hexadecimal
+    Hex,
+    /// Base58 with a 4-byte checksum
+    Base58,
+    /// RFC 4648 base32, ignoring case, whitespace, and dashes
+    Base32,
 }
 
 #[cfg(feature = "cli")]
 #[derive(Args)]
 struct EncodeArgs {
     /// The Bitcoin descriptor string to encode
+    #[cfg(not(feature = "clipboard"))]
     descriptor: String,
+    /// The Bitcoin descriptor string to encode, or omit to read it from the clipboard
+    #[cfg(feature = "clipboard")]
+    #[clap(required_unless_present = "clipboard")]
+    descriptor: Option<String>,
+    /// Output format
+    #[clap(long, value_enum, default_value = "hex")]
+    format: Format,
+    /// Remove whitespace and invisible characters before parsing
+    #[clap(long)]
+    lenient: bool,
+    /// Key to splice in for `$NAME` in the descriptor, such as `a=xprv.../84h/0h/0h`
+    #[clap(long = "key", value_name = "NAME=KEY")]
+    keys: Vec<String>,
+    /// Write the raw encoded bytes to stdout instead of text
+    #[clap(long, conflicts_with = "format")]
+    #[cfg_attr(feature = "clipboard", clap(conflicts_with = "clipboard"))]
+    binary: bool,
+    /// Also write a QR code of the hex encoding to a `.png` or `.svg` file
+    #[cfg(feature = "qr-image")]
+    #[clap(long)]
+    qr_output: Option<std::path::PathBuf>,
+    #[cfg(feature = "clipboard")]
+    #[clap(flatten)]
+    clipboard: ClipboardArgs,
 }
 
 #[cfg(feature = "cli")]
 #[derive(Args)]
 struct DecodeArgs {
-    /// Hex-encoded descriptor data
-    data: String,
+    /// Hex-encoded descriptor data, or omit with `--binary`
+    #[cfg(not(feature = "clipboard"))]
+    #[clap(required_unless_present = "binary")]
+    data: Option<String>,
+    /// Hex-encoded descriptor data, or omit to read it from the clipboard or with `--binary`
+    #[cfg(feature = "clipboard")]
+    #[clap(required_unless_present_any = ["clipboard", "binary"])]
+    data: Option<String>,
+    /// Input format
+    #[clap(long, value_enum, default_value = "hex")]
+    format: Format,
+    /// Read the raw encoded bytes from stdin instead of text
+    #[clap(long, conflicts_with_all = ["data", "format"])]
+    #[cfg_attr(feature = "clipboard", clap(conflicts_with = "clipboard"))]
+    binary: bool,
+    /// Suggest corrections of a single mistyped or swapped character if base58 input fails
+    #[clap(long)]
+    repair: bool,
+    #[cfg(feature = "clipboard")]
+    #[clap(flatten)]
+    clipboard: ClipboardArgs,
+}
+
+#[cfg(feature = "cli")]
+#[derive(Args)]
+struct ChecksumArgs {
+    /// The Bitcoin descriptor string, with or without a checksum
+    descriptor: String,
+    /// Check the existing checksum instead of replacing it
+    #[clap(long)]
+    verify: bool,
+}
+
+#[cfg(feature = "cli")]
+#[derive(Args)]
+struct ExportArgs {
+    /// The Bitcoin descriptor strings to back up
+    #[clap(required = true)]
+    descriptors: Vec<String>,
+    /// Path of the backup file to write, conventionally ending in `.descbak`
+    #[clap(long, short)]
+    output: std::path::PathBuf,
+}
+
+#[cfg(feature = "cli")]
+#[derive(Args)]
+struct ImportArgs {
+    /// Path of the backup file to read
+    path: std::path::PathBuf,
+}
+
+#[cfg(feature = "cli")]
+#[derive(Args)]
+struct StatsArgs {
+    /// Path of a file with one descriptor per line, skipping blank lines and `#` comments
+    path: std::path::PathBuf,
+}
+
+#[cfg(feature = "cli")]
+#[derive(Args)]
+struct SheetArgs {
+    #[clap(flatten)]
+    input: DecodeArgs,
+    /// Path of the text file to write instead of printing the sheet
+    #[clap(long, short)]
+    output: Option<std::path::PathBuf>,
+}
+
+#[cfg(feature = "cli")]
+#[derive(Args)]
+struct ScriptHashesArgs {
+    #[clap(flatten)]
+    input: DecodeArgs,
+    /// First index to derive
+    #[clap(long, default_value = "0")]
+    start: u32,
+    /// Number of indices to derive
+    #[clap(long, default_value = "20")]
+    count: u32,
+}
+
+#[cfg(all(feature = "cli", feature = "compiler"))]
+#[derive(Clone, Copy, ValueEnum)]
+enum ScriptContext {
+    /// Segwit v0 script
+    Wsh,
+    /// Taproot, spending by key path with the most likely key
+    Tr,
+}
+
+#[cfg(all(feature = "cli", feature = "compiler"))]
+impl From<ScriptContext> for descriptor_codec::CompileContext {
+    fn from(context: ScriptContext) -> Self {
+        match context {
+            ScriptContext::Wsh => Self::Wsh,
+            ScriptContext::Tr => Self::Tr,
+        }
+    }
+}
+
+#[cfg(all(feature = "cli", feature = "compiler"))]
+#[derive(Args)]
+struct CompileArgs {
+    /// The policy to compile, such as `or(pk(A),and(pk(B),older(144)))`
+    policy: String,
+    /// Descriptor to compile into
+    #[clap(long, value_enum, default_value = "wsh")]
+    context: ScriptContext,
+    /// Output format
+    #[clap(long, value_enum, default_value = "hex")]
+    format: Format,
+    /// Print the encoded size and spend weight of both a `wsh` and a `tr` compilation
+    #[clap(long, conflicts_with_all = ["context", "format"])]
+    compare: bool,
+}
+
+#[cfg(feature = "cli")]
+#[derive(Clone, Copy, ValueEnum)]
+enum Transport {
+    /// Hexadecimal
+    Hex,
+    /// RFC 4648 base64
+    Base64,
+    /// Bech32 with the bech32m checksum
+    Bech32,
+}
+
+#[cfg(feature = "cli")]
+impl From<Transport> for descriptor_codec::Transport {
+    fn from(transport: Transport) -> Self {
+        match transport {
+            Transport::Hex => Self::Hex,
+            Transport::Base64 => Self::Base64,
+            Transport::Bech32 => Self::Bech32,
+        }
+    }
+}
+
+#[cfg(feature = "cli")]
+#[derive(Args)]
+#[clap(group(clap::ArgGroup::new("direction").required(true).args(["from", "to"])))]
+struct ConvertArgs {
+    /// Read text in this transport and write raw bytes
+    #[clap(long, value_enum)]
+    from: Option<Transport>,
+    /// Read raw bytes and write text in this transport
+    #[clap(long, value_enum)]
+    to: Option<Transport>,
+}
+
+#[cfg(feature = "cli")]
+#[derive(Args)]
+struct AuditArgs {
+    /// Directory to search for `.descbak` files and `.hex` files of one encoding per line
+    dir: std::path::PathBuf,
+}
+
+#[cfg(feature = "clipboard")]
+#[derive(Args)]
+struct ClipboardArgs {
+    /// Read input from and write output to the system clipboard
+    #[clap(long)]
+    clipboard: bool,
+    /// Seconds before clearing the clipboard when the output holds secret keys
+    #[clap(long, default_value = "30")]
+    clear_after: u64,
+}
+
+/// Exit code after a crash, `EX_SOFTWARE` from `sysexits.h`
+#[cfg(feature = "cli")]
+const CRASH_EXIT_CODE: i32 = 70;
+
+/// Replaces the default panic output, whose message may quote a descriptor or key, with a
+/// sanitized report, and exits with [`CRASH_EXIT_CODE`]
+#[cfg(feature = "cli")]
+fn install_panic_hook() {
+    std::panic::set_hook(Box::new(|info| {
+        eprintln!(
+            "descriptor-codec {} crashed (format version {})",
+            env!("CARGO_PKG_VERSION"),
+            descriptor_codec::FORMAT_VERSION
+        );
+        if let Some(location) = info.location() {
+            eprintln!("location: {}:{}", location.file(), location.line());
+        }
+        eprintln!("Details are not shown, as they may contain descriptors or keys.");
+        std::process::exit(CRASH_EXIT_CODE);
+    }));
 }
 
 #[cfg(feature = "cli")]
 fn main() -> Result<()> {
+    install_panic_hook();
     let cli = Cli::parse();
 
     match cli.command {
         Commands::Encode(args) => handle_encode(args),
         Commands::Decode(args) => handle_decode(args),
+        Commands::Inspect(args) => handle_inspect(args),
+        Commands::Checksum(args) => handle_checksum(args),
+        Commands::Export(args) => handle_export(args),
+        Commands::Import(args) => handle_import(args),
+        Commands::Stats(args) => handle_stats(args),
+        Commands::Sheet(args) => handle_sheet(args),
+        Commands::ScriptHashes(args) => handle_script_hashes(args),
+        #[cfg(feature = "compiler")]
+        Commands::Compile(args) => handle_compile(args),
+        Commands::Audit(args) => handle_audit(args),
+        Commands::Convert(args) => handle_convert(args),
+        #[cfg(feature = "tui")]
+        Commands::Tui => tui::run(&mut std::io::stdin().lock(), &mut std::io::stdout()),
     }
 }
 
@@ -57,21 +319,1001 @@ fn main() {
 
 #[cfg(feature = "cli")]
 fn handle_encode(args: EncodeArgs) -> Result<()> {
-    let encoded_data =
-        descriptor_codec::encode(&args.descriptor).context("Failed to parse descriptor string")?;
+    let codec = descriptor_codec::Codec::new().with_lenient_parse(args.lenient);
+    #[cfg(not(feature = "clipboard"))]
+    let descriptor = args.descriptor;
+    #[cfg(feature = "clipboard")]
+    let descriptor = clipboard::input(args.descriptor, &args.clipboard)?;
+    let descriptor = substitute_keys(&descriptor, &args.keys)?;
+    let encoded_data = codec
+        .encode(&descriptor)
+        .context("Failed to parse descriptor string")?;
+
+    #[cfg(feature = "qr-image")]
+    if let Some(path) = &args.qr_output {
+        write_qr_image(path, &encoded_data)?;
+    }
+
+    if args.binary {
+        use std::io::Write;
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(&encoded_data)?;
+        stdout.flush()?;
+        return Ok(());
+    }
+
+    let output = match args.format {
+        Format::Hex => encoded_data.to_lower_hex_string(),
+        Format::Base58 => bitcoin::base58::encode_check(&encoded_data),
+        Format::Base32 => descriptor_codec::base32(&encoded_data),
+    };
 
-    println!("{}", hex::encode(encoded_data));
+    #[cfg(not(feature = "clipboard"))]
+    println!("{output}");
+    #[cfg(feature = "clipboard")]
+    {
+        let secret = descriptor_codec::decode(&encoded_data)
+            .map(|desc| clipboard::has_secret_keys(&desc))
+            .unwrap_or(true);
+        clipboard::output(&output, secret, &args.clipboard)?;
+    }
 
     Ok(())
 }
 
+/// Replaces each `$NAME` in a descriptor template with the key bound by a `NAME=KEY` argument
 #[cfg(feature = "cli")]
-fn handle_decode(args: DecodeArgs) -> Result<()> {
-    let data = hex::decode(&args.data).context("Failed to decode hex data")?;
+fn substitute_keys(template: &str, keys: &[String]) -> Result<String> {
+    let mut bindings = std::collections::BTreeMap::new();
+    // Arguments may hold secret keys, so errors name them by position only
+    for (position, key) in keys.iter().enumerate().map(|(i, key)| (i + 1, key)) {
+        let (name, value) = key
+            .split_once('=')
+            .with_context(|| format!("Key argument {position} must be given as NAME=KEY"))?;
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            anyhow::bail!("Key argument {position} has an invalid name");
+        }
+        if bindings.insert(name, value).is_some() {
+            anyhow::bail!("Key ${name} is given more than once");
+        }
+    }
+    descriptor_codec::fill_template(template, &bindings).context("Failed to fill in keys")
+}
+
+#[cfg(all(feature = "cli", feature = "qr-image"))]
+fn write_qr_image(path: &std::path::Path, data: &[u8]) -> Result<()> {
+    use descriptor_codec::{QrCode, QrPlan, qr::EccLevel};
+
+    // Pixels per module of a PNG, about 0.25 mm each at 300 dpi
+    const PNG_SCALE: usize = 3;
 
+    let hex = data.to_lower_hex_string();
+    let ecc = match descriptor_codec::qr_capacity_report(hex.len()) {
+        QrPlan::Single { ecc, .. } => ecc,
+        _ => EccLevel::L,
+    };
+    let qr = QrCode::encode(hex.as_bytes(), ecc).context("Encoding is too long for a QR code")?;
+
+    let image = match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) if ext.eq_ignore_ascii_case("png") => qr.to_png(PNG_SCALE),
+        Some(ext) if ext.eq_ignore_ascii_case("svg") => qr.to_svg().into_bytes(),
+        _ => anyhow::bail!("QR output must end in .png or .svg"),
+    };
+    descriptor_codec::write_file_atomic(path, &image).context("Failed to write QR image")?;
+    eprintln!(
+        "Wrote version {} QR code to {}",
+        qr.version(),
+        path.display()
+    );
+
+    Ok(())
+}
+
+#[cfg(feature = "cli")]
+fn handle_decode(args: DecodeArgs) -> Result<()> {
+    let data = read_data(&args)?;
     let desc = descriptor_codec::decode(&data).context("Unable to decode")?;
 
+    #[cfg(not(feature = "clipboard"))]
     println!("{desc}");
+    #[cfg(feature = "clipboard")]
+    clipboard::output(&desc, clipboard::has_secret_keys(&desc), &args.clipboard)?;
+
+    Ok(())
+}
+
+#[cfg(feature = "cli")]
+fn handle_inspect(args: DecodeArgs) -> Result<()> {
+    let data = read_data(&args)?;
+    let inspection = descriptor_codec::inspect(&data).context("Unable to decode")?;
+
+    #[cfg(not(feature = "clipboard"))]
+    print!("{inspection}");
+    #[cfg(feature = "clipboard")]
+    clipboard::output(&inspection.to_string(), false, &args.clipboard)?;
+
+    Ok(())
+}
+
+#[cfg(feature = "cli")]
+fn handle_checksum(args: ChecksumArgs) -> Result<()> {
+    if args.verify {
+        descriptor_codec::verify_checksum(&args.descriptor).context("Checksum is not valid")?;
+        println!("Checksum is valid");
+    } else {
+        let checksum =
+            descriptor_codec::checksum(&args.descriptor).context("Failed to compute checksum")?;
+        let body = args.descriptor.split('#').next().unwrap_or_default();
+        println!("{body}#{checksum}");
+    }
 
     Ok(())
 }
+
+#[cfg(feature = "cli")]
+fn handle_export(args: ExportArgs) -> Result<()> {
+    let entries = args
+        .descriptors
+        .iter()
+        .enumerate()
+        .map(|(id, descriptor)| {
+            let bytes = descriptor_codec::encode(descriptor)?;
+            Ok(descriptor_codec::BackupEntry::plain(
+                id as u32, None, &bytes,
+            ))
+        })
+        .collect::<Result<Vec<_>, descriptor_codec::EncodeError>>()
+        .context("Failed to parse descriptor string")?;
+    let backup = descriptor_codec::Backup {
+        metadata: Vec::new(),
+        entries,
+    };
+    descriptor_codec::write_backup_file(&args.output, &backup)
+        .context("Failed to write backup file")?;
+
+    // Catch truncation or corruption before the user relies on the file
+    let written =
+        descriptor_codec::read_backup_file(&args.output).context("Failed to verify backup file")?;
+    if written != backup {
+        anyhow::bail!("Backup file does not match what was written");
+    }
+    for entry in written.entries.iter().filter_map(|entry| entry.bytes()) {
+        descriptor_codec::decode(entry).context("Failed to verify backup file")?;
+    }
+
+    eprintln!(
+        "Wrote {} descriptors to {}",
+        backup.entries.len(),
+        args.output.display()
+    );
+
+    Ok(())
+}
+
+#[cfg(feature = "cli")]
+fn handle_import(args: ImportArgs) -> Result<()> {
+    let backup = descriptor_codec::read_backup_file(&args.path)?;
+    for entry in &backup.entries {
+        let label = entry.label.as_deref().unwrap_or("unlabeled");
+        match entry.bytes() {
+            Some(bytes) => println!(
+                "{}",
+                descriptor_codec::decode(bytes).context("Unable to decode")?
+            ),
+            None => eprintln!("Skipping encrypted entry {} ({label})", entry.id),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "cli")]
+fn handle_stats(args: StatsArgs) -> Result<()> {
+    use std::collections::BTreeMap;
+
+    let file = std::fs::read_to_string(&args.path).context("Failed to read descriptor file")?;
+
+    let mut sizes = Vec::new();
+    let mut chars = 0;
+    let (mut template_len, mut payload_len) = (0, 0);
+    // Total count and number of descriptors using each tag
+    let mut tags: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+    let mut failed = 0;
+    for (line, descriptor) in file.lines().enumerate() {
+        let descriptor = descriptor.trim();
+        if descriptor.is_empty() || descriptor.starts_with('#') {
+            continue;
+        }
+        let stats = match descriptor_codec::encode_with_stats(descriptor) {
+            Ok((_, stats)) => stats,
+            Err(e) => {
+                eprintln!("line {}: {e}", line + 1);
+                failed += 1;
+                continue;
+            }
+        };
+
+        sizes.push(stats.len());
+        chars += descriptor.split('#').next().unwrap_or_default().len();
+        template_len += stats.template_len;
+        payload_len += stats.payload_len;
+        for (name, count) in stats.tags {
+            let entry = tags.entry(name).or_default();
+            entry.0 += count;
+            entry.1 += 1;
+        }
+    }
+
+    println!("descriptors: {} ({failed} failed)", sizes.len());
+    if sizes.is_empty() {
+        return Ok(());
+    }
+
+    let total = template_len + payload_len;
+    println!(
+        "encoded: {total} bytes from {chars} characters ({}% smaller)",
+        100 - (total * 100 / chars.max(1)).min(100)
+    );
+    println!("template: {template_len} bytes, payload: {payload_len} bytes");
+    println!(
+        "size: min {}, mean {}, max {} bytes",
+        sizes.iter().min().unwrap_or(&0),
+        total / sizes.len(),
+        sizes.iter().max().unwrap_or(&0)
+    );
+
+    // Sizes are bucketed by power of two, starting with everything under 32 bytes
+    let mut buckets: BTreeMap<usize, usize> = BTreeMap::new();
+    for size in &sizes {
+        let bucket = match *size < 32 {
+            true => 0,
+            false => 1 << size.ilog2(),
+        };
+        *buckets.entry(bucket).or_default() += 1;
+    }
+    println!();
+    println!("size histogram:");
+    let widest = buckets.values().max().copied().unwrap_or(1);
+    for (bucket, count) in &buckets {
+        let end = (*bucket).max(16) * 2 - 1;
+        println!(
+            "  {:>6}-{:<6} {count:>8}  {}",
+            bucket,
+            end,
+            "#".repeat((count * 40).div_ceil(widest))
+        );
+    }
+
+    let mut tags: Vec<_> = tags.into_iter().collect();
+    tags.sort_by(|a, b| b.1.0.cmp(&a.1.0).then(a.0.cmp(b.0)));
+    println!();
+    println!("tag histogram:");
+    for (name, (count, descriptors)) in tags {
+        println!("  {name:<24} {count:>8}  in {descriptors} descriptors");
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "cli")]
+fn handle_sheet(args: SheetArgs) -> Result<()> {
+    let data = read_data(&args.input)?;
+    let sheet = descriptor_codec::recovery_sheet(&data).context("Unable to decode")?;
+    if sheet.has_secrets {
+        eprintln!("Warning: the sheet holds secret keys");
+    }
+
+    match args.output {
+        Some(path) => {
+            descriptor_codec::write_file_atomic(&path, sheet.to_string().as_bytes())
+                .context("Failed to write sheet")?;
+            eprintln!("Wrote recovery sheet to {}", path.display());
+        }
+        None => print!("{sheet}"),
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "cli")]
+fn handle_script_hashes(args: ScriptHashesArgs) -> Result<()> {
+    let data = read_data(&args.input)?;
+    let end = args.start.saturating_add(args.count);
+    let hashes = descriptor_codec::electrum_script_hashes(&data, args.start..end)
+        .context("Unable to derive scripts")?;
+    for hash in hashes {
+        println!("{hash}");
+    }
+    Ok(())
+}
+
+#[cfg(all(feature = "cli", feature = "compiler"))]
+fn handle_compile(args: CompileArgs) -> Result<()> {
+    if args.compare {
+        let compilations =
+            descriptor_codec::compare_policy(&args.policy).context("Failed to compile policy")?;
+        println!("context  encoded  max spend weight");
+        for compilation in &compilations {
+            let context = match compilation.context {
+                descriptor_codec::CompileContext::Wsh => "wsh",
+                descriptor_codec::CompileContext::Tr => "tr",
+            };
+            println!(
+                "{context:<7}  {:>5} B  {:>13} WU",
+                compilation.encoded.len(),
+                compilation.max_spend_weight
+            );
+        }
+        for compilation in &compilations {
+            println!();
+            println!("{}", compilation.descriptor);
+        }
+        return Ok(());
+    }
+
+    let (descriptor, encoded_data) =
+        descriptor_codec::encode_policy(&args.policy, args.context.into())
+            .context("Failed to compile policy")?;
+    eprintln!("Compiled descriptor: {descriptor}");
+
+    let output = match args.format {
+        Format::Hex => encoded_data.to_lower_hex_string(),
+        Format::Base58 => bitcoin::base58::encode_check(&encoded_data),
+        Format::Base32 => descriptor_codec::base32(&encoded_data),
+    };
+    println!("{output}");
+
+    Ok(())
+}
+
+#[cfg(feature = "cli")]
+fn handle_convert(args: ConvertArgs) -> Result<()> {
+    let (stdin, mut stdout) = (std::io::stdin().lock(), std::io::stdout().lock());
+    match (args.from, args.to) {
+        (Some(from), _) => {
+            descriptor_codec::decode_stream(stdin, &mut stdout, from.into())
+                .context("Failed to decode input")?;
+        }
+        (_, Some(to)) => {
+            use std::io::Write;
+            descriptor_codec::encode_stream(stdin, &mut stdout, to.into())
+                .context("Failed to encode input")?;
+            writeln!(stdout).context("Failed to write output")?;
+        }
+        (None, None) => unreachable!("clap requires one direction"),
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "cli")]
+fn handle_audit(args: AuditArgs) -> Result<()> {
+    let mut paths = Vec::new();
+    find_backups(&args.dir, &mut paths).context("Failed to search directory")?;
+
+    // Status, whether secret keys are stored in the clear, and location of each encoding
+    let mut rows: Vec<(String, &str, String)> = Vec::new();
+    for path in &paths {
+        let location = path.display();
+        if path.extension().is_some_and(|ext| ext == "descbak") {
+            let backup = match descriptor_codec::read_backup_file(path) {
+                Ok(backup) => backup,
+                Err(e) => {
+                    rows.push((format!("error: {e}"), "-", location.to_string()));
+                    continue;
+                }
+            };
+            for entry in &backup.entries {
+                let location = match &entry.label {
+                    Some(label) => format!("{location} #{} ({label})", entry.id),
+                    None => format!("{location} #{}", entry.id),
+                };
+                match entry.bytes() {
+                    Some(bytes) => {
+                        let (status, secrets) = audit_encoding(bytes);
+                        rows.push((status, secrets, location));
+                    }
+                    None => rows.push(("encrypted".to_string(), "-", location)),
+                }
+            }
+            continue;
+        }
+
+        let file = match std::fs::read_to_string(path) {
+            Ok(file) => file,
+            Err(e) => {
+                rows.push((format!("error: {e}"), "-", location.to_string()));
+                continue;
+            }
+        };
+        for (line, data) in file.lines().enumerate() {
+            let data = data.trim();
+            if data.is_empty() || data.starts_with('#') {
+                continue;
+            }
+            let location = format!("{location}:{}", line + 1);
+            match Vec::<u8>::from_hex(data) {
+                Ok(bytes) => {
+                    let (status, secrets) = audit_encoding(&bytes);
+                    rows.push((status, secrets, location));
+                }
+                Err(_) => rows.push(("error: invalid hex".to_string(), "-", location)),
+            }
+        }
+    }
+
+    let width = rows
+        .iter()
+        .map(|(status, _, _)| status.len())
+        .max()
+        .unwrap_or_default()
+        .max("STATUS".len());
+    println!("{:<width$}  SECRETS  LOCATION", "STATUS");
+    for (status, secrets, location) in &rows {
+        println!("{status:<width$}  {secrets:<7}  {location}");
+    }
+
+    let ok = rows.iter().filter(|(status, _, _)| status == "ok").count();
+    let encrypted = rows
+        .iter()
+        .filter(|(status, _, _)| status == "encrypted")
+        .count();
+    let failed = rows.len() - ok - encrypted;
+    let exposed = rows
+        .iter()
+        .filter(|(_, secrets, _)| *secrets == "yes")
+        .count();
+    println!();
+    println!(
+        "{} encodings in {} files: {ok} ok, {failed} failed, {encrypted} encrypted and not checked",
+        rows.len(),
+        paths.len()
+    );
+    if exposed > 0 {
+        println!("warning: {exposed} unencrypted encodings hold secret keys");
+    }
+    if failed > 0 {
+        anyhow::bail!("{failed} encodings failed the audit");
+    }
+
+    Ok(())
+}
+
+/// Returns the audit status of an encoding and whether it holds secret keys
+///
+/// An encoding passes if it decodes, its descriptor has a valid checksum, and encoding the
+/// descriptor again gives the same bytes. Encodings with metadata are not compared, since the
+/// metadata cannot be recovered from the string.
+#[cfg(feature = "cli")]
+fn audit_encoding(bytes: &[u8]) -> (String, &'static str) {
+    let inspection = match descriptor_codec::inspect(bytes) {
+        Ok(inspection) => inspection,
+        Err(e) => return (format!("error: {e}"), "-"),
+    };
+    let secrets = match inspection.has_secrets {
+        true => "yes",
+        false => "no",
+    };
+    let status = |status: &str| (status.to_string(), secrets);
+
+    let Ok(descriptor) = descriptor_codec::decode(bytes) else {
+        return status("error: does not decode");
+    };
+    if descriptor_codec::verify_checksum(&descriptor).is_err() {
+        return status("error: bad checksum");
+    }
+    if !inspection.features.metadata {
+        // Payload commitments and presets are container options, so they are removed first
+        let base = descriptor_codec::transcode(bytes, &Default::default());
+        if base.ok() != descriptor_codec::encode(&descriptor).ok() {
+            return status("error: not canonical");
+        }
+    }
+
+    status("ok")
+}
+
+/// Appends the paths of `.descbak` and `.hex` files under `dir`, in sorted order
+#[cfg(feature = "cli")]
+fn find_backups(dir: &std::path::Path, paths: &mut Vec<std::path::PathBuf>) -> std::io::Result<()> {
+    let mut entries = std::fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            find_backups(&path, paths)?;
+        } else if path
+            .extension()
+            .is_some_and(|ext| ext == "descbak" || ext == "hex")
+        {
+            paths.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "cli")]
+fn read_data(args: &DecodeArgs) -> Result<Vec<u8>> {
+    if args.binary {
+        use std::io::Read;
+        let mut data = Vec::new();
+        std::io::stdin()
+            .lock()
+            .read_to_end(&mut data)
+            .context("Failed to read stdin")?;
+        return Ok(data);
+    }
+
+    #[cfg(not(feature = "clipboard"))]
+    let input = args.data.as_deref().context("No input given")?;
+    #[cfg(feature = "clipboard")]
+    let input = clipboard::input(args.data.clone(), &args.clipboard)?;
+    #[cfg(feature = "clipboard")]
+    let input = input.trim();
+
+    let data = match args.format {
+        Format::Hex => Vec::<u8>::from_hex(input).context("Failed to decode hex data")?,
+        Format::Base58 => match bitcoin::base58::decode_check(input) {
+            Ok(data) => data,
+            Err(e) if args.repair => {
+                report_corrections(input);
+                return Err(e).context("Failed to decode base58 data");
+            }
+            Err(e) => return Err(e).context("Failed to decode base58 data"),
+        },
+        Format::Base32 => {
+            descriptor_codec::parse_base32(input).context("Failed to decode base32 data")?
+        }
+    };
+
+    Ok(data)
+}
+
+#[cfg(feature = "cli")]
+fn report_corrections(input: &str) {
+    let corrections = descriptor_codec::repair_base58(input);
+    if corrections.is_empty() {
+        eprintln!("No single-character correction found");
+    }
+    for correction in corrections {
+        let edit = match correction.edit {
+            descriptor_codec::Edit::Substitution {
+                position,
+                found,
+                expected,
+            } => format!("'{expected}' instead of '{found}' at character {position}"),
+            descriptor_codec::Edit::Transposition { position } => {
+                format!("characters {position} and {} swapped", position + 1)
+            }
+        };
+        eprintln!("Candidate ({edit}): {}", correction.corrected);
+        eprintln!("  decodes to: {}", correction.descriptor);
+    }
+}
+
+/// System clipboard access through the platform's clipboard tools, which avoids a dependency on
+/// a windowing library
+#[cfg(feature = "clipboard")]
+mod clipboard {
+    use super::ClipboardArgs;
+    use anyhow::{Context, Result, bail};
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+    use std::thread;
+    use std::time::Duration;
+
+    /// Commands that write standard input to the clipboard, in order of preference
+    const COPY: &[&[&str]] = &[
+        &["pbcopy"],
+        &["clip"],
+        &["wl-copy"],
+        &["xclip", "-selection", "clipboard"],
+        &["xsel", "--clipboard", "--input"],
+    ];
+
+    /// Commands that write the clipboard to standard output, in order of preference
+    const PASTE: &[&[&str]] = &[
+        &["pbpaste"],
+        &["powershell", "-NoProfile", "-Command", "Get-Clipboard"],
+        &["wl-paste", "--no-newline"],
+        &["xclip", "-selection", "clipboard", "-o"],
+        &["xsel", "--clipboard", "--output"],
+    ];
+
+    /// Returns the argument, or the clipboard contents if `--clipboard` is set and it is absent
+    pub fn input(arg: Option<String>, args: &ClipboardArgs) -> Result<String> {
+        match arg {
+            Some(arg) => Ok(arg),
+            None if args.clipboard => paste(),
+            None => bail!("No input given"),
+        }
+    }
+
+    /// Prints the output, or copies it to the clipboard if `--clipboard` is set
+    ///
+    /// If the output holds secret keys, the clipboard is cleared after `--clear-after` seconds,
+    /// unless it has been overwritten in the meantime.
+    pub fn output(text: &str, secret: bool, args: &ClipboardArgs) -> Result<()> {
+        if !args.clipboard {
+            println!("{}", text.trim_end());
+            return Ok(());
+        }
+
+        copy(text)?;
+        eprintln!("Copied to clipboard");
+        if secret {
+            eprintln!(
+                "Output holds secret keys, clearing clipboard in {} seconds",
+                args.clear_after
+            );
+            thread::sleep(Duration::from_secs(args.clear_after));
+            if paste().is_ok_and(|current| is_unchanged(&current, text)) {
+                copy("")?;
+                eprintln!("Clipboard cleared");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether the clipboard still holds the copied text
+    ///
+    /// Line endings are ignored, since some tools add one when pasting, such as the CRLF appended
+    /// by `Get-Clipboard`.
+    fn is_unchanged(current: &str, copied: &str) -> bool {
+        current.trim_end_matches(['\r', '\n']) == copied.trim_end_matches(['\r', '\n'])
+    }
+
+    /// Returns whether a descriptor holds secret keys
+    pub fn has_secret_keys(desc: &str) -> bool {
+        let secp = bitcoin::secp256k1::Secp256k1::signing_only();
+        miniscript::Descriptor::parse_descriptor(&secp, desc)
+            .map(|(_, key_map)| !key_map.is_empty())
+            .unwrap_or(true)
+    }
+
+    fn copy(text: &str) -> Result<()> {
+        for command in COPY {
+            let Ok(mut child) = Command::new(command[0])
+                .args(&command[1..])
+                .stdin(Stdio::piped())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+            else {
+                continue;
+            };
+            if let Some(mut stdin) = child.stdin.take() {
+                stdin
+                    .write_all(text.as_bytes())
+                    .context("Failed to write to clipboard")?;
+            }
+            if child
+                .wait()
+                .context("Failed to write to clipboard")?
+                .success()
+            {
+                return Ok(());
+            }
+        }
+
+        bail!("No clipboard tool found")
+    }
+
+    fn paste() -> Result<String> {
+        for command in PASTE {
+            let Ok(output) = Command::new(command[0])
+                .args(&command[1..])
+                .stderr(Stdio::null())
+                .output()
+            else {
+                continue;
+            };
+            if output.status.success() {
+                return String::from_utf8(output.stdout).context("Clipboard is not UTF-8");
+            }
+        }
+
+        bail!("No clipboard tool found")
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_is_unchanged() {
+            let copied = "wpkh(xprv.../0/*)#abcdefgh";
+            assert!(is_unchanged(copied, copied));
+            assert!(is_unchanged(&format!("{copied}\r\n"), copied));
+            assert!(is_unchanged(&format!("{copied}\n"), &format!("{copied}\n")));
+            assert!(is_unchanged(copied, &format!("{copied}\n")));
+            assert!(!is_unchanged("something else\r\n", copied));
+            assert!(!is_unchanged("", copied));
+        }
+    }
+}
+
+/// Interactive session for users who would rather not compose command flags, such as during a
+/// recovery
+#[cfg(feature = "tui")]
+mod tui {
+    use anyhow::Result;
+    use bitcoin::hex::{DisplayHex, FromHex};
+    use descriptor_codec::{Codec, QrCode, QrPlan};
+    use std::io::{BufRead, Write};
+
+    /// Options that can be toggled during a session
+    #[derive(Default)]
+    struct Options {
+        lenient: bool,
+        sorted_multi: bool,
+        commitment: bool,
+        base58: bool,
+        hide_qr: bool,
+    }
+
+    impl Options {
+        fn codec(&self) -> Codec {
+            Codec::new()
+                .with_lenient_parse(self.lenient)
+                .with_sorted_multi(self.sorted_multi)
+                .with_payload_commitment(self.commitment)
+        }
+    }
+
+    /// Runs the session until the user quits or the input ends
+    pub fn run(input: &mut impl BufRead, output: &mut impl Write) -> Result<()> {
+        let mut options = Options {
+            lenient: true,
+            ..Default::default()
+        };
+        let mut descriptor: Option<String> = None;
+
+        writeln!(output, "descriptor-codec interactive mode")?;
+        loop {
+            write_menu(output, &options)?;
+            write!(output, "> ")?;
+            output.flush()?;
+            let Some(choice) = read_line(input)? else {
+                return Ok(());
+            };
+
+            match choice.trim() {
+                "e" => {
+                    writeln!(
+                        output,
+                        "Paste a descriptor, then press enter on an empty line:"
+                    )?;
+                    descriptor = Some(read_block(input)?);
+                }
+                "d" => {
+                    writeln!(
+                        output,
+                        "Paste encoded data, then press enter on an empty line:"
+                    )?;
+                    let data = read_block(input)?;
+                    write_decoded(output, &data, &options)?;
+                    continue;
+                }
+                "1" => options.lenient = !options.lenient,
+                "2" => options.sorted_multi = !options.sorted_multi,
+                "3" => options.commitment = !options.commitment,
+                "4" => options.base58 = !options.base58,
+                "5" => options.hide_qr = !options.hide_qr,
+                "q" => return Ok(()),
+                "" => continue,
+                other => {
+                    writeln!(output, "Unknown command `{other}`")?;
+                    continue;
+                }
+            }
+
+            if let Some(descriptor) = &descriptor {
+                write_encoded(output, descriptor, &options)?;
+            }
+        }
+    }
+
+    fn write_menu(output: &mut impl Write, options: &Options) -> Result<()> {
+        let on_off = |enabled| if enabled { "on" } else { "off" };
+        writeln!(output)?;
+        writeln!(output, "  e  encode a descriptor")?;
+        writeln!(output, "  d  decode data")?;
+        writeln!(
+            output,
+            "  1  ignore whitespace in pasted descriptors: {}",
+            on_off(options.lenient)
+        )?;
+        writeln!(
+            output,
+            "  2  encode multi() as sortedmulti(): {}",
+            on_off(options.sorted_multi)
+        )?;
+        writeln!(
+            output,
+            "  3  add payload commitment: {}",
+            on_off(options.commitment)
+        )?;
+        writeln!(
+            output,
+            "  4  format: {}",
+            if options.base58 { "base58" } else { "hex" }
+        )?;
+        writeln!(output, "  5  show QR code: {}", on_off(!options.hide_qr))?;
+        writeln!(output, "  q  quit")?;
+        Ok(())
+    }
+
+    fn write_encoded(output: &mut impl Write, descriptor: &str, options: &Options) -> Result<()> {
+        let encoded = match options.codec().encode(descriptor) {
+            Ok(encoded) => encoded,
+            Err(e) => {
+                writeln!(output, "Invalid descriptor: {e}")?;
+                return Ok(());
+            }
+        };
+
+        let hex = encoded.to_lower_hex_string();
+        let base58 = bitcoin::base58::encode_check(&encoded);
+        writeln!(output)?;
+        if options.base58 {
+            writeln!(output, "{base58}")?;
+        } else {
+            writeln!(output, "{hex}")?;
+        }
+        writeln!(output)?;
+
+        let len = descriptor.chars().filter(|c| !c.is_whitespace()).count();
+        writeln!(output, "descriptor: {len} characters")?;
+        writeln!(
+            output,
+            "encoded: {} bytes ({}% smaller)",
+            encoded.len(),
+            100 - (encoded.len() * 100 / len.max(1)).min(100)
+        )?;
+        writeln!(output, "hex: {} characters", hex.len())?;
+        writeln!(output, "base58: {} characters", base58.len())?;
+
+        if !options.hide_qr {
+            let text = if options.base58 { base58 } else { hex };
+            let qr = match descriptor_codec::qr_capacity_report(text.len()) {
+                QrPlan::Single { ecc, .. } => QrCode::encode(text.as_bytes(), ecc),
+                _ => None,
+            };
+            writeln!(output)?;
+            match qr {
+                Some(qr) => write!(output, "{}", qr.to_ascii())?,
+                None => writeln!(output, "Too long for a single QR code")?,
+            }
+        }
+        Ok(())
+    }
+
+    fn write_decoded(output: &mut impl Write, data: &str, options: &Options) -> Result<()> {
+        let data = if options.base58 {
+            bitcoin::base58::decode_check(data).map_err(|e| e.to_string())
+        } else {
+            Vec::<u8>::from_hex(data).map_err(|e| e.to_string())
+        };
+        let decoded =
+            data.and_then(|data| descriptor_codec::decode(&data).map_err(|e| e.to_string()));
+
+        writeln!(output)?;
+        match decoded {
+            Ok(descriptor) => writeln!(output, "{descriptor}")?,
+            Err(e) => writeln!(output, "Unable to decode: {e}")?,
+        }
+        Ok(())
+    }
+
+    fn read_line(input: &mut impl BufRead) -> Result<Option<String>> {
+        let mut line = String::new();
+        match input.read_line(&mut line)? {
+            0 => Ok(None),
+            _ => Ok(Some(line)),
+        }
+    }
+
+    /// Reads lines until an empty line or the end of input, joined without whitespace
+    fn read_block(input: &mut impl BufRead) -> Result<String> {
+        let mut block = String::new();
+        while let Some(line) = read_line(input)? {
+            let line = line.trim();
+            if line.is_empty() {
+                break;
+            }
+            block.push_str(line);
+        }
+        Ok(block)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        const DESCRIPTOR: &str =
+            "wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)#8zl0zxma";
+
+        /// Runs a session on the given input, returning everything written
+        fn session(input: &str) -> String {
+            let mut output = Vec::new();
+            run(&mut input.as_bytes(), &mut output).unwrap();
+            String::from_utf8(output).unwrap()
+        }
+
+        #[test]
+        fn test_encode() {
+            let encoded = descriptor_codec::encode(DESCRIPTOR).unwrap();
+            let hex = encoded.to_lower_hex_string();
+            let output = session(&format!("e\n{DESCRIPTOR}\n\nq\n"));
+            assert!(output.contains(&format!("\n{hex}\n")));
+            assert!(output.contains(&format!("encoded: {} bytes", encoded.len())));
+
+            // The QR code holds the displayed text, drawn by `QrCode::to_ascii`
+            let QrPlan::Single { ecc, .. } = descriptor_codec::qr_capacity_report(hex.len()) else {
+                panic!("expected a single QR code");
+            };
+            let qr = QrCode::encode(hex.as_bytes(), ecc).unwrap();
+            assert!(output.contains(&qr.to_ascii()));
+
+            // Switching to base58 shows it and its QR code
+            let base58 = bitcoin::base58::encode_check(&encoded);
+            let output = session(&format!("e\n{DESCRIPTOR}\n\n4\nq\n"));
+            assert!(output.contains("  4  format: base58"));
+            assert!(output.contains(&format!("\n{base58}\n")));
+            let QrPlan::Single { ecc, .. } = descriptor_codec::qr_capacity_report(base58.len())
+            else {
+                panic!("expected a single QR code");
+            };
+            let qr = QrCode::encode(base58.as_bytes(), ecc).unwrap();
+            assert!(output.contains(&qr.to_ascii()));
+
+            // The QR code can be hidden
+            let output = session(&format!("5\ne\n{DESCRIPTOR}\n\nq\n"));
+            assert!(output.contains("  5  show QR code: off"));
+            assert!(!output.contains('█'));
+        }
+
+        #[test]
+        fn test_decode_and_errors() {
+            let encoded = descriptor_codec::encode(DESCRIPTOR).unwrap();
+            let hex = encoded.to_lower_hex_string();
+            let (first, second) = hex.split_at(hex.len() / 2);
+
+            // Pasted data may span lines
+            let output = session(&format!("d\n{first}\n{second}\n\nq\n"));
+            assert!(output.contains(&format!("\n{DESCRIPTOR}\n")));
+
+            let output = session("d\nzz\n\ne\nwpkh(invalid)\n\nx\nq\n");
+            assert!(output.contains("Unable to decode"));
+            assert!(output.contains("Invalid descriptor"));
+            assert!(output.contains("Unknown command `x`"));
+
+            // The session ends with its input
+            assert!(session("1\n").contains("ignore whitespace in pasted descriptors: off"));
+        }
+    }
+}
+
+#[cfg(all(test, feature = "cli"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_substitute_keys_hides_keys() {
+        let key = "xprv9s21ZrQH143K31xYSDQpPDxsXRTUcvj2iNHm5NUtrGiGG5e2DtALGdso3pGz6ssrdK4PFmM8NSpSBHNqPqm55Qn3LqFtT2emdEXVYsCzC2U";
+        assert_eq!(
+            substitute_keys("wpkh($a/0/*)", &[format!("a={key}")]).unwrap(),
+            format!("wpkh({key}/0/*)")
+        );
+
+        for keys in [vec![key.to_string()], vec![format!("{key}/0=a")]] {
+            let err = substitute_keys("wpkh($a)", &keys).unwrap_err();
+            assert!(err.to_string().starts_with("Key argument 1 "));
+            assert!(!format!("{err:#}").contains(&key[4..]));
+        }
+    }
+}
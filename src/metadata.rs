@@ -0,0 +1,363 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! # Metadata
+//!
+//! Optional TLV records carried after the payload. A template starting with the `Metadata` tag,
+//! after any payload commitment, ends its payload with a variable-length count of records, each
+//! a variable-length type, a variable-length length, and the value.
+//!
+//! Unknown even types are safe to skip and are returned to the caller. Unknown odd types must be
+//! understood, so decoding fails on them.
+
+use super::*;
+use decoder::DecodeContext;
+
+/// A metadata record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tlv {
+    /// Record type
+    pub kind: u64,
+    /// Record value
+    pub value: Vec<u8>,
+}
+
+impl Tlv {
+    /// Returns whether decoders must understand the record, which holds for odd types
+    pub fn is_required(&self) -> bool {
+        self.kind % 2 == 1
+    }
+}
+
+/// Record type of a [`KeyRotation`]
+pub const KEY_ROTATION: u64 = 0;
+
+/// Record type marking that a top-level `multi()` was encoded as `sortedmulti()`, with an empty
+/// value
+pub const SORTED_MULTI: u64 = 4;
+
+/// Record type holding the edits from the canonical string to the original, as described in
+/// [`crate::original`]
+pub const ORIGINAL_STRING: u64 = 6;
+
+/// Record type holding the key derivation parameters of a backup file, as described in
+/// [`crate::kdf`]
+pub const KDF_PARAMS: u64 = 8;
+
+/// Record type holding the entry key of a backup file, wrapped by a [`crate::BackupCipher`]
+pub const WRAPPED_KEY: u64 = 10;
+
+/// Record type holding the height of the first block funding the descriptor, as a
+/// variable-length integer, so a restore can skip scanning earlier blocks
+pub const BIRTH_HEIGHT: u64 = 12;
+
+/// Validity period and rotation policy of a key
+///
+/// Stored as a record of type [`KEY_ROTATION`] holding the key index, a bitmask of the fields
+/// present, and each present field, all as variable-length integers.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct KeyRotation {
+    /// Index of the key, in order of appearance in the descriptor
+    pub key_index: u32,
+    /// Unix time from which the key is valid
+    pub valid_from: Option<u64>,
+    /// Unix time after which the key is no longer valid
+    pub valid_until: Option<u64>,
+    /// Block height after which the key should be rotated
+    pub rotate_after_height: Option<u32>,
+}
+
+impl KeyRotation {
+    const VALID_FROM: u64 = 1 << 0;
+    const VALID_UNTIL: u64 = 1 << 1;
+    const ROTATE_AFTER_HEIGHT: u64 = 1 << 2;
+
+    /// Parses the value of a record of type [`KEY_ROTATION`]
+    fn from_value(value: &[u8]) -> Result<Self, Error> {
+        let invalid = |_| Error::InvalidTlv(KEY_ROTATION);
+        let mut index = 0;
+        let key_index = read_varint(value, &mut index).map_err(invalid)?;
+        let flags = read_varint(value, &mut index).map_err(invalid)?;
+        if flags & !(Self::VALID_FROM | Self::VALID_UNTIL | Self::ROTATE_AFTER_HEIGHT) != 0 {
+            return Err(Error::InvalidTlv(KEY_ROTATION));
+        }
+
+        let mut read_field = |flag| match flags & flag {
+            0 => Ok(None),
+            _ => read_varint(value, &mut index).map(Some).map_err(invalid),
+        };
+        let valid_from = read_field(Self::VALID_FROM)?;
+        let valid_until = read_field(Self::VALID_UNTIL)?;
+        let rotate_after_height = read_field(Self::ROTATE_AFTER_HEIGHT)?;
+
+        if index != value.len() {
+            return Err(Error::InvalidTlv(KEY_ROTATION));
+        }
+        Ok(Self {
+            key_index: key_index
+                .try_into()
+                .map_err(|_| Error::InvalidTlv(KEY_ROTATION))?,
+            valid_from,
+            valid_until,
+            rotate_after_height: rotate_after_height
+                .map(u32::try_from)
+                .transpose()
+                .map_err(|_| Error::InvalidTlv(KEY_ROTATION))?,
+        })
+    }
+}
+
+impl From<KeyRotation> for Tlv {
+    fn from(rotation: KeyRotation) -> Self {
+        let mut flags = 0;
+        let mut fields = Vec::new();
+        let optional = [
+            (KeyRotation::VALID_FROM, rotation.valid_from),
+            (KeyRotation::VALID_UNTIL, rotation.valid_until),
+            (
+                KeyRotation::ROTATE_AFTER_HEIGHT,
+                rotation.rotate_after_height.map(u64::from),
+            ),
+        ];
+        for (flag, field) in optional {
+            if let Some(field) = field {
+                flags |= flag;
+                varint::encode_to_vec(field as u128, &mut fields);
+            }
+        }
+
+        let mut value = varint::encode(rotation.key_index as u128);
+        varint::encode_to_vec(flags as u128, &mut value);
+        value.append(&mut fields);
+        Tlv {
+            kind: KEY_ROTATION,
+            value,
+        }
+    }
+}
+
+/// Returns a record of type [`BIRTH_HEIGHT`]
+pub fn birth_height_record(height: u32) -> Tlv {
+    Tlv {
+        kind: BIRTH_HEIGHT,
+        value: varint::encode(height as u128),
+    }
+}
+
+/// Returns the birth height in the records, if any
+pub fn birth_height(records: &[Tlv]) -> Result<Option<u32>, Error> {
+    let Some(record) = records.iter().find(|record| record.kind == BIRTH_HEIGHT) else {
+        return Ok(None);
+    };
+    match varint::decode_as::<u32>(&record.value) {
+        Ok((height, size)) if size == record.value.len() => Ok(Some(height)),
+        _ => Err(Error::InvalidTlv(BIRTH_HEIGHT)),
+    }
+}
+
+/// Parses and encodes a Bitcoin descriptor with metadata records
+pub fn encode_with_metadata(s: &str, records: &[Tlv]) -> Result<Vec<u8>, encoder::Error> {
+    let mut encoded = Vec::new();
+    encode_with(s, &mut encoded, records, &CodecOptions::default())?;
+    Ok(encoded)
+}
+
+/// Decodes a Bitcoin descriptor and the metadata records not understood by this decoder
+///
+/// Fails with [`Error::UnknownRequiredTlv`] on an unknown record of odd type.
+pub fn decode_with_metadata(bytes: &[u8]) -> Result<(String, Vec<Tlv>), Error> {
+    let mut decoded = String::new();
+    let (unknown, _) = decode_with(bytes, &mut decoded, &CodecOptions::default())?;
+    Ok((decoded, unknown))
+}
+
+/// Appends records to the payload
+pub(crate) fn encode_records(records: &[Tlv], payload: &mut Vec<u8>) {
+    payload.extend(varint::encode(records.len() as u128));
+    for record in records {
+        payload.extend(varint::encode(record.kind as u128));
+        payload.extend(varint::encode(record.value.len() as u128));
+        payload.extend_from_slice(&record.value);
+    }
+}
+
+/// Returns the number of payload bytes written by `encode_records`
+pub(crate) fn records_len(records: &[Tlv]) -> usize {
+    records
+        .iter()
+        .fold(varint::encoded_len(records.len() as u128), |len, record| {
+            len + varint::encoded_len(record.kind as u128)
+                + varint::encoded_len(record.value.len() as u128)
+                + record.value.len()
+        })
+}
+
+/// Reads records without interpreting them
+pub(crate) fn read_records(payload: &[u8], payload_index: &mut usize) -> Result<Vec<Tlv>, Error> {
+    let count = read_varint(payload, payload_index)?;
+
+    let mut records = Vec::new();
+    for _ in 0..count {
+        let kind = read_varint(payload, payload_index)?;
+        let len = read_varint(payload, payload_index)? as usize;
+        let value = payload
+            .get(*payload_index..payload_index.saturating_add(len))
+            .ok_or(Error::MissingBytes)?;
+        *payload_index += len;
+        records.push(Tlv {
+            kind,
+            value: value.to_vec(),
+        });
+    }
+
+    Ok(records)
+}
+
+/// Reads records from the payload into `ctx`
+pub(crate) fn decode_records(
+    payload: &[u8],
+    payload_index: &mut usize,
+    ctx: &mut DecodeContext,
+) -> Result<(), Error> {
+    for record in read_records(payload, payload_index)? {
+        match record.kind {
+            KEY_ROTATION => ctx
+                .key_rotations
+                .push(KeyRotation::from_value(&record.value)?),
+            SORTED_MULTI if record.value.is_empty() => ctx.sorted_multi = true,
+            SORTED_MULTI => return Err(Error::InvalidTlv(SORTED_MULTI)),
+            ORIGINAL_STRING => ctx.original_edits = Some(record.value),
+            kind if record.is_required() => return Err(Error::UnknownRequiredTlv(kind)),
+            _ => ctx.metadata.push(record),
+        }
+    }
+
+    Ok(())
+}
+
+fn read_varint(payload: &[u8], payload_index: &mut usize) -> Result<u64, Error> {
+    if *payload_index >= payload.len() {
+        return Err(Error::MissingBytes);
+    }
+
+    let current_index = *payload_index;
+    let (value, size) = varint::decode_as::<u64>(&payload[current_index..])
+        .map_err(|e| Error::InvalidPayload(current_index, decoder::PayloadError::VarInt(e)))?;
+    *payload_index += size;
+
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metadata() {
+        let desc_str =
+            "wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)#8zl0zxma";
+        let optional = Tlv {
+            kind: 1000,
+            value: b"label".to_vec(),
+        };
+        let encoded = encode_with_metadata(desc_str, std::slice::from_ref(&optional)).unwrap();
+        assert_eq!(
+            encoded.len(),
+            encode(desc_str).unwrap().len() + 1 + 1 + 2 + 1 + 5
+        );
+
+        // Decoders skip unknown even types and return them to the caller
+        assert_eq!(decode(&encoded).unwrap(), desc_str);
+        assert_eq!(
+            decode_with_metadata(&encoded).unwrap(),
+            (desc_str.to_string(), vec![optional.clone()])
+        );
+
+        // Decoders fail on unknown odd types
+        let required = Tlv {
+            kind: 1001,
+            value: Vec::new(),
+        };
+        let encoded = encode_with_metadata(desc_str, &[optional, required]).unwrap();
+        assert_eq!(decode(&encoded), Err(Error::UnknownRequiredTlv(1001)));
+
+        // Metadata works alongside presets and payload commitments
+        let mut presets = PresetRegistry::new();
+        presets.define(1, desc_str).unwrap();
+        let codec = Codec::new()
+            .with_presets(presets)
+            .with_payload_commitment(true);
+        let records = [Tlv {
+            kind: 2,
+            value: vec![7],
+        }];
+        let encoded = codec.encode_with_metadata(desc_str, &records).unwrap();
+        assert_eq!(
+            codec.decode_with_metadata(&encoded).unwrap(),
+            (desc_str.to_string(), records.to_vec())
+        );
+
+        // Truncated metadata
+        let encoded = encode_with_metadata(desc_str, &records).unwrap();
+        assert_eq!(
+            decode(&encoded[..encoded.len() - 1]),
+            Err(Error::MissingBytes)
+        );
+    }
+
+    #[test]
+    fn test_key_rotation() {
+        let rotation = KeyRotation {
+            key_index: 3,
+            valid_from: Some(1_700_000_000),
+            valid_until: None,
+            rotate_after_height: Some(900_000),
+        };
+        let record = Tlv::from(rotation);
+        assert_eq!(record.kind, KEY_ROTATION);
+        assert!(!record.is_required());
+        assert_eq!(KeyRotation::from_value(&record.value).unwrap(), rotation);
+
+        let empty = Tlv::from(KeyRotation::default());
+        assert_eq!(empty.value, vec![0, 0]);
+
+        // Unknown fields, missing fields, and trailing bytes are rejected
+        let invalid = Err(Error::InvalidTlv(KEY_ROTATION));
+        assert_eq!(KeyRotation::from_value(&[0, 8, 0]), invalid);
+        assert_eq!(KeyRotation::from_value(&[0, 1]), invalid);
+        assert_eq!(KeyRotation::from_value(&[0, 0, 0]), invalid);
+
+        let desc_str =
+            "wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)#8zl0zxma";
+        let malformed = Tlv {
+            kind: KEY_ROTATION,
+            value: vec![0, 8],
+        };
+        let encoded = encode_with_metadata(desc_str, &[malformed]).unwrap();
+        assert_eq!(decode(&encoded), Err(Error::InvalidTlv(KEY_ROTATION)));
+
+        // Key rotations are understood, so not returned as unknown metadata
+        let encoded = encode_with_metadata(desc_str, &[record]).unwrap();
+        assert_eq!(
+            decode_with_metadata(&encoded).unwrap(),
+            (desc_str.to_string(), Vec::new())
+        );
+    }
+    #[test]
+    fn test_birth_height() {
+        let record = birth_height_record(840_000);
+        assert_eq!(record.kind, BIRTH_HEIGHT);
+        assert!(!record.is_required());
+        assert_eq!(
+            birth_height(std::slice::from_ref(&record)),
+            Ok(Some(840_000))
+        );
+        assert_eq!(birth_height(&[]), Ok(None));
+
+        let mut trailing = record;
+        trailing.value.push(0);
+        assert_eq!(
+            birth_height(&[trailing]),
+            Err(Error::InvalidTlv(BIRTH_HEIGHT))
+        );
+    }
+}
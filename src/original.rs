@@ -0,0 +1,258 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! # Original Strings
+//!
+//! Decoding returns the canonical miniscript string, which may differ from the string that was
+//! encoded in hardened markers, hex case, whitespace, or checksum. A [`Codec`] built with
+//! [`with_original_string`](Codec::with_original_string) records the edits from the canonical
+//! string back to the original in a metadata record, so [`decode_dual`] can return both.
+//!
+//! The record holds a sequence of edits, each a variable-length count of bytes to keep, a
+//! variable-length count of bytes to delete, and a variable-length length followed by the bytes
+//! to insert.
+
+use super::*;
+use metadata::ORIGINAL_STRING;
+
+/// Largest number of single-byte edits searched for, beyond which the original is stored whole
+const MAX_EDIT_DISTANCE: usize = 1024;
+
+/// Canonical and original string forms of a decoded descriptor
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DualString {
+    /// String form produced by miniscript, as returned by [`decode`]
+    pub canonical: String,
+    /// String that was encoded, or the canonical form if it was not recorded
+    pub original: String,
+}
+
+/// Decodes a Bitcoin descriptor in both its canonical and original string forms
+pub fn decode_dual(bytes: &[u8]) -> Result<DualString, Error> {
+    decode_dual_with(bytes, &CodecOptions::default())
+}
+
+pub(crate) fn decode_dual_with(bytes: &[u8], options: &CodecOptions) -> Result<DualString, Error> {
+    let mut canonical = String::new();
+    let (_, edits) = decode_with(bytes, &mut canonical, options)?;
+    let original = match edits {
+        Some(edits) => apply(&canonical, &edits).ok_or(Error::InvalidTlv(ORIGINAL_STRING))?,
+        None => canonical.clone(),
+    };
+
+    Ok(DualString {
+        canonical,
+        original,
+    })
+}
+
+/// Returns the edits that turn `canonical` into `original`, or `None` if they are equal
+pub(crate) fn edits(canonical: &str, original: &str) -> Option<Vec<u8>> {
+    if canonical == original {
+        return None;
+    }
+
+    let (a, b) = (canonical.as_bytes(), original.as_bytes());
+    let mut value = Vec::new();
+    match diff(a, b) {
+        Some(ops) => {
+            for (keep, delete, insert) in ops {
+                varint::encode_to_vec(keep as u128, &mut value);
+                varint::encode_to_vec(delete as u128, &mut value);
+                varint::encode_to_vec(insert.len() as u128, &mut value);
+                value.extend_from_slice(insert);
+            }
+        }
+        None => {
+            varint::encode_to_vec(0, &mut value);
+            varint::encode_to_vec(a.len() as u128, &mut value);
+            varint::encode_to_vec(b.len() as u128, &mut value);
+            value.extend_from_slice(b);
+        }
+    }
+
+    Some(value)
+}
+
+/// Applies `edits` to `canonical`, returning `None` if they do not fit
+fn apply(canonical: &str, edits: &[u8]) -> Option<String> {
+    let canonical = canonical.as_bytes();
+    let mut out = Vec::with_capacity(canonical.len());
+    let mut pos: usize = 0;
+    let mut index = 0;
+    while index < edits.len() {
+        let mut read = || {
+            let (n, size) = varint::decode_as::<usize>(edits.get(index..)?).ok()?;
+            index += size;
+            Some(n)
+        };
+        let (keep, delete, insert) = (read()?, read()?, read()?);
+
+        let end = pos.checked_add(keep)?;
+        out.extend_from_slice(canonical.get(pos..end)?);
+        pos = end.checked_add(delete)?;
+        if pos > canonical.len() {
+            return None;
+        }
+        out.extend_from_slice(edits.get(index..index.checked_add(insert)?)?);
+        index += insert;
+    }
+    out.extend_from_slice(&canonical[pos..]);
+
+    String::from_utf8(out).ok()
+}
+
+/// Returns the shortest edit script from `a` to `b` as runs of kept, deleted, and inserted bytes
+///
+/// Uses Myers' algorithm, giving up with `None` beyond [`MAX_EDIT_DISTANCE`] single-byte edits.
+fn diff<'b>(a: &[u8], b: &'b [u8]) -> Option<Vec<(usize, usize, &'b [u8])>> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = (a.len() + b.len()).min(MAX_EDIT_DISTANCE) as isize;
+    let offset = max + 1;
+    let mut v = vec![0isize; 2 * offset as usize + 1];
+    let mut trace = Vec::new();
+
+    let mut distance = None;
+    'search: for d in 0..=max {
+        trace.push(v[(offset - d - 1) as usize..=(offset + d + 1) as usize].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let at = |k: isize| (offset + k) as usize;
+            let mut x = match k == -d || (k != d && v[at(k - 1)] < v[at(k + 1)]) {
+                true => v[at(k + 1)],
+                false => v[at(k - 1)] + 1,
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[at(k)] = x;
+            if x >= n && y >= m {
+                distance = Some(d);
+                break 'search;
+            }
+        }
+    }
+
+    // Walk back through the saved rounds, recording each step from the end
+    let mut steps = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for d in (0..=distance?).rev() {
+        let v = &trace[d as usize];
+        let at = |k: isize| (k + d + 1) as usize;
+        let k = x - y;
+        let prev_k = match k == -d || (k != d && v[at(k - 1)] < v[at(k + 1)]) {
+            true => k + 1,
+            false => k - 1,
+        };
+        let prev_x = v[at(prev_k)];
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            steps.push(Step::Keep);
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            steps.push(match x == prev_x {
+                true => Step::Insert(prev_y as usize),
+                false => Step::Delete,
+            });
+        }
+        (x, y) = (prev_x, prev_y);
+    }
+
+    let mut ops: Vec<(usize, usize, &[u8])> = Vec::new();
+    let mut run = (0, 0, None::<(usize, usize)>);
+    for step in steps.into_iter().rev() {
+        match step {
+            Step::Keep if run.1 > 0 || run.2.is_some() => {
+                ops.push(finish(run, b));
+                run = (1, 0, None);
+            }
+            Step::Keep => run.0 += 1,
+            Step::Delete => run.1 += 1,
+            Step::Insert(at) => {
+                run.2 = Some(run.2.map_or((at, at + 1), |(start, _)| (start, at + 1)));
+            }
+        }
+    }
+    if run != (0, 0, None) {
+        ops.push(finish(run, b));
+    }
+
+    Some(ops)
+}
+
+/// A single step of an edit script
+enum Step {
+    Keep,
+    Delete,
+    /// Inserts the byte of the new string at the given index
+    Insert(usize),
+}
+
+/// Converts a run of kept, deleted, and inserted bytes into an edit
+fn finish(run: (usize, usize, Option<(usize, usize)>), b: &[u8]) -> (usize, usize, &[u8]) {
+    let insert = run.2.map_or(&b[..0], |(start, end)| &b[start..end]);
+    (run.0, run.1, insert)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edits() {
+        let cases = [
+            ("abc", "abc"),
+            ("abc", ""),
+            ("", "abc"),
+            ("pkh(a/0h/1h)#xyz", "pkh(a/0'/1')"),
+            ("wsh(pk(ABCDEF))", "wsh(pk(abcdef))#12345678"),
+            ("a/0h/1h/2h", " a/0H/1'/2h\n"),
+        ];
+        for (canonical, original) in cases {
+            let edits = edits(canonical, original);
+            assert_eq!(edits.is_none(), canonical == original);
+            if let Some(edits) = edits {
+                assert_eq!(apply(canonical, &edits).unwrap(), original);
+            }
+        }
+
+        let long = "x".repeat(4000);
+        let edits = edits(&long, &"y".repeat(4000)).unwrap();
+        assert_eq!(apply(&long, &edits).unwrap(), "y".repeat(4000));
+
+        assert_eq!(apply("abc", &[4, 0, 0]), None);
+        assert_eq!(apply("abc", &[0, 0, 2, b'x']), None);
+    }
+
+    #[test]
+    fn test_decode_dual() {
+        let original = "wpkh([73c5da0a/84'/0'/0']xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V/0/*)";
+        let canonical = normalize(original).unwrap();
+
+        let encoded = encode(original).unwrap();
+        let dual = decode_dual(&encoded).unwrap();
+        assert_eq!(dual.canonical, canonical);
+        assert_eq!(dual.original, canonical);
+
+        let codec = Codec::new().with_original_string(true);
+        let encoded = codec.encode(original).unwrap();
+        assert_eq!(decode(&encoded).unwrap(), canonical);
+        let dual = codec.decode_dual(&encoded).unwrap();
+        assert_eq!(dual.canonical, canonical);
+        assert_eq!(dual.original, original);
+        assert!(encoded.len() < encode(original).unwrap().len() + 20);
+
+        // Nothing is recorded when the original is already canonical
+        assert_eq!(
+            codec.encode(&canonical).unwrap(),
+            encode(&canonical).unwrap()
+        );
+
+        let pasted = format!(" {original}\n");
+        let codec = codec.with_lenient_parse(true);
+        let dual = codec.decode_dual(&codec.encode(&pasted).unwrap()).unwrap();
+        assert_eq!(dual.original, pasted);
+    }
+}
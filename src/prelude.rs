@@ -0,0 +1,22 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! # Prelude
+//!
+//! Re-exports the commonly used parts of the public API, so downstream crates can import them
+//! from one path:
+//!
+//! ```rust
+//! use descriptor_codec::prelude::*;
+//! ```
+
+pub use crate::codec::Codec;
+pub use crate::decoded::DecodedDescriptor;
+pub use crate::decoder::{Error, PayloadError};
+pub use crate::encoded::EncodedDescriptor;
+pub use crate::encoder::Error as EncodeError;
+pub use crate::extension::TagExtension;
+pub use crate::key_codec::{DefaultKeyCodec, KeyCodec};
+pub use crate::preset::PresetRegistry;
+pub use crate::secret::RevealSecrets;
+pub use crate::transcode::{TranscodeOptions, transcode};
+pub use crate::{decode, decode_into, decode_to_descriptor, encode, encode_into};
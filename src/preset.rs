@@ -0,0 +1,155 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! # Descriptor Presets
+//!
+//! A preset is a descriptor shape registered under an ID. Descriptors that match a registered
+//! shape are encoded as the `Preset` tag and ID followed by the payload, omitting the template.
+
+use super::{tag::Tag, *};
+use std::borrow::Cow;
+
+/// A registry of descriptor shapes identified by preset ID
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PresetRegistry {
+    templates: BTreeMap<u64, Vec<u8>>,
+    ids: BTreeMap<Vec<u8>, u64>,
+}
+
+impl PresetRegistry {
+    /// Creates an empty registry
+    pub const fn new() -> Self {
+        Self {
+            templates: BTreeMap::new(),
+            ids: BTreeMap::new(),
+        }
+    }
+
+    /// Defines a preset with the shape of `descriptor`, replacing any preset with the same ID
+    ///
+    /// Only the structure of `descriptor` is stored, so its keys, fingerprints, hashes, and
+    /// timelocks may be arbitrary.
+    pub fn define(&mut self, id: u64, descriptor: &str) -> Result<(), encoder::Error> {
+        let (descriptor, key_map) = parse_descriptor(descriptor)?;
+        let mut template = Vec::new();
+        descriptor.encode_template(
+            &mut template,
+            &mut Vec::new(),
+            &EncodeContext::new(&key_map),
+        );
+
+        if let Some(previous) = self.templates.insert(id, template.clone()) {
+            self.ids.remove(&previous);
+        }
+        self.ids.insert(template, id);
+
+        Ok(())
+    }
+
+    /// Returns the shape of a preset, with dummy keys, fingerprints, hashes, and timelocks
+    pub fn resolve(&self, id: u64) -> Option<Descriptor<DescriptorPublicKey>> {
+        let template = self.templates.get(&id)?;
+        let (descriptor, _, _) = decoder::decode_template(template).ok()?;
+        Some(descriptor)
+    }
+
+    /// Returns the ID of the preset matching an encoded template, if any
+    pub fn id_of(&self, template: &[u8]) -> Option<u64> {
+        self.ids.get(template).copied()
+    }
+
+    /// Returns whether the registry has no presets
+    pub fn is_empty(&self) -> bool {
+        self.templates.is_empty()
+    }
+
+    /// Replaces a template matching a preset with the `Preset` tag and ID, keeping any header
+    pub(crate) fn compress(&self, template: &mut Vec<u8>) {
+        let start = encoder::header_len(template);
+        if let Some(id) = self.id_of(&template[start..]) {
+            template.truncate(start);
+            template.push(Tag::Preset.value());
+            template.extend(varint::encode(id as u128));
+        }
+    }
+
+    /// Replaces a leading `Preset` tag and ID with the preset's template
+    ///
+    /// Header tags preceding the `Preset` tag are kept.
+    pub(crate) fn expand<'a>(&self, bytes: &'a [u8]) -> Result<Cow<'a, [u8]>, Error> {
+        let start = encoder::header_len(bytes);
+        if bytes.get(start) != Some(&Tag::Preset.value()) {
+            return Ok(Cow::Borrowed(bytes));
+        }
+
+        let (id, size) = varint::decode_as::<u64>(&bytes[start + 1..])
+            .map_err(|e| Error::InvalidVarInt(start + 1, e))?;
+        let template = self.templates.get(&id).ok_or(Error::UnknownPreset(id))?;
+
+        let mut expanded = bytes[..start].to_vec();
+        expanded.extend_from_slice(template);
+        expanded.extend_from_slice(&bytes[start + 1 + size..]);
+        Ok(Cow::Owned(expanded))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VAULT: &str = "wsh(or_d(pk([00000000/48'/0'/0'/2']xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8/<0;1>/*),and_v(v:pk([00000000/48'/0'/0'/2']xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8/<2;3>/*),older(52560))))";
+
+    #[test]
+    fn test_define_resolve() {
+        let mut presets = PresetRegistry::new();
+        assert!(presets.is_empty());
+        assert_eq!(presets.resolve(7), None);
+
+        presets.define(7, VAULT).unwrap();
+        let shape = presets.resolve(7).unwrap();
+        let (descriptor, key_map) = parse_descriptor(VAULT).unwrap();
+        assert_eq!(
+            template_of(&shape, &KeyMap::new()),
+            template_of(&descriptor, &key_map)
+        );
+        assert_eq!(presets.id_of(&template_of(&shape, &KeyMap::new())), Some(7));
+
+        // Redefining an ID replaces its shape
+        presets
+            .define(
+                7,
+                "wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)",
+            )
+            .unwrap();
+        assert_eq!(presets.id_of(&template_of(&descriptor, &key_map)), None);
+    }
+
+    #[test]
+    fn test_compress_expand() {
+        let mut presets = PresetRegistry::new();
+        presets.define(300, VAULT).unwrap();
+
+        let encoded = encode(VAULT).unwrap();
+        let (descriptor, key_map) = parse_descriptor(VAULT).unwrap();
+        let template = template_of(&descriptor, &key_map);
+
+        let mut compressed = template.clone();
+        presets.compress(&mut compressed);
+        assert_eq!(compressed, vec![Tag::Preset.value(), 0xAC, 0x02]);
+
+        compressed.extend_from_slice(&encoded[template.len()..]);
+        assert_eq!(presets.expand(&compressed).unwrap(), encoded);
+        assert_eq!(presets.expand(&encoded).unwrap(), encoded);
+
+        assert_eq!(
+            PresetRegistry::new().expand(&compressed),
+            Err(Error::UnknownPreset(300))
+        );
+        assert_eq!(decode(&compressed), Err(Error::UnknownPreset(300)));
+    }
+
+    fn template_of(descriptor: &Descriptor<DescriptorPublicKey>, key_map: &KeyMap) -> Vec<u8> {
+        let mut template = Vec::new();
+        descriptor.encode_template(&mut template, &mut Vec::new(), &EncodeContext::new(key_map));
+        template
+    }
+}
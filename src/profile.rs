@@ -0,0 +1,150 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! # Transport Profiles
+//!
+//! Size limits of common ways to move an encoding, so apps can check at policy-design time that
+//! a descriptor will fit, and catch size regressions in their tests.
+
+use super::*;
+use qr::{EccLevel, MAX_STATIC_VERSION, capacity};
+use std::{error, fmt};
+
+/// User memory of an NTAG215 tag, in bytes
+const NTAG215_MEMORY: usize = 504;
+
+/// NDEF framing of a long record with a one-byte type: a 4-byte TLV header, a 7-byte record
+/// header, and a terminator
+const NDEF_OVERHEAD: usize = 12;
+
+/// Largest encoding whose base58check string, with its 4-byte checksum, fits in 160 characters
+const SMS_BYTES: usize = 113;
+
+/// A way of moving an encoding with a fixed size limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransportProfile {
+    /// An NFC NTAG215 tag holding the encoding in a single NDEF record
+    Ntag215,
+    /// A static QR code of version 20 at error correction level M, in byte mode
+    QrV20M,
+    /// A single SMS of 160 characters, holding the encoding in base58check
+    Sms,
+}
+
+impl fmt::Display for TransportProfile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Ntag215 => write!(f, "NFC NTAG215"),
+            Self::QrV20M => write!(f, "QR version 20 at level M"),
+            Self::Sms => write!(f, "single SMS"),
+        }
+    }
+}
+
+/// Returns the largest encoding, in bytes, that fits a transport profile
+pub fn max_encoded_size_for(profile: TransportProfile) -> usize {
+    match profile {
+        TransportProfile::Ntag215 => NTAG215_MEMORY - NDEF_OVERHEAD,
+        TransportProfile::QrV20M => capacity(MAX_STATIC_VERSION, EccLevel::M),
+        TransportProfile::Sms => SMS_BYTES,
+    }
+}
+
+/// Error checking that a descriptor fits a transport profile
+#[derive(Debug, PartialEq)]
+pub enum FitError {
+    /// Descriptor cannot be encoded
+    Encode(encoder::Error),
+    /// Encoding is larger than the profile allows
+    TooLarge {
+        /// Profile the encoding was checked against
+        profile: TransportProfile,
+        /// Size of the encoding, in bytes
+        size: usize,
+        /// Largest size the profile allows, in bytes
+        max: usize,
+    },
+}
+
+impl fmt::Display for FitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Encode(_) => write!(f, "invalid descriptor"),
+            Self::TooLarge { profile, size, max } => {
+                write!(
+                    f,
+                    "encoding of {size} bytes exceeds {max} bytes of {profile}"
+                )
+            }
+        }
+    }
+}
+
+impl error::Error for FitError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Encode(err) => Some(err),
+            Self::TooLarge { .. } => None,
+        }
+    }
+}
+
+/// Checks that a descriptor's encoding fits a transport profile, returning its size in bytes
+///
+/// Fails with [`FitError::TooLarge`] if it does not, so a policy that outgrows its transport is
+/// caught when it is designed rather than when it is backed up.
+pub fn assert_fits(descriptor: &str, profile: TransportProfile) -> Result<usize, FitError> {
+    let size = encode(descriptor).map_err(FitError::Encode)?.len();
+    let max = max_encoded_size_for(profile);
+    match size <= max {
+        true => Ok(size),
+        false => Err(FitError::TooLarge { profile, size, max }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::base58;
+
+    #[test]
+    fn test_max_encoded_size_for() {
+        assert_eq!(max_encoded_size_for(TransportProfile::Ntag215), 492);
+        assert_eq!(max_encoded_size_for(TransportProfile::QrV20M), 666);
+        assert_eq!(max_encoded_size_for(TransportProfile::Sms), 113);
+
+        // The largest SMS encoding fills the message only when no byte is zero
+        let max = max_encoded_size_for(TransportProfile::Sms);
+        assert_eq!(base58::encode_check(&[0xFF; 113]).len(), 160);
+        assert!(base58::encode_check(&vec![0xFF; max + 1]).len() > 160);
+    }
+
+    #[test]
+    fn test_assert_fits() {
+        let xpub = "[73c5da0a/48'/0'/0'/2']xpub6DkFAXWQ2dHxq2vatrt9qyA3bXYU4ToWQwCHbf5XB2mSTexcHZCeKS1VZYcPoBd5X8yVcbXFHJR9R8UCVpt82VX1VhR28mCyxUFL4r6KFrf/<0;1>/*";
+        let single = format!("wpkh({xpub})");
+        let size = encode(&single).unwrap().len();
+        assert_eq!(assert_fits(&single, TransportProfile::Sms), Ok(size));
+
+        let multisig = format!("wsh(sortedmulti(2,{xpub},{xpub},{xpub}))");
+        let size = encode(&multisig).unwrap().len();
+        assert_eq!(assert_fits(&multisig, TransportProfile::QrV20M), Ok(size));
+        let err = assert_fits(&multisig, TransportProfile::Sms).unwrap_err();
+        assert_eq!(
+            err,
+            FitError::TooLarge {
+                profile: TransportProfile::Sms,
+                size,
+                max: 113,
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            format!("encoding of {size} bytes exceeds 113 bytes of single SMS")
+        );
+
+        assert!(matches!(
+            assert_fits("wpkh(invalid)", TransportProfile::Ntag215),
+            Err(FitError::Encode(_))
+        ));
+    }
+}
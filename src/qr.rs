@@ -0,0 +1,340 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! # QR Capacity
+//!
+//! Suggests how to display an encoded descriptor as QR codes. Capacities are for byte mode and
+//! exclude any framing added by a multi-part or animated format.
+
+use std::error;
+use std::fmt;
+use std::time::Duration;
+
+/// QR error correction level
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EccLevel {
+    /// Recovers about 7% of the symbol
+    L,
+    /// Recovers about 15% of the symbol
+    M,
+    /// Recovers about 25% of the symbol
+    Q,
+    /// Recovers about 30% of the symbol
+    H,
+}
+
+/// A plan for displaying an encoded descriptor as QR codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QrPlan {
+    /// A single static QR code
+    Single {
+        /// QR version, from 1 to 40
+        version: u8,
+        /// Error correction level
+        ecc: EccLevel,
+    },
+    /// A few static QR codes, each holding an equal share of the bytes
+    MultiPart {
+        /// Number of QR codes
+        parts: usize,
+        /// QR version of each part
+        version: u8,
+        /// Error correction level
+        ecc: EccLevel,
+    },
+    /// An animated sequence of QR codes, such as animated UR
+    Animated {
+        /// Number of bytes per frame
+        fragment_len: usize,
+        /// Minimum number of frames needed to carry every byte once
+        fragments: usize,
+        /// QR version of each frame
+        version: u8,
+        /// Error correction level
+        ecc: EccLevel,
+    },
+}
+
+/// Largest QR version suggested for a static code, beyond which phone cameras struggle
+pub const MAX_STATIC_VERSION: u8 = 20;
+
+/// Largest number of static codes suggested before switching to an animated sequence
+pub const MAX_STATIC_PARTS: usize = 4;
+
+/// QR version of each frame in an animated sequence
+pub const ANIMATED_VERSION: u8 = 10;
+
+/// Byte mode capacity of each QR version at levels L, M, Q, and H
+const CAPACITY: [[u16; 4]; 40] = [
+    [17, 14, 11, 7],
+    [32, 26, 20, 14],
+    [53, 42, 32, 24],
+    [78, 62, 46, 34],
+    [106, 84, 60, 44],
+    [134, 106, 74, 58],
+    [154, 122, 86, 64],
+    [192, 152, 108, 84],
+    [230, 180, 130, 98],
+    [271, 213, 151, 119],
+    [321, 251, 177, 137],
+    [367, 287, 203, 155],
+    [425, 331, 241, 177],
+    [458, 362, 258, 194],
+    [520, 412, 292, 220],
+    [586, 450, 322, 250],
+    [644, 504, 364, 280],
+    [718, 560, 394, 310],
+    [792, 624, 442, 338],
+    [858, 666, 482, 382],
+    [929, 711, 509, 403],
+    [1003, 779, 565, 439],
+    [1091, 857, 611, 461],
+    [1171, 911, 661, 511],
+    [1273, 997, 715, 535],
+    [1367, 1059, 751, 593],
+    [1465, 1125, 805, 625],
+    [1528, 1190, 868, 658],
+    [1628, 1264, 908, 698],
+    [1732, 1370, 982, 742],
+    [1840, 1452, 1030, 790],
+    [1952, 1538, 1112, 842],
+    [2068, 1628, 1168, 898],
+    [2188, 1722, 1228, 958],
+    [2303, 1809, 1283, 983],
+    [2431, 1911, 1351, 1051],
+    [2563, 1989, 1423, 1093],
+    [2699, 2099, 1499, 1139],
+    [2809, 2213, 1579, 1219],
+    [2953, 2331, 1663, 1273],
+];
+
+/// Returns the number of bytes a QR code of the given version and level can hold
+///
+/// Panics if `version` is not between 1 and 40.
+pub fn capacity(version: u8, ecc: EccLevel) -> usize {
+    CAPACITY[version as usize - 1][ecc as usize] as usize
+}
+
+/// Suggests how to display `len` encoded bytes as QR codes
+///
+/// Prefers a single static code of at most [`MAX_STATIC_VERSION`] at level M, raising the level
+/// when the same version allows it. Longer encodings are split into up to [`MAX_STATIC_PARTS`]
+/// static codes, and anything longer into frames of version [`ANIMATED_VERSION`] at level L.
+pub fn qr_capacity_report(len: usize) -> QrPlan {
+    if let Some(version) = smallest_version(len, EccLevel::M) {
+        return QrPlan::Single {
+            version,
+            ecc: strongest_ecc(len, version),
+        };
+    }
+
+    let max_part = capacity(MAX_STATIC_VERSION, EccLevel::M);
+    let parts = len.div_ceil(max_part);
+    if parts <= MAX_STATIC_PARTS {
+        let part_len = len.div_ceil(parts);
+        let version = smallest_version(part_len, EccLevel::M).unwrap_or(MAX_STATIC_VERSION);
+        return QrPlan::MultiPart {
+            parts,
+            version,
+            ecc: strongest_ecc(part_len, version),
+        };
+    }
+
+    let fragment_len = capacity(ANIMATED_VERSION, EccLevel::L);
+    QrPlan::Animated {
+        fragment_len,
+        fragments: len.div_ceil(fragment_len),
+        version: ANIMATED_VERSION,
+        ecc: EccLevel::L,
+    }
+}
+
+/// Recommended display time of each animated frame
+///
+/// Slow enough for phone cameras, which typically decode a few dense codes per second.
+pub const FRAME_DURATION: Duration = Duration::from_millis(200);
+
+/// A frame of an animated QR sequence
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame<'a> {
+    /// Index of the fragment carried by the frame
+    pub index: usize,
+    /// Number of distinct fragments
+    pub fragments: usize,
+    /// Bytes of the fragment
+    pub data: &'a [u8],
+    /// Recommended display time
+    pub duration: Duration,
+}
+
+/// Iterator over the frames of an animated QR sequence
+#[derive(Debug, Clone)]
+pub struct Frames<'a> {
+    bytes: &'a [u8],
+    fragment_len: usize,
+    fragments: usize,
+    position: usize,
+    len: usize,
+}
+
+impl<'a> Iterator for Frames<'a> {
+    type Item = Frame<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.position >= self.len {
+            return None;
+        }
+
+        let index = self.position % self.fragments;
+        self.position += 1;
+
+        let start = index * self.fragment_len;
+        let end = (start + self.fragment_len).min(self.bytes.len());
+        Some(Frame {
+            index,
+            fragments: self.fragments,
+            data: &self.bytes[start..end],
+            duration: FRAME_DURATION,
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.len - self.position;
+        (remaining, Some(remaining))
+    }
+}
+
+impl ExactSizeIterator for Frames<'_> {}
+
+/// Returns the number of times an animated sequence of `fragments` frames should loop
+///
+/// A camera that misses a frame must wait a full loop to see it again, so short sequences loop
+/// more often to keep the expected scan time low.
+pub fn redundancy(fragments: usize) -> usize {
+    match fragments {
+        0..=4 => 3,
+        _ => 2,
+    }
+}
+
+/// Splits `bytes` into frames of version [`ANIMATED_VERSION`] at level L, looping
+/// [`redundancy`] times
+///
+/// Frames carry sequential fragments rather than fountain-coded parts, so a scanner needs every
+/// index from 0 to `fragments - 1`.
+pub fn animated_frames(bytes: &[u8]) -> Frames<'_> {
+    let fragment_len = capacity(ANIMATED_VERSION, EccLevel::L);
+    let fragments = bytes.len().div_ceil(fragment_len).max(1);
+
+    Frames {
+        bytes,
+        fragment_len,
+        fragments,
+        position: 0,
+        len: fragments * redundancy(fragments),
+    }
+}
+
+/// Returns the smallest static version holding `len` bytes at the given level
+fn smallest_version(len: usize, ecc: EccLevel) -> Option<u8> {
+    (1..=MAX_STATIC_VERSION).find(|&version| capacity(version, ecc) >= len)
+}
+
+/// Returns the strongest level at which `version` holds `len` bytes
+fn strongest_ecc(len: usize, version: u8) -> EccLevel {
+    [EccLevel::H, EccLevel::Q, EccLevel::M, EccLevel::L]
+        .into_iter()
+        .find(|&ecc| capacity(version, ecc) >= len)
+        .unwrap_or(EccLevel::L)
+}
+
+/// Error reading a scanned QR part
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QrError {
+    /// Scanned QR payload cannot be read
+    InvalidPart,
+    /// Scanned QR part belongs to a different sequence than earlier parts
+    PartMismatch,
+}
+
+impl fmt::Display for QrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::InvalidPart => write!(f, "invalid QR part"),
+            Self::PartMismatch => write!(f, "QR part belongs to a different sequence"),
+        }
+    }
+}
+
+impl error::Error for QrError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_qr_capacity_report() {
+        assert_eq!(
+            qr_capacity_report(75),
+            QrPlan::Single {
+                version: 5,
+                ecc: EccLevel::M
+            }
+        );
+        assert_eq!(
+            qr_capacity_report(20),
+            QrPlan::Single {
+                version: 2,
+                ecc: EccLevel::Q
+            }
+        );
+        assert_eq!(
+            qr_capacity_report(666),
+            QrPlan::Single {
+                version: 20,
+                ecc: EccLevel::M
+            }
+        );
+        assert_eq!(
+            qr_capacity_report(1000),
+            QrPlan::MultiPart {
+                parts: 2,
+                version: 17,
+                ecc: EccLevel::M
+            }
+        );
+        assert_eq!(
+            qr_capacity_report(5000),
+            QrPlan::Animated {
+                fragment_len: 271,
+                fragments: 19,
+                version: 10,
+                ecc: EccLevel::L
+            }
+        );
+    }
+
+    #[test]
+    fn test_animated_frames() {
+        let bytes: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+        let frames: Vec<_> = animated_frames(&bytes).collect();
+        assert_eq!(frames.len(), 4 * 3);
+        assert_eq!(animated_frames(&bytes).len(), 4 * 3);
+
+        let mut reassembled = Vec::new();
+        for frame in &frames[..4] {
+            assert_eq!(frame.fragments, 4);
+            assert_eq!(frame.duration, FRAME_DURATION);
+            reassembled.extend_from_slice(frame.data);
+        }
+        assert_eq!(reassembled, bytes);
+        assert_eq!(frames[4], frames[0]);
+        assert_eq!(frames[3].data.len(), 1000 - 3 * 271);
+
+        let frames: Vec<_> = animated_frames(&[0; 5000]).collect();
+        assert_eq!(frames.len(), 19 * 2);
+
+        let frames: Vec<_> = animated_frames(&[]).collect();
+        assert_eq!(frames.len(), 3);
+        assert!(frames[0].data.is_empty());
+    }
+}
@@ -0,0 +1,808 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! # QR Codes
+//!
+//! Renders an encoded descriptor as a QR code in byte mode, for recovery sheets and terminals.
+//! Uses the smallest version holding the bytes at the requested level, matching
+//! [`capacity`](crate::qr::capacity), and the mask with the lowest penalty score.
+
+use crate::qr::EccLevel;
+
+/// Error correction codewords per block, by level and version
+const ECC_CODEWORDS_PER_BLOCK: [[u8; 40]; 4] = [
+    [
+        7, 10, 15, 20, 26, 18, 20, 24, 30, 18, 20, 24, 26, 30, 22, 24, 28, 30, 28, 28, 28, 28, 30,
+        30, 26, 28, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30,
+    ],
+    [
+        10, 16, 26, 18, 24, 16, 18, 22, 22, 26, 30, 22, 22, 24, 24, 28, 28, 26, 26, 26, 26, 28, 28,
+        28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28,
+    ],
+    [
+        13, 22, 18, 26, 18, 24, 18, 22, 20, 24, 28, 26, 24, 20, 30, 24, 28, 28, 26, 30, 28, 30, 30,
+        30, 30, 28, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30,
+    ],
+    [
+        17, 28, 22, 16, 22, 28, 26, 26, 24, 28, 24, 28, 22, 24, 24, 30, 28, 28, 26, 28, 30, 24, 30,
+        30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30,
+    ],
+];
+
+/// Error correction blocks, by level and version
+const NUM_ERROR_CORRECTION_BLOCKS: [[u8; 40]; 4] = [
+    [
+        1, 1, 1, 1, 1, 2, 2, 2, 2, 4, 4, 4, 4, 4, 6, 6, 6, 6, 7, 8, 8, 9, 9, 10, 12, 12, 12, 13,
+        14, 15, 16, 17, 18, 19, 19, 20, 21, 22, 24, 25,
+    ],
+    [
+        1, 1, 1, 2, 2, 4, 4, 4, 5, 5, 5, 8, 9, 9, 10, 10, 11, 13, 14, 16, 17, 17, 18, 20, 21, 23,
+        25, 26, 28, 29, 31, 33, 35, 37, 38, 40, 43, 45, 47, 49,
+    ],
+    [
+        1, 1, 2, 2, 4, 4, 6, 6, 8, 8, 8, 10, 12, 16, 12, 17, 16, 18, 21, 20, 23, 23, 25, 27, 29,
+        34, 34, 35, 38, 40, 43, 45, 48, 51, 53, 56, 59, 62, 65, 68,
+    ],
+    [
+        1, 1, 2, 4, 4, 4, 5, 6, 8, 8, 11, 11, 16, 16, 18, 16, 19, 21, 25, 25, 25, 34, 30, 32, 35,
+        37, 40, 42, 45, 48, 51, 54, 57, 60, 63, 66, 70, 74, 77, 81,
+    ],
+];
+
+/// Light modules drawn around the symbol in text and images, as required by the QR specification
+pub(crate) const QUIET_ZONE: usize = 4;
+
+/// A QR code symbol
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QrCode {
+    version: u8,
+    ecc: EccLevel,
+    size: usize,
+    modules: Vec<bool>,
+    function: Vec<bool>,
+}
+
+impl QrCode {
+    /// Encodes `bytes` in the smallest version holding them at the given level
+    ///
+    /// Returns `None` if the bytes do not fit in version 40.
+    pub fn encode(bytes: &[u8], ecc: EccLevel) -> Option<Self> {
+        let version = (1..=40).find(|&version| data_capacity(version, ecc) >= bytes.len())?;
+        let size = version as usize * 4 + 17;
+        let mut qr = Self {
+            version,
+            ecc,
+            size,
+            modules: vec![false; size * size],
+            function: vec![false; size * size],
+        };
+
+        qr.draw_function_patterns();
+        let codewords = qr.add_ecc_and_interleave(&data_codewords(bytes, version, ecc));
+        qr.draw_codewords(&codewords);
+
+        let mask = (0..8)
+            .min_by_key(|&mask| {
+                qr.apply_mask(mask);
+                qr.draw_format_bits(mask);
+                let penalty = qr.penalty();
+                qr.apply_mask(mask);
+                penalty
+            })
+            .unwrap_or(0);
+        qr.apply_mask(mask);
+        qr.draw_format_bits(mask);
+
+        Some(qr)
+    }
+
+    /// Returns the QR version, from 1 to 40
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// Returns the error correction level
+    pub fn ecc(&self) -> EccLevel {
+        self.ecc
+    }
+
+    /// Returns the width and height of the symbol in modules, excluding any quiet zone
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns whether the module at column `x` and row `y` is dark
+    ///
+    /// Modules outside the symbol are light.
+    pub fn is_dark(&self, x: usize, y: usize) -> bool {
+        x < self.size && y < self.size && self.modules[y * self.size + x]
+    }
+
+    /// Renders the symbol as text, drawing two rows per line with half-block characters
+    ///
+    /// Dark modules are drawn as blocks, so the result scans best as dark text on a light
+    /// background.
+    pub fn to_ascii(&self) -> String {
+        let border = QUIET_ZONE as isize;
+        let end = (self.size + QUIET_ZONE) as isize;
+        let dark = |x: isize, y: isize| x >= 0 && y >= 0 && self.is_dark(x as usize, y as usize);
+
+        let mut out = String::new();
+        for y in (-border..end).step_by(2) {
+            for x in -border..end {
+                out.push(match (dark(x, y), dark(x, y + 1)) {
+                    (true, true) => '█',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (false, false) => ' ',
+                });
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    fn set(&mut self, x: usize, y: usize, dark: bool) {
+        self.modules[y * self.size + x] = dark;
+    }
+
+    fn set_function(&mut self, x: usize, y: usize, dark: bool) {
+        self.set(x, y, dark);
+        self.function[y * self.size + x] = true;
+    }
+
+    fn draw_function_patterns(&mut self) {
+        let size = self.size;
+        for i in 0..size {
+            self.set_function(6, i, i % 2 == 0);
+            self.set_function(i, 6, i % 2 == 0);
+        }
+
+        self.draw_finder(3, 3);
+        self.draw_finder(size - 4, 3);
+        self.draw_finder(3, size - 4);
+
+        let positions = alignment_positions(self.version);
+        let last = positions.len().saturating_sub(1);
+        for (i, &x) in positions.iter().enumerate() {
+            for (j, &y) in positions.iter().enumerate() {
+                // Skip the corners taken by finder patterns
+                let corner = (i == 0 && (j == 0 || j == last)) || (i == last && j == 0);
+                if !corner {
+                    self.draw_alignment(x, y);
+                }
+            }
+        }
+
+        // Reserve the format areas until a mask is chosen
+        self.draw_format_bits(0);
+        self.draw_version_bits();
+    }
+
+    fn draw_finder(&mut self, x: usize, y: usize) {
+        for dy in -4..=4isize {
+            for dx in -4..=4isize {
+                let (xx, yy) = (x as isize + dx, y as isize + dy);
+                if (0..self.size as isize).contains(&xx) && (0..self.size as isize).contains(&yy) {
+                    let dist = dx.abs().max(dy.abs());
+                    self.set_function(xx as usize, yy as usize, dist != 2 && dist != 4);
+                }
+            }
+        }
+    }
+
+    fn draw_alignment(&mut self, x: usize, y: usize) {
+        for dy in -2..=2isize {
+            for dx in -2..=2isize {
+                let dark = dx.abs().max(dy.abs()) != 1;
+                self.set_function((x as isize + dx) as usize, (y as isize + dy) as usize, dark);
+            }
+        }
+    }
+
+    fn draw_format_bits(&mut self, mask: u8) {
+        let bits = format_bits(self.ecc, mask);
+        let bit = |i: usize| (bits >> i) & 1 != 0;
+        let size = self.size;
+
+        for i in 0..6 {
+            self.set_function(8, i, bit(i));
+        }
+        self.set_function(8, 7, bit(6));
+        self.set_function(8, 8, bit(7));
+        self.set_function(7, 8, bit(8));
+        for i in 9..15 {
+            self.set_function(14 - i, 8, bit(i));
+        }
+
+        for i in 0..8 {
+            self.set_function(size - 1 - i, 8, bit(i));
+        }
+        for i in 8..15 {
+            self.set_function(8, size - 15 + i, bit(i));
+        }
+        self.set_function(8, size - 8, true);
+    }
+
+    fn draw_version_bits(&mut self) {
+        if self.version < 7 {
+            return;
+        }
+        let bits = version_bits(self.version);
+        for i in 0..18 {
+            let dark = (bits >> i) & 1 != 0;
+            let (a, b) = (self.size - 11 + i % 3, i / 3);
+            self.set_function(a, b, dark);
+            self.set_function(b, a, dark);
+        }
+    }
+
+    fn add_ecc_and_interleave(&self, data: &[u8]) -> Vec<u8> {
+        let (version, ecc) = (self.version as usize - 1, self.ecc as usize);
+        let num_blocks = NUM_ERROR_CORRECTION_BLOCKS[ecc][version] as usize;
+        let block_ecc_len = ECC_CODEWORDS_PER_BLOCK[ecc][version] as usize;
+        let raw_codewords = num_raw_data_modules(self.version) / 8;
+        let num_short_blocks = num_blocks - raw_codewords % num_blocks;
+        let short_block_len = raw_codewords / num_blocks;
+
+        let divisor = reed_solomon_divisor(block_ecc_len);
+        let mut blocks = Vec::with_capacity(num_blocks);
+        let mut start = 0;
+        for i in 0..num_blocks {
+            let len = short_block_len - block_ecc_len + usize::from(i >= num_short_blocks);
+            let mut block = data[start..start + len].to_vec();
+            start += len;
+            let remainder = reed_solomon_remainder(&block, &divisor);
+            if i < num_short_blocks {
+                block.push(0);
+            }
+            block.extend_from_slice(&remainder);
+            blocks.push(block);
+        }
+
+        let mut out = Vec::with_capacity(raw_codewords);
+        for i in 0..=short_block_len {
+            for (j, block) in blocks.iter().enumerate() {
+                // Skip the padding byte of the short blocks
+                if i != short_block_len - block_ecc_len || j >= num_short_blocks {
+                    out.push(block[i]);
+                }
+            }
+        }
+        out
+    }
+
+    fn draw_codewords(&mut self, codewords: &[u8]) {
+        let size = self.size;
+        let mut i = 0;
+        let mut right = size - 1;
+        while right >= 1 {
+            if right == 6 {
+                right = 5;
+            }
+            for vert in 0..size {
+                for j in 0..2 {
+                    let x = right - j;
+                    let upward = (right + 1) & 2 == 0;
+                    let y = if upward { size - 1 - vert } else { vert };
+                    if !self.function[y * size + x] && i < codewords.len() * 8 {
+                        self.set(x, y, (codewords[i >> 3] >> (7 - (i & 7))) & 1 != 0);
+                        i += 1;
+                    }
+                }
+            }
+            if right < 2 {
+                break;
+            }
+            right -= 2;
+        }
+    }
+
+    /// Flips every data module selected by `mask`, so applying it twice undoes it
+    fn apply_mask(&mut self, mask: u8) {
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let index = y * self.size + x;
+                if !self.function[index] && mask_bit(mask, x, y) {
+                    self.modules[index] = !self.modules[index];
+                }
+            }
+        }
+    }
+
+    /// Returns the penalty score used to compare masks
+    fn penalty(&self) -> usize {
+        let size = self.size;
+        let mut penalty = 0;
+
+        for transpose in [false, true] {
+            let at = |a: usize, b: usize| match transpose {
+                false => self.modules[a * size + b],
+                true => self.modules[b * size + a],
+            };
+            for a in 0..size {
+                let mut run = 1;
+                for b in 1..=size {
+                    if b < size && at(a, b) == at(a, b - 1) {
+                        run += 1;
+                        continue;
+                    }
+                    if run >= 5 {
+                        penalty += run - 2;
+                    }
+                    run = 1;
+                }
+
+                // Patterns resembling a finder, with four light modules on one side
+                const FINDER: [bool; 11] = [
+                    true, false, true, true, true, false, true, false, false, false, false,
+                ];
+                for b in 0..size.saturating_sub(10) {
+                    let forward = (0..11).all(|i| at(a, b + i) == FINDER[i]);
+                    let backward = (0..11).all(|i| at(a, b + i) == FINDER[10 - i]);
+                    penalty += 40 * (usize::from(forward) + usize::from(backward));
+                }
+            }
+        }
+
+        for y in 0..size - 1 {
+            for x in 0..size - 1 {
+                let dark = self.is_dark(x, y);
+                if dark == self.is_dark(x + 1, y)
+                    && dark == self.is_dark(x, y + 1)
+                    && dark == self.is_dark(x + 1, y + 1)
+                {
+                    penalty += 3;
+                }
+            }
+        }
+
+        let total = size * size;
+        let dark = self.modules.iter().filter(|&&dark| dark).count();
+        let k = (dark * 20).abs_diff(total * 10).div_ceil(total) - 1;
+        penalty + k * 10
+    }
+}
+
+/// Returns the number of bytes the given version holds at the given level in byte mode
+fn data_capacity(version: u8, ecc: EccLevel) -> usize {
+    let count_bits = if version < 10 { 8 } else { 16 };
+    (num_data_codewords(version, ecc) * 8 - 4 - count_bits) / 8
+}
+
+/// Returns the number of modules available for data and error correction
+fn num_raw_data_modules(version: u8) -> usize {
+    let version = version as usize;
+    let mut result = (16 * version + 128) * version + 64;
+    if version >= 2 {
+        let num_align = version / 7 + 2;
+        result -= (25 * num_align - 10) * num_align - 55;
+        if version >= 7 {
+            result -= 36;
+        }
+    }
+    result
+}
+
+fn num_data_codewords(version: u8, ecc: EccLevel) -> usize {
+    let (v, e) = (version as usize - 1, ecc as usize);
+    num_raw_data_modules(version) / 8
+        - ECC_CODEWORDS_PER_BLOCK[e][v] as usize * NUM_ERROR_CORRECTION_BLOCKS[e][v] as usize
+}
+
+/// Returns the data codewords, with the mode, length, terminator, and padding
+fn data_codewords(bytes: &[u8], version: u8, ecc: EccLevel) -> Vec<u8> {
+    let capacity = num_data_codewords(version, ecc);
+    let mut bits = Vec::with_capacity(capacity * 8);
+    let mut push = |value: usize, len: usize| {
+        bits.extend((0..len).rev().map(|i| (value >> i) & 1 != 0));
+    };
+    push(0b0100, 4);
+    push(bytes.len(), if version < 10 { 8 } else { 16 });
+    for &byte in bytes {
+        push(byte as usize, 8);
+    }
+
+    let terminator = (capacity * 8 - bits.len()).min(4);
+    bits.extend(std::iter::repeat_n(false, terminator));
+    bits.resize(bits.len().div_ceil(8) * 8, false);
+
+    let mut out: Vec<u8> = bits
+        .chunks(8)
+        .map(|bits| {
+            bits.iter()
+                .fold(0, |byte, &bit| (byte << 1) | u8::from(bit))
+        })
+        .collect();
+    for pad in [0xEC, 0x11].into_iter().cycle() {
+        if out.len() >= capacity {
+            break;
+        }
+        out.push(pad);
+    }
+    out
+}
+
+/// Returns the centers of the alignment patterns along each axis
+fn alignment_positions(version: u8) -> Vec<usize> {
+    if version == 1 {
+        return Vec::new();
+    }
+    let version = version as usize;
+    let num_align = version / 7 + 2;
+    let step = (version * 8 + num_align * 3 + 5) / (num_align * 4 - 4) * 2;
+    let size = version * 4 + 17;
+    let mut positions: Vec<usize> = (0..num_align - 1).map(|i| size - 7 - i * step).collect();
+    positions.push(6);
+    positions.reverse();
+    positions
+}
+
+/// Returns the 15 format bits for the given level and mask
+fn format_bits(ecc: EccLevel, mask: u8) -> u32 {
+    let level = match ecc {
+        EccLevel::L => 1,
+        EccLevel::M => 0,
+        EccLevel::Q => 3,
+        EccLevel::H => 2,
+    };
+    let data = (level << 3) | mask as u32;
+    let mut rem = data;
+    for _ in 0..10 {
+        rem = (rem << 1) ^ ((rem >> 9) * 0x537);
+    }
+    ((data << 10) | rem) ^ 0x5412
+}
+
+/// Returns the 18 version bits drawn for versions 7 and up
+fn version_bits(version: u8) -> u32 {
+    let mut rem = version as u32;
+    for _ in 0..12 {
+        rem = (rem << 1) ^ ((rem >> 11) * 0x1F25);
+    }
+    ((version as u32) << 12) | rem
+}
+
+fn mask_bit(mask: u8, x: usize, y: usize) -> bool {
+    match mask {
+        0 => (x + y) % 2 == 0,
+        1 => y % 2 == 0,
+        2 => x % 3 == 0,
+        3 => (x + y) % 3 == 0,
+        4 => (x / 3 + y / 2) % 2 == 0,
+        5 => x * y % 2 + x * y % 3 == 0,
+        6 => (x * y % 2 + x * y % 3) % 2 == 0,
+        _ => ((x + y) % 2 + x * y % 3) % 2 == 0,
+    }
+}
+
+/// Returns the generator polynomial of the given degree, omitting the leading coefficient
+fn reed_solomon_divisor(degree: usize) -> Vec<u8> {
+    let mut result = vec![0; degree];
+    result[degree - 1] = 1;
+    let mut root = 1;
+    for _ in 0..degree {
+        for j in 0..degree {
+            result[j] = gf_multiply(result[j], root);
+            if j + 1 < degree {
+                result[j] ^= result[j + 1];
+            }
+        }
+        root = gf_multiply(root, 0x02);
+    }
+    result
+}
+
+fn reed_solomon_remainder(data: &[u8], divisor: &[u8]) -> Vec<u8> {
+    let mut result = vec![0; divisor.len()];
+    for &byte in data {
+        let factor = byte ^ result.remove(0);
+        result.push(0);
+        for (r, &d) in result.iter_mut().zip(divisor) {
+            *r ^= gf_multiply(d, factor);
+        }
+    }
+    result
+}
+
+/// CRC-32 as used by UR, zlib, and PNG
+#[cfg(any(feature = "scanner", feature = "qr-image"))]
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+/// Multiplies in GF(2^8) modulo x^8 + x^4 + x^3 + x^2 + 1
+fn gf_multiply(x: u8, y: u8) -> u8 {
+    let mut z: u32 = 0;
+    for i in (0..8).rev() {
+        z = (z << 1) ^ ((z >> 7) * 0x11D);
+        z ^= ((y as u32 >> i) & 1) * x as u32;
+    }
+    z as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::qr::capacity;
+
+    /// Reads the data bytes back from a symbol, checking the format bits and error correction
+    fn read(qr: &QrCode) -> Vec<u8> {
+        let size = qr.size;
+        let first = (0..6).map(|i| qr.is_dark(8, i)).chain([
+            qr.is_dark(8, 7),
+            qr.is_dark(8, 8),
+            qr.is_dark(7, 8),
+        ]);
+        let first: Vec<bool> = first
+            .chain((9..15).map(|i| qr.is_dark(14 - i, 8)))
+            .collect();
+        let second: Vec<bool> = (0..8)
+            .map(|i| qr.is_dark(size - 1 - i, 8))
+            .chain((8..15).map(|i| qr.is_dark(8, size - 15 + i)))
+            .collect();
+        assert_eq!(first, second);
+        let bits = first
+            .iter()
+            .rev()
+            .fold(0, |acc, &bit| (acc << 1) | u32::from(bit));
+        let mask = (0..8)
+            .find(|&mask| format_bits(qr.ecc, mask) == bits)
+            .unwrap();
+
+        let mut unmasked = qr.clone();
+        unmasked.apply_mask(mask);
+        let raw_len = num_raw_data_modules(qr.version) / 8;
+        let mut codewords = vec![0u8; raw_len];
+        let mut i = 0;
+        let mut right = size - 1;
+        while right >= 1 {
+            if right == 6 {
+                right = 5;
+            }
+            for vert in 0..size {
+                for j in 0..2 {
+                    let x = right - j;
+                    let y = if (right + 1) & 2 == 0 {
+                        size - 1 - vert
+                    } else {
+                        vert
+                    };
+                    if !qr.function[y * size + x] && i < raw_len * 8 {
+                        codewords[i >> 3] |= u8::from(unmasked.is_dark(x, y)) << (7 - (i & 7));
+                        i += 1;
+                    }
+                }
+            }
+            if right < 2 {
+                break;
+            }
+            right -= 2;
+        }
+
+        // De-interleave, checking each block is a codeword of the generator polynomial
+        let (v, e) = (qr.version as usize - 1, qr.ecc as usize);
+        let num_blocks = NUM_ERROR_CORRECTION_BLOCKS[e][v] as usize;
+        let ecc_len = ECC_CODEWORDS_PER_BLOCK[e][v] as usize;
+        let num_short = num_blocks - raw_len % num_blocks;
+        let short_data = raw_len / num_blocks - ecc_len;
+        let mut blocks = vec![Vec::new(); num_blocks];
+        let mut iter = codewords.into_iter();
+        for i in 0..=short_data {
+            for (j, block) in blocks.iter_mut().enumerate() {
+                if i < short_data || j >= num_short {
+                    block.push(iter.next().unwrap());
+                }
+            }
+        }
+        for _ in 0..ecc_len {
+            for block in blocks.iter_mut() {
+                block.push(iter.next().unwrap());
+            }
+        }
+        assert!(iter.next().is_none());
+
+        let divisor = reed_solomon_divisor(ecc_len);
+        let mut data = Vec::new();
+        for block in blocks {
+            let (payload, check) = block.split_at(block.len() - ecc_len);
+            assert_eq!(reed_solomon_remainder(payload, &divisor), check);
+            data.extend_from_slice(payload);
+        }
+
+        assert_eq!(data[0] >> 4, 0b0100);
+        let (len, start) = match qr.version < 10 {
+            true => (((data[0] as usize & 0xF) << 4) | (data[1] as usize >> 4), 1),
+            false => {
+                let len = ((data[0] as usize & 0xF) << 12)
+                    | ((data[1] as usize) << 4)
+                    | (data[2] as usize >> 4);
+                (len, 2)
+            }
+        };
+        (0..len)
+            .map(|i| (data[start + i] << 4) | (data[start + i + 1] >> 4))
+            .collect()
+    }
+
+    #[test]
+    fn test_capacity() {
+        for version in 1..=40 {
+            for ecc in [EccLevel::L, EccLevel::M, EccLevel::Q, EccLevel::H] {
+                assert_eq!(data_capacity(version, ecc), capacity(version, ecc));
+            }
+        }
+    }
+
+    #[test]
+    fn test_alignment_positions() {
+        assert_eq!(alignment_positions(1), Vec::<usize>::new());
+        assert_eq!(alignment_positions(2), vec![6, 18]);
+        assert_eq!(alignment_positions(7), vec![6, 22, 38]);
+        assert_eq!(alignment_positions(32), vec![6, 34, 60, 86, 112, 138]);
+        assert_eq!(alignment_positions(40), vec![6, 30, 58, 86, 114, 142, 170]);
+    }
+
+    #[test]
+    fn test_format_and_version_bits() {
+        assert_eq!(format_bits(EccLevel::M, 0), 0b101010000010010);
+        assert_eq!(format_bits(EccLevel::L, 4), 0b110011000101111);
+        assert_eq!(version_bits(7), 0x07C94);
+        assert_eq!(version_bits(40), 0x28C69);
+    }
+
+    #[test]
+    fn test_encode() {
+        for (len, ecc) in [
+            (0, EccLevel::M),
+            (17, EccLevel::L),
+            (18, EccLevel::L),
+            (20, EccLevel::H),
+            (30, EccLevel::H),
+            (40, EccLevel::H),
+            (60, EccLevel::H),
+            (80, EccLevel::L),
+            (100, EccLevel::H),
+            (300, EccLevel::M),
+            (666, EccLevel::M),
+            (2953, EccLevel::L),
+        ] {
+            let bytes: Vec<u8> = (0..len).map(|i| (i * 7) as u8).collect();
+            let qr = QrCode::encode(&bytes, ecc).unwrap();
+            assert_eq!(qr.ecc(), ecc);
+            assert_eq!(qr.size(), qr.version() as usize * 4 + 17);
+            assert!(capacity(qr.version(), ecc) >= len);
+            assert!(qr.version() == 1 || capacity(qr.version() - 1, ecc) < len);
+            assert_eq!(read(&qr), bytes);
+        }
+        assert_eq!(QrCode::encode(&[0; 2954], EccLevel::L), None);
+    }
+
+    #[test]
+    fn test_to_ascii() {
+        let qr = QrCode::encode(b"descriptor", EccLevel::M).unwrap();
+        assert_eq!(qr.version(), 1);
+        let ascii = qr.to_ascii();
+        let lines: Vec<&str> = ascii.lines().collect();
+        assert_eq!(lines.len(), (21 + 2 * QUIET_ZONE).div_ceil(2));
+        assert!(
+            lines
+                .iter()
+                .all(|line| line.chars().count() == 21 + 2 * QUIET_ZONE)
+        );
+        assert!(lines[0].trim().is_empty());
+        // Top two rows of the upper left finder pattern
+        let finder: String = lines[QUIET_ZONE / 2]
+            .chars()
+            .skip(QUIET_ZONE)
+            .take(7)
+            .collect();
+        assert_eq!(finder, "█▀▀▀▀▀█");
+    }
+
+    // Module matrices from Kazuhiko Arase's reference QR encoder, forced to masks 1 and 2 as chosen
+    // here, since its own mask choice does not follow the specification's penalty rules
+    const DESCRIPTOR_V1_M: [&str; 21] = [
+        "#######.##.##.#######",
+        "#.....#...#...#.....#",
+        "#.###.#.#..##.#.###.#",
+        "#.###.#...#.#.#.###.#",
+        "#.###.#..#..#.#.###.#",
+        "#.....#.#.....#.....#",
+        "#######.#.#.#.#######",
+        "...........#.........",
+        "#.#...##.####..#..#.#",
+        "..#.##.###...#.###..#",
+        ".#.#.###.##..##.#.#.#",
+        "#.##...#.#..##...#...",
+        "#.#.#######..#.##...#",
+        "........####.####...#",
+        "#######.##.##...#...#",
+        "#.....#..###.#.#.#..#",
+        "#.###.#...###..#.#.#.",
+        "#.###.#...#.....#....",
+        "#.###.#.#....#..#..##",
+        "#.....#..##.#...##...",
+        "#######.##...####...#",
+    ];
+
+    const WPKH_V7_Q: [&str; 45] = [
+        "#######.#...###..##.##..#..##.##.#..#.#######",
+        "#.....#....####.#.#.#....##..#..##.#..#.....#",
+        "#.###.#..#.#.....####.#.....#.#..#.#..#.###.#",
+        "#.###.#..#.##...##.#..#.##.#..#..#.##.#.###.#",
+        "#.###.#.###...###...#####..#..##..###.#.###.#",
+        "#.....#.#..#.##.##.##...##.#.#........#.....#",
+        "#######.#.#.#.#.#.#.#.#.#.#.#.#.#.#.#.#######",
+        "............#.#..#.##...##..#.####...........",
+        ".#######.##.##..#...######.#......##...##...#",
+        ".#.##..##..##.#..#.##..#...##.##....##..#.#.#",
+        "#.##.#####.##..###.##.##.###.#.#.##.#.######.",
+        "###.#...#######..##.##.#..#.#..####....####..",
+        ".##...##.#.#.##..#......#..#.##..##...#....#.",
+        ".#####....#.#.......##......#.#.#..##..##.###",
+        "..#.###.#.#####..##.#.##.###.#.#..#.#####.#..",
+        "##..##.###.#..###.######..###.####...#..#.#..",
+        "...#..###.#..####.##..####....#..#.#.#.#.#...",
+        ".##.##.....####......####..#..##.#..##.##.###",
+        "#...#.##....#.#.#####.#.#.####.#..#.#.#..#.#.",
+        "#...##..#..#######.#.#.#####.#.###...#..#.##.",
+        ".##.#######...#..########.#.#.....########...",
+        "#...#...#.....#..#..#...######.#.#.##...###.#",
+        "....#.#.#....#.##.###.#.#...#.#...###.#.##...",
+        "...##...#.....###...#...##.##.#####.#...####.",
+        "##..#####.#####.#.#######....#......######..#",
+        ".#.....###....##...#.###.#.##.#.#...#..#..###",
+        "##.#.##......#..#.#.#........#.#.##....#.##..",
+        ".##.#..#..###...#.###.....#.###.##.######.#.#",
+        "#...#.####..#.#.##.#...#...#..##..#.....##.#.",
+        ".#...#.##....#.##.#.#.##...##.##.#..##.#.####",
+        ".....##......#.#..#...#.####.#.##.#....#.....",
+        "...##..#.#####..#..#...#...##.#.##.######.###",
+        "#.##..#####.##....###.#.###....#.##..##.##...",
+        ".#......##.#.##.#.####....#...###......#..###",
+        "....#.#.....##..#..#.##..#.###...##......#...",
+        ".####...#.#.##.###.#....#...#...#########.#..",
+        "#..##.#####.#..#.##.######.#.###.#..######.#.",
+        "........####.#..#..##...##...##.#...#...#.###",
+        "#######.#.#.#..#..#.#.#.##.##..#.####.#.###..",
+        "#.....#.#..##.#.#...#...#...#.####.##...####.",
+        "#.###.#.#####.##..########.#.#...##.#####....",
+        "#.###.#.#.####.#.###.####.##..#....#..###..#.",
+        "#.###.#.####.#.#.###.#.#..##.#.#.##.#.....##.",
+        "#.....#.#.#.###########...#...####.##..#.##..",
+        "#######..##...###...#.##...#.##...###.##.#.#.",
+    ];
+
+    #[test]
+    fn test_reference_vectors() {
+        let wpkh =
+            "wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)#8zl0zxma";
+        for (bytes, ecc, version, rows) in [
+            (
+                b"descriptor".as_slice(),
+                EccLevel::M,
+                1,
+                DESCRIPTOR_V1_M.as_slice(),
+            ),
+            (wpkh.as_bytes(), EccLevel::Q, 7, WPKH_V7_Q.as_slice()),
+        ] {
+            let qr = QrCode::encode(bytes, ecc).unwrap();
+            assert_eq!(qr.version(), version);
+            let matrix: Vec<String> = (0..qr.size())
+                .map(|y| {
+                    (0..qr.size())
+                        .map(|x| if qr.is_dark(x, y) { '#' } else { '.' })
+                        .collect()
+                })
+                .collect();
+            assert_eq!(matrix, rows);
+        }
+    }
+}
@@ -0,0 +1,193 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! # QR Images
+//!
+//! Renders a [`QrCode`] as an SVG or PNG image for printing. Images have the four-module quiet
+//! zone required by the QR specification. PNGs are black and white at one bit per pixel, stored
+//! without compression, so no compression library is needed.
+
+use super::*;
+use qr_code::{QUIET_ZONE, crc32};
+use std::fmt::Write;
+
+/// Largest length of a stored deflate block
+const MAX_STORED_LEN: usize = 0xFFFF;
+
+impl QrCode {
+    /// Renders the symbol as an SVG image, one unit per module
+    ///
+    /// The image scales to any size without blurring, so it suits printing.
+    pub fn to_svg(&self) -> String {
+        let width = self.size() + 2 * QUIET_ZONE;
+        let mut path = String::new();
+        for y in 0..self.size() {
+            for x in 0..self.size() {
+                if self.is_dark(x, y) {
+                    let (x, y) = (x + QUIET_ZONE, y + QUIET_ZONE);
+                    write!(path, "M{x},{y}h1v1h-1z").expect("writing to a string");
+                }
+            }
+        }
+
+        format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 {width} {width}\" \
+            shape-rendering=\"crispEdges\">\
+            <rect width=\"{width}\" height=\"{width}\" fill=\"#fff\"/>\
+            <path d=\"{path}\" fill=\"#000\"/></svg>\n"
+        )
+    }
+
+    /// Renders the symbol as a PNG image with `scale` pixels per module
+    ///
+    /// A `scale` of 0 is treated as 1.
+    pub fn to_png(&self, scale: usize) -> Vec<u8> {
+        let scale = scale.max(1);
+        let width = (self.size() + 2 * QUIET_ZONE) * scale;
+        let row_len = width.div_ceil(8);
+
+        // Each row starts with filter type 0, and a set bit is a white pixel
+        let mut pixels = Vec::with_capacity((row_len + 1) * width);
+        for y in 0..width {
+            pixels.push(0);
+            let mut row = vec![0xFF; row_len];
+            for x in 0..width {
+                let dark = (x / scale)
+                    .checked_sub(QUIET_ZONE)
+                    .zip((y / scale).checked_sub(QUIET_ZONE))
+                    .is_some_and(|(x, y)| self.is_dark(x, y));
+                if dark {
+                    row[x / 8] &= !(0x80 >> (x % 8));
+                }
+            }
+            pixels.extend(row);
+        }
+
+        let mut header = Vec::with_capacity(13);
+        header.extend((width as u32).to_be_bytes());
+        header.extend((width as u32).to_be_bytes());
+        // Bit depth 1, grayscale, deflate, no filter, no interlace
+        header.extend([1, 0, 0, 0, 0]);
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        write_chunk(&mut png, b"IHDR", &header);
+        write_chunk(&mut png, b"IDAT", &zlib_stored(&pixels));
+        write_chunk(&mut png, b"IEND", &[]);
+        png
+    }
+}
+
+/// Appends a PNG chunk with its length and CRC-32
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend((data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend(kind);
+    png.extend(data);
+    let crc = crc32(&png[start..]);
+    png.extend(crc.to_be_bytes());
+}
+
+/// Wraps `data` in a zlib stream of uncompressed deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let blocks = data.len().div_ceil(MAX_STORED_LEN).max(1);
+    let mut out = Vec::with_capacity(data.len() + blocks * 5 + 6);
+    out.extend([0x78, 0x01]);
+    for i in 0..blocks {
+        let block = &data[i * MAX_STORED_LEN..((i + 1) * MAX_STORED_LEN).min(data.len())];
+        out.push(u8::from(i + 1 == blocks));
+        out.extend((block.len() as u16).to_le_bytes());
+        out.extend((!(block.len() as u16)).to_le_bytes());
+        out.extend(block);
+    }
+    out.extend(adler32(data).to_be_bytes());
+    out
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use qr::EccLevel;
+
+    #[test]
+    fn test_to_svg() {
+        let qr = QrCode::encode(b"descriptor", EccLevel::M).unwrap();
+        let svg = qr.to_svg();
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 29 29\""));
+        let dark = (0..21)
+            .flat_map(|y| (0..21).map(move |x| (x, y)))
+            .filter(|&(x, y)| qr.is_dark(x, y))
+            .count();
+        assert_eq!(svg.matches("h1v1h-1z").count(), dark);
+        assert!(svg.contains("M4,4h1v1h-1z"));
+    }
+
+    #[test]
+    fn test_to_png() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+
+        let qr = QrCode::encode(&[0xAB; 1000], EccLevel::L).unwrap();
+        let scale = 8;
+        let png = qr.to_png(scale);
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+
+        // Walk the chunks, checking each CRC
+        let mut chunks = Vec::new();
+        let mut rest = &png[8..];
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            let (body, crc) = rest[4..].split_at(4 + len);
+            assert_eq!(crc32(body).to_be_bytes(), crc[..4]);
+            chunks.push((&body[..4], &body[4..]));
+            rest = &crc[4..];
+        }
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[0].0, b"IHDR");
+        let width = (qr.size() + 2 * QUIET_ZONE) * scale;
+        assert_eq!(chunks[0].1[..4], (width as u32).to_be_bytes());
+        assert_eq!(chunks[2], (&b"IEND"[..], &[][..]));
+
+        // Inflate the stored blocks and compare every pixel
+        let zlib = chunks[1].1;
+        assert_eq!(zlib[..2], [0x78, 0x01]);
+        let mut pixels = Vec::new();
+        let mut pos = 2;
+        loop {
+            let last = zlib[pos] == 1;
+            let len = u16::from_le_bytes([zlib[pos + 1], zlib[pos + 2]]) as usize;
+            assert_eq!(
+                u16::from_le_bytes([zlib[pos + 3], zlib[pos + 4]]),
+                !(len as u16)
+            );
+            pixels.extend_from_slice(&zlib[pos + 5..pos + 5 + len]);
+            pos += 5 + len;
+            if last {
+                break;
+            }
+        }
+        assert!(pixels.len() > MAX_STORED_LEN);
+        assert_eq!(zlib[pos..], adler32(&pixels).to_be_bytes());
+
+        let row_len = width.div_ceil(8) + 1;
+        assert_eq!(pixels.len(), row_len * width);
+        for y in 0..width {
+            let row = &pixels[y * row_len..(y + 1) * row_len];
+            assert_eq!(row[0], 0);
+            for x in 0..width {
+                let white = row[1 + x / 8] & (0x80 >> (x % 8)) != 0;
+                let module = (x / scale)
+                    .checked_sub(QUIET_ZONE)
+                    .zip((y / scale).checked_sub(QUIET_ZONE));
+                let dark = module.is_some_and(|(x, y)| qr.is_dark(x, y));
+                assert_eq!(white, !dark);
+            }
+        }
+    }
+}
@@ -0,0 +1,435 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! # QR Scanning
+//!
+//! Reassembles the payloads of scanned QR codes into an encoded descriptor. An app passes each
+//! payload string as its camera reads it, whether a whole encoding in hex or base58check or one
+//! part of a `ur:bytes` sequence, and shows the progress until every part is in.
+//!
+//! UR parts are read as in BCR-2020-005, using minimal bytewords. Only pure fragments, with a
+//! sequence number up to the part count, are used. Fountain-mixed parts are accepted but skipped,
+//! so a sequence must repeat its pure fragments until all are scanned, as [`ur_parts`] produces.
+
+use super::*;
+use bitcoin::{base58, hex::FromHex};
+use qr_code::crc32;
+
+/// The 256 bytewords, each four letters, in byte order
+const BYTEWORDS: &str = concat!(
+    "ableacidalsoapexaquaarchatomauntawayaxisbackbaldbarnbeltbetabiasbluebodybragbrewbulbbuzz",
+    "calmcashcatschefcityclawcodecolacookcostcruxcurlcuspcyandarkdatadaysdelidicedietdoordown",
+    "drawdropdrumdulldutyeacheasyechoedgeepicevenexamexiteyesfactfairfernfigsfilmfishfizzflap",
+    "flewfluxfoxyfreefrogfuelfundgalagamegeargemsgiftgirlglowgoodgraygrimgurugushgyrohalfhang",
+    "hardhawkheathelphighhillholyhopehornhutsicedideaidleinchinkyintoirisironitemjadejazzjoin",
+    "joltjowljudojugsjumpjunkjurykeepkenokeptkeyskickkilnkingkitekiwiknoblamblavalazyleaflegs",
+    "liarlimplionlistlogoloudloveluaulucklungmainmanymathmazememomenumeowmildmintmissmonknail",
+    "navyneednewsnextnoonnotenumbobeyoboeomitonyxopenovalowlspaidpartpeckplaypluspoempoolpose",
+    "puffpumapurrquadquizraceramprealredorichroadrockroofrubyruinrunsrustsafesagascarsetssilk",
+    "skewslotsoapsolosongstubsurfswantacotasktaxitenttiedtimetinytoiltombtoystriptunatwinugly",
+    "undouniturgeuservastveryvetovialvibeviewvisavoidvowswallwandwarmwaspwavewaxywebswhatwhen",
+    "whizwolfworkyankyawnyellyogayurtzapszerozestzinczonezoom",
+);
+
+/// Number of parts and bytes of a scan received and needed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    /// Number of distinct parts received
+    pub received: usize,
+    /// Number of parts needed, or 0 before the first part
+    pub needed: usize,
+    /// Number of message bytes received, for a progress bar that fills by size
+    pub bytes_received: usize,
+    /// Number of message bytes needed, or 0 before the first part
+    pub bytes_needed: usize,
+}
+
+impl Progress {
+    /// Returns whether every part has been received
+    pub fn is_complete(&self) -> bool {
+        self.needed > 0 && self.received == self.needed
+    }
+}
+
+/// Collects scanned QR payloads until an encoded descriptor is complete
+#[derive(Debug, Default, Clone)]
+pub struct Scanner {
+    sequence: Option<Sequence>,
+    bytes: Option<Vec<u8>>,
+}
+
+/// Parts of a multi-part UR received so far
+#[derive(Debug, Clone, PartialEq)]
+struct Sequence {
+    message_len: usize,
+    checksum: u32,
+    fragments: Vec<Option<Vec<u8>>>,
+}
+
+impl Scanner {
+    /// Creates an empty scanner
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the payload of a scanned QR code, returning the progress
+    ///
+    /// Parts may arrive in any order and more than once. Parts after completion are ignored. Fails
+    /// with [`QrError::InvalidPart`] if the payload cannot be read, or [`QrError::PartMismatch`] if
+    /// it belongs to a different sequence than earlier parts.
+    pub fn add_part(&mut self, part: &str) -> Result<Progress, Error> {
+        if self.bytes.is_some() {
+            return Ok(self.progress());
+        }
+
+        // QR alphanumeric mode makes UR parts uppercase
+        let part = part.trim();
+        let ur = part
+            .get(..3)
+            .filter(|prefix| prefix.eq_ignore_ascii_case("ur:"));
+        match ur.map(|_| part[3..].to_ascii_lowercase()) {
+            Some(ur) => self.add_ur(&ur)?,
+            None if self.sequence.is_some() => return Err(Error::Qr(QrError::PartMismatch)),
+            None => {
+                let bytes = Vec::<u8>::from_hex(part)
+                    .or_else(|_| base58::decode_check(part))
+                    .map_err(|_| Error::Qr(QrError::InvalidPart))?;
+                self.bytes = Some(bytes);
+            }
+        }
+
+        Ok(self.progress())
+    }
+
+    /// Returns the number of parts received and needed
+    pub fn progress(&self) -> Progress {
+        match (&self.sequence, &self.bytes) {
+            (Some(sequence), _) => {
+                let fragments = sequence.fragments.iter().flatten();
+                let bytes_received = fragments.clone().map(Vec::len).sum::<usize>();
+                Progress {
+                    received: fragments.count(),
+                    needed: sequence.fragments.len(),
+                    bytes_received: bytes_received.min(sequence.message_len),
+                    bytes_needed: sequence.message_len,
+                }
+            }
+            (None, Some(bytes)) => Progress {
+                received: 1,
+                needed: 1,
+                bytes_received: bytes.len(),
+                bytes_needed: bytes.len(),
+            },
+            (None, None) => Progress {
+                received: 0,
+                needed: 0,
+                bytes_received: 0,
+                bytes_needed: 0,
+            },
+        }
+    }
+
+    /// Returns the encoded descriptor, once every part has been received
+    pub fn bytes(&self) -> Option<&[u8]> {
+        self.bytes.as_deref()
+    }
+
+    /// Decodes the descriptor, once every part has been received
+    pub fn result(&self) -> Option<Result<String, Error>> {
+        self.bytes().map(decode)
+    }
+
+    fn add_ur(&mut self, ur: &str) -> Result<(), Error> {
+        let (kind, rest) = ur.split_once('/').ok_or(Error::Qr(QrError::InvalidPart))?;
+        if kind != "bytes" {
+            return Err(Error::Qr(QrError::InvalidPart));
+        }
+
+        let Some((seq, body)) = rest.split_once('/') else {
+            if self.sequence.is_some() {
+                return Err(Error::Qr(QrError::PartMismatch));
+            }
+            let message = from_bytewords(rest)?;
+            self.bytes = Some(read_cbor_bytes(&message)?);
+            return Ok(());
+        };
+
+        let (seq_num, seq_len) = seq.split_once('-').ok_or(Error::Qr(QrError::InvalidPart))?;
+        let seq_num = usize::from_str(seq_num).map_err(|_| Error::Qr(QrError::InvalidPart))?;
+        let seq_len = usize::from_str(seq_len).map_err(|_| Error::Qr(QrError::InvalidPart))?;
+
+        let body = from_bytewords(body)?;
+        let mut index = 0;
+        if read_cbor_head(&body, &mut index)? != (4, 5) {
+            return Err(Error::Qr(QrError::InvalidPart));
+        }
+        let mut fields = [0u64; 4];
+        for field in &mut fields {
+            *field = read_cbor_uint(&body, &mut index)?;
+        }
+        let fragment = read_cbor_bytes(&body[index..])?;
+        let [num, len, message_len, checksum] = fields;
+        if num as usize != seq_num || len as usize != seq_len || seq_num == 0 || seq_len == 0 {
+            return Err(Error::Qr(QrError::InvalidPart));
+        }
+        // Every fragment holds at least one byte of the message
+        if message_len < len || message_len as usize > len as usize * fragment.len() {
+            return Err(Error::Qr(QrError::InvalidPart));
+        }
+        let checksum = u32::try_from(checksum).map_err(|_| Error::Qr(QrError::InvalidPart))?;
+
+        let sequence = self.sequence.get_or_insert_with(|| Sequence {
+            message_len: message_len as usize,
+            checksum,
+            fragments: vec![None; seq_len],
+        });
+        if sequence.message_len != message_len as usize
+            || sequence.checksum != checksum
+            || sequence.fragments.len() != seq_len
+            || sequence
+                .fragments
+                .iter()
+                .flatten()
+                .any(|other| other.len() != fragment.len())
+        {
+            return Err(Error::Qr(QrError::PartMismatch));
+        }
+
+        // Fountain-mixed parts are skipped
+        if seq_num > seq_len {
+            return Ok(());
+        }
+        sequence.fragments[seq_num - 1] = Some(fragment);
+
+        if sequence.fragments.iter().all(Option::is_some) {
+            let mut message: Vec<u8> = sequence
+                .fragments
+                .iter()
+                .flatten()
+                .flatten()
+                .copied()
+                .collect();
+            message.truncate(sequence.message_len);
+            if crc32(&message) != sequence.checksum {
+                return Err(Error::Qr(QrError::InvalidPart));
+            }
+            self.bytes = Some(read_cbor_bytes(&message)?);
+        }
+        Ok(())
+    }
+}
+
+/// Splits an encoded descriptor into `ur:bytes` parts of at most `max_fragment_len` bytes each
+///
+/// Returns a single part if the encoding fits, else the pure fragments of a multi-part UR, which
+/// should be displayed in a loop. Each part takes two characters per byte, plus framing.
+pub fn ur_parts(bytes: &[u8], max_fragment_len: usize) -> Vec<String> {
+    let mut message = Vec::new();
+    write_cbor_head(2, bytes.len() as u64, &mut message);
+    message.extend_from_slice(bytes);
+
+    let max_fragment_len = max_fragment_len.max(1);
+    if message.len() <= max_fragment_len {
+        return vec![format!("ur:bytes/{}", to_bytewords(&message))];
+    }
+
+    let count = message.len().div_ceil(max_fragment_len);
+    let fragment_len = message.len().div_ceil(count);
+    let checksum = crc32(&message);
+    (0..count)
+        .map(|i| {
+            let start = (i * fragment_len).min(message.len());
+            let end = (start + fragment_len).min(message.len());
+            let mut fragment = message[start..end].to_vec();
+            fragment.resize(fragment_len, 0);
+
+            let mut body = Vec::new();
+            write_cbor_head(4, 5, &mut body);
+            for field in [i + 1, count, message.len(), checksum as usize] {
+                write_cbor_head(0, field as u64, &mut body);
+            }
+            write_cbor_head(2, fragment.len() as u64, &mut body);
+            body.extend(fragment);
+            format!("ur:bytes/{}-{count}/{}", i + 1, to_bytewords(&body))
+        })
+        .collect()
+}
+
+/// Encodes bytes as minimal bytewords, followed by their CRC-32
+fn to_bytewords(bytes: &[u8]) -> String {
+    let checksum = crc32(bytes).to_be_bytes();
+    let mut out = String::with_capacity((bytes.len() + 4) * 2);
+    for &byte in bytes.iter().chain(&checksum) {
+        let word = &BYTEWORDS[byte as usize * 4..byte as usize * 4 + 4];
+        out.push_str(&word[..1]);
+        out.push_str(&word[3..]);
+    }
+    out
+}
+
+/// Decodes minimal bytewords, validating and removing the CRC-32
+fn from_bytewords(s: &str) -> Result<Vec<u8>, Error> {
+    if s.len() % 2 != 0 || !s.is_ascii() {
+        return Err(Error::Qr(QrError::InvalidPart));
+    }
+
+    let mut bytes = Vec::with_capacity(s.len() / 2);
+    for pair in s.as_bytes().chunks(2) {
+        let byte = BYTEWORDS
+            .as_bytes()
+            .chunks(4)
+            .position(|word| word[0] == pair[0] && word[3] == pair[1])
+            .ok_or(Error::Qr(QrError::InvalidPart))?;
+        bytes.push(byte as u8);
+    }
+
+    if bytes.len() < 4 {
+        return Err(Error::Qr(QrError::InvalidPart));
+    }
+    let checksum = bytes.split_off(bytes.len() - 4);
+    if crc32(&bytes).to_be_bytes()[..] != checksum[..] {
+        return Err(Error::Qr(QrError::InvalidPart));
+    }
+    Ok(bytes)
+}
+
+fn write_cbor_head(major: u8, value: u64, out: &mut Vec<u8>) {
+    let major = major << 5;
+    match value {
+        0..24 => out.push(major | value as u8),
+        24..0x100 => out.extend([major | 24, value as u8]),
+        0x100..0x1_0000 => {
+            out.push(major | 25);
+            out.extend((value as u16).to_be_bytes());
+        }
+        0x1_0000..0x1_0000_0000 => {
+            out.push(major | 26);
+            out.extend((value as u32).to_be_bytes());
+        }
+        _ => {
+            out.push(major | 27);
+            out.extend(value.to_be_bytes());
+        }
+    }
+}
+
+/// Reads a CBOR head, returning its major type and argument
+fn read_cbor_head(bytes: &[u8], index: &mut usize) -> Result<(u8, u64), Error> {
+    let initial = *bytes.get(*index).ok_or(Error::Qr(QrError::InvalidPart))?;
+    *index += 1;
+
+    let len = match initial & 0x1F {
+        info @ 0..24 => return Ok((initial >> 5, info as u64)),
+        24 => 1,
+        25 => 2,
+        26 => 4,
+        27 => 8,
+        _ => return Err(Error::Qr(QrError::InvalidPart)),
+    };
+    let arg = bytes
+        .get(*index..*index + len)
+        .ok_or(Error::Qr(QrError::InvalidPart))?;
+    *index += len;
+    Ok((
+        initial >> 5,
+        arg.iter().fold(0, |value, &byte| value << 8 | byte as u64),
+    ))
+}
+
+fn read_cbor_uint(bytes: &[u8], index: &mut usize) -> Result<u64, Error> {
+    match read_cbor_head(bytes, index)? {
+        (0, value) => Ok(value),
+        _ => Err(Error::Qr(QrError::InvalidPart)),
+    }
+}
+
+/// Reads a CBOR byte string filling the rest of `bytes`
+fn read_cbor_bytes(bytes: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut index = 0;
+    match read_cbor_head(bytes, &mut index)? {
+        (2, len) if len == (bytes.len() - index) as u64 => Ok(bytes[index..].to_vec()),
+        _ => Err(Error::Qr(QrError::InvalidPart)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hex::DisplayHex;
+
+    #[test]
+    fn test_bytewords() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(to_bytewords(&[0, 1, 2, 128, 255]), "aeadaolazmjendeoti");
+        assert_eq!(
+            from_bytewords("aeadaolazmjendeoti").unwrap(),
+            vec![0, 1, 2, 128, 255]
+        );
+        assert_eq!(
+            from_bytewords("aeadaolazmjendeota"),
+            Err(Error::Qr(QrError::InvalidPart))
+        );
+    }
+
+    #[test]
+    fn test_scanner() {
+        let desc_str = "wsh(multi(1,03a0434d9e47f3c86235477c7b1ae6ae5d3442d49b1943c2b752a68e2a47e247c7,036d2b085e9e382ed10b69fc311a03f8641ccfff21574de0927513a49d9a688a00))#rd2pu9rk";
+        let encoded = encode(desc_str).unwrap();
+
+        // A whole encoding in one part
+        for part in [
+            encoded.to_lower_hex_string(),
+            base58::encode_check(&encoded),
+            ur_parts(&encoded, 1000)[0].to_uppercase(),
+        ] {
+            let mut scanner = Scanner::new();
+            assert!(!scanner.progress().is_complete());
+            assert!(scanner.add_part(&part).unwrap().is_complete());
+            assert_eq!(scanner.bytes(), Some(&encoded[..]));
+            assert_eq!(scanner.result(), Some(Ok(desc_str.to_string())));
+        }
+
+        // Parts in any order, with repeats
+        let parts = ur_parts(&encoded, 20);
+        assert_eq!(parts.len(), 4);
+        assert!(parts[1].starts_with("ur:bytes/2-4/"));
+        let mut scanner = Scanner::new();
+        for (index, received) in [(2, 1), (0, 2), (2, 2), (3, 3)] {
+            let progress = scanner.add_part(&parts[index]).unwrap();
+            assert_eq!(progress.needed, 4);
+            assert_eq!(progress.received, received);
+            // The message is the encoding behind a two-byte CBOR header, split evenly
+            let message_len = encoded.len() + 2;
+            assert_eq!(progress.bytes_received, received * message_len.div_ceil(4));
+            assert_eq!(progress.bytes_needed, message_len);
+            assert!(scanner.result().is_none());
+        }
+        let progress = scanner.add_part(&parts[1]).unwrap();
+        assert!(progress.is_complete());
+        assert_eq!(progress.bytes_received, progress.bytes_needed);
+        assert_eq!(scanner.result(), Some(Ok(desc_str.to_string())));
+
+        // Parts of another sequence are rejected
+        let other =
+            encode("wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)")
+                .unwrap();
+        let mut scanner = Scanner::new();
+        scanner.add_part(&parts[0]).unwrap();
+        assert_eq!(
+            scanner.add_part(&ur_parts(&other, 20)[0]),
+            Err(Error::Qr(QrError::PartMismatch))
+        );
+        assert_eq!(
+            scanner.add_part(&other.to_lower_hex_string()),
+            Err(Error::Qr(QrError::PartMismatch))
+        );
+
+        assert_eq!(
+            Scanner::new().add_part("ur:crypto-psbt/aeadaolazmjendeoti"),
+            Err(Error::Qr(QrError::InvalidPart))
+        );
+        assert_eq!(
+            Scanner::new().add_part("not a payload"),
+            Err(Error::Qr(QrError::InvalidPart))
+        );
+    }
+}
@@ -0,0 +1,155 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! # Script Derivation
+//!
+//! Derives the output scripts of an encoding, for tools that watch a backup's addresses without
+//! handling its descriptor or keys. Secret keys are first made watch-only, so encodings holding
+//! extended private keys with hardened steps derive as well.
+
+use super::*;
+use bitcoin::{Script, ScriptBuf};
+use miniscript::descriptor::Wildcard;
+use std::ops::Range;
+
+/// Derives the output scripts of an encoding at each index in `range`
+///
+/// A multipath descriptor is split into its single-path descriptors, such as receive and change,
+/// and their scripts are returned in that order. A descriptor without a wildcard has one script,
+/// returned if `range` is not empty. Fails with [`Error::UnderivableKey`] if a key cannot be
+/// derived, such as through a hardened step or wildcard.
+pub fn derive_scripts(bytes: &[u8], range: Range<u32>) -> Result<Vec<ScriptBuf>, Error> {
+    let watch_only = derive_watch_only(bytes)?;
+    let (descriptor, _) = Codec::new().decode_to_descriptor(&watch_only)?;
+    let singles = descriptor
+        .into_single_descriptors()
+        .map_err(|_| Error::UnderivableKey)?;
+
+    // Miniscript panics deriving an xpub through a hardened step or wildcard, so refuse it up front
+    let hardened = |key: &DescriptorPublicKey| match key {
+        DescriptorPublicKey::XPub(xpub) => {
+            xpub.wildcard == Wildcard::Hardened
+                || xpub
+                    .derivation_path
+                    .into_iter()
+                    .any(|step| step.is_hardened())
+        }
+        _ => false,
+    };
+    if singles.iter().any(|single| single.for_any_key(hardened)) {
+        return Err(Error::UnderivableKey);
+    }
+
+    let mut scripts = Vec::new();
+    for single in singles {
+        let indices = match single.has_wildcard() {
+            true => range.clone(),
+            false => range.start..range.end.min(range.start.saturating_add(1)),
+        };
+        for index in indices {
+            let derived = single
+                .at_derivation_index(index)
+                .map_err(|_| Error::UnderivableKey)?;
+            scripts.push(derived.script_pubkey());
+        }
+    }
+    Ok(scripts)
+}
+
+/// Returns the Electrum script hash of a script, the reversed SHA256 hash in hex
+pub fn electrum_script_hash(script: &Script) -> String {
+    let mut hash = sha256::Hash::hash(script.as_bytes()).to_byte_array();
+    hash.reverse();
+    hash.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Returns the Electrum script hashes of an encoding's scripts at each index in `range`
+///
+/// Monitoring services can pass these to `blockchain.scripthash.subscribe` without handling the
+/// descriptor. See [`derive_scripts`] for the order.
+pub fn electrum_script_hashes(bytes: &[u8], range: Range<u32>) -> Result<Vec<String>, Error> {
+    Ok(derive_scripts(bytes, range)?
+        .iter()
+        .map(|script| electrum_script_hash(script))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::{Address, Network};
+
+    #[test]
+    fn test_derive_scripts() {
+        // BIP 84 test vectors
+        let xpub = "[73c5da0a/84'/0'/0']xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V";
+        let encoded = encode(&format!("wpkh({xpub}/<0;1>/*)")).unwrap();
+        let scripts = derive_scripts(&encoded, 0..2).unwrap();
+        let addresses: Vec<_> = scripts
+            .iter()
+            .map(|script| {
+                Address::from_script(script, Network::Bitcoin)
+                    .unwrap()
+                    .to_string()
+            })
+            .collect();
+        assert_eq!(
+            addresses,
+            [
+                "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu",
+                "bc1qnjg0jd8228aq7egyzacy8cys3knf9xvrerkf9g",
+                "bc1q8c6fshw2dlwun7ekn9qwf37cu2rn755upcp6el",
+                "bc1qggnasd834t54yulsep6fta8lpjekv4zj6gv5rf",
+            ]
+        );
+
+        // The same scripts derive from the private key
+        let xprv = "[73c5da0a]xprv9s21ZrQH143K3GJpoapnV8SFfukcVBSfeCficPSGfubmSFDxo1kuHnLisriDvSnRRuL2Qrg5ggqHKNVpxR86QEC8w35uxmGoggxtQTPvfUu/84'/0'/0'";
+        let encoded = encode(&format!("wpkh({xprv}/<0;1>/*)")).unwrap();
+        assert_eq!(derive_scripts(&encoded, 0..2).unwrap(), scripts);
+
+        let single =
+            encode("wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)")
+                .unwrap();
+        assert_eq!(derive_scripts(&single, 5..10).unwrap().len(), 1);
+        assert!(derive_scripts(&single, 0..0).unwrap().is_empty());
+
+        let hardened = encode(&format!("wpkh({xpub}/0/*h)")).unwrap();
+        assert_eq!(derive_scripts(&hardened, 0..1), Err(Error::UnderivableKey));
+    }
+
+    #[test]
+    fn test_derive_scripts_hardened_step() {
+        let xpub = "xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V";
+        for desc_str in [
+            format!("wpkh({xpub}/1'/*)"),
+            format!("wpkh({xpub}/<0;1>/1h/*)"),
+            format!("wpkh({xpub}/0/1')"),
+        ] {
+            let encoded = encode(&desc_str).unwrap();
+            assert_eq!(derive_scripts(&encoded, 0..2), Err(Error::UnderivableKey));
+            assert_eq!(
+                electrum_script_hashes(&encoded, 0..2),
+                Err(Error::UnderivableKey)
+            );
+        }
+    }
+    #[test]
+    fn test_electrum_script_hashes() {
+        // Example from the Electrum protocol documentation
+        let p2pkh =
+            ScriptBuf::from_hex("76a91462e907b15cbf27d5425399ebf6f0fb50ebb88f1888ac").unwrap();
+        assert_eq!(
+            electrum_script_hash(&p2pkh),
+            "8b01df4e368ea28f8dc0423bcf7a4923e3a12d307c875e47a0cfbf90b5c39161"
+        );
+
+        let xpub = "xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V";
+        let encoded = encode(&format!("wpkh({xpub}/0/*)")).unwrap();
+        let hashes = electrum_script_hashes(&encoded, 10..15).unwrap();
+        assert_eq!(hashes.len(), 5);
+        assert_eq!(
+            hashes[0],
+            electrum_script_hash(&derive_scripts(&encoded, 10..11).unwrap()[0])
+        );
+    }
+}
@@ -0,0 +1,26 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! # Secret Redaction
+//!
+//! Types in this crate that can hold secret keys redact them when formatted, so accidental
+//! logging does not leak them. Wrap a value in [`RevealSecrets`] to display it in full.
+
+use miniscript::descriptor::DescriptorSecretKey;
+use std::str::FromStr;
+
+/// Text shown in place of a secret key
+pub const REDACTED: &str = "<redacted>";
+
+/// Displays a value including its secret keys
+#[derive(Clone, Copy)]
+pub struct RevealSecrets<'a, T: ?Sized>(pub &'a T);
+
+/// Returns whether a key string holds a secret key
+pub(crate) fn is_secret(key: &str) -> bool {
+    DescriptorSecretKey::from_str(key).is_ok()
+}
+
+/// Returns a key string, or [`REDACTED`] if it holds a secret key
+pub(crate) fn redact(key: &str) -> &str {
+    if is_secret(key) { REDACTED } else { key }
+}
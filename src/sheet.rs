@@ -0,0 +1,249 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! # Recovery Sheets
+//!
+//! Lays out a printable recovery document for an encoded descriptor: QR codes of the encoding,
+//! a base32 transcription of it, the key fingerprints, a summary of the spending policy, and
+//! restore instructions. Everything is derived from the encoded bytes alone.
+
+use super::*;
+use bitcoin::bip32::Fingerprint;
+use decoder::DecodeContext;
+use miniscript::ForEachKey;
+use qr::{EccLevel, MAX_STATIC_PARTS, MAX_STATIC_VERSION};
+use std::fmt;
+
+/// Number of base32 characters per group on a sheet
+const GROUP_LEN: usize = 4;
+
+/// Number of base32 groups per line on a sheet
+const GROUPS_PER_LINE: usize = 8;
+
+/// A printable recovery document for an encoded descriptor
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecoverySheet {
+    /// QR codes of the `ur:bytes` parts of the encoding, or none if it needs more than
+    /// [`MAX_STATIC_PARTS`] codes
+    pub qr_codes: Vec<QrCode>,
+    /// Encoding as base32, for manual transcription
+    pub text: String,
+    /// Master fingerprint of each key, in order of appearance
+    pub fingerprints: Vec<Fingerprint>,
+    /// Descriptor with each key replaced by `@` and its index, without checksum
+    pub policy: String,
+    /// Whether the encoding holds secret keys
+    pub has_secrets: bool,
+}
+
+impl fmt::Display for RecoverySheet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "DESCRIPTOR RECOVERY SHEET")?;
+        writeln!(f)?;
+        if self.has_secrets {
+            writeln!(
+                f,
+                "WARNING: this sheet holds secret keys. Anyone who reads it can spend the funds."
+            )?;
+            writeln!(f)?;
+        }
+
+        writeln!(f, "Policy: {}", self.policy)?;
+        writeln!(f)?;
+        writeln!(f, "Keys:")?;
+        for (index, fingerprint) in self.fingerprints.iter().enumerate() {
+            writeln!(f, "  @{index}  fingerprint {fingerprint}")?;
+        }
+        writeln!(f)?;
+
+        let count = self.qr_codes.len();
+        for (index, qr_code) in self.qr_codes.iter().enumerate() {
+            writeln!(f, "QR code {} of {count}:", index + 1)?;
+            write!(f, "{}", qr_code.to_ascii())?;
+            writeln!(f)?;
+        }
+        if count == 0 {
+            writeln!(
+                f,
+                "QR code: too long for a printed sheet, use the text below"
+            )?;
+            writeln!(f)?;
+        }
+
+        writeln!(f, "Text (base32, {} characters):", self.text.len())?;
+        let groups: Vec<&str> = self
+            .text
+            .as_bytes()
+            .chunks(GROUP_LEN)
+            .map(|group| std::str::from_utf8(group).expect("base32 is ascii"))
+            .collect();
+        for (line, groups) in groups.chunks(GROUPS_PER_LINE).enumerate() {
+            writeln!(f, "  {:>3}: {}", line + 1, groups.join(" "))?;
+        }
+        writeln!(f)?;
+
+        writeln!(f, "To restore:")?;
+        writeln!(
+            f,
+            "  1. Scan every QR code, in any order, or type the text without its line numbers.\n     Case and spaces in the text are ignored."
+        )?;
+        writeln!(
+            f,
+            "  2. Decode it, such as with `descriptor-codec decode --format base32 \"TEXT\"`."
+        )?;
+        writeln!(
+            f,
+            "  3. Check that the key fingerprints match your signers, then import the descriptor."
+        )
+    }
+}
+
+/// Builds a recovery sheet for an encoded descriptor
+pub fn recovery_sheet(bytes: &[u8]) -> Result<RecoverySheet, Error> {
+    let mut ctx = DecodeContext::default();
+    let descriptor = decoder::decode_bytes_in(bytes, &mut ctx)?;
+    let (descriptor, _) =
+        decoder::derive_public_keys(&descriptor, &ctx.key_map, &secp256k1::Secp256k1::new())?;
+
+    let mut keys = Vec::new();
+    descriptor.for_each_key(|key| {
+        keys.push(key.clone());
+        true
+    });
+
+    Ok(RecoverySheet {
+        qr_codes: qr_codes(bytes),
+        text: base32(bytes),
+        fingerprints: keys.iter().map(|key| key.master_fingerprint()).collect(),
+        policy: match ctx.raw_tr {
+            true => "rawtr(@0)".to_string(),
+            false => policy(&descriptor, &keys),
+        },
+        has_secrets: !ctx.key_map.is_empty(),
+    })
+}
+
+/// Returns QR codes of the `ur:bytes` parts of `bytes`, or none if there would be too many
+fn qr_codes(bytes: &[u8]) -> Vec<QrCode> {
+    // Each byte takes two characters, and the framing of each part fits in 32 bytes
+    let max_fragment_len = qr::capacity(MAX_STATIC_VERSION, EccLevel::M) / 2 - 32;
+    let parts = ur_parts(bytes, max_fragment_len);
+    if parts.len() > MAX_STATIC_PARTS {
+        return Vec::new();
+    }
+
+    parts
+        .iter()
+        .filter_map(|part| match qr_capacity_report(part.len()) {
+            QrPlan::Single { ecc, .. } => QrCode::encode(part.as_bytes(), ecc),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Returns the descriptor with each key replaced by `@` and its index in `keys`
+fn policy(descriptor: &Descriptor<DescriptorPublicKey>, keys: &[DescriptorPublicKey]) -> String {
+    struct Placeholders<'a>(&'a [DescriptorPublicKey]);
+
+    impl Translator<DescriptorPublicKey, String, ()> for Placeholders<'_> {
+        fn pk(&mut self, pk: &DescriptorPublicKey) -> Result<String, ()> {
+            let index = self.0.iter().position(|key| key == pk).ok_or(())?;
+            Ok(format!("@{index}"))
+        }
+
+        fn sha256(&mut self, hash: &sha256::Hash) -> Result<String, ()> {
+            Ok(hash.to_string())
+        }
+
+        fn hash256(&mut self, hash: &hash256::Hash) -> Result<String, ()> {
+            Ok(hash.to_string())
+        }
+
+        fn ripemd160(&mut self, hash: &ripemd160::Hash) -> Result<String, ()> {
+            Ok(hash.to_string())
+        }
+
+        fn hash160(&mut self, hash: &hash160::Hash) -> Result<String, ()> {
+            Ok(hash.to_string())
+        }
+    }
+
+    let policy = descriptor
+        .translate_pk(&mut Placeholders(keys))
+        .expect("every key was collected");
+    format!("{policy:#}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recovery_sheet() {
+        let desc_str = "wsh(multi(2,[73c5da0a/48'/0'/0'/2']xpub6DkFAXWQ2dHxq2vatrt9qyA3bXYU4ToWQwCHbf5XB2mSTexcHZCeKS1VZYcPoBd5X8yVcbXFHJR9R8UCVpt82VX1VhR28mCyxUFL4r6KFrf/0/*,[b2f1b1a3/48'/0'/0'/2']xpub6FC1fXFP1GXQpyRFfSE1vzzySqs3Vg63bzimYLeqtNUYbzA87kMNTcuy9ubr7MmavGRjW2FRYHP4WGKjwutbf1ghgkUW9H7e3ceaPLRcVwa/0/*))";
+        let encoded = encode(desc_str).unwrap();
+        let sheet = recovery_sheet(&encoded).unwrap();
+
+        assert_eq!(sheet.policy, "wsh(multi(2,@0,@1))");
+        assert_eq!(
+            sheet.fingerprints,
+            vec![
+                "73c5da0a".parse::<Fingerprint>().unwrap(),
+                "b2f1b1a3".parse::<Fingerprint>().unwrap()
+            ]
+        );
+        assert_eq!(parse_base32(&sheet.text).unwrap(), encoded);
+        assert!(!sheet.has_secrets);
+        assert_eq!(sheet.qr_codes.len(), 1);
+
+        let printed = sheet.to_string();
+        assert!(printed.contains("Policy: wsh(multi(2,@0,@1))"));
+        assert!(printed.contains("@1  fingerprint b2f1b1a3"));
+        assert!(printed.contains("QR code 1 of 1:"));
+        assert!(!printed.contains("WARNING"));
+
+        // The transcribed text decodes once the line numbers are removed
+        let text: String = printed
+            .lines()
+            .skip_while(|line| !line.starts_with("Text"))
+            .skip(1)
+            .take_while(|line| !line.is_empty())
+            .map(|line| line.split_once(": ").unwrap().1)
+            .collect();
+        assert_eq!(
+            decode(&parse_base32(&text).unwrap()).unwrap(),
+            decode(&encoded).unwrap()
+        );
+
+        // Repeated keys share an index, and secret keys are flagged
+        let wif = "L4rK1yDtCWekvXuE6oXD9jCYfFNV2cWRpVuPLBcCU2z8TrisoyY1";
+        let encoded = encode(&format!(
+            "wsh(or_d(pk({wif}),and_v(v:pk({wif}),older(144))))"
+        ))
+        .unwrap();
+        let sheet = recovery_sheet(&encoded).unwrap();
+        assert_eq!(sheet.policy, "wsh(or_d(pk(@0),and_v(v:pk(@0),older(144))))");
+        assert!(sheet.has_secrets);
+        assert!(sheet.to_string().contains("WARNING"));
+        assert!(!sheet.to_string().contains(wif));
+
+        let encoded =
+            encode("rawtr(03a0434d9e47f3c86235477c7b1ae6ae5d3442d49b1943c2b752a68e2a47e247c7)")
+                .unwrap();
+        assert_eq!(recovery_sheet(&encoded).unwrap().policy, "rawtr(@0)");
+
+        // Long encodings are split over several codes, and very long ones get none
+        let mut scanner = Scanner::new();
+        let encoded: Vec<u8> = (0..800).map(|i| i as u8).collect();
+        let parts = ur_parts(
+            &encoded,
+            qr::capacity(MAX_STATIC_VERSION, EccLevel::M) / 2 - 32,
+        );
+        assert_eq!(qr_codes(&encoded).len(), parts.len());
+        assert!(parts.len() > 1);
+        for part in &parts {
+            scanner.add_part(part).unwrap();
+        }
+        assert_eq!(scanner.bytes(), Some(&encoded[..]));
+        assert!(qr_codes(&[0; 2000]).is_empty());
+    }
+}
@@ -0,0 +1,468 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! # Streaming Transports
+//!
+//! Converts between encoded bytes and a text transport over [`io::Read`] and [`io::Write`], a
+//! chunk at a time, so large bundles are never held in memory in both binary and text form.
+//!
+//! Bech32 text uses the `desc` human-readable part and the bech32m checksum, without the length
+//! limit of addresses. Text is written without whitespace, and whitespace is ignored when reading.
+
+use std::io::{self, Read, Write};
+
+/// Human-readable part of bech32 text
+pub const BECH32_HRP: &str = "desc";
+
+/// Number of bytes read from the input at a time
+const CHUNK_LEN: usize = 8192;
+
+/// Characters of the lowercase hex alphabet
+const HEX_ALPHABET: &[u8; 16] = b"0123456789abcdef";
+
+/// Characters of the RFC 4648 base64 alphabet
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Characters of the bech32 alphabet
+const BECH32_ALPHABET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+/// Number of checksum characters ending bech32 text
+const BECH32_CHECKSUM_LEN: usize = 6;
+
+/// Constant the bech32m checksum is combined with
+const BECH32M_CONST: u32 = 0x2bc8_30a3;
+
+/// A text transport for encoded bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    /// Lowercase hexadecimal
+    Hex,
+    /// RFC 4648 base64 with padding
+    Base64,
+    /// Bech32 with the bech32m checksum and the [`BECH32_HRP`] prefix
+    Bech32,
+}
+
+impl Transport {
+    /// Returns the number of bits each character holds
+    fn bits(self) -> u32 {
+        match self {
+            Self::Hex => 4,
+            Self::Base64 => 6,
+            Self::Bech32 => 5,
+        }
+    }
+
+    /// Returns the character of a value
+    fn char(self, value: u32) -> u8 {
+        let value = value as usize;
+        match self {
+            Self::Hex => HEX_ALPHABET[value],
+            Self::Base64 => BASE64_ALPHABET[value],
+            Self::Bech32 => BECH32_ALPHABET[value],
+        }
+    }
+
+    /// Returns the value of a character, ignoring case except in base64
+    fn value(self, c: u8) -> Option<u32> {
+        let (alphabet, c): (&[u8], u8) = match self {
+            Self::Hex => (HEX_ALPHABET, c.to_ascii_lowercase()),
+            Self::Base64 => (BASE64_ALPHABET, c),
+            Self::Bech32 => (BECH32_ALPHABET, c.to_ascii_lowercase()),
+        };
+        alphabet.iter().position(|&a| a == c).map(|v| v as u32)
+    }
+}
+
+/// Reads bytes from `reader` and writes them to `writer` as text, returning the bytes read
+pub fn encode_stream<R: Read, W: Write>(
+    mut reader: R,
+    mut writer: W,
+    transport: Transport,
+) -> io::Result<u64> {
+    let mut encoder = Encoder::new(transport);
+    let mut chunk = vec![0; CHUNK_LEN];
+    let mut text = Vec::with_capacity(CHUNK_LEN * 2 + BECH32_HRP.len() + 1);
+    if transport == Transport::Bech32 {
+        text.extend(BECH32_HRP.bytes());
+        text.push(b'1');
+    }
+
+    let mut total = 0;
+    loop {
+        let len = match reader.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        total += len as u64;
+        chunk[..len]
+            .iter()
+            .for_each(|&byte| encoder.push(byte, &mut text));
+        writer.write_all(&text)?;
+        text.clear();
+    }
+    encoder.finish(&mut text);
+    writer.write_all(&text)?;
+    writer.flush()?;
+
+    Ok(total)
+}
+
+/// Reads text from `reader` and writes the bytes it holds to `writer`, returning the bytes written
+///
+/// Fails with [`io::ErrorKind::InvalidData`] on an invalid character, padding, or checksum, after
+/// writing the bytes before it.
+pub fn decode_stream<R: Read, W: Write>(
+    mut reader: R,
+    mut writer: W,
+    transport: Transport,
+) -> io::Result<u64> {
+    let mut decoder = Decoder::new(transport);
+    let mut chunk = vec![0; CHUNK_LEN];
+    let mut bytes = Vec::with_capacity(CHUNK_LEN);
+
+    let mut total = 0;
+    loop {
+        let len = match reader.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        let result = chunk[..len]
+            .iter()
+            .try_for_each(|&c| decoder.push(c, &mut bytes));
+        total += bytes.len() as u64;
+        writer.write_all(&bytes)?;
+        bytes.clear();
+        result?;
+    }
+    decoder.finish()?;
+    writer.flush()?;
+
+    Ok(total)
+}
+
+/// Converts bytes to characters as they arrive
+struct Encoder {
+    transport: Transport,
+    acc: u32,
+    bits: u32,
+    len: u64,
+    checksum: u32,
+}
+
+impl Encoder {
+    fn new(transport: Transport) -> Self {
+        Self {
+            transport,
+            acc: 0,
+            bits: 0,
+            len: 0,
+            checksum: bech32_hrp_checksum(),
+        }
+    }
+
+    fn push(&mut self, byte: u8, out: &mut Vec<u8>) {
+        self.len += 1;
+        self.acc = (self.acc << 8) | byte as u32;
+        self.bits += 8;
+        let char_bits = self.transport.bits();
+        while self.bits >= char_bits {
+            self.bits -= char_bits;
+            self.push_value((self.acc >> self.bits) & ((1 << char_bits) - 1), out);
+        }
+        self.acc &= (1 << self.bits) - 1;
+    }
+
+    fn push_value(&mut self, value: u32, out: &mut Vec<u8>) {
+        if self.transport == Transport::Bech32 {
+            self.checksum = bech32_polymod(self.checksum, value);
+        }
+        out.push(self.transport.char(value));
+    }
+
+    fn finish(mut self, out: &mut Vec<u8>) {
+        let char_bits = self.transport.bits();
+        if self.bits > 0 {
+            self.push_value(
+                (self.acc << (char_bits - self.bits)) & ((1 << char_bits) - 1),
+                out,
+            );
+        }
+        match self.transport {
+            Transport::Hex => {}
+            Transport::Base64 => {
+                let padding = (3 - self.len % 3) % 3;
+                out.extend((0..padding).map(|_| b'='));
+            }
+            Transport::Bech32 => {
+                let checksum = (0..BECH32_CHECKSUM_LEN)
+                    .fold(self.checksum, |checksum, _| bech32_polymod(checksum, 0))
+                    ^ BECH32M_CONST;
+                out.extend(
+                    (0..BECH32_CHECKSUM_LEN)
+                        .rev()
+                        .map(|i| self.transport.char((checksum >> (5 * i)) & 31)),
+                );
+            }
+        }
+    }
+}
+
+/// Converts characters to bytes as they arrive
+struct Decoder {
+    transport: Transport,
+    acc: u32,
+    bits: u32,
+    /// Number of characters read, for error positions
+    position: usize,
+    /// Number of characters of the bech32 prefix still expected
+    prefix_left: usize,
+    /// Whether letters are uppercase, once one has been read
+    uppercase: Option<bool>,
+    /// Number of base64 padding characters read
+    padding: usize,
+    /// Number of base64 characters read, excluding padding
+    len: usize,
+    checksum: u32,
+    /// Last bech32 values read, which are held back until known not to be the checksum
+    held: [u32; BECH32_CHECKSUM_LEN],
+    held_len: usize,
+}
+
+impl Decoder {
+    fn new(transport: Transport) -> Self {
+        Self {
+            transport,
+            acc: 0,
+            bits: 0,
+            position: 0,
+            prefix_left: match transport {
+                Transport::Bech32 => BECH32_HRP.len() + 1,
+                _ => 0,
+            },
+            uppercase: None,
+            padding: 0,
+            len: 0,
+            checksum: bech32_hrp_checksum(),
+            held: [0; BECH32_CHECKSUM_LEN],
+            held_len: 0,
+        }
+    }
+
+    fn push(&mut self, c: u8, out: &mut Vec<u8>) -> io::Result<()> {
+        if c.is_ascii_whitespace() {
+            return Ok(());
+        }
+        let position = self.position;
+        self.position += 1;
+        let invalid = || invalid_data(format!("invalid character (position: {position})"));
+
+        if self.transport == Transport::Bech32 && c.is_ascii_alphabetic() {
+            let uppercase = c.is_ascii_uppercase();
+            if *self.uppercase.get_or_insert(uppercase) != uppercase {
+                return Err(invalid_data(format!("mixed case (position: {position})")));
+            }
+        }
+        if self.prefix_left > 0 {
+            let prefix = [BECH32_HRP.as_bytes(), b"1"].concat();
+            if c.to_ascii_lowercase() != prefix[prefix.len() - self.prefix_left] {
+                return Err(invalid());
+            }
+            self.prefix_left -= 1;
+            return Ok(());
+        }
+
+        if self.transport == Transport::Base64 && c == b'=' {
+            self.padding += 1;
+            return match self.padding <= 2 {
+                true => Ok(()),
+                false => Err(invalid()),
+            };
+        }
+        if self.padding > 0 {
+            return Err(invalid());
+        }
+        let value = self.transport.value(c).ok_or_else(invalid)?;
+        self.len += 1;
+
+        if self.transport != Transport::Bech32 {
+            self.push_value(value, out);
+            return Ok(());
+        }
+        self.checksum = bech32_polymod(self.checksum, value);
+        if self.held_len < BECH32_CHECKSUM_LEN {
+            self.held[self.held_len] = value;
+            self.held_len += 1;
+            return Ok(());
+        }
+        let value = std::mem::replace(&mut self.held[(self.len - 1) % BECH32_CHECKSUM_LEN], value);
+        self.push_value(value, out);
+        Ok(())
+    }
+
+    fn push_value(&mut self, value: u32, out: &mut Vec<u8>) {
+        self.acc = (self.acc << self.transport.bits()) | value;
+        self.bits += self.transport.bits();
+        if self.bits >= 8 {
+            self.bits -= 8;
+            out.push((self.acc >> self.bits) as u8);
+            self.acc &= (1 << self.bits) - 1;
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self.transport {
+            Transport::Bech32 if self.prefix_left > 0 || self.held_len < BECH32_CHECKSUM_LEN => {
+                return Err(invalid_data("missing bech32 checksum".to_string()));
+            }
+            Transport::Bech32 if self.checksum != BECH32M_CONST => {
+                return Err(invalid_data("invalid bech32 checksum".to_string()));
+            }
+            Transport::Base64 if self.padding > 0 && (self.len + self.padding) % 4 != 0 => {
+                return Err(invalid_data("invalid base64 padding".to_string()));
+            }
+            _ => {}
+        }
+
+        // Leftover bits must be padding, shorter than a character and set to zero
+        if self.bits >= self.transport.bits() || self.acc != 0 {
+            return Err(invalid_data("invalid padding bits".to_string()));
+        }
+        Ok(())
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Returns the bech32 checksum state after the expanded [`BECH32_HRP`]
+fn bech32_hrp_checksum() -> u32 {
+    let hrp = BECH32_HRP.bytes();
+    let high = hrp.clone().map(|c| c as u32 >> 5);
+    let low = hrp.map(|c| c as u32 & 31);
+    high.chain([0]).chain(low).fold(1, bech32_polymod)
+}
+
+/// Advances the bech32 checksum state by one value
+fn bech32_polymod(checksum: u32, value: u32) -> u32 {
+    const GENERATOR: [u32; 5] = [
+        0x3b6a_57b2,
+        0x2650_8e6d,
+        0x1ea1_19fa,
+        0x3d42_33dd,
+        0x2a14_62b3,
+    ];
+    let top = checksum >> 25;
+    let mut checksum = ((checksum & 0x1ff_ffff) << 5) ^ value;
+    for (i, generator) in GENERATOR.iter().enumerate() {
+        if (top >> i) & 1 == 1 {
+            checksum ^= generator;
+        }
+    }
+    checksum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::bech32::{self, Bech32m, Hrp};
+
+    fn encode_to_string(bytes: &[u8], transport: Transport) -> String {
+        let mut text = Vec::new();
+        assert_eq!(
+            encode_stream(bytes, &mut text, transport).unwrap(),
+            bytes.len() as u64
+        );
+        String::from_utf8(text).unwrap()
+    }
+
+    fn decode_from_str(text: &str, transport: Transport) -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        let len = decode_stream(text.as_bytes(), &mut bytes, transport)?;
+        assert_eq!(len, bytes.len() as u64);
+        Ok(bytes)
+    }
+
+    #[test]
+    fn test_stream() {
+        // RFC 4648 test vectors
+        let vectors = [
+            ("", "", ""),
+            ("f", "66", "Zg=="),
+            ("fo", "666f", "Zm8="),
+            ("foo", "666f6f", "Zm9v"),
+            ("foob", "666f6f62", "Zm9vYg=="),
+            ("fooba", "666f6f6261", "Zm9vYmE="),
+            ("foobar", "666f6f626172", "Zm9vYmFy"),
+        ];
+        for (bytes, hex, base64) in vectors {
+            assert_eq!(encode_to_string(bytes.as_bytes(), Transport::Hex), hex);
+            assert_eq!(
+                encode_to_string(bytes.as_bytes(), Transport::Base64),
+                base64
+            );
+            assert_eq!(
+                decode_from_str(hex, Transport::Hex).unwrap(),
+                bytes.as_bytes()
+            );
+            assert_eq!(
+                decode_from_str(base64, Transport::Base64).unwrap(),
+                bytes.as_bytes()
+            );
+
+            let hrp = Hrp::parse(BECH32_HRP).unwrap();
+            let bech32 = bech32::encode::<Bech32m>(hrp, bytes.as_bytes()).unwrap();
+            assert_eq!(
+                encode_to_string(bytes.as_bytes(), Transport::Bech32),
+                bech32
+            );
+            assert_eq!(
+                decode_from_str(&bech32.to_uppercase(), Transport::Bech32).unwrap(),
+                bytes.as_bytes()
+            );
+        }
+
+        // Inputs longer than a chunk round-trip, ignoring whitespace
+        let bytes: Vec<u8> = (0..CHUNK_LEN * 3 + 7).map(|i| (i * 7) as u8).collect();
+        for transport in [Transport::Hex, Transport::Base64, Transport::Bech32] {
+            let text = encode_to_string(&bytes, transport);
+            let wrapped: Vec<&str> = text
+                .as_bytes()
+                .chunks(76)
+                .map(|line| std::str::from_utf8(line).unwrap())
+                .collect();
+            assert_eq!(
+                decode_from_str(&wrapped.join("\n"), transport).unwrap(),
+                bytes
+            );
+        }
+
+        let invalid = |text: &str, transport| {
+            decode_from_str(text, transport).unwrap_err().kind() == io::ErrorKind::InvalidData
+        };
+        assert!(invalid("666", Transport::Hex));
+        assert!(invalid("66g6", Transport::Hex));
+        assert!(invalid("Zg=", Transport::Base64));
+        assert!(invalid("Zh==", Transport::Base64));
+        assert!(invalid("Zg==Zg==", Transport::Base64));
+        assert!(invalid("Z", Transport::Base64));
+        let bech32 = encode_to_string(b"foobar", Transport::Bech32);
+        let (body, last) = bech32.split_at(bech32.len() - 1);
+        let other = if last == "q" { "p" } else { "q" };
+        assert!(invalid(&format!("{body}{other}"), Transport::Bech32));
+        assert!(invalid(
+            &format!("{}{last}", body.to_uppercase()),
+            Transport::Bech32
+        ));
+        assert!(invalid(
+            &bech32.replacen("desc", "bc", 1),
+            Transport::Bech32
+        ));
+        assert!(invalid("desc1qqqq", Transport::Bech32));
+    }
+}
@@ -0,0 +1,148 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! # Support Matrix
+//!
+//! Lists every descriptor wrapper, miniscript fragment, and key type this version of the library
+//! can encode, so applications can gate features in their UI without trial encodings.
+
+use super::tag::Tag;
+
+/// Kind of element described by a [`FragmentInfo`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FragmentKind {
+    /// A top-level descriptor or descriptor-level wrapper, such as `wsh` or `sortedmulti`
+    Descriptor,
+    /// A miniscript fragment, such as `and_v` or `older`
+    Fragment,
+    /// A miniscript wrapper, such as `v:`
+    Wrapper,
+    /// A key type
+    Key,
+}
+
+/// How an element is written in the template
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FragmentEncoding {
+    /// The element has its own tag
+    Tag(u8),
+    /// The element is syntactic sugar, encoded as the given expansion
+    Expansion(&'static str),
+}
+
+/// An element the library can encode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FragmentInfo {
+    /// Name as written in a descriptor string, or a short description for key types
+    pub name: &'static str,
+    /// Kind of element
+    pub kind: FragmentKind,
+    /// How the element is encoded
+    pub encoding: FragmentEncoding,
+}
+
+const fn tagged(kind: FragmentKind, tag: Tag, name: &'static str) -> FragmentInfo {
+    FragmentInfo {
+        name,
+        kind,
+        encoding: FragmentEncoding::Tag(tag as u8),
+    }
+}
+
+const fn sugar(kind: FragmentKind, name: &'static str, expansion: &'static str) -> FragmentInfo {
+    FragmentInfo {
+        name,
+        kind,
+        encoding: FragmentEncoding::Expansion(expansion),
+    }
+}
+
+use FragmentKind::{Descriptor, Fragment, Key, Wrapper};
+
+static FRAGMENTS: [FragmentInfo; 51] = [
+    tagged(Descriptor, Tag::Sh, "sh"),
+    tagged(Descriptor, Tag::Wsh, "wsh"),
+    tagged(Descriptor, Tag::Wpkh, "wpkh"),
+    tagged(Descriptor, Tag::Pkh, "pkh"),
+    tagged(Descriptor, Tag::Tr, "tr"),
+    tagged(Descriptor, Tag::RawTr, "rawtr"),
+    tagged(Descriptor, Tag::Bare, "bare"),
+    tagged(Descriptor, Tag::SortedMulti, "sortedmulti"),
+    tagged(Fragment, Tag::False, "0"),
+    tagged(Fragment, Tag::True, "1"),
+    tagged(Fragment, Tag::PkK, "pk_k"),
+    tagged(Fragment, Tag::PkH, "pk_h"),
+    tagged(Fragment, Tag::RawPkH, "expr_raw_pkh"),
+    sugar(Fragment, "pk", "c:pk_k(KEY)"),
+    sugar(Fragment, "pkh", "c:pk_h(KEY)"),
+    tagged(Fragment, Tag::After, "after"),
+    tagged(Fragment, Tag::Older, "older"),
+    tagged(Fragment, Tag::Sha256, "sha256"),
+    tagged(Fragment, Tag::Hash256, "hash256"),
+    tagged(Fragment, Tag::Ripemd160, "ripemd160"),
+    tagged(Fragment, Tag::Hash160, "hash160"),
+    tagged(Fragment, Tag::AndV, "and_v"),
+    tagged(Fragment, Tag::AndB, "and_b"),
+    sugar(Fragment, "and_n", "andor(X,Y,0)"),
+    tagged(Fragment, Tag::AndOr, "andor"),
+    tagged(Fragment, Tag::OrB, "or_b"),
+    tagged(Fragment, Tag::OrC, "or_c"),
+    tagged(Fragment, Tag::OrD, "or_d"),
+    tagged(Fragment, Tag::OrI, "or_i"),
+    tagged(Fragment, Tag::Thresh, "thresh"),
+    tagged(Fragment, Tag::Multi, "multi"),
+    tagged(Fragment, Tag::MultiA, "multi_a"),
+    tagged(Wrapper, Tag::Alt, "a:"),
+    tagged(Wrapper, Tag::Swap, "s:"),
+    tagged(Wrapper, Tag::Check, "c:"),
+    tagged(Wrapper, Tag::DupIf, "d:"),
+    tagged(Wrapper, Tag::Verify, "v:"),
+    tagged(Wrapper, Tag::NonZero, "j:"),
+    tagged(Wrapper, Tag::ZeroNotEqual, "n:"),
+    sugar(Wrapper, "t:", "and_v(X,1)"),
+    sugar(Wrapper, "l:", "or_i(0,X)"),
+    sugar(Wrapper, "u:", "or_i(X,0)"),
+    tagged(Key, Tag::CompressedFullKey, "compressed key"),
+    tagged(Key, Tag::UncompressedFullKey, "uncompressed key"),
+    tagged(Key, Tag::XOnly, "x-only key"),
+    tagged(Key, Tag::XPub, "xpub"),
+    tagged(Key, Tag::MultiXPub, "multipath xpub"),
+    tagged(Key, Tag::CompressedSinglePriv, "compressed secret key"),
+    tagged(Key, Tag::UncompressedSinglePriv, "uncompressed secret key"),
+    tagged(Key, Tag::XPriv, "xpriv"),
+    tagged(Key, Tag::MultiXPriv, "multipath xpriv"),
+];
+
+/// Returns every descriptor wrapper, miniscript fragment, wrapper, and key type this version can
+/// encode
+pub fn supported_fragments() -> &'static [FragmentInfo] {
+    &FRAGMENTS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_supported_fragments() {
+        let mut seen = std::collections::HashSet::new();
+        for info in supported_fragments() {
+            assert!(
+                seen.insert((info.kind, info.name)),
+                "duplicate {}",
+                info.name
+            );
+            if let FragmentEncoding::Tag(value) = info.encoding {
+                assert_eq!(Tag::from(value).name(), info.name);
+            }
+        }
+
+        let tags: std::collections::HashSet<_> = supported_fragments()
+            .iter()
+            .filter_map(|info| match info.encoding {
+                FragmentEncoding::Tag(value) => Some(value),
+                FragmentEncoding::Expansion(_) => None,
+            })
+            .collect();
+        assert_eq!(tags.len(), 45);
+    }
+}
@@ -51,6 +51,12 @@ pub enum Tag {
     NoWildcard = 0x2F,
     UnhardenedWildcard = 0x30,
     HardenedWildcard = 0x31,
+    SingleSigWpkh = 0x32,
+    Preset = 0x33,
+    PayloadCommitment = 0x34,
+    Metadata = 0x35,
+    RawTr = 0x36,
+    HashRef = 0x37,
     Unrecognized,
 }
 
@@ -59,15 +65,145 @@ impl Tag {
         *self as u8
     }
 
-    #[allow(unsafe_code)]
     pub fn from(value: u8) -> Self {
-        match value {
-            0x00..=0x31 => unsafe { std::mem::transmute::<u8, Tag>(value) },
-            _ => Tag::Unrecognized,
+        TAGS[value as usize]
+    }
+
+    /// Returns the name of the fragment, key part, or header the tag introduces
+    pub fn name(&self) -> &'static str {
+        match self {
+            Tag::False => "0",
+            Tag::True => "1",
+            Tag::Pkh => "pkh",
+            Tag::Sh => "sh",
+            Tag::Wpkh => "wpkh",
+            Tag::Wsh => "wsh",
+            Tag::Tr => "tr",
+            Tag::Bare => "bare",
+            Tag::TapTree => "taptree",
+            Tag::SortedMulti => "sortedmulti",
+            Tag::Alt => "a:",
+            Tag::Swap => "s:",
+            Tag::Check => "c:",
+            Tag::DupIf => "d:",
+            Tag::Verify => "v:",
+            Tag::NonZero => "j:",
+            Tag::ZeroNotEqual => "n:",
+            Tag::AndV => "and_v",
+            Tag::AndB => "and_b",
+            Tag::AndOr => "andor",
+            Tag::OrB => "or_b",
+            Tag::OrC => "or_c",
+            Tag::OrD => "or_d",
+            Tag::OrI => "or_i",
+            Tag::Thresh => "thresh",
+            Tag::Multi => "multi",
+            Tag::MultiA => "multi_a",
+            Tag::PkK => "pk_k",
+            Tag::PkH => "pk_h",
+            Tag::RawPkH => "expr_raw_pkh",
+            Tag::After => "after",
+            Tag::Older => "older",
+            Tag::Sha256 => "sha256",
+            Tag::Hash256 => "hash256",
+            Tag::Ripemd160 => "ripemd160",
+            Tag::Hash160 => "hash160",
+            Tag::Origin => "origin",
+            Tag::NoOrigin => "no origin",
+            Tag::UncompressedFullKey => "uncompressed key",
+            Tag::CompressedFullKey => "compressed key",
+            Tag::XOnly => "x-only key",
+            Tag::XPub => "xpub",
+            Tag::MultiXPub => "multipath xpub",
+            Tag::UncompressedSinglePriv => "uncompressed secret key",
+            Tag::CompressedSinglePriv => "compressed secret key",
+            Tag::XPriv => "xpriv",
+            Tag::MultiXPriv => "multipath xpriv",
+            Tag::NoWildcard => "no wildcard",
+            Tag::UnhardenedWildcard => "wildcard",
+            Tag::HardenedWildcard => "hardened wildcard",
+            Tag::SingleSigWpkh => "single-sig wpkh",
+            Tag::Preset => "preset",
+            Tag::PayloadCommitment => "payload commitment",
+            Tag::Metadata => "metadata",
+            Tag::RawTr => "rawtr",
+            Tag::HashRef => "hash ref",
+            Tag::Unrecognized => "unrecognized",
         }
     }
 }
 
+/// Every recognized tag, in order of value
+const ALL: [Tag; 56] = [
+    Tag::False,
+    Tag::True,
+    Tag::Pkh,
+    Tag::Sh,
+    Tag::Wpkh,
+    Tag::Wsh,
+    Tag::Tr,
+    Tag::Bare,
+    Tag::TapTree,
+    Tag::SortedMulti,
+    Tag::Alt,
+    Tag::Swap,
+    Tag::Check,
+    Tag::DupIf,
+    Tag::Verify,
+    Tag::NonZero,
+    Tag::ZeroNotEqual,
+    Tag::AndV,
+    Tag::AndB,
+    Tag::AndOr,
+    Tag::OrB,
+    Tag::OrC,
+    Tag::OrD,
+    Tag::OrI,
+    Tag::Thresh,
+    Tag::Multi,
+    Tag::MultiA,
+    Tag::PkK,
+    Tag::PkH,
+    Tag::RawPkH,
+    Tag::After,
+    Tag::Older,
+    Tag::Sha256,
+    Tag::Hash256,
+    Tag::Ripemd160,
+    Tag::Hash160,
+    Tag::Origin,
+    Tag::NoOrigin,
+    Tag::UncompressedFullKey,
+    Tag::CompressedFullKey,
+    Tag::XOnly,
+    Tag::XPub,
+    Tag::MultiXPub,
+    Tag::UncompressedSinglePriv,
+    Tag::CompressedSinglePriv,
+    Tag::XPriv,
+    Tag::MultiXPriv,
+    Tag::NoWildcard,
+    Tag::UnhardenedWildcard,
+    Tag::HardenedWildcard,
+    Tag::SingleSigWpkh,
+    Tag::Preset,
+    Tag::PayloadCommitment,
+    Tag::Metadata,
+    Tag::RawTr,
+    Tag::HashRef,
+];
+
+/// Lookup table from byte to tag
+const TAGS: [Tag; 256] = {
+    let mut table = [Tag::Unrecognized; 256];
+    let mut i = 0;
+    while i < ALL.len() {
+        table[ALL[i] as usize] = ALL[i];
+        i += 1;
+    }
+    table
+};
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -124,6 +260,12 @@ mod tests {
         assert_eq!(Tag::NoWildcard.value(), 0x2F);
         assert_eq!(Tag::UnhardenedWildcard.value(), 0x30);
         assert_eq!(Tag::HardenedWildcard.value(), 0x31);
+        assert_eq!(Tag::SingleSigWpkh.value(), 0x32);
+        assert_eq!(Tag::Preset.value(), 0x33);
+        assert_eq!(Tag::PayloadCommitment.value(), 0x34);
+        assert_eq!(Tag::Metadata.value(), 0x35);
+        assert_eq!(Tag::RawTr.value(), 0x36);
+        assert_eq!(Tag::HashRef.value(), 0x37);
     }
 
     #[test]
@@ -178,11 +320,17 @@ mod tests {
         assert_eq!(Tag::NoWildcard, Tag::from(0x2F));
         assert_eq!(Tag::UnhardenedWildcard, Tag::from(0x30));
         assert_eq!(Tag::HardenedWildcard, Tag::from(0x31));
+        assert_eq!(Tag::SingleSigWpkh, Tag::from(0x32));
+        assert_eq!(Tag::Preset, Tag::from(0x33));
+        assert_eq!(Tag::PayloadCommitment, Tag::from(0x34));
+        assert_eq!(Tag::Metadata, Tag::from(0x35));
+        assert_eq!(Tag::RawTr, Tag::from(0x36));
+        assert_eq!(Tag::HashRef, Tag::from(0x37));
     }
 
     #[test]
     fn test_unrecognized() {
-        for i in 0x32..=0xFF {
+        for i in 0x38..=0xFF {
             assert_eq!(Tag::Unrecognized, Tag::from(i));
         }
     }
@@ -0,0 +1,119 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! # Descriptor Templates
+//!
+//! Fills a descriptor template such as `wsh(multi(2,$a,$b,$c))` with named keys, so integrators
+//! do not splice keys into descriptors with string formatting. A name is `$` followed by letters,
+//! digits, and underscores. Every name must be bound, every binding must be used, and every bound
+//! value must parse as a public or secret descriptor key.
+
+use super::*;
+use std::borrow::Borrow;
+use std::collections::BTreeSet;
+
+/// Replaces each `$name` in a descriptor template with the key bound to `name`
+///
+/// Fails with [`UnboundName`](encoder::Error::UnboundName) if the template uses a name that is
+/// not bound, [`UnusedBinding`](encoder::Error::UnusedBinding) if a binding is not used, and
+/// [`InvalidKey`](encoder::Error::InvalidKey) if a bound value is not a key.
+pub fn fill_template<K, V>(
+    template: &str,
+    bindings: &BTreeMap<K, V>,
+) -> Result<String, encoder::Error>
+where
+    K: Borrow<str> + Ord,
+    V: AsRef<str>,
+{
+    for value in bindings.values() {
+        let value = value.as_ref();
+        if DescriptorSecretKey::from_str(value).is_err() {
+            DescriptorPublicKey::from_str(value)?;
+        }
+    }
+
+    let mut used = BTreeSet::new();
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('$') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let len = after
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(after.len());
+        let name = &after[..len];
+        let value = bindings
+            .get(name)
+            .ok_or_else(|| encoder::Error::UnboundName(name.to_string()))?;
+        out.push_str(value.as_ref());
+        used.insert(name);
+        rest = &after[len..];
+    }
+    out.push_str(rest);
+
+    if let Some(name) = bindings
+        .keys()
+        .map(Borrow::borrow)
+        .find(|name| !used.contains(name))
+    {
+        return Err(encoder::Error::UnusedBinding(name.to_string()));
+    }
+
+    Ok(out)
+}
+
+/// Fills a descriptor template with named keys and encodes it
+///
+/// Returns the filled descriptor along with its encoding. See [`fill_template`].
+pub fn encode_template_str<K, V>(
+    template: &str,
+    bindings: &BTreeMap<K, V>,
+) -> Result<(String, Vec<u8>), encoder::Error>
+where
+    K: Borrow<str> + Ord,
+    V: AsRef<str>,
+{
+    let descriptor = fill_template(template, bindings)?;
+    let encoded = encode(&descriptor)?;
+    Ok((descriptor, encoded))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_template_str() {
+        let a = "[73c5da0a/48'/0'/0'/2']xpub6DkFAXWQ2dHxq2vatrt9qyA3bXYU4ToWQwCHbf5XB2mSTexcHZCeKS1VZYcPoBd5X8yVcbXFHJR9R8UCVpt82VX1VhR28mCyxUFL4r6KFrf/0/*";
+        let b = "03a0434d9e47f3c86235477c7b1ae6ae5d3442d49b1943c2b752a68e2a47e247c7";
+        let c = "L4rK1yDtCWekvXuE6oXD9jCYfFNV2cWRpVuPLBcCU2z8TrisoyY1";
+        let bindings = BTreeMap::from([("a", a), ("b", b), ("key_c", c)]);
+
+        let (descriptor, encoded) =
+            encode_template_str("wsh(multi(2,$a,$b,$key_c))", &bindings).unwrap();
+        assert_eq!(descriptor, format!("wsh(multi(2,{a},{b},{c}))"));
+        assert_eq!(encoded, encode(&descriptor).unwrap());
+
+        // Owned strings work as well
+        let owned: BTreeMap<String, String> = bindings
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        assert_eq!(
+            fill_template("tr($b,{pk($a),and_v(v:pk($key_c),older(144))})", &owned).unwrap(),
+            format!("tr({b},{{pk({a}),and_v(v:pk({c}),older(144))}})")
+        );
+
+        assert_eq!(
+            fill_template("wsh(multi(2,$a,$b,$d))", &bindings),
+            Err(encoder::Error::UnboundName("d".to_string()))
+        );
+        assert_eq!(
+            fill_template("wsh(multi(1,$a,$b))", &bindings),
+            Err(encoder::Error::UnusedBinding("key_c".to_string()))
+        );
+        assert!(matches!(
+            fill_template("wpkh($a)", &BTreeMap::from([("a", "notakey")])),
+            Err(encoder::Error::InvalidKey(_))
+        ));
+    }
+}
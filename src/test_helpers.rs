@@ -1,35 +1,29 @@
 use crate::dummy;
-#[cfg(test)]
-use crate::encoder::EncodeTemplate;
+use crate::encoder::{EncodeContext, EncodeTemplate};
 use bitcoin::PublicKey;
-#[cfg(test)]
 use bitcoin::{
     NetworkKind, PrivateKey, XOnlyPublicKey,
     bip32::{DerivationPath, Fingerprint, Xpriv, Xpub},
 };
-#[cfg(test)]
 use miniscript::descriptor::{
     DerivPaths, DescriptorMultiXKey, DescriptorSecretKey, DescriptorXKey, KeyMap, SinglePriv,
     Wildcard,
 };
 use miniscript::descriptor::{DescriptorPublicKey, SinglePub, SinglePubKey};
-#[cfg(test)]
 use std::str::FromStr;
 
 // Helper to create a DerivationPath from a string
-#[cfg(test)]
 pub(crate) fn dp_from_str(s: &str) -> DerivationPath {
     DerivationPath::from_str(s).unwrap()
 }
 
 // Helper to create a Fingerprint from a hex string
-#[cfg(test)]
 pub(crate) fn fp_from_str(s: &str) -> Fingerprint {
     Fingerprint::from_hex(s).unwrap()
 }
 
 // Helper to create a simple DescriptorPublicKey (Single, FullKey, Compressed, No Origin)
-pub fn create_dpk_single_compressed_no_origin(index: u32) -> DescriptorPublicKey {
+pub(crate) fn create_dpk_single_compressed_no_origin(index: u32) -> DescriptorPublicKey {
     let pk = PublicKey {
         inner: dummy::pk_at_index(index),
         compressed: true,
@@ -41,7 +35,6 @@ pub fn create_dpk_single_compressed_no_origin(index: u32) -> DescriptorPublicKey
 }
 
 // Helper to create an XOnly DescriptorPublicKey
-#[cfg(test)]
 pub(crate) fn create_dpk_xonly_no_origin(index: u32) -> (XOnlyPublicKey, DescriptorPublicKey) {
     let xonly_pk = XOnlyPublicKey::from(dummy::pk_at_index(index));
     let dpk = DescriptorPublicKey::Single(SinglePub {
@@ -53,7 +46,6 @@ pub(crate) fn create_dpk_xonly_no_origin(index: u32) -> (XOnlyPublicKey, Descrip
 }
 
 // Helper to generate a DescriptorPublicKey::Single(FullKey)
-#[cfg(test)]
 pub(crate) fn create_dpk_single_full(
     compressed: bool,
     origin: Option<(Fingerprint, DerivationPath)>,
@@ -72,7 +64,6 @@ pub(crate) fn create_dpk_single_full(
 }
 
 // Helper to generate a DescriptorPublicKey::XPub
-#[cfg(test)]
 pub(crate) fn create_dpk_xpub(
     origin: Option<(Fingerprint, DerivationPath)>,
     xpub_derivation_path_str: &str,
@@ -90,7 +81,6 @@ pub(crate) fn create_dpk_xpub(
 }
 
 // Helper to generate a DescriptorPublicKey::MultiXPub
-#[cfg(test)]
 pub(crate) fn create_dpk_multixpub(
     origin: Option<(Fingerprint, DerivationPath)>,
     xpub_derivation_paths_str: &[&str],
@@ -112,7 +102,6 @@ pub(crate) fn create_dpk_multixpub(
 }
 
 // Helper to generate a DescriptorSecretKey::Single
-#[cfg(test)]
 pub(crate) fn create_dsk_single(
     compressed: bool,
     origin: Option<(Fingerprint, DerivationPath)>,
@@ -130,7 +119,6 @@ pub(crate) fn create_dsk_single(
 }
 
 // Helper to generate a DescriptorSecretKey::XPub
-#[cfg(test)]
 pub(crate) fn create_dsk_xpriv(
     origin: Option<(Fingerprint, DerivationPath)>,
     xpriv_derivation_paths_str: &str,
@@ -148,7 +136,6 @@ pub(crate) fn create_dsk_xpriv(
 }
 
 // Helper to generate a DescriptorSecretKey::MultiXPub
-#[cfg(test)]
 pub(crate) fn create_dsk_multixpriv(
     origin: Option<(Fingerprint, DerivationPath)>,
     xpriv_derivation_paths_str: &[&str],
@@ -170,19 +157,25 @@ pub(crate) fn create_dsk_multixpriv(
 }
 
 /// Helper to convert any EncodeTemplate to template bytes
-#[cfg(test)]
 pub(crate) fn template_of<T: EncodeTemplate>(t: T) -> Vec<u8> {
     let mut template = Vec::new();
     let mut payload = Vec::new();
-    t.encode_template(&mut template, &mut payload, &KeyMap::new());
+    t.encode_template(
+        &mut template,
+        &mut payload,
+        &EncodeContext::new(&KeyMap::new()),
+    );
     template
 }
 
 /// Helper to convert any EncodeTemplate to payload bytes
-#[cfg(test)]
 pub(crate) fn payload_of<T: EncodeTemplate>(t: T) -> Vec<u8> {
     let mut template = Vec::new();
     let mut payload = Vec::new();
-    t.encode_template(&mut template, &mut payload, &KeyMap::new());
+    t.encode_template(
+        &mut template,
+        &mut payload,
+        &EncodeContext::new(&KeyMap::new()),
+    );
     payload
 }
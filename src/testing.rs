@@ -0,0 +1,713 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! # Property Testing
+//!
+//! Generates random descriptors with random key types and checks that they survive an encode
+//! and decode round trip. Wallets can run the same suite in their CI against their integration
+//! code.
+
+use super::*;
+use bitcoin::{
+    NetworkKind, PrivateKey,
+    bip32::{DerivationPath, Xpriv, Xpub},
+    secp256k1::{All, Secp256k1, SecretKey},
+};
+use std::path::Path;
+use std::{error, fmt, fs, io};
+
+/// Error found while checking a descriptor round trip
+#[derive(Debug, PartialEq)]
+pub enum RoundTripError {
+    /// Descriptor could not be encoded
+    Encode(encoder::Error),
+    /// Encoding could not be decoded
+    Decode(Error),
+    /// Decoded string differs from the original
+    Mismatch {
+        /// Original descriptor
+        expected: String,
+        /// Decoded descriptor, without its checksum
+        found: String,
+    },
+    /// Decoded descriptor does not parse to the same miniscript as the original
+    NotEquivalent,
+}
+
+impl fmt::Display for RoundTripError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Encode(_) => write!(f, "failed to encode"),
+            Self::Decode(_) => write!(f, "failed to decode"),
+            Self::Mismatch { expected, found } => {
+                write!(
+                    f,
+                    "round trip mismatch (expected {expected}, found {found})"
+                )
+            }
+            Self::NotEquivalent => write!(f, "decoded descriptor is not equivalent"),
+        }
+    }
+}
+
+impl error::Error for RoundTripError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Encode(err) => Some(err),
+            Self::Decode(err) => Some(err),
+            Self::Mismatch { .. } | Self::NotEquivalent => None,
+        }
+    }
+}
+
+/// Checks that `decode(encode(descriptor))` returns `descriptor` with its checksum, and that
+/// both parse to the same miniscript
+///
+/// `descriptor` must be in the canonical form printed by miniscript, without a checksum.
+pub fn check_round_trip(descriptor: &str) -> Result<(), RoundTripError> {
+    let encoded = encode(descriptor).map_err(RoundTripError::Encode)?;
+    let decoded = decode(&encoded).map_err(RoundTripError::Decode)?;
+
+    let found = decoded.split('#').next().unwrap_or_default();
+    if found != descriptor {
+        return Err(RoundTripError::Mismatch {
+            expected: descriptor.to_string(),
+            found: found.to_string(),
+        });
+    }
+
+    let expected = Descriptor::<String>::from_str(descriptor);
+    let found = Descriptor::<String>::from_str(&decoded);
+    match (expected, found) {
+        (Ok(expected), Ok(found)) if expected == found => Ok(()),
+        _ => Err(RoundTripError::NotEquivalent),
+    }
+}
+
+/// Checks the round trip of `cases` random descriptors generated from `seed`
+///
+/// Returns the first descriptor that fails along with its error.
+pub fn run(seed: u64, cases: usize) -> Result<(), (String, RoundTripError)> {
+    DescriptorGenerator::new(seed)
+        .take(cases)
+        .try_for_each(|descriptor| check_round_trip(&descriptor).map_err(|e| (descriptor, e)))
+}
+
+/// Returns valid encodings and their decoded descriptors, exercising every tag
+///
+/// Starts with hand-picked descriptors covering every tag other than `RawPkH`, which no
+/// descriptor string produces, and `Preset`, which needs a registry. These are followed by
+/// `random` descriptors from a [`DescriptorGenerator`] with a fixed seed.
+pub fn corpus(random: usize) -> Vec<(Vec<u8>, String)> {
+    corpus_with_options(random)
+        .into_iter()
+        .map(|(encoded, decoded, _)| (encoded, decoded))
+        .collect()
+}
+
+/// Options a [`corpus`] entry is encoded with
+enum CorpusOptions {
+    Default,
+    Commitment,
+    Metadata(Vec<Tlv>),
+}
+
+impl CorpusOptions {
+    fn encode(&self, descriptor: &str) -> Result<Vec<u8>, encoder::Error> {
+        match self {
+            Self::Default => encode(descriptor),
+            Self::Commitment => Codec::new()
+                .with_payload_commitment(true)
+                .encode(descriptor),
+            Self::Metadata(records) => encode_with_metadata(descriptor, records),
+        }
+    }
+}
+
+/// Returns the [`corpus`] with the options each entry was encoded with
+fn corpus_with_options(random: usize) -> Vec<(Vec<u8>, String, CorpusOptions)> {
+    let secp = Secp256k1::new();
+    let sk = |i: u32| dummy::sk_at_index(i + 1);
+    let pk = |i: u32| sk(i).public_key(&secp).to_string();
+    let xonly = |i: u32| sk(i).x_only_public_key(&secp).0.to_string();
+    let xpriv = |i: u8| Xpriv::new_master(NetworkKind::Main, &[i; 32]).expect("valid seed");
+    let xpub = |i: u8| Xpub::from_priv(&secp, &xpriv(i)).to_string();
+    let wif = |i: u32, compressed: bool| {
+        let mut key = PrivateKey::new(sk(i), NetworkKind::Main);
+        key.compressed = compressed;
+        key.to_wif()
+    };
+    let uncompressed = bitcoin::PublicKey::new_uncompressed(sk(9).public_key(&secp));
+    let h32 = "01".repeat(32);
+    let h20 = "02".repeat(20);
+
+    let descriptors = [
+        format!("pkh({})", pk(0)),
+        format!("sh(wpkh({}))", pk(0)),
+        format!("wsh(sortedmulti(2,{},{},{}))", pk(0), pk(1), pk(2)),
+        format!(
+            "tr({},{{pk({}),multi_a(1,{},{})}})",
+            xonly(0),
+            xonly(1),
+            xonly(2),
+            xonly(3)
+        ),
+        format!("pk({uncompressed})"),
+        format!("wsh(or_d(pk({}),and_v(v:pkh({}),older(144))))", pk(0), pk(1)),
+        format!(
+            "wsh(andor(pk({}),after(1000),or_i(pk({}),pk({}))))",
+            pk(0),
+            pk(1),
+            pk(2)
+        ),
+        format!("wsh(and_b(pk({}),s:pk({})))", pk(0), pk(1)),
+        format!("wsh(or_b(pk({}),a:pk({})))", pk(0), pk(1)),
+        format!("wsh(and_v(or_c(pk({}),v:pk({})),pk({})))", pk(0), pk(1), pk(2)),
+        format!(
+            "wsh(thresh(2,pk({}),s:pk({}),sln:older(10)))",
+            pk(0),
+            pk(1)
+        ),
+        format!(
+            "wsh(and_v(v:pk({}),or_d(j:pk({}),dv:older(1))))",
+            pk(0),
+            pk(1)
+        ),
+        format!("wsh(and_v(v:pk({}),and_v(v:pk({}),1)))", pk(0), pk(1)),
+        format!(
+            "wsh(and_v(v:pk({}),and_v(v:sha256({h32}),and_v(v:hash256({h32}),and_v(v:ripemd160({h20}),hash160({h20}))))))",
+            pk(0)
+        ),
+        format!(
+            "wsh(multi(2,[00112233/48'/0'/0'/2']{}/0/*,{}/<0;1>/*,{}/7))",
+            xpub(1),
+            xpub(2),
+            xpub(3)
+        ),
+        format!(
+            "wsh(multi(1,{}/<0;1>/*,{},{}/1/*h))",
+            xpriv(4),
+            wif(0, true),
+            xpriv(5)
+        ),
+        format!("sh(multi(1,{}))", wif(1, false)),
+        "wpkh([e2867bb6/84'/1'/0']tpubDDPRy5xWxJTuVmsh7YRzK8o2EdMWgn4t41fTLxXRgyRN7EKvN2L8BKCFC1gUfPu8Xp6rr667Yc26zrXsiBZsgBc8dQiYnhPNk2Q7CsBrer5/<0;1>/*)".to_string(),
+    ];
+
+    let label = Tlv {
+        kind: 2,
+        value: b"corpus".to_vec(),
+    };
+    let mut entries: Vec<_> = descriptors
+        .iter()
+        .map(|s| (s.clone(), CorpusOptions::Default))
+        .collect();
+    entries.push((descriptors[0].clone(), CorpusOptions::Commitment));
+    entries.push((descriptors[0].clone(), CorpusOptions::Metadata(vec![label])));
+    entries.extend(
+        DescriptorGenerator::new(0)
+            .take(random)
+            .map(|s| (s, CorpusOptions::Default)),
+    );
+
+    entries
+        .into_iter()
+        .map(|(descriptor, options)| {
+            let encoded = options.encode(&descriptor).expect("valid descriptor");
+            let decoded = decode(&encoded).expect("valid encoding");
+            (encoded, decoded, options)
+        })
+        .collect()
+}
+
+/// Named descriptor benchmarked by this crate
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scenario {
+    /// Short name, for reports
+    pub name: &'static str,
+    /// Descriptor in canonical form, without a checksum
+    pub descriptor: String,
+}
+
+/// Returns the descriptors used by this crate's benchmarks
+///
+/// Integrations can benchmark their own code on the same inputs: a single-sig wallet, a 2-of-3
+/// multisig, a 3-of-5 multisig with a timelocked recovery key, and a 100-key `multi_a`.
+pub fn scenarios() -> Vec<Scenario> {
+    let secp = Secp256k1::new();
+    let account = |i: u8, path: &str| {
+        let master = Xpriv::new_master(NetworkKind::Main, &[i; 32]).expect("valid seed");
+        let path = DerivationPath::from_str(path).expect("valid path");
+        let xpriv = master.derive_priv(&secp, &path).expect("valid path");
+        let xpub = Xpub::from_priv(&secp, &xpriv);
+        format!("[{}/{path}]{xpub}/<0;1>/*", master.fingerprint(&secp))
+    };
+    let cosigner = |i: u8| account(i, "48'/0'/0'/2'");
+    let xonly = |i: u32| {
+        dummy::sk_at_index(i + 1)
+            .x_only_public_key(&secp)
+            .0
+            .to_string()
+    };
+
+    let multi_a: Vec<_> = (1..=100).map(xonly).collect();
+
+    vec![
+        Scenario {
+            name: "single-sig",
+            descriptor: format!("wpkh({})", account(0, "84'/0'/0'")),
+        },
+        Scenario {
+            name: "2-of-3",
+            descriptor: format!(
+                "wsh(sortedmulti(2,{},{},{}))",
+                cosigner(1),
+                cosigner(2),
+                cosigner(3)
+            ),
+        },
+        Scenario {
+            name: "3-of-5-timelock",
+            descriptor: format!(
+                "wsh(or_d(multi(3,{},{},{},{},{}),and_v(v:pkh({}),older(52560))))",
+                cosigner(1),
+                cosigner(2),
+                cosigner(3),
+                cosigner(4),
+                cosigner(5),
+                cosigner(6)
+            ),
+        },
+        Scenario {
+            name: "multi_a-100",
+            descriptor: format!("tr({},multi_a(67,{}))", xonly(0), multi_a.join(",")),
+        },
+    ]
+}
+
+/// Number of random descriptors in the corpus checked by [`determinism_check`]
+const DETERMINISM_RANDOM: usize = 12;
+
+/// SHA256 hashes of the encodings of the [`corpus`] with [`DETERMINISM_RANDOM`] random descriptors,
+/// produced on x86-64
+const DETERMINISM_REFERENCE: [&str; 32] = [
+    "b6391258f1fdc816a56c590d362b5415c2154aa6a818e561db96d72e53015893",
+    "934f96ad44ada2b01eedceef10e7fc6dff8b3bf601c61720a6f093526c3689c6",
+    "e2f5f8d2da7e46046d6befc15be41f4b4acf9cd97810a06dfc6b0e0a43ea3b71",
+    "790f26e12036519829bdb30f88d1850ddee4f3908298ed6b30e1f2336a1ebf9b",
+    "5326f1c2906a76c498d8732845e93a7610a3b3450e724b14c68e6c08e681714a",
+    "8d35c6ea5d0ded1324bb5a6690519e6e5f74d8ee293109c23b99a0be31dc0739",
+    "26a6649dcff1a3c8a4cce623391824206060bd7ae794ba60d2f822df0cd2b228",
+    "1f735c05439eb0d7bdc82d8e539fec46e5c977f6949123fc478ae9f63adc83ac",
+    "b538ebc5f3216801a0d8107560d38b66054b771335c86ea3a6a43e401f54c1c2",
+    "5600517d59e1ec793b145dfe42817704fab3fcc8cec1cf92d3fadda415e65f87",
+    "9b063983faa8c265e11d30856118cb2660267c116c1db18804fcc94b1fb0b6e2",
+    "4009484c67c8d0f096af18dfc2cc98a50e3cc22ffe19dad195edfc5ea8355e52",
+    "f5161a7857f69ce908626c73eda17f87f85aeca6e3997e2eff2fb2a69a664315",
+    "7ee18a452b2a24c9f779f8f3d7d0d1e9a0c613864eb6d2537ffe0b35968445c4",
+    "d7d571d8d0cc35f86297442f43529cbc7dafc52446f8a902e9588b5d1d7f1e3e",
+    "d3cd983073a08cec7b749c4942d361a0bebe407a964950a9d064ab80c81d51c6",
+    "6fb5b58678727e7506ea2e898f0c6c3fd3ca8134920d6030cd9e614e14b29a6f",
+    "e4bb94be05ae5c6be7c395ddd765ae8da5d57cec12d33a0cba62bd02b96a972b",
+    "bdfe69575babeea386dfa584c4fab12691ea074e2a441b8f32b19eec170ea1e1",
+    "2579d2cd68e8866ce46e1784d961abf265c0f2c0b9f62e5def7441e5802aba84",
+    "e3ab16f33b413c2af3994306efc4b887c429653a90d9cd6fb31bfd88ce253a88",
+    "76fd3c50fb5c53be973363e16eed6cc6563c7d2454387d18252dd80a22497b4d",
+    "01b34676d32de37101acf89bfd00789cb5420f7a54707dc0fccee8d48e0426d6",
+    "b770ca2a649a0b845adf6ba349bed880294a9bee84ae83444a1d514f6a2dc6f4",
+    "897fee119ffbf03ffa0e8b26a08e6ee733d84382a077ad03ffa8557fe9bc6d23",
+    "6f05ca5f1822c7e351467a71a411ed92d1d0a34ac667459a5ceb1bef868e4293",
+    "b89c5fe34c4f9ac51ce9da2d35ebb1aebde8563035a0201530f86bc15aedbf99",
+    "2762377f69ba5ea8a10d0981f50ce3d1764e5ab5a676b74f0fe55775062bb083",
+    "1ab3fe522a3eb9f6726102f07168c9e18f8f0322d257304c348d60fd0fc22b98",
+    "4b7c37280ea4f89f628d1ab266e141235832f6a736d4bccc5b36d52afcb6fd81",
+    "2d95a467d2621deaad2c6c23f01fa7c0eed04790471f9a127966f87a3d843860",
+    "81d27423953684e942612eecb5cc8909b996e5bba73d514d4cb43c12de617ef2",
+];
+
+/// Error found while checking that encoding is deterministic
+#[derive(Debug, PartialEq)]
+pub enum DeterminismError {
+    /// Corpus descriptor could not be encoded, by corpus index
+    Encode(usize, encoder::Error),
+    /// Encoding differs from the reference, by corpus index
+    Mismatch {
+        /// Index of the descriptor in the corpus
+        index: usize,
+        /// Descriptor whose encoding differs
+        descriptor: String,
+    },
+}
+
+impl fmt::Display for DeterminismError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Encode(index, _) => write!(f, "failed to encode corpus entry {index}"),
+            Self::Mismatch { index, descriptor } => write!(
+                f,
+                "encoding of corpus entry {index} differs from the reference ({descriptor})"
+            ),
+        }
+    }
+}
+
+impl error::Error for DeterminismError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Encode(_, err) => Some(err),
+            Self::Mismatch { .. } => None,
+        }
+    }
+}
+
+/// Checks that this platform encodes a fixed corpus byte for byte like the reference platform
+///
+/// Each descriptor of the [`corpus`] is encoded again and the hash of its encoding compared with
+/// an embedded reference, so integrators on other targets, such as wasm32 or armv7, can confirm
+/// their encodings match. Returns the first descriptor that differs.
+pub fn determinism_check() -> Result<(), DeterminismError> {
+    check_against(&DETERMINISM_REFERENCE)
+}
+
+/// Compares the corpus encodings with reference hashes in hex
+///
+/// Each decoded descriptor is encoded again with the options of its entry, so entries with a
+/// payload commitment or metadata are checked as such.
+fn check_against(reference: &[&str]) -> Result<(), DeterminismError> {
+    let corpus = corpus_with_options(DETERMINISM_RANDOM);
+    for (index, (_, descriptor, options)) in corpus.into_iter().enumerate() {
+        let encoded = options
+            .encode(&descriptor)
+            .map_err(|e| DeterminismError::Encode(index, e))?;
+        let hash = sha256::Hash::hash(&encoded).to_string();
+        if reference.get(index) != Some(&hash.as_str()) {
+            return Err(DeterminismError::Mismatch { index, descriptor });
+        }
+    }
+    Ok(())
+}
+
+/// Writes the [`corpus`] to `dir` for differential fuzzing against other implementations
+///
+/// Each entry is written as `NNNN.bin`, holding the encoding, and `NNNN.txt`, holding the
+/// decoded descriptor. Returns the number of entries written.
+pub fn export_corpus(dir: &Path, random: usize) -> io::Result<usize> {
+    fs::create_dir_all(dir)?;
+
+    let corpus = corpus(random);
+    for (i, (encoded, decoded)) in corpus.iter().enumerate() {
+        write_file_atomic(dir.join(format!("{i:04}.bin")), encoded)?;
+        write_file_atomic(dir.join(format!("{i:04}.txt")), decoded.as_bytes())?;
+    }
+
+    Ok(corpus.len())
+}
+
+/// Deterministic generator of random valid descriptors
+///
+/// Produces an endless sequence of descriptors in the canonical form printed by miniscript,
+/// covering every top-level descriptor, common miniscript fragments, and single, extended, and
+/// multipath keys, both public and secret. Single secret keys are mainnet keys without origin,
+/// since the encoding does not preserve anything else.
+#[derive(Debug, Clone)]
+pub struct DescriptorGenerator {
+    state: u64,
+    secp: Secp256k1<All>,
+}
+
+impl DescriptorGenerator {
+    /// Creates a generator from a seed
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: seed,
+            secp: Secp256k1::new(),
+        }
+    }
+
+    /// Returns a random descriptor
+    pub fn descriptor(&mut self) -> String {
+        let multipath = self.below(4) == 0;
+        match self.below(10) {
+            0 => format!("pkh({})", self.key(false, multipath)),
+            1 => format!("wpkh({})", self.key(false, multipath)),
+            2 => format!("sh(wpkh({}))", self.key(false, multipath)),
+            3 => format!("tr({})", self.key(true, multipath)),
+            4 => format!(
+                "tr({},{{pk({}),pk({})}})",
+                self.key(true, multipath),
+                self.key(true, multipath),
+                self.key(true, multipath)
+            ),
+            5 => {
+                let n = 1 + self.below(4) as usize;
+                let k = 1 + self.below(n as u64);
+                let keys: Vec<_> = (0..n).map(|_| self.key(false, multipath)).collect();
+                let multi = if self.below(2) == 0 {
+                    "multi"
+                } else {
+                    "sortedmulti"
+                };
+                format!("wsh({multi}({k},{}))", keys.join(","))
+            }
+            6 => {
+                let n = 1 + self.below(3) as usize;
+                let keys: Vec<_> = (0..n).map(|_| self.key(false, multipath)).collect();
+                format!("sh(wsh(sortedmulti({n},{})))", keys.join(","))
+            }
+            7 => format!("wsh({})", self.miniscript(multipath)),
+            8 => format!(
+                "tr({},and_v(v:pk({}),{}))",
+                self.key(true, multipath),
+                self.key(true, multipath),
+                self.timelock()
+            ),
+            _ => format!("sh({})", self.miniscript(multipath)),
+        }
+    }
+
+    /// Returns a random miniscript fragment valid under `wsh` and `sh`
+    fn miniscript(&mut self, multipath: bool) -> String {
+        match self.below(5) {
+            0 => format!(
+                "and_v(v:pk({}),{})",
+                self.key(false, multipath),
+                self.timelock()
+            ),
+            1 => format!(
+                "or_d(pk({}),and_v(v:pkh({}),{}))",
+                self.key(false, multipath),
+                self.key(false, multipath),
+                self.timelock()
+            ),
+            2 => format!(
+                "andor(pk({}),{},pk({}))",
+                self.key(false, multipath),
+                self.timelock(),
+                self.key(false, multipath)
+            ),
+            3 => format!(
+                "and_v(v:pk({}),sha256({}))",
+                self.key(false, multipath),
+                self.hex(32)
+            ),
+            _ => {
+                let a = self.key(false, multipath);
+                let b = self.key(false, multipath);
+                format!("thresh(2,pk({a}),s:pk({b}),sln:{})", self.timelock())
+            }
+        }
+    }
+
+    /// Returns a random relative or absolute timelock
+    fn timelock(&mut self) -> String {
+        if self.below(2) == 0 {
+            format!("older({})", 1 + self.below(0xFFFF))
+        } else {
+            format!("after({})", 1 + self.below(500_000_000))
+        }
+    }
+
+    /// Returns a random key, using x-only single keys if `x_only`
+    fn key(&mut self, x_only: bool, multipath: bool) -> String {
+        let origin = match self.below(2) {
+            0 => String::new(),
+            _ => {
+                let path: String = (0..self.below(4))
+                    .map(|_| {
+                        let hardened = if self.below(2) == 0 { "'" } else { "" };
+                        format!("/{}{hardened}", self.below(100))
+                    })
+                    .collect();
+                format!("[{}{path}]", self.hex(4))
+            }
+        };
+
+        let sk = self.secret_key();
+        let network = if self.below(2) == 0 {
+            NetworkKind::Main
+        } else {
+            NetworkKind::Test
+        };
+        let key = match self.below(5) {
+            0 if x_only => sk.x_only_public_key(&self.secp).0.to_string(),
+            0 => sk.public_key(&self.secp).to_string(),
+            1 if multipath => return self.key(x_only, multipath),
+            // Single secret keys are encoded without their network, and miniscript does not
+            // print their origin
+            1 => return PrivateKey::new(sk, NetworkKind::Main).to_wif(),
+            2 => {
+                let xpriv = Xpriv::new_master(network, &sk.secret_bytes()).expect("valid seed");
+                return format!("{origin}{xpriv}{}", self.suffix(multipath));
+            }
+            _ => {
+                let xpriv = Xpriv::new_master(network, &sk.secret_bytes()).expect("valid seed");
+                let xpub = Xpub::from_priv(&self.secp, &xpriv);
+                return format!("{origin}{xpub}{}", self.suffix(multipath));
+            }
+        };
+
+        format!("{origin}{key}")
+    }
+
+    /// Returns a random derivation suffix for an extended key
+    fn suffix(&mut self, multipath: bool) -> String {
+        if multipath {
+            return "/<0;1>/*".to_string();
+        }
+        match self.below(4) {
+            0 => String::new(),
+            1 => format!("/{}", self.below(100)),
+            2 => "/0/*".to_string(),
+            _ => "/1/*h".to_string(),
+        }
+    }
+
+    fn secret_key(&mut self) -> SecretKey {
+        loop {
+            let mut bytes = [0u8; 32];
+            for chunk in bytes.chunks_mut(8) {
+                chunk.copy_from_slice(&self.next_u64().to_be_bytes());
+            }
+            if let Ok(sk) = SecretKey::from_slice(&bytes) {
+                return sk;
+            }
+        }
+    }
+
+    fn hex(&mut self, len: usize) -> String {
+        (0..len)
+            .map(|_| format!("{:02x}", self.below(256)))
+            .collect()
+    }
+
+    /// Returns a random number below `n`
+    fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    /// Advances the SplitMix64 state
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+impl Iterator for DescriptorGenerator {
+    type Item = String;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.descriptor())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tag::Tag;
+
+    #[test]
+    fn test_round_trip() {
+        for seed in 0..4 {
+            if let Err((descriptor, err)) = run(seed, 250) {
+                panic!("{descriptor}: {err:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_scenarios() {
+        let scenarios = scenarios();
+        assert_eq!(scenarios.len(), 4);
+        for scenario in scenarios {
+            if let Err(err) = check_round_trip(&scenario.descriptor) {
+                panic!("{}: {err:?}", scenario.name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_generator_deterministic() {
+        let a: Vec<_> = DescriptorGenerator::new(7).take(10).collect();
+        let b: Vec<_> = DescriptorGenerator::new(7).take(10).collect();
+        assert_eq!(a, b);
+        assert_ne!(a, DescriptorGenerator::new(8).take(10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_check_round_trip() {
+        assert!(matches!(
+            check_round_trip("wpkh(invalid)"),
+            Err(RoundTripError::Encode(_))
+        ));
+        // Non-canonical input decodes to the canonical form
+        assert!(matches!(
+            check_round_trip(
+                "wpkh([d34db33f/84h]02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)"
+            ),
+            Err(RoundTripError::Mismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_determinism_check() {
+        assert_eq!(determinism_check(), Ok(()));
+
+        let mut reference = DETERMINISM_REFERENCE;
+        reference[5] = reference[4];
+        let err = check_against(&reference).unwrap_err();
+        assert_eq!(
+            err,
+            DeterminismError::Mismatch {
+                index: 5,
+                descriptor: corpus(0)[5].1.clone(),
+            }
+        );
+        assert!(err.to_string().contains("corpus entry 5"));
+
+        // Entries with a payload commitment or metadata are checked with them
+        let mut reference = DETERMINISM_REFERENCE;
+        reference[18] = reference[0];
+        assert!(matches!(
+            check_against(&reference),
+            Err(DeterminismError::Mismatch { index: 18, .. })
+        ));
+        assert_ne!(DETERMINISM_REFERENCE[19], DETERMINISM_REFERENCE[0]);
+    }
+
+    #[test]
+    fn test_corpus() {
+        let corpus = corpus(10);
+        assert_eq!(corpus.len(), 20 + 10);
+
+        // Every tag byte appears in some template
+        let templates: Vec<_> = corpus
+            .iter()
+            .map(|(encoded, _)| {
+                let (_, _, size) = decoder::decode_template(encoded).unwrap();
+                &encoded[..size]
+            })
+            .collect();
+        for value in 0..=Tag::Metadata.value() {
+            let tag = Tag::from(value);
+            if matches!(tag, Tag::RawPkH | Tag::Preset) {
+                continue;
+            }
+            assert!(
+                templates.iter().any(|t| t.contains(&value)),
+                "missing {tag:?}"
+            );
+        }
+
+        let dir =
+            std::env::temp_dir().join(format!("descriptor-codec-corpus-{}", std::process::id()));
+        assert_eq!(export_corpus(&dir, 10).unwrap(), corpus.len());
+        assert_eq!(fs::read(dir.join("0000.bin")).unwrap(), corpus[0].0);
+        assert_eq!(
+            fs::read_to_string(dir.join("0029.txt")).unwrap(),
+            corpus[29].1
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
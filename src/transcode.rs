@@ -0,0 +1,109 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! # Transcoding
+//!
+//! Rewrites an encoding with different container options by copying its template and payload
+//! verbatim. Keys are never parsed, so secret material is not exposed.
+
+use super::{tag::Tag, *};
+
+/// Container options applied when transcoding
+///
+/// Text transport formats are applied separately, with the functions in [`format`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TranscodeOptions {
+    /// Whether the template commits to a hash of the payload
+    pub commit_payload: bool,
+    /// Presets used to compress the template
+    pub presets: PresetRegistry,
+}
+
+/// Rewrites an encoded descriptor with the given container options
+///
+/// Any payload commitment in `bytes` is verified before it is removed or kept.
+pub fn transcode(bytes: &[u8], opts: &TranscodeOptions) -> Result<Vec<u8>, Error> {
+    transcode_with(bytes, &CodecOptions::default(), opts)
+}
+
+/// Rewrites an encoded descriptor, reading it with the given codec options
+pub(crate) fn transcode_with(
+    bytes: &[u8],
+    options: &CodecOptions,
+    opts: &TranscodeOptions,
+) -> Result<Vec<u8>, Error> {
+    let bytes = options.presets.expand(bytes)?;
+    let size = decoder::template_size(&bytes, &options.decode_context())?;
+    let (template, payload) = bytes.split_at(size);
+
+    let mut out = template.to_vec();
+    if out.first() == Some(&Tag::PayloadCommitment.value()) {
+        verify_payload(template, payload)?;
+        out.drain(..1 + encoder::COMMITMENT_SIZE);
+    }
+    opts.presets.compress(&mut out);
+    if opts.commit_payload {
+        encoder::commit_payload(&mut out, payload);
+    }
+    out.extend_from_slice(payload);
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transcode() {
+        let xprv = "xprv9s21ZrQH143K31xYSDQpPDxsXRTUcvj2iNHm5NUtrGiGG5e2DtALGdso3pGz6ssrdK4PFmM8NSpSBHNqPqm55Qn3LqFtT2emdEXVYsCzC2U";
+        let desc_str = format!("wsh(multi(1,{xprv}/0,{xprv}/<0;1>/*))");
+        let encoded = encode(&desc_str).unwrap();
+
+        let committed = transcode(
+            &encoded,
+            &TranscodeOptions {
+                commit_payload: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            committed,
+            Codec::new()
+                .with_payload_commitment(true)
+                .encode(&desc_str)
+                .unwrap()
+        );
+        assert_eq!(
+            transcode(&committed, &TranscodeOptions::default()).unwrap(),
+            encoded
+        );
+
+        // Presets are expanded with the codec's registry and compressed with the target's
+        let mut presets = PresetRegistry::new();
+        presets.define(5, &desc_str).unwrap();
+        let opts = TranscodeOptions {
+            commit_payload: true,
+            presets: presets.clone(),
+        };
+        let compressed = transcode(&encoded, &opts).unwrap();
+        let codec = Codec::new()
+            .with_presets(presets)
+            .with_payload_commitment(true);
+        assert_eq!(compressed, codec.encode(&desc_str).unwrap());
+        assert_eq!(
+            codec
+                .transcode(&compressed, &TranscodeOptions::default())
+                .unwrap(),
+            encoded
+        );
+
+        // Mismatched commitments are rejected
+        let mut corrupted = committed.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        assert_eq!(
+            transcode(&corrupted, &TranscodeOptions::default()),
+            Err(Error::PayloadMismatch)
+        );
+    }
+}
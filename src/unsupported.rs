@@ -0,0 +1,431 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! # Unsupported Features
+//!
+//! Reports the parts of a descriptor the format cannot represent, or represents lossily, so
+//! integrators can warn users before they rely on an encoding as a backup, and narrows down why
+//! a descriptor fails to encode, so users can report it without revealing their keys.
+
+use super::*;
+use bitcoin::NetworkKind;
+use miniscript::{BareCtx, ForEachKey, Legacy, Miniscript, Segwitv0, Tap};
+use std::{error, fmt};
+
+/// Descriptor types that cannot be parsed, and so cannot be encoded
+const UNSUPPORTED_TYPES: [&str; 3] = ["addr", "combo", "raw"];
+
+/// Descriptor types that wrap a script or key, rather than being a miniscript fragment
+const DESCRIPTOR_TYPES: [&str; 6] = ["pkh", "rawtr", "sh", "tr", "wpkh", "wsh"];
+
+/// A part of a descriptor the format cannot represent
+#[derive(Debug, PartialEq)]
+pub enum Unsupported {
+    /// Descriptor type, such as `addr` or `raw`
+    DescriptorType(String),
+    /// Single private key on a test network, by key index, which decodes as a mainnet key
+    TestnetSecretKey(usize),
+    /// Origin of a single private key, by key index, which is dropped
+    SecretKeyOrigin(usize),
+    /// Descriptor is invalid
+    Invalid(encoder::Error),
+}
+
+impl fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::DescriptorType(name) => write!(f, "unsupported descriptor type `{name}`"),
+            Self::TestnetSecretKey(idx) => {
+                write!(f, "testnet private key decodes as mainnet (key: {idx})")
+            }
+            Self::SecretKeyOrigin(idx) => write!(f, "private key origin is dropped (key: {idx})"),
+            Self::Invalid(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+/// Error encoding a descriptor, with the smallest sub-expression that fails to parse
+#[derive(Debug, PartialEq)]
+pub struct UnsupportedError {
+    /// Error returned by [`encode`]
+    pub error: encoder::Error,
+    /// Smallest failing sub-expression, with every key redacted, if the descriptor failed to
+    /// parse and the failure could be narrowed down
+    pub expression: Option<String>,
+}
+
+impl fmt::Display for UnsupportedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.expression {
+            Some(expression) => write!(f, "{} in `{expression}`", self.error),
+            None => write!(f, "{}", self.error),
+        }
+    }
+}
+
+impl error::Error for UnsupportedError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// Parses and encodes a Bitcoin descriptor, narrowing a parse failure down to a minimal
+/// reproduction
+///
+/// Gives the same bytes as [`encode`]. On failure, the error carries the smallest sub-expression
+/// that fails on its own, such as `multi(1,<x-only key>,<x-only key>)` for a `multi()` in a
+/// taproot leaf. Keys are redacted to their kind, origin path, and derivation path, so the
+/// expression can be shared in a bug report without revealing the wallet.
+pub fn encode_with_reproduction(s: &str) -> Result<Vec<u8>, UnsupportedError> {
+    encode(s).map_err(|error| {
+        let expression = match error {
+            encoder::Error::InvalidDescriptor(_)
+            | encoder::Error::InvalidKey(_)
+            | encoder::Error::InvalidHash(_) => minimal_reproduction(s),
+            _ => None,
+        };
+        UnsupportedError { error, expression }
+    })
+}
+
+/// A sub-expression of a descriptor, such as `pk(A)` or the leaf `A`
+struct Node<'a> {
+    /// Text of the sub-expression
+    text: &'a str,
+    /// Fragment name with any wrappers, empty for a taproot branch `{A,B}`
+    name: &'a str,
+    /// Arguments, or branches of a taproot branch
+    args: Vec<Node<'a>>,
+}
+
+impl<'a> Node<'a> {
+    /// Splits an expression into its name and arguments, or returns `None` if it is malformed
+    fn parse(text: &'a str) -> Option<Self> {
+        let (name, inner) = match text.strip_prefix('{') {
+            Some(rest) => ("", rest.strip_suffix('}')?),
+            None => match text.split_once('(') {
+                Some((name, rest)) => (name, rest.strip_suffix(')')?),
+                None if text.contains([')', '{', '}']) => return None,
+                None => {
+                    return Some(Self {
+                        text,
+                        name: text,
+                        args: Vec::new(),
+                    });
+                }
+            },
+        };
+
+        let mut args = Vec::new();
+        let mut depth = 0usize;
+        let mut start = 0;
+        for (i, c) in inner.char_indices() {
+            match c {
+                '(' | '{' | '[' => depth += 1,
+                ')' | '}' | ']' => depth = depth.checked_sub(1)?,
+                ',' if depth == 0 => {
+                    args.push(Self::parse(&inner[start..i])?);
+                    start = i + 1;
+                }
+                _ => {}
+            }
+        }
+        if depth != 0 {
+            return None;
+        }
+        args.push(Self::parse(&inner[start..])?);
+
+        Some(Self { text, name, args })
+    }
+
+    /// Returns the fragment name without wrappers, such as `pk` for `v:pk`
+    fn fragment(&self) -> &'a str {
+        self.name.rsplit(':').next().unwrap_or_default()
+    }
+
+    /// Returns whether an argument is a key
+    fn is_key_arg(&self, index: usize) -> bool {
+        match self.fragment() {
+            "pk" | "pk_k" | "pk_h" | "pkh" | "wpkh" | "rawtr" | "tr" => index == 0,
+            "multi" | "multi_a" | "sortedmulti" | "sortedmulti_a" => index > 0,
+            _ => false,
+        }
+    }
+
+    /// Returns the text of the sub-expression with every key redacted
+    fn redacted(&self) -> String {
+        if self.args.is_empty() {
+            return self.text.to_string();
+        }
+        let args = self
+            .args
+            .iter()
+            .enumerate()
+            .map(|(i, arg)| match self.is_key_arg(i) {
+                true => redact_key(arg.text),
+                false => arg.redacted(),
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        match self.name.is_empty() {
+            true => format!("{{{args}}}"),
+            false => format!("{}({args})", self.name),
+        }
+    }
+}
+
+/// Script context a sub-expression is parsed in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Scope {
+    /// A descriptor such as `wsh(...)`, encoded on its own
+    Descriptor,
+    /// A fragment in `bare()`
+    Bare,
+    /// A fragment in `sh()`
+    Legacy,
+    /// A fragment in `wsh()`
+    Segwitv0,
+    /// A fragment in a taproot leaf
+    Tap,
+}
+
+impl Scope {
+    /// Returns the scope of an argument of `node`, which is in this scope
+    fn of_arg(self, node: &Node, arg: &Node) -> Self {
+        match (self, node.fragment()) {
+            (Self::Descriptor, _) if DESCRIPTOR_TYPES.contains(&arg.fragment()) => Self::Descriptor,
+            (Self::Descriptor, "sh") => Self::Legacy,
+            (Self::Descriptor, "wsh") => Self::Segwitv0,
+            (Self::Descriptor, "tr") => Self::Tap,
+            (scope, _) => scope,
+        }
+    }
+
+    /// Returns whether a sub-expression in this scope fails on its own
+    fn fails(self, node: &Node) -> bool {
+        let invalid_key = node
+            .args
+            .iter()
+            .enumerate()
+            .any(|(i, arg)| node.is_key_arg(i) && !is_key(arg.text));
+        if invalid_key || node.args.is_empty() {
+            return invalid_key;
+        }
+        // Taproot branches and sorted multisigs are not fragments, so only their arguments can fail
+        let fragment_fails = match self {
+            _ if node.name.is_empty() || node.fragment().starts_with("sortedmulti") => false,
+            Self::Descriptor => return encode(node.text).is_err(),
+            Self::Bare => fragment_fails::<BareCtx>(node.text),
+            Self::Legacy => fragment_fails::<Legacy>(node.text),
+            Self::Segwitv0 => fragment_fails::<Segwitv0>(node.text),
+            Self::Tap => fragment_fails::<Tap>(node.text),
+        };
+        // Keys are parsed as strings, so are checked through the arguments
+        fragment_fails || node.args.iter().any(|arg| self.fails(arg))
+    }
+}
+
+/// Returns whether a miniscript fragment fails to parse, other than for not being top-level
+fn fragment_fails<Ctx: miniscript::ScriptContext>(text: &str) -> bool {
+    match Miniscript::<String, Ctx>::from_str_insane(text) {
+        Ok(_) | Err(miniscript::Error::NonTopLevel(_)) => false,
+        Err(_) => true,
+    }
+}
+
+/// Returns whether a string is a public or private descriptor key
+fn is_key(s: &str) -> bool {
+    DescriptorPublicKey::from_str(s).is_ok() || DescriptorSecretKey::from_str(s).is_ok()
+}
+
+/// Replaces the fingerprint and key material of a key with placeholders, keeping its kind and
+/// derivation paths
+fn redact_key(key: &str) -> String {
+    let (origin, key) = match key.strip_prefix('[').and_then(|key| key.split_once(']')) {
+        Some((origin, key)) => match origin.split_once('/') {
+            Some((_, path)) => (format!("[<fingerprint>/{path}]"), key),
+            None => ("[<fingerprint>]".to_string(), key),
+        },
+        None => (String::new(), key),
+    };
+    let (body, path) = match key.split_once('/') {
+        Some((body, path)) => (body, format!("/{path}")),
+        None => (key, String::new()),
+    };
+    let kind = match body.get(..4) {
+        Some(prefix @ ("xpub" | "tpub" | "xprv" | "tprv")) => prefix,
+        _ if body.bytes().all(|b| b.is_ascii_hexdigit()) => match body.len() {
+            64 => "x-only key",
+            66 => "compressed key",
+            130 => "uncompressed key",
+            _ => "key",
+        },
+        _ => "key",
+    };
+    format!("{origin}<{kind}>{path}")
+}
+
+/// Returns the smallest sub-expression of a descriptor that fails on its own, with every key
+/// redacted
+fn minimal_reproduction(s: &str) -> Option<String> {
+    let s = s.trim();
+    let s = s.split_once('#').map_or(s, |(s, _)| s);
+    let mut node = Node::parse(s)?;
+    let mut scope = match DESCRIPTOR_TYPES.contains(&node.fragment()) {
+        true => Scope::Descriptor,
+        false => Scope::Bare,
+    };
+    if !scope.fails(&node) {
+        return None;
+    }
+
+    // Descend into the first failing argument until none fails, stopping at the fragment holding
+    // an invalid key
+    loop {
+        let mut failing = None;
+        for (i, arg) in node.args.iter().enumerate() {
+            if node.is_key_arg(i) {
+                match is_key(arg.text) {
+                    true => continue,
+                    false => break,
+                }
+            }
+            let arg_scope = scope.of_arg(&node, arg);
+            if arg_scope.fails(arg) {
+                failing = Some((i, arg_scope));
+                break;
+            }
+        }
+        let Some((i, arg_scope)) = failing else {
+            return Some(node.redacted());
+        };
+        scope = arg_scope;
+        node = node.args.swap_remove(i);
+    }
+}
+
+/// Checks that a descriptor can be encoded and decoded without loss
+///
+/// Returns every problem found, or the parse error alone if the descriptor is invalid.
+pub fn check_encodable(s: &str) -> Result<(), Vec<Unsupported>> {
+    let name = s.trim_start().split('(').next().unwrap_or_default();
+    if UNSUPPORTED_TYPES.contains(&name) {
+        return Err(vec![Unsupported::DescriptorType(name.to_string())]);
+    }
+
+    // A `rawtr()` is encoded as the equivalent `tr()`, so has the same problems
+    let raw_tr = raw_tr_as_tr(s).map_err(|err| vec![Unsupported::Invalid(err)])?;
+    let s = raw_tr.as_deref().unwrap_or(s);
+
+    parse_descriptor(s).map_err(|err| vec![Unsupported::Invalid(err)])?;
+
+    // Single private keys are checked in their string form, as parsing drops their origin
+    let descriptor =
+        Descriptor::<String>::from_str(s).map_err(|err| vec![Unsupported::Invalid(err.into())])?;
+    let mut unsupported = Vec::new();
+    let mut index = 0;
+    descriptor.for_each_key(|key| {
+        if let Ok(DescriptorSecretKey::Single(sk)) = DescriptorSecretKey::from_str(key) {
+            if sk.key.network == NetworkKind::Test {
+                unsupported.push(Unsupported::TestnetSecretKey(index));
+            }
+            if key.starts_with('[') {
+                unsupported.push(Unsupported::SecretKeyOrigin(index));
+            }
+        }
+        index += 1;
+        true
+    });
+
+    match unsupported.is_empty() {
+        true => Ok(()),
+        false => Err(unsupported),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_encodable() {
+        let wif = "L4rK1yDtCWekvXuE6oXD9jCYfFNV2cWRpVuPLBcCU2z8TrisoyY1";
+        assert_eq!(check_encodable(&format!("wpkh({wif})")), Ok(()));
+
+        let testnet_wif = "cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy";
+        let desc_str = format!(
+            "wsh(multi(1,02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9,{testnet_wif},[deadbeef/0']{wif}))"
+        );
+        assert_eq!(
+            check_encodable(&desc_str),
+            Err(vec![
+                Unsupported::TestnetSecretKey(1),
+                Unsupported::SecretKeyOrigin(2)
+            ])
+        );
+
+        assert_eq!(
+            check_encodable("raw(deadbeef)"),
+            Err(vec![Unsupported::DescriptorType("raw".to_string())])
+        );
+        assert_eq!(
+            check_encodable(
+                "rawtr(c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5)"
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            check_encodable(&format!("rawtr({testnet_wif})")),
+            Err(vec![Unsupported::TestnetSecretKey(0)])
+        );
+        assert!(matches!(
+            check_encodable("wsh(pk(invalid))").unwrap_err()[..],
+            [Unsupported::Invalid(encoder::Error::InvalidKey(_))]
+        ));
+    }
+
+    #[test]
+    fn test_encode_with_reproduction() {
+        let a = "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+        let b = "02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9";
+        let xpub = "[73c5da0a/48'/0'/0'/2']xpub6DkFAXWQ2dHxq2vatrt9qyA3bXYU4ToWQwCHbf5XB2mSTexcHZCeKS1VZYcPoBd5X8yVcbXFHJR9R8UCVpt82VX1VhR28mCyxUFL4r6KFrf/<0;1>/*";
+        let expression = |s: &str| encode_with_reproduction(s).unwrap_err().expression;
+
+        let valid = format!("wsh(and_v(v:pk({xpub}),older(144)))");
+        assert_eq!(
+            encode_with_reproduction(&valid),
+            Ok(encode(&valid).unwrap())
+        );
+
+        // A key with a bad checksum is reported with the fragment holding it
+        let bad_xpub = xpub.replace("Frf", "Frg");
+        assert_eq!(
+            expression(&format!(
+                "wsh(or_d(pk({a}),and_v(v:pk({bad_xpub}),older(144))))"
+            )),
+            Some("v:pk([<fingerprint>/48'/0'/0'/2']<xpub>/<0;1>/*)".to_string())
+        );
+
+        // A fragment not allowed in its context
+        assert_eq!(
+            expression(&format!("tr({a},{{pk({b}),multi(1,{a},{b})}})")),
+            Some("multi(1,<compressed key>,<compressed key>)".to_string())
+        );
+
+        // Arguments of the wrong type
+        let err =
+            encode_with_reproduction(&format!("sh(wsh(and_v(pk({a}),pk({b}))))")).unwrap_err();
+        assert!(matches!(err.error, encoder::Error::InvalidDescriptor(_)));
+        assert_eq!(
+            err.expression.as_deref(),
+            Some("and_v(pk(<compressed key>),pk(<compressed key>))")
+        );
+        assert_eq!(
+            err.to_string(),
+            "invalid descriptor in `and_v(pk(<compressed key>),pk(<compressed key>))`"
+        );
+
+        // Failures other than parsing are not narrowed down
+        assert_eq!(expression(&format!("{valid}#aaaaaaaa")), None);
+        assert_eq!(expression("wsh(pk(A)"), None);
+    }
+}
@@ -1,5 +1,21 @@
 // Source: ordinals/varint.rs
 
+//! # Variable-Length Integers
+//!
+//! LEB128 encoding of unsigned integers, used for derivation paths, thresholds, and timelocks.
+
+/// Maximum number of bytes in an encoded `u128`
+pub const MAX_ENCODED_LEN: usize = 19;
+
+/// Maximum number of bytes in an encoded `u64`
+#[cfg_attr(not(feature = "internals"), allow(dead_code))]
+pub const MAX_ENCODED_LEN_U64: usize = 10;
+
+/// Maximum number of bytes in an encoded `u32`
+#[cfg_attr(not(feature = "internals"), allow(dead_code))]
+pub const MAX_ENCODED_LEN_U32: usize = 5;
+
+/// Appends the LEB128 encoding of `n` to `v`
 pub fn encode_to_vec(mut n: u128, v: &mut Vec<u8>) {
     while n >> 7 > 0 {
         v.push(n.to_le_bytes()[0] | 0b1000_0000);
@@ -8,17 +24,25 @@ pub fn encode_to_vec(mut n: u128, v: &mut Vec<u8>) {
     v.push(n.to_le_bytes()[0]);
 }
 
+/// Returns the number of bytes in the LEB128 encoding of `n`
+pub fn encoded_len(n: u128) -> usize {
+    let bits = 128 - n.leading_zeros() as usize;
+    bits.div_ceil(7).max(1)
+}
+
+/// Decodes a LEB128 integer from the start of `buffer`, returning the value and the number of
+/// bytes read
 pub fn decode(buffer: &[u8]) -> Result<(u128, usize), Error> {
     let mut n = 0u128;
 
     for (i, &byte) in buffer.iter().enumerate() {
-        if i > 18 {
+        if i >= MAX_ENCODED_LEN {
             return Err(Error::Overlong);
         }
 
         let value = u128::from(byte) & 0b0111_1111;
 
-        if i == 18 && value & 0b0111_1100 != 0 {
+        if i == MAX_ENCODED_LEN - 1 && value & 0b0111_1100 != 0 {
             return Err(Error::Overflow);
         }
 
@@ -32,16 +56,29 @@ pub fn decode(buffer: &[u8]) -> Result<(u128, usize), Error> {
     Err(Error::Unterminated)
 }
 
+/// Decodes a LEB128 integer from the start of `buffer` into an integer type narrower than `u128`,
+/// returning [`Error::Overflow`] if the value does not fit
+pub fn decode_as<T: TryFrom<u128>>(buffer: &[u8]) -> Result<(T, usize), Error> {
+    let (n, length) = decode(buffer)?;
+    let n = T::try_from(n).map_err(|_| Error::Overflow)?;
+    Ok((n, length))
+}
+
+/// Returns the LEB128 encoding of `n`
 pub fn encode(n: u128) -> Vec<u8> {
     let mut v = Vec::new();
     encode_to_vec(n, &mut v);
     v
 }
 
+/// Varint error
 #[derive(PartialEq, Debug)]
 pub enum Error {
+    /// Encoding is longer than the maximum length
     Overlong,
+    /// Value does not fit in the target integer type
     Overflow,
+    /// Input ends before the final byte
     Unterminated,
 }
 
@@ -168,5 +205,42 @@ mod tests {
     #[test]
     fn varints_must_be_terminated() {
         assert_eq!(decode(&[128]), Err(Error::Unterminated));
+        assert_eq!(decode(&[]), Err(Error::Unterminated));
+    }
+
+    #[test]
+    fn encoded_len_matches_encode() {
+        for i in 0..128 {
+            for n in [(1u128 << i) - 1, 1 << i] {
+                assert_eq!(encoded_len(n), encode(n).len());
+            }
+        }
+        assert_eq!(encoded_len(u128::MAX), MAX_ENCODED_LEN);
+    }
+
+    #[test]
+    fn max_encoded_lengths_are_tight() {
+        assert_eq!(encode(u128::MAX).len(), MAX_ENCODED_LEN);
+        assert_eq!(encode(u64::MAX.into()).len(), MAX_ENCODED_LEN_U64);
+        assert_eq!(encode(u32::MAX.into()).len(), MAX_ENCODED_LEN_U32);
+    }
+
+    #[test]
+    fn decode_as_rejects_values_that_do_not_fit() {
+        let encoded = encode(u32::MAX.into());
+        assert_eq!(
+            decode_as::<u32>(&encoded),
+            Ok((u32::MAX, MAX_ENCODED_LEN_U32))
+        );
+        assert_eq!(decode_as::<u16>(&encoded), Err(Error::Overflow));
+
+        let encoded = encode(u128::from(u32::MAX) + 1);
+        assert_eq!(decode_as::<u32>(&encoded), Err(Error::Overflow));
+        assert_eq!(
+            decode_as::<u64>(&encoded),
+            Ok((u64::from(u32::MAX) + 1, MAX_ENCODED_LEN_U32))
+        );
+
+        assert_eq!(decode_as::<u32>(&[255]), Err(Error::Unterminated));
     }
 }
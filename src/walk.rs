@@ -0,0 +1,431 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! # Template Walk
+//!
+//! Reads the tags of an encoded template without building a descriptor, listing the values the
+//! template calls for in the payload. Checks that need only the encoding, such as whether it can
+//! be decoded or which hash locks it holds, use this instead of a full decode.
+//!
+//! Keys are read with the default key codec, and neither keys nor miniscript types are checked.
+
+use super::decoder::{DEFAULT_MAX_PATH_LEN, Error, PayloadError};
+use super::encoder::COMMITMENT_SIZE;
+use super::tag::Tag;
+use super::varint;
+use miniscript::{AbsLockTime, RelLockTime};
+
+/// A value the template calls for in the payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Item {
+    /// Fixed number of bytes, such as a key or fingerprint
+    Bytes(usize),
+    /// Absolute timelock, as a variable-length integer
+    After,
+    /// Relative timelock, as a variable-length integer
+    Older,
+    /// Hash of a hash-lock fragment, by its tag
+    Hash(Tag),
+}
+
+impl Item {
+    /// Reads the item at `payload_index`, returning its bytes
+    fn read<'a>(&self, payload: &'a [u8], payload_index: &mut usize) -> Result<&'a [u8], Error> {
+        let current_index = *payload_index;
+        let size = match self {
+            Item::Bytes(size) => *size,
+            Item::Hash(Tag::Sha256 | Tag::Hash256) => 32,
+            Item::Hash(_) => 20,
+            Item::After | Item::Older => {
+                if current_index >= payload.len() {
+                    return Err(Error::MissingBytes);
+                }
+                let (value, size) = varint::decode_as::<u32>(&payload[current_index..])
+                    .map_err(|e| Error::InvalidPayload(current_index, PayloadError::VarInt(e)))?;
+                if *self == Item::After {
+                    AbsLockTime::from_consensus(value).map_err(|err| {
+                        Error::InvalidLocktime(current_index, PayloadError::AbsLockTime(err))
+                    })?;
+                } else {
+                    RelLockTime::from_consensus(value).map_err(|err| {
+                        Error::InvalidLocktime(current_index, PayloadError::RelLockTime(err))
+                    })?;
+                }
+                size
+            }
+        };
+
+        *payload_index += size;
+        payload
+            .get(current_index..*payload_index)
+            .ok_or(Error::MissingBytes)
+    }
+}
+
+/// Tags and payload layout of an encoded template
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Walk {
+    /// Number of bytes in the template
+    pub template_len: usize,
+    /// Whether the template starts with a payload commitment
+    pub commitment: bool,
+    /// Whether the payload ends with metadata records
+    pub metadata: bool,
+    /// Values the template calls for in the payload, in order
+    pub items: Vec<Item>,
+}
+
+impl Walk {
+    /// Reads each item from the payload in turn, returning the index after the last
+    pub fn read_payload<'a>(
+        &self,
+        payload: &'a [u8],
+        mut visit: impl FnMut(Item, &'a [u8]),
+    ) -> Result<usize, Error> {
+        let mut payload_index = 0;
+        for item in &self.items {
+            visit(*item, item.read(payload, &mut payload_index)?);
+        }
+        Ok(payload_index)
+    }
+}
+
+/// Walks the template at the start of `bytes`
+///
+/// Fails with the same errors as decoding for a malformed template, and with
+/// [`Error::UnrecognizedTag`] on a custom tag, as no [`TagExtension`](crate::TagExtension) is
+/// available.
+pub(crate) fn walk(bytes: &[u8]) -> Result<Walk, Error> {
+    let mut walker = Walker {
+        input: bytes,
+        index: 0,
+        walk: Walk {
+            template_len: 0,
+            commitment: false,
+            metadata: false,
+            items: Vec::new(),
+        },
+        hashes: 0,
+    };
+    walker.descriptor()?;
+    walker.walk.template_len = walker.index;
+    Ok(walker.walk)
+}
+
+struct Walker<'a> {
+    input: &'a [u8],
+    index: usize,
+    walk: Walk,
+    /// Number of distinct hashes read so far, which `HashRef` tags refer back to
+    hashes: usize,
+}
+
+impl Walker<'_> {
+    /// Returns the tag at the current index
+    fn peek(&self) -> Result<Tag, Error> {
+        self.input
+            .get(self.index)
+            .map(|&value| Tag::from(value))
+            .ok_or(Error::MissingBytes)
+    }
+
+    /// Reads a variable-length integer from the template
+    fn varint<T: TryFrom<u128>>(&mut self) -> Result<T, Error> {
+        let rest = self.input.get(self.index..).unwrap_or_default();
+        let (value, size) =
+            varint::decode_as::<T>(rest).map_err(|e| Error::InvalidVarInt(self.index, e))?;
+        self.index += size;
+        Ok(value)
+    }
+
+    fn push(&mut self, item: Item) {
+        self.walk.items.push(item);
+    }
+
+    fn descriptor(&mut self) -> Result<(), Error> {
+        let current_index = self.index;
+        match self.peek()? {
+            Tag::Unrecognized => return Err(Error::UnrecognizedTag(current_index)),
+            Tag::Sh => {
+                self.index += 1;
+                match self.peek()? {
+                    Tag::Unrecognized => return Err(Error::UnrecognizedTag(self.index)),
+                    Tag::SortedMulti => self.sorted_multi()?,
+                    Tag::Wsh => self.descriptor()?,
+                    Tag::Wpkh => self.descriptor()?,
+                    _ => self.miniscript()?,
+                }
+            }
+            Tag::Wsh => {
+                self.index += 1;
+                match self.peek()? {
+                    Tag::Unrecognized => return Err(Error::UnrecognizedTag(self.index)),
+                    Tag::SortedMulti => self.sorted_multi()?,
+                    _ => self.miniscript()?,
+                }
+            }
+            Tag::Tr => {
+                self.index += 1;
+                self.peek()?;
+                self.key()?;
+                if self.peek().ok() == Some(Tag::TapTree) {
+                    self.tap_tree()?;
+                }
+            }
+            Tag::Wpkh | Tag::Pkh | Tag::RawTr => {
+                self.index += 1;
+                self.key()?;
+            }
+            Tag::Bare => {
+                self.index += 1;
+                self.miniscript()?;
+            }
+            Tag::Preset => {
+                self.index += 1;
+                return Err(Error::UnknownPreset(self.varint()?));
+            }
+            Tag::PayloadCommitment if current_index == 0 => {
+                self.index += 1 + COMMITMENT_SIZE;
+                if self.index > self.input.len() {
+                    return Err(Error::MissingBytes);
+                }
+                self.walk.commitment = true;
+                self.descriptor()?;
+            }
+            Tag::Metadata
+                if current_index == 0
+                    || (current_index == 1 + COMMITMENT_SIZE && self.walk.commitment) =>
+            {
+                self.index += 1;
+                self.walk.metadata = true;
+                self.descriptor()?;
+            }
+            Tag::SingleSigWpkh => {
+                self.index += 1;
+                self.peek()?;
+                self.varint::<u32>()?;
+                // Fingerprint, parent fingerprint, chain code, and compressed key
+                for size in [4, 4, 32, 33] {
+                    self.push(Item::Bytes(size));
+                }
+            }
+            _ => return Err(Error::InvalidTag(current_index)),
+        }
+        Ok(())
+    }
+
+    fn tap_tree(&mut self) -> Result<(), Error> {
+        self.index += 1;
+        if self.peek()? == Tag::TapTree {
+            self.tap_tree()?;
+            match self.peek() {
+                Ok(Tag::TapTree) => self.tap_tree(),
+                _ => Err(Error::MissingBytes),
+            }
+        } else {
+            self.miniscript()
+        }
+    }
+
+    fn sorted_multi(&mut self) -> Result<(), Error> {
+        self.index += 1;
+        self.peek()?;
+        self.threshold(Self::key)
+    }
+
+    /// Reads `k` and `n`, then `n` children
+    fn threshold(
+        &mut self,
+        mut child: impl FnMut(&mut Self) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        self.peek()?;
+        self.varint::<usize>()?;
+        let n = self.varint::<usize>()?;
+        for _ in 0..n {
+            child(self)?;
+        }
+        Ok(())
+    }
+
+    fn miniscript(&mut self) -> Result<(), Error> {
+        let current_index = self.index;
+        let tag = self.peek()?;
+        self.index += 1;
+        match tag {
+            Tag::True | Tag::False => {}
+            Tag::PkK | Tag::PkH => self.key()?,
+            Tag::RawPkH => self.push(Item::Bytes(20)),
+            Tag::After => self.push(Item::After),
+            Tag::Older => self.push(Item::Older),
+            Tag::Sha256 | Tag::Hash256 | Tag::Ripemd160 | Tag::Hash160 => {
+                self.hashes += 1;
+                self.push(Item::Hash(tag));
+            }
+            Tag::HashRef => {
+                if self.varint::<usize>()? >= self.hashes {
+                    return Err(Error::InvalidHashRef(current_index));
+                }
+            }
+            Tag::Alt
+            | Tag::Swap
+            | Tag::Check
+            | Tag::DupIf
+            | Tag::Verify
+            | Tag::NonZero
+            | Tag::ZeroNotEqual => self.miniscript()?,
+            Tag::AndV | Tag::AndB | Tag::OrB | Tag::OrC | Tag::OrD | Tag::OrI => {
+                self.miniscript()?;
+                self.miniscript()?;
+            }
+            Tag::AndOr => {
+                for _ in 0..3 {
+                    self.miniscript()?;
+                }
+            }
+            Tag::Thresh => self.threshold(Self::miniscript)?,
+            Tag::Multi | Tag::MultiA => self.threshold(Self::key)?,
+            Tag::Unrecognized => return Err(Error::UnrecognizedTag(current_index)),
+            _ => return Err(Error::InvalidTag(current_index)),
+        }
+        Ok(())
+    }
+
+    fn key(&mut self) -> Result<(), Error> {
+        let current_index = self.index;
+        if current_index + 1 >= self.input.len() {
+            return Err(Error::MissingBytes);
+        }
+        self.index += 2;
+
+        match Tag::from(self.input[current_index + 1]) {
+            Tag::Unrecognized => return Err(Error::UnrecognizedTag(current_index + 1)),
+            Tag::Origin => {
+                self.push(Item::Bytes(4));
+                self.path()?;
+            }
+            Tag::NoOrigin => {}
+            _ => return Err(Error::InvalidTag(current_index + 1)),
+        }
+
+        match Tag::from(self.input[current_index]) {
+            Tag::Unrecognized => return Err(Error::UnrecognizedTag(current_index)),
+            Tag::UncompressedFullKey => self.push(Item::Bytes(65)),
+            Tag::CompressedFullKey => self.push(Item::Bytes(33)),
+            Tag::XOnly | Tag::CompressedSinglePriv | Tag::UncompressedSinglePriv => {
+                self.push(Item::Bytes(32))
+            }
+            Tag::XPub | Tag::XPriv => {
+                self.push(Item::Bytes(78));
+                self.path()?;
+                self.wildcard()?;
+            }
+            Tag::MultiXPub | Tag::MultiXPriv => {
+                self.push(Item::Bytes(78));
+                self.peek()?;
+                let paths_index = self.index;
+                let len = self.varint::<usize>()?;
+                for _ in 0..len {
+                    self.path()?;
+                }
+                if len == 0 {
+                    return Err(Error::MissingDerivPaths(paths_index));
+                }
+                self.wildcard()?;
+            }
+            _ => return Err(Error::InvalidTag(current_index)),
+        }
+        Ok(())
+    }
+
+    fn path(&mut self) -> Result<(), Error> {
+        self.peek()?;
+        let len_index = self.index;
+        let len = self.varint::<usize>()?;
+        if len > DEFAULT_MAX_PATH_LEN {
+            return Err(Error::PathTooLong(len_index));
+        }
+        for _ in 0..len {
+            self.peek()?;
+            let step_index = self.index;
+            let value = self.varint::<u64>()?;
+            u32::try_from(value >> 1)
+                .map_err(|_| Error::InvalidVarInt(step_index, varint::Error::Overflow))?;
+        }
+        Ok(())
+    }
+
+    fn wildcard(&mut self) -> Result<(), Error> {
+        let current_index = self.index;
+        match self.peek()? {
+            Tag::NoWildcard | Tag::UnhardenedWildcard | Tag::HardenedWildcard => {
+                self.index += 1;
+                Ok(())
+            }
+            Tag::Unrecognized => Err(Error::UnrecognizedTag(current_index)),
+            _ => Err(Error::InvalidTag(current_index)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{decoder, encode};
+
+    #[test]
+    fn test_walk() {
+        let descriptors = [
+            "wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)",
+            "wpkh([73c5da0a/84'/0'/0']xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V/<0;1>/*)",
+            "sh(wsh(sortedmulti(1,03a0434d9e47f3c86235477c7b1ae6ae5d3442d49b1943c2b752a68e2a47e247c7,036d2b085e9e382ed10b69fc311a03f8641ccfff21574de0927513a49d9a688a00)))",
+            "wsh(and_v(v:pk(03a0434d9e47f3c86235477c7b1ae6ae5d3442d49b1943c2b752a68e2a47e247c7),or_i(older(144),after(800000))))",
+            "wsh(andor(pk(03a0434d9e47f3c86235477c7b1ae6ae5d3442d49b1943c2b752a68e2a47e247c7),sha256(e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855),sha256(e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855)))",
+            "tr(c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5,{pk(xpub661MyMwAqRbcFhCvdhTAfpEEDV58oqDvv65YNHC686NNs4KbH8YZQJWVmrfbve7aAVHzxw8bKFxA7MLeDK6BbLfkE3bqkvHLPgaGHHtYGeY/0/*),multi_a(1,c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5)})",
+            "pkh(L4rK1yDtCWekvXuE6oXD9jCYfFNV2cWRpVuPLBcCU2z8TrisoyY1)",
+            "rawtr(c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5)",
+        ];
+        for desc_str in descriptors {
+            let encoded = encode(desc_str).unwrap();
+            let walk = walk(&encoded).unwrap();
+            let template_len =
+                decoder::template_size(&encoded, &decoder::DecodeContext::default()).unwrap();
+            assert_eq!(walk.template_len, template_len, "{desc_str}");
+            let payload = &encoded[template_len..];
+            assert_eq!(walk.read_payload(payload, |_, _| {}), Ok(payload.len()));
+        }
+    }
+
+    #[test]
+    fn test_walk_errors() {
+        assert_eq!(walk(&[]), Err(Error::MissingBytes));
+        assert_eq!(walk(&[0xC0]), Err(Error::UnrecognizedTag(0)));
+        assert_eq!(walk(&[0xFF]), Err(Error::UnrecognizedTag(0)));
+        assert_eq!(walk(&[Tag::Sha256.value()]), Err(Error::InvalidTag(0)));
+        assert_eq!(
+            walk(&[Tag::Wsh.value(), Tag::HashRef.value(), 0]),
+            Err(Error::InvalidHashRef(1))
+        );
+        assert_eq!(
+            walk(&[Tag::Wpkh.value(), Tag::CompressedFullKey.value()]),
+            Err(Error::MissingBytes)
+        );
+        assert_eq!(
+            walk(&[Tag::Preset.value(), 7]),
+            Err(Error::UnknownPreset(7))
+        );
+
+        let encoded = encode(
+            "wsh(and_v(v:pk(03a0434d9e47f3c86235477c7b1ae6ae5d3442d49b1943c2b752a68e2a47e247c7),after(800000)))",
+        )
+        .unwrap();
+        let walk = walk(&encoded).unwrap();
+        assert_eq!(walk.items, vec![Item::Bytes(33), Item::After]);
+        let payload = &encoded[walk.template_len..];
+        assert_eq!(
+            walk.read_payload(&payload[..payload.len() - 1], |_, _| {}),
+            Err(Error::InvalidPayload(
+                33,
+                PayloadError::VarInt(varint::Error::Unterminated)
+            ))
+        );
+    }
+}
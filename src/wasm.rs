@@ -0,0 +1,73 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! # WASM Glue
+//!
+//! A scanning session for browser wallets, taking and returning only strings and plain numbers
+//! so it maps directly onto JavaScript. Callers need no knowledge of the format: feed each QR
+//! payload to [`ScannerSession::add_part`] and read the descriptor from
+//! [`ScannerSession::result`].
+
+use super::*;
+use scanner::{Progress, Scanner};
+
+/// An animated-QR restore in progress
+#[derive(Debug, Default, Clone)]
+pub struct ScannerSession {
+    scanner: Scanner,
+}
+
+impl ScannerSession {
+    /// Creates an empty session
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the payload of a scanned QR code, returning the progress or an error message
+    pub fn add_part(&mut self, part: &str) -> Result<Progress, String> {
+        self.scanner.add_part(part).map_err(|e| e.to_string())
+    }
+
+    /// Returns the number of parts and bytes received and needed
+    pub fn progress(&self) -> Progress {
+        self.scanner.progress()
+    }
+
+    /// Returns the decoded descriptor, or `None` until every part has been received
+    ///
+    /// Secret keys are included, so the result should not be logged.
+    pub fn result(&self) -> Result<Option<String>, String> {
+        self.scanner.result().transpose().map_err(|e| e.to_string())
+    }
+
+    /// Discards every part, to restart a scan
+    pub fn reset(&mut self) {
+        self.scanner = Scanner::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scanner_session() {
+        let desc_str =
+            "wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)#8zl0zxma";
+        let parts = scanner::ur_parts(&encode(desc_str).unwrap(), 10);
+
+        let mut session = ScannerSession::new();
+        assert_eq!(session.result(), Ok(None));
+        for part in parts.iter().rev() {
+            session.add_part(part).unwrap();
+        }
+        assert!(session.progress().is_complete());
+        assert_eq!(session.result(), Ok(Some(desc_str.to_string())));
+
+        session.reset();
+        assert_eq!(session.progress().needed, 0);
+        assert_eq!(
+            session.add_part("ur:bytes/invalid"),
+            Err("QR scan error: invalid QR part".to_string())
+        );
+    }
+}
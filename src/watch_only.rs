@@ -0,0 +1,197 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! # Watch-Only Export
+//!
+//! Converts an encoding with extended private keys into its public counterpart. Hardened
+//! derivation steps are applied to each private key and moved into its origin, so every key
+//! carries the master fingerprint and full path a hardware signer needs.
+
+use super::*;
+use bitcoin::bip32::{ChildNumber, DerivationPath, Fingerprint, Xpriv, Xpub};
+use decoder::DecodeContext;
+use miniscript::descriptor::{
+    DerivPaths, DescriptorMultiXKey, DescriptorXKey, SinglePub, SinglePubKey, Wildcard,
+};
+use secp256k1::{Secp256k1, SignOnly};
+
+/// Derives the public form of an encoding with secret keys
+///
+/// Each extended private key is replaced by the extended public key at its last hardened
+/// derivation step, with the origin extended by those steps. A key without origin is given one,
+/// starting from its own fingerprint. Single private keys are replaced by their public keys. The
+/// result carries no payload commitment or metadata.
+///
+/// Fails with [`Error::UnderivableKey`] if a key has a hardened wildcard, which no public key can
+/// derive, or if the paths of a multipath key differ before their last hardened step.
+pub fn derive_watch_only(bytes: &[u8]) -> Result<Vec<u8>, Error> {
+    let (descriptor, key_map) = decoder::decode_bytes_underived(bytes, DecodeContext::default())?;
+
+    struct Exporter<'a> {
+        key_map: &'a KeyMap,
+        secp: Secp256k1<SignOnly>,
+    }
+
+    impl Translator<DescriptorPublicKey, DescriptorPublicKey, Error> for Exporter<'_> {
+        fn pk(&mut self, pk: &DescriptorPublicKey) -> Result<DescriptorPublicKey, Error> {
+            match self.key_map.get(pk) {
+                Some(sk) => to_watch_only(sk, &self.secp),
+                None => Ok(pk.clone()),
+            }
+        }
+
+        miniscript::translate_hash_clone!(DescriptorPublicKey, DescriptorPublicKey, Error);
+    }
+
+    let mut exporter = Exporter {
+        key_map: &key_map,
+        secp: Secp256k1::signing_only(),
+    };
+    let descriptor = descriptor
+        .translate_pk(&mut exporter)
+        .map_err(|e| match e {
+            TranslateErr::TranslatorErr(e) => e,
+            TranslateErr::OuterError(e) => Error::InvalidMiniscript(0, e),
+        })?;
+
+    let (mut template, mut payload) = encoder::encode(descriptor, &KeyMap::new());
+    template.append(&mut payload);
+    Ok(template)
+}
+
+fn to_watch_only(
+    sk: &DescriptorSecretKey,
+    secp: &Secp256k1<SignOnly>,
+) -> Result<DescriptorPublicKey, Error> {
+    match sk {
+        DescriptorSecretKey::Single(single) => Ok(DescriptorPublicKey::Single(SinglePub {
+            origin: single.origin.clone(),
+            key: SinglePubKey::FullKey(single.key.public_key(secp)),
+        })),
+        DescriptorSecretKey::XPrv(xkey) => {
+            if xkey.wildcard == Wildcard::Hardened {
+                return Err(Error::UnderivableKey);
+            }
+            let (hardened, unhardened) = split_hardened(&xkey.derivation_path);
+            let (origin, xpub) = derive_hardened(&xkey.origin, &xkey.xkey, hardened, secp)?;
+            Ok(DescriptorPublicKey::XPub(DescriptorXKey {
+                origin: Some(origin),
+                xkey: xpub,
+                derivation_path: unhardened.into(),
+                wildcard: xkey.wildcard,
+            }))
+        }
+        DescriptorSecretKey::MultiXPrv(xkey) => {
+            if xkey.wildcard == Wildcard::Hardened {
+                return Err(Error::UnderivableKey);
+            }
+            let paths = xkey.derivation_paths.paths();
+            let (hardened, _) = split_hardened(&paths[0]);
+            let mut suffixes = Vec::with_capacity(paths.len());
+            for path in paths {
+                let (prefix, suffix) = split_hardened(path);
+                if prefix != hardened {
+                    return Err(Error::UnderivableKey);
+                }
+                suffixes.push(DerivationPath::from(suffix));
+            }
+
+            let (origin, xpub) = derive_hardened(&xkey.origin, &xkey.xkey, hardened, secp)?;
+            Ok(DescriptorPublicKey::MultiXPub(DescriptorMultiXKey {
+                origin: Some(origin),
+                xkey: xpub,
+                derivation_paths: DerivPaths::new(suffixes).ok_or(Error::UnderivableKey)?,
+                wildcard: xkey.wildcard,
+            }))
+        }
+    }
+}
+
+/// Splits a path after its last hardened step
+fn split_hardened(path: &DerivationPath) -> (&[ChildNumber], &[ChildNumber]) {
+    let unhardened = path
+        .into_iter()
+        .rev()
+        .take_while(|child| child.is_normal())
+        .count();
+    path.as_ref().split_at(path.len() - unhardened)
+}
+
+/// Derives `hardened` from `xprv`, returning the extended origin and the derived public key
+fn derive_hardened(
+    origin: &Option<(Fingerprint, DerivationPath)>,
+    xprv: &Xpriv,
+    hardened: &[ChildNumber],
+    secp: &Secp256k1<SignOnly>,
+) -> Result<((Fingerprint, DerivationPath), Xpub), Error> {
+    let derived = xprv
+        .derive_priv(secp, &hardened)
+        .map_err(|_| Error::UnderivableKey)?;
+    let origin = match origin {
+        Some((fingerprint, path)) => (*fingerprint, path.extend(hardened)),
+        None => (xprv.fingerprint(secp), hardened.into()),
+    };
+    Ok((origin, Xpub::from_priv(secp, &derived)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XPRV: &str = "xprv9s21ZrQH143K31xYSDQpPDxsXRTUcvj2iNHm5NUtrGiGG5e2DtALGdso3pGz6ssrdK4PFmM8NSpSBHNqPqm55Qn3LqFtT2emdEXVYsCzC2U";
+
+    #[test]
+    fn test_derive_watch_only() {
+        let secp = Secp256k1::signing_only();
+        let master = Xpriv::from_str(XPRV).unwrap();
+        let fingerprint = master.fingerprint(&secp);
+        let path = DerivationPath::from_str("m/84'/0'/0'").unwrap();
+        let xpub = Xpub::from_priv(&secp, &master.derive_priv(&secp, &path).unwrap());
+
+        // A multipath key from a master key gains an origin
+        let encoded = encode(&format!("wpkh({XPRV}/84'/0'/0'/<0;1>/*)")).unwrap();
+        let watch_only = derive_watch_only(&encoded).unwrap();
+        let expected = format!("wpkh([{fingerprint}/84'/0'/0']{xpub}/<0;1>/*)");
+        assert_eq!(watch_only, encode(&expected).unwrap());
+
+        // An existing origin is extended
+        let account = master.derive_priv(&secp, &path).unwrap();
+        let encoded = encode(&format!("tr([{fingerprint}/84']{account}/0'/1/*)")).unwrap();
+        let child = Xpub::from_priv(
+            &secp,
+            &account
+                .derive_priv(&secp, &[ChildNumber::from_hardened_idx(0).unwrap()])
+                .unwrap(),
+        );
+        let expected = format!("tr([{fingerprint}/84'/0']{child}/1/*)");
+        assert_eq!(
+            derive_watch_only(&encoded).unwrap(),
+            encode(&expected).unwrap()
+        );
+
+        // Public keys and hashes are kept
+        let desc_str = "wsh(and_v(v:pk(03a0434d9e47f3c86235477c7b1ae6ae5d3442d49b1943c2b752a68e2a47e247c7),sha256(e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855)))";
+        let encoded = encode(desc_str).unwrap();
+        assert_eq!(derive_watch_only(&encoded).unwrap(), encoded);
+
+        // Single private keys become public keys
+        let wif = "L4rK1yDtCWekvXuE6oXD9jCYfFNV2cWRpVuPLBcCU2z8TrisoyY1";
+        let pk = bitcoin::PrivateKey::from_str(wif)
+            .unwrap()
+            .public_key(&secp);
+        let encoded = encode(&format!("wpkh({wif})")).unwrap();
+        assert_eq!(
+            derive_watch_only(&encoded).unwrap(),
+            encode(&format!("wpkh({pk})")).unwrap()
+        );
+
+        // Multipath keys must share their hardened steps
+        let encoded = encode(&format!("wpkh({XPRV}/<0';1'>/*)")).unwrap();
+        assert_eq!(derive_watch_only(&encoded), Err(Error::UnderivableKey));
+
+        // Hardened wildcards cannot be derived from a public key
+        for key in [format!("{XPRV}/84'/0'/0'/0/*'"), format!("{XPRV}/<0;1>/*h")] {
+            let encoded = encode(&format!("wpkh({key})")).unwrap();
+            assert_eq!(derive_watch_only(&encoded), Err(Error::UnderivableKey));
+        }
+    }
+}